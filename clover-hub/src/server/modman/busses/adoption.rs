@@ -0,0 +1,149 @@
+//! # Bus Adoption
+//!
+//! The adoption handshake that bus proxies run with a module they haven't bound yet, see [adoption](crate::server::modman::modules::adoption). Each bus only has to provide an [`AdoptionLink`] to the module, and the [connection](ModuleConnection) it will be bound with.
+//!
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tracing::{
+  debug,
+  warn,
+};
+
+use crate::server::modman::{
+  busses::models::{
+    AdoptionHello,
+    AdoptionProbe,
+    BusMessage,
+  },
+  connections::ModuleConnection,
+  models::modules::{
+    AdoptionError,
+    AdoptionRequest,
+    AdoptionSuccess,
+  },
+  MODULE_EVT_ID,
+};
+
+/// How often a module waiting to be approved is requested again.
+pub const APPROVAL_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A bus' link to a module that's being adopted.
+pub trait AdoptionLink {
+  fn send(
+    &mut self,
+    message: &BusMessage,
+  ) -> impl std::future::Future<Output = Result<(), anyhow::Error>> + Send;
+  /// Wait for the next message, `None` once the module is gone (e.g. it disconnected).
+  fn recv(
+    &mut self,
+  ) -> impl std::future::Future<Output = Option<Result<BusMessage, anyhow::Error>>> + Send;
+}
+
+/// Probe the module until it sends its hello, other messages are ignored.
+async fn wait_for_hello<L: AdoptionLink>(
+  link: &mut L,
+  probe: &AdoptionProbe,
+  hello_timeout: Duration,
+) -> Result<AdoptionHello, anyhow::Error> {
+  link.send(&BusMessage::Probe(probe.clone())).await?;
+
+  tokio::time::timeout(hello_timeout, async {
+    loop {
+      match link.recv().await {
+        Some(Ok(BusMessage::Hello(hello))) => return Ok(hello),
+        Some(Ok(_)) => {}
+        Some(Err(err)) => {
+          warn!("Ignoring a message while waiting for a hello, due to:\n{err}");
+        }
+        None => return Err(anyhow!("Module disconnected before sending a hello.")),
+      }
+    }
+  })
+  .await
+  .map_err(|_| anyhow!("Module did not send a hello within {hello_timeout:?}."))?
+}
+
+/// Ask ModMan to adopt the module, see [adoption](crate::server::modman::modules::adoption).
+async fn request_adoption(
+  session: &zenoh::Session,
+  request: &AdoptionRequest,
+) -> Result<Result<AdoptionSuccess, AdoptionError>, anyhow::Error> {
+  let key_expr = format!("{MODULE_EVT_ID}/modules/adopt");
+
+  let replies = session
+    .get(&key_expr)
+    .payload(serde_json::to_string(request)?)
+    .await
+    .map_err(|err| anyhow!("Failed to query: {key_expr}, due to:\n{err}"))?;
+
+  match replies.recv_async().await {
+    Ok(reply) => match reply.result() {
+      Ok(sample) => Ok(serde_json_lenient::from_str::<
+        Result<AdoptionSuccess, AdoptionError>,
+      >(&sample.payload().try_to_string()?)?),
+      Err(reply_err) => Err(anyhow!(
+        "Error reply from: {key_expr}: {}",
+        reply_err.payload().try_to_string()?
+      )),
+    },
+    Err(err) => Err(anyhow!("No reply from: {key_expr}, due to:\n{err}")),
+  }
+}
+
+/// Probe a module, request its adoption (answering a challenge, and waiting up to `approval_timeout` for it to be approved), then send it its key provision. Returns the module's ID.
+pub async fn adopt_over_link<L: AdoptionLink>(
+  session: &zenoh::Session,
+  link: &mut L,
+  connection: ModuleConnection,
+  hello_timeout: Duration,
+  approval_timeout: Duration,
+) -> Result<String, anyhow::Error> {
+  let mut probe = AdoptionProbe::default();
+  let approval_deadline = tokio::time::Instant::now() + approval_timeout;
+
+  let (module_id, provision) = loop {
+    let hello = wait_for_hello(link, &probe, hello_timeout).await?;
+
+    match request_adoption(
+      session,
+      &AdoptionRequest {
+        connection: connection.clone(),
+        hello,
+      },
+    )
+    .await?
+    {
+      Ok(AdoptionSuccess::Adopted {
+        module_id,
+        provision,
+        ..
+      }) => break (module_id, provision),
+      Ok(AdoptionSuccess::AlreadyAdopted {
+        module_id,
+        provision,
+      }) => break (module_id, provision),
+      // Only answer one challenge, a module that can't prove itself would be probed forever otherwise.
+      Err(AdoptionError::ChallengeRequired { challenge }) if probe.challenge.is_none() => {
+        debug!("Module on: {connection:?}, is already adopted, probing it with a challenge...");
+        probe.challenge = Some(challenge);
+      }
+      Err(AdoptionError::ApprovalRequired { pending_id }) => {
+        if tokio::time::Instant::now() >= approval_deadline {
+          return Err(anyhow!(
+            "Module: {pending_id}, wasn't approved within {approval_timeout:?}."
+          ));
+        }
+
+        debug!("Module: {pending_id}, is waiting to be approved...");
+        tokio::time::sleep(APPROVAL_POLL_INTERVAL).await;
+      }
+      Err(adoption_error) => return Err(anyhow!("Adoption was refused: {adoption_error}")),
+    }
+  };
+
+  link.send(&BusMessage::Provision(provision)).await?;
+
+  Ok(module_id)
+}
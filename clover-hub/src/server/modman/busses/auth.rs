@@ -0,0 +1,1198 @@
+//! # Content Message Authentication
+//!
+//! Every [`ContentMessage`] sent over a proxy bus carries a counter, a random nonce, and an HMAC-SHA256 of `counter || nonce || data` using the module's key. Proxies verify messages from modules before republishing them to Zenoh, reject nonces that were already seen, and sign messages before sending them to modules.
+//!
+//! Counters must increase with every message a sender sends. The highest counter accepted from each module is kept in the [`Module`], and saved with it on shutdown, so messages from before a restart can't be replayed either. ModMan's own counters are based on the time, so they keep increasing across restarts without being saved.
+//!
+//! Which key is used depends on the module's [`SecurityLevel`]:
+//!
+//! - L1: the static key provisioned during adoption.
+//! - L2: a session key derived from the root key (from the X25519 exchange during adoption) and the session number. Sessions are rotated by ModMan, see [`rotate_session_if_due`], and messages signed with the previous session's key are still accepted while the module catches up. The session number is kept in the [`Module`], and saved with it on shutdown, so ModMan and the module still agree on it after a restart.
+//! - L3: a key per message. The sender generates a new X25519 key for every message and sends its public key along with it, the message key is derived from the root key, the exchange between that key and the receiver's X25519 key, and the nonce. The sender drops the ephemeral secret once the message is signed, so its keys can't be recovered from the sender later on, and a leaked root key alone isn't enough to derive them.
+//!
+//! Modules that are adopted again must prove that they hold their current key by answering a challenge, see [`adoption_proof`].
+//!
+//! Failures are published on `{MODULE_EVT_ID}/modules/by-id/{module_id}/error` as a [`ContentError`], so apps watching a module can tell when it's misbehaving (or being spoofed.)
+//!
+
+use std::{
+  collections::{
+    HashMap,
+    HashSet,
+    VecDeque,
+  },
+  sync::Arc,
+  time::{
+    SystemTime,
+    UNIX_EPOCH,
+  },
+};
+
+use hkdf::Hkdf;
+use hmac::{
+  Hmac,
+  Mac,
+};
+use rand::{
+  rngs::OsRng,
+  RngCore,
+};
+use serde::{
+  Deserialize,
+  Serialize,
+};
+use sha2::Sha256;
+use tokio::time::Instant;
+use tracing::{
+  debug,
+  error,
+  instrument,
+  warn,
+};
+use x25519_dalek::{
+  EphemeralSecret,
+  PublicKey,
+  StaticSecret,
+};
+
+use crate::server::modman::{
+  busses::models::{
+    AdoptionHello,
+    BusMessage,
+    ContentMessage,
+    KeyProvision,
+  },
+  models::{
+    modules::{
+      AdoptionError,
+      Module,
+      SecurityLevel,
+    },
+    store::ModManStore,
+  },
+  MODULE_EVT_ID,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of the nonces that ModMan generates, modules may use longer ones.
+pub const NONCE_LEN: usize = 16;
+/// Shortest nonce that will be accepted from a module.
+pub const MIN_NONCE_LEN: usize = 8;
+/// How many nonces are remembered per module for replay detection.
+pub const NONCE_WINDOW: usize = 1024;
+/// Length of generated L1 keys, and of derived keys.
+pub const KEY_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Debug, strum_macros::Display)]
+#[serde(tag = "error")]
+pub enum ContentError {
+  #[serde(rename = "missing-key")]
+  #[strum(serialize = "missing-key")]
+  MissingKey,
+  #[serde(rename = "invalid-key")]
+  #[strum(serialize = "invalid-key")]
+  InvalidKey { reason: String },
+  #[serde(rename = "invalid-nonce")]
+  #[strum(serialize = "invalid-nonce")]
+  InvalidNonce,
+  #[serde(rename = "replayed-nonce")]
+  #[strum(serialize = "replayed-nonce")]
+  ReplayedNonce,
+  /// The message's counter isn't higher than the last one accepted from the module.
+  #[serde(rename = "stale-counter")]
+  #[strum(serialize = "stale-counter")]
+  StaleCounter,
+  #[serde(rename = "invalid-hmac")]
+  #[strum(serialize = "invalid-hmac")]
+  InvalidHmac,
+  #[serde(rename = "malformed-message")]
+  #[strum(serialize = "malformed-message")]
+  MalformedMessage { reason: String },
+  #[serde(rename = "send-failed")]
+  #[strum(serialize = "send-failed")]
+  SendFailed { reason: String },
+  /// A frame from the module's port was corrupted, `count` is the total for the port since it was bound.
+  #[serde(rename = "framing")]
+  #[strum(serialize = "framing")]
+  Framing {
+    port: String,
+    reason: String,
+    count: u64,
+  },
+}
+
+/// The most recent nonces seen from a module.
+#[derive(Debug, Clone, Default)]
+pub struct NonceWindow {
+  order: VecDeque<Vec<u8>>,
+  seen: HashSet<Vec<u8>>,
+}
+
+impl NonceWindow {
+  /// Remember a nonce, returns `false` if it's already been seen.
+  pub fn insert(&mut self, nonce: &[u8]) -> bool {
+    if !self.seen.insert(nonce.to_vec()) {
+      return false;
+    }
+
+    self.order.push_back(nonce.to_vec());
+    if self.order.len() > NONCE_WINDOW {
+      if let Some(oldest) = self.order.pop_front() {
+        self.seen.remove(&oldest);
+      }
+    }
+
+    true
+  }
+}
+
+/// When an L2 module's session was last rotated, the session number itself is kept in the [`Module`].
+#[derive(Debug, Clone)]
+pub struct ModuleSession {
+  pub rotated_at: Instant,
+}
+
+impl Default for ModuleSession {
+  fn default() -> Self {
+    ModuleSession {
+      rotated_at: Instant::now(),
+    }
+  }
+}
+
+/// Everything needed to derive the keys for a module's messages.
+#[derive(Debug, Clone)]
+pub struct ModuleKeys {
+  pub security_level: SecurityLevel,
+  pub key: Vec<u8>,
+  pub session: u32,
+  /// Our X25519 secret, only for L3.
+  pub secret: Option<[u8; 32]>,
+  /// The other side's X25519 public key, only for L3.
+  pub peer_public_key: Option<[u8; 32]>,
+}
+
+impl ModuleKeys {
+  /// Key to sign a message with this nonce, and for L3, the ephemeral public key that must be sent with the message.
+  pub fn signing_key(&self, nonce: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>), ContentError> {
+    match self.security_level {
+      SecurityLevel::L1 => Ok((self.key.clone(), None)),
+      SecurityLevel::L2 => Ok((session_key(&self.key, self.session), None)),
+      SecurityLevel::L3 => {
+        let peer_public_key = match self.peer_public_key {
+          Some(peer_public_key) => PublicKey::from(peer_public_key),
+          None => return Err(ContentError::MissingKey),
+        };
+
+        let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_key = PublicKey::from(&ephemeral_secret);
+        let shared_secret = ephemeral_secret.diffie_hellman(&peer_public_key);
+        if !shared_secret.was_contributory() {
+          return Err(ContentError::InvalidKey {
+            reason: "public key is a low order point".to_string(),
+          });
+        }
+
+        Ok((
+          message_key(
+            &self.key,
+            nonce,
+            shared_secret.as_bytes(),
+            ephemeral_key.as_bytes(),
+          ),
+          Some(ephemeral_key.as_bytes().to_vec()),
+        ))
+      }
+    }
+  }
+
+  /// Keys that a message may be signed with.
+  pub fn verification_keys(&self, message: &ContentMessage) -> Result<Vec<Vec<u8>>, ContentError> {
+    match self.security_level {
+      SecurityLevel::L1 => Ok(vec![self.key.clone()]),
+      SecurityLevel::L2 if self.session > 0 => Ok(vec![
+        session_key(&self.key, self.session),
+        session_key(&self.key, self.session - 1),
+      ]),
+      SecurityLevel::L2 => Ok(vec![session_key(&self.key, self.session)]),
+      SecurityLevel::L3 => {
+        let secret = match self.secret {
+          Some(secret) => StaticSecret::from(secret),
+          None => return Err(ContentError::MissingKey),
+        };
+        let ephemeral_key: [u8; 32] = match &message.ephemeral_key {
+          Some(ephemeral_key) => match ephemeral_key.as_slice().try_into() {
+            Ok(ephemeral_key) => ephemeral_key,
+            Err(_) => {
+              return Err(ContentError::InvalidKey {
+                reason: "ephemeral key must be 32 bytes".to_string(),
+              })
+            }
+          },
+          None => {
+            return Err(ContentError::MalformedMessage {
+              reason: "L3 messages must carry an ephemeral key".to_string(),
+            })
+          }
+        };
+
+        let shared_secret = secret.diffie_hellman(&PublicKey::from(ephemeral_key));
+        if !shared_secret.was_contributory() {
+          return Err(ContentError::InvalidKey {
+            reason: "ephemeral key is a low order point".to_string(),
+          });
+        }
+
+        Ok(vec![message_key(
+          &self.key,
+          &message.nonce,
+          shared_secret.as_bytes(),
+          &ephemeral_key,
+        )])
+      }
+    }
+  }
+}
+
+fn derive_key(root_key: &[u8], salt: Option<&[u8]>, info: &[u8]) -> Vec<u8> {
+  let mut key = vec![0u8; KEY_LEN];
+  // Only fails if the output is longer than 255 hashes.
+  Hkdf::<Sha256>::new(salt, root_key)
+    .expand(info, &mut key)
+    .unwrap();
+  key
+}
+
+fn session_key(root_key: &[u8], session: u32) -> Vec<u8> {
+  derive_key(
+    root_key,
+    None,
+    format!("clover/session/{session}").as_bytes(),
+  )
+}
+
+fn message_key(
+  root_key: &[u8],
+  nonce: &[u8],
+  shared_secret: &[u8],
+  ephemeral_key: &[u8],
+) -> Vec<u8> {
+  derive_key(
+    &[shared_secret, root_key].concat(),
+    Some(nonce),
+    &[b"clover/message/".as_slice(), ephemeral_key].concat(),
+  )
+}
+
+/// Generate keys for a module that's being adopted, returns ModMan's side of the keys (see [`store_module_keys`]), and the provision to send to it.
+pub fn provision_keys(
+  security_level: SecurityLevel,
+  module_public_key: Option<&Vec<u8>>,
+) -> Result<(ModuleKeys, KeyProvision), AdoptionError> {
+  match security_level {
+    SecurityLevel::L1 => {
+      let mut key = vec![0u8; KEY_LEN];
+      OsRng.fill_bytes(&mut key);
+
+      Ok((
+        ModuleKeys {
+          security_level,
+          key: key.clone(),
+          session: 0,
+          secret: None,
+          peer_public_key: None,
+        },
+        KeyProvision {
+          security_level,
+          key: Some(key),
+          public_key: None,
+        },
+      ))
+    }
+    SecurityLevel::L2 | SecurityLevel::L3 => {
+      let module_public_key: [u8; 32] = match module_public_key {
+        Some(public_key) => match public_key.as_slice().try_into() {
+          Ok(public_key) => public_key,
+          Err(_) => return Err(AdoptionError::InvalidPublicKey),
+        },
+        None => return Err(AdoptionError::MissingPublicKey),
+      };
+
+      // Only kept for L3, which needs it for every message's key exchange.
+      let secret = StaticSecret::random_from_rng(OsRng);
+      let public_key = PublicKey::from(&secret);
+      let shared_secret = secret.diffie_hellman(&PublicKey::from(module_public_key));
+
+      // Low order points would give a predictable key.
+      if !shared_secret.was_contributory() {
+        return Err(AdoptionError::InvalidPublicKey);
+      }
+
+      let salt = [
+        public_key.as_bytes().as_slice(),
+        module_public_key.as_slice(),
+      ]
+      .concat();
+
+      let is_l3 = security_level == SecurityLevel::L3;
+
+      Ok((
+        ModuleKeys {
+          security_level,
+          key: derive_key(shared_secret.as_bytes(), Some(&salt), b"clover/root"),
+          session: 0,
+          secret: is_l3.then(|| secret.to_bytes()),
+          peer_public_key: is_l3.then_some(module_public_key),
+        },
+        KeyProvision {
+          security_level,
+          key: None,
+          public_key: Some(public_key.as_bytes().to_vec()),
+        },
+      ))
+    }
+  }
+}
+
+/// The module's side of [`provision_keys`], derives the same keys from the provision and the module's secret.
+///
+/// Used by modules that ModMan runs itself (e.g. the [bus harness](super::harness)), real modules do this in firmware.
+pub fn accept_provision(
+  provision: &KeyProvision,
+  module_secret: Option<&StaticSecret>,
+) -> Result<ModuleKeys, ContentError> {
+  let (key, exchange_keys) = match provision.security_level {
+    SecurityLevel::L1 => match &provision.key {
+      Some(key) => (key.clone(), None),
+      None => return Err(ContentError::MissingKey),
+    },
+    SecurityLevel::L2 | SecurityLevel::L3 => {
+      let hub_public_key: [u8; 32] = match &provision.public_key {
+        Some(public_key) => match public_key.as_slice().try_into() {
+          Ok(public_key) => public_key,
+          Err(_) => {
+            return Err(ContentError::InvalidKey {
+              reason: "public key must be 32 bytes".to_string(),
+            })
+          }
+        },
+        None => return Err(ContentError::MissingKey),
+      };
+      let module_secret = match module_secret {
+        Some(module_secret) => module_secret,
+        None => return Err(ContentError::MissingKey),
+      };
+
+      let shared_secret = module_secret.diffie_hellman(&PublicKey::from(hub_public_key));
+      if !shared_secret.was_contributory() {
+        return Err(ContentError::InvalidKey {
+          reason: "public key is a low order point".to_string(),
+        });
+      }
+
+      let salt = [
+        hub_public_key.as_slice(),
+        PublicKey::from(module_secret).as_bytes().as_slice(),
+      ]
+      .concat();
+
+      (
+        derive_key(shared_secret.as_bytes(), Some(&salt), b"clover/root"),
+        Some((module_secret.to_bytes(), hub_public_key)),
+      )
+    }
+  };
+  let exchange_keys = exchange_keys.filter(|_| provision.security_level == SecurityLevel::L3);
+
+  Ok(ModuleKeys {
+    security_level: provision.security_level,
+    key,
+    session: 0,
+    secret: exchange_keys.map(|(secret, _)| secret),
+    peer_public_key: exchange_keys.map(|(_, peer_public_key)| peer_public_key),
+  })
+}
+
+fn keyed_mac(key: &[u8], parts: &[&[u8]]) -> Result<HmacSha256, ContentError> {
+  match HmacSha256::new_from_slice(key) {
+    Ok(mut mac) => {
+      for part in parts {
+        mac.update(part);
+      }
+      Ok(mac)
+    }
+    Err(err) => Err(ContentError::InvalidKey {
+      reason: err.to_string(),
+    }),
+  }
+}
+
+fn content_mac(
+  key: &[u8],
+  counter: u64,
+  nonce: &[u8],
+  data: &[u8],
+) -> Result<HmacSha256, ContentError> {
+  keyed_mac(key, &[&counter.to_be_bytes(), nonce, data])
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>, ContentError> {
+  base64::Engine::decode(&base64::prelude::BASE64_STANDARD, key).map_err(|err| {
+    ContentError::InvalidKey {
+      reason: err.to_string(),
+    }
+  })
+}
+
+fn decode_exchange_key(key: &Option<String>) -> Result<Option<[u8; 32]>, ContentError> {
+  match key {
+    Some(key) => match decode_key(key)?.as_slice().try_into() {
+      Ok(key) => Ok(Some(key)),
+      Err(_) => Err(ContentError::InvalidKey {
+        reason: "exchange keys must be 32 bytes".to_string(),
+      }),
+    },
+    None => Ok(None),
+  }
+}
+
+/// Save ModMan's side of a module's keys into the module.
+pub fn store_module_keys(module: &mut Module, keys: &ModuleKeys) {
+  let encode = |key: &[u8]| base64::Engine::encode(&base64::prelude::BASE64_STANDARD, key);
+
+  module.security_level = keys.security_level;
+  module.key = Some(encode(&keys.key));
+  module.session = keys.session;
+  module.exchange_secret = keys.secret.map(|secret| encode(&secret));
+  module.public_key = keys.peer_public_key.map(|public_key| encode(&public_key));
+}
+
+fn adoption_mac(
+  keys: &ModuleKeys,
+  challenge: &[u8],
+  hello: &AdoptionHello,
+) -> Result<HmacSha256, ContentError> {
+  // Binds the proof to the keys it's asking for, so it can't be reused with someone else's public key.
+  let hello_data = [
+    hello.module_type.as_bytes(),
+    &[0],
+    hello.serial.as_bytes(),
+    &[0],
+    hello.security_level.to_string().as_bytes(),
+    &[0],
+    hello.public_key.as_deref().unwrap_or_default(),
+  ]
+  .concat();
+
+  keyed_mac(
+    &derive_key(&keys.key, None, b"clover/adoption"),
+    &[challenge, &hello_data],
+  )
+}
+
+/// Proof that a module holds its current key, sent in its hello when it's adopted again. An HMAC of the challenge from the [probe](crate::server::modman::busses::models::AdoptionProbe) and the hello itself, with a key derived from the module's current key.
+pub fn adoption_proof(
+  keys: &ModuleKeys,
+  challenge: &[u8],
+  hello: &AdoptionHello,
+) -> Result<Vec<u8>, ContentError> {
+  Ok(
+    adoption_mac(keys, challenge, hello)?
+      .finalize()
+      .into_bytes()
+      .to_vec(),
+  )
+}
+
+/// Check the proof in a hello from a module that's already adopted. The challenge is issued by the first call without a proof (or without an outstanding challenge), and can only be answered once.
+pub async fn check_adoption_proof(
+  store: &ModManStore,
+  module_id: &String,
+  hello: &AdoptionHello,
+) -> Result<(), AdoptionError> {
+  let challenge = store.adoption_challenges.lock().await.remove(module_id);
+
+  match (challenge, &hello.proof) {
+    (Some(challenge), Some(proof)) => {
+      let keys = module_keys(store, module_id)
+        .await
+        .map_err(|_| AdoptionError::InvalidProof)?;
+
+      match adoption_mac(&keys, &challenge, hello).map(|mac| mac.verify_slice(proof)) {
+        Ok(Ok(())) => Ok(()),
+        _ => Err(AdoptionError::InvalidProof),
+      }
+    }
+    _ => {
+      let mut challenge = vec![0u8; NONCE_LEN];
+      OsRng.fill_bytes(&mut challenge);
+
+      store
+        .adoption_challenges
+        .lock()
+        .await
+        .insert(module_id.clone(), challenge.clone());
+      Err(AdoptionError::ChallengeRequired { challenge })
+    }
+  }
+}
+
+/// Get the keys registered for a module.
+pub async fn module_keys(
+  store: &ModManStore,
+  module_id: &String,
+) -> Result<ModuleKeys, ContentError> {
+  let (security_level, key, session, secret, peer_public_key) =
+    match store.modules.lock().await.get(module_id) {
+      Some(module) => (
+        module.security_level,
+        module.key.clone(),
+        module.session,
+        module.exchange_secret.clone(),
+        module.public_key.clone(),
+      ),
+      None => return Err(ContentError::MissingKey),
+    };
+
+  let key = match key {
+    Some(key) => decode_key(&key)?,
+    None => return Err(ContentError::MissingKey),
+  };
+
+  Ok(ModuleKeys {
+    security_level,
+    key,
+    session,
+    secret: decode_exchange_key(&secret)?,
+    peer_public_key: decode_exchange_key(&peer_public_key)?,
+  })
+}
+
+/// Wrap data in a [`ContentMessage`] with a fresh nonce, signed with the module's keys. The counter must be higher than the one in the last message sent with these keys.
+pub fn sign_content(
+  keys: &ModuleKeys,
+  counter: u64,
+  data: Vec<u8>,
+) -> Result<ContentMessage, ContentError> {
+  let mut nonce = vec![0u8; NONCE_LEN];
+  OsRng.fill_bytes(&mut nonce);
+
+  let (key, ephemeral_key) = keys.signing_key(&nonce)?;
+  let hmac = content_mac(&key, counter, &nonce, &data)?
+    .finalize()
+    .into_bytes()
+    .to_vec();
+
+  Ok(ContentMessage {
+    counter,
+    nonce,
+    data,
+    hmac,
+    ephemeral_key,
+  })
+}
+
+/// Check a message's HMAC against the module's keys, without checking for replays.
+pub fn verify_content(keys: &ModuleKeys, message: &ContentMessage) -> Result<(), ContentError> {
+  if message.nonce.len() < MIN_NONCE_LEN {
+    return Err(ContentError::InvalidNonce);
+  }
+
+  for key in keys.verification_keys(message)? {
+    if content_mac(&key, message.counter, &message.nonce, &message.data)?
+      .verify_slice(&message.hmac)
+      .is_ok()
+    {
+      return Ok(());
+    }
+  }
+
+  Err(ContentError::InvalidHmac)
+}
+
+/// Verify a message received from a module, content messages must be signed with the module's key, use a nonce that hasn't been seen recently, and a counter that's higher than the last one accepted.
+///
+/// Handshake messages aren't signed, since they're sent before the module has a key.
+#[instrument(skip(store, message))]
+pub async fn verify_bus_message(
+  store: &ModManStore,
+  module_id: &String,
+  message: &BusMessage,
+) -> Result<(), ContentError> {
+  match message {
+    BusMessage::Content(content) | BusMessage::Rekey(content) => {
+      verify_content(&module_keys(store, module_id).await?, content)?;
+
+      // Only remember nonces from authentic messages, otherwise anyone could fill the window.
+      let mut seen_nonces = store.seen_nonces.lock().await;
+      if !seen_nonces
+        .entry(module_id.clone())
+        .or_default()
+        .insert(&content.nonce)
+      {
+        return Err(ContentError::ReplayedNonce);
+      }
+      drop(seen_nonces);
+
+      match store.modules.lock().await.get_mut(module_id) {
+        Some(module) if content.counter > module.last_counter => {
+          module.last_counter = content.counter;
+          Ok(())
+        }
+        Some(_) => Err(ContentError::StaleCounter),
+        None => Err(ContentError::MissingKey),
+      }
+    }
+    _ => Ok(()),
+  }
+}
+
+/// Next counter for a message that ModMan sends to a module. Microseconds since the UNIX epoch, or one more than the last counter if that's higher, so it keeps increasing across restarts without being saved.
+pub async fn next_counter(store: &ModManStore, module_id: &str) -> u64 {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|since_epoch| since_epoch.as_micros() as u64)
+    .unwrap_or_default();
+
+  let mut sent_counters = store.sent_counters.lock().await;
+  let sent_counter = sent_counters.entry(module_id.to_string()).or_default();
+  *sent_counter = now.max(sent_counter.saturating_add(1));
+  *sent_counter
+}
+
+/// Sign data to be sent to a module with its key.
+pub async fn sign_for_module(
+  store: &ModManStore,
+  module_id: &String,
+  data: Vec<u8>,
+) -> Result<BusMessage, ContentError> {
+  let keys = module_keys(store, module_id).await?;
+
+  Ok(BusMessage::Content(sign_content(
+    &keys,
+    next_counter(store, module_id).await,
+    data,
+  )?))
+}
+
+/// Move an L2 module to its next session once the current one is older than the configured `session_key_lifetime`.
+///
+/// Returns the [`BusMessage::Rekey`] that must be sent to the module before anything signed with the new session's key.
+#[instrument(skip(store))]
+pub async fn rotate_session_if_due(
+  store: &ModManStore,
+  module_id: &String,
+) -> Result<Option<BusMessage>, ContentError> {
+  let security_level = match store.modules.lock().await.get(module_id) {
+    Some(module) => module.security_level,
+    None => return Ok(None),
+  };
+  if security_level != SecurityLevel::L2 {
+    return Ok(None);
+  }
+
+  let keys = module_keys(store, module_id).await?;
+  let counter = next_counter(store, module_id).await;
+
+  let lifetime = store.config.lock().await.modman.session_key_lifetime;
+  let mut module_sessions = store.module_sessions.lock().await;
+  let module_session = module_sessions.entry(module_id.clone()).or_default();
+
+  if module_session.rotated_at.elapsed().as_secs() < lifetime {
+    return Ok(None);
+  }
+
+  let next_session = keys.session.wrapping_add(1);
+  let rekey = sign_content(&keys, counter, next_session.to_be_bytes().to_vec())?;
+
+  if let Some(module) = store.modules.lock().await.get_mut(module_id) {
+    module.session = next_session;
+  }
+  module_session.rotated_at = Instant::now();
+  debug!("Rotated module: {module_id}, to session: {next_session}.");
+
+  Ok(Some(BusMessage::Rekey(rekey)))
+}
+
+/// Forget a module's session, nonces, and counter, used when it's given new keys.
+pub async fn reset_module_keys(store: &ModManStore, module_id: &String) {
+  store.module_sessions.lock().await.remove(module_id);
+  store.seen_nonces.lock().await.remove(module_id);
+  if let Some(module) = store.modules.lock().await.get_mut(module_id) {
+    module.session = 0;
+    module.last_counter = 0;
+  }
+}
+
+/// Write the sessions and counters of modules defined in the configuration back into it, so they're still in sync with the modules after a restart, and replays from before it are still rejected.
+#[instrument(skip(store))]
+pub async fn save_module_auth_state(store: &ModManStore) {
+  let auth_states: HashMap<String, (u32, u64)> = store
+    .modules
+    .lock()
+    .await
+    .iter()
+    .map(|(module_id, module)| (module_id.clone(), (module.session, module.last_counter)))
+    .collect();
+
+  let mut config = store.config.lock().await;
+  for (module_id, static_module) in config.modman.static_modules.iter_mut() {
+    if let Some((session, last_counter)) = auth_states.get(module_id) {
+      static_module.session = *session;
+      static_module.last_counter = *last_counter;
+    }
+  }
+}
+
+/// Publish an error on the module's error topic.
+#[instrument(skip(session))]
+pub async fn report_content_error(
+  session: &Arc<zenoh::Session>,
+  module_id: &String,
+  content_error: &ContentError,
+) {
+  let key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/error");
+  warn!("Module: {module_id}, content error: {content_error}");
+
+  match serde_json::to_string(content_error) {
+    Ok(error_str) => match session.put(&key_expr, error_str).await {
+      Ok(_) => {}
+      Err(err) => {
+        error!("Failed to publish content error on: {key_expr}, due to:\n{err}");
+      }
+    },
+    Err(err) => {
+      error!("Failed to serialize content error; this is a bug and should be reported! This happened due to:\n{err}");
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::server::modman::{
+    connections::ModuleConnection,
+    models::modules::Module,
+  };
+
+  /// L3 keys exchange with themselves, so the same keys can sign and verify.
+  fn keys(security_level: SecurityLevel, session: u32) -> ModuleKeys {
+    let secret = StaticSecret::from([9u8; 32]);
+    let is_l3 = security_level == SecurityLevel::L3;
+
+    ModuleKeys {
+      security_level,
+      key: vec![7u8; KEY_LEN],
+      session,
+      secret: is_l3.then(|| secret.to_bytes()),
+      peer_public_key: is_l3.then(|| PublicKey::from(&secret).to_bytes()),
+    }
+  }
+
+  async fn store_with_module(module_id: &str, keys: &ModuleKeys) -> ModManStore {
+    let store = ModManStore::new(None, None);
+    let mut module = Module {
+      module_type: "com.example.module".to_string(),
+      module_name: "Example".to_string(),
+      custom_name: None,
+      initialized: true,
+      components: vec![],
+      registered_by: "com.reboot-codes.clover.hub".to_string(),
+      connection: ModuleConnection::Simulated(module_id.to_string()),
+      firmware_version: None,
+      serial: Some("0001".to_string()),
+      security_level: SecurityLevel::L1,
+      key: None,
+      exchange_secret: None,
+      public_key: None,
+      insecure_movement: false,
+      session: 0,
+      last_counter: 0,
+    };
+    store_module_keys(&mut module, keys);
+    store
+      .modules
+      .lock()
+      .await
+      .insert(module_id.to_string(), module);
+    store
+  }
+
+  #[test]
+  fn good_hmac_verifies() {
+    for security_level in [SecurityLevel::L1, SecurityLevel::L2, SecurityLevel::L3] {
+      let keys = keys(security_level, 3);
+      let message = sign_content(&keys, 1, b"hello".to_vec()).unwrap();
+
+      assert!(verify_content(&keys, &message).is_ok());
+    }
+  }
+
+  #[test]
+  fn flipped_bit_fails() {
+    let keys = keys(SecurityLevel::L1, 0);
+
+    let mut message = sign_content(&keys, 1, b"hello".to_vec()).unwrap();
+    message.data[0] ^= 0b0000_0001;
+    assert!(matches!(
+      verify_content(&keys, &message),
+      Err(ContentError::InvalidHmac)
+    ));
+
+    let mut message = sign_content(&keys, 1, b"hello".to_vec()).unwrap();
+    message.hmac[0] ^= 0b1000_0000;
+    assert!(matches!(
+      verify_content(&keys, &message),
+      Err(ContentError::InvalidHmac)
+    ));
+
+    let mut message = sign_content(&keys, 1, b"hello".to_vec()).unwrap();
+    message.nonce[0] ^= 0b0001_0000;
+    assert!(matches!(
+      verify_content(&keys, &message),
+      Err(ContentError::InvalidHmac)
+    ));
+  }
+
+  #[test]
+  fn wrong_key_fails() {
+    let keys = keys(SecurityLevel::L1, 0);
+    let message = sign_content(&keys, 1, b"hello".to_vec()).unwrap();
+    let wrong_keys = ModuleKeys {
+      key: vec![8u8; KEY_LEN],
+      ..keys
+    };
+
+    assert!(matches!(
+      verify_content(&wrong_keys, &message),
+      Err(ContentError::InvalidHmac)
+    ));
+  }
+
+  #[test]
+  fn short_nonce_fails() {
+    let keys = keys(SecurityLevel::L1, 0);
+    let mut message = sign_content(&keys, 1, b"hello".to_vec()).unwrap();
+    message.nonce.truncate(MIN_NONCE_LEN - 1);
+
+    assert!(matches!(
+      verify_content(&keys, &message),
+      Err(ContentError::InvalidNonce)
+    ));
+  }
+
+  #[tokio::test]
+  async fn replayed_nonce_fails() {
+    let keys = keys(SecurityLevel::L1, 0);
+    let store = store_with_module("module", &keys).await;
+    let message = BusMessage::Content(sign_content(&keys, 1, b"hello".to_vec()).unwrap());
+
+    assert!(verify_bus_message(&store, &"module".to_string(), &message)
+      .await
+      .is_ok());
+    assert!(matches!(
+      verify_bus_message(&store, &"module".to_string(), &message).await,
+      Err(ContentError::ReplayedNonce)
+    ));
+  }
+
+  #[tokio::test]
+  async fn forged_message_does_not_fill_window() {
+    let keys = keys(SecurityLevel::L1, 0);
+    let store = store_with_module("module", &keys).await;
+    let message = sign_content(&keys, 1, b"hello".to_vec()).unwrap();
+    let mut forged = message.clone();
+    forged.hmac[0] ^= 1;
+
+    assert!(matches!(
+      verify_bus_message(&store, &"module".to_string(), &BusMessage::Content(forged)).await,
+      Err(ContentError::InvalidHmac)
+    ));
+    assert!(
+      verify_bus_message(&store, &"module".to_string(), &BusMessage::Content(message))
+        .await
+        .is_ok()
+    );
+  }
+
+  #[test]
+  fn counter_is_covered_by_hmac() {
+    let keys = keys(SecurityLevel::L1, 0);
+    let mut message = sign_content(&keys, 1, b"hello".to_vec()).unwrap();
+    message.counter = 2;
+
+    assert!(matches!(
+      verify_content(&keys, &message),
+      Err(ContentError::InvalidHmac)
+    ));
+  }
+
+  #[tokio::test]
+  async fn stale_counter_fails() {
+    let keys = keys(SecurityLevel::L1, 0);
+    let store = store_with_module("module", &keys).await;
+    let verify = |counter| {
+      let message = BusMessage::Content(sign_content(&keys, counter, b"hello".to_vec()).unwrap());
+      let store = &store;
+      async move { verify_bus_message(store, &"module".to_string(), &message).await }
+    };
+
+    assert!(verify(2).await.is_ok());
+    assert!(matches!(verify(2).await, Err(ContentError::StaleCounter)));
+    assert!(matches!(verify(1).await, Err(ContentError::StaleCounter)));
+    assert!(verify(3).await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn counter_survives_restart() {
+    let keys = keys(SecurityLevel::L1, 0);
+    let store = store_with_module("module", &keys).await;
+    let message = BusMessage::Content(sign_content(&keys, 5, b"hello".to_vec()).unwrap());
+
+    let module = store.modules.lock().await.get("module").cloned().unwrap();
+    store
+      .config
+      .lock()
+      .await
+      .modman
+      .static_modules
+      .insert("module".to_string(), module);
+
+    assert!(verify_bus_message(&store, &"module".to_string(), &message)
+      .await
+      .is_ok());
+    save_module_auth_state(&store).await;
+
+    // A fresh store, with the module loaded from the saved configuration.
+    let config = store.config.lock().await.clone();
+    let reloaded = ModManStore::new(Some(Arc::new(tokio::sync::Mutex::new(config))), None);
+    let static_modules = reloaded.config.lock().await.modman.static_modules.clone();
+    reloaded.modules.lock().await.extend(static_modules);
+
+    assert!(matches!(
+      verify_bus_message(&reloaded, &"module".to_string(), &message).await,
+      Err(ContentError::StaleCounter)
+    ));
+  }
+
+  #[tokio::test]
+  async fn session_survives_restart() {
+    let keys = keys(SecurityLevel::L2, 0);
+    let store = store_with_module("module", &keys).await;
+    store.config.lock().await.modman.session_key_lifetime = 0;
+
+    let module = store.modules.lock().await.get("module").cloned().unwrap();
+    store
+      .config
+      .lock()
+      .await
+      .modman
+      .static_modules
+      .insert("module".to_string(), module);
+
+    for _ in 0..2 {
+      assert!(matches!(
+        rotate_session_if_due(&store, &"module".to_string()).await,
+        Ok(Some(BusMessage::Rekey(_)))
+      ));
+    }
+    save_module_auth_state(&store).await;
+
+    // A fresh store, with the module loaded from the saved configuration.
+    let config = store.config.lock().await.clone();
+    let reloaded = ModManStore::new(Some(Arc::new(tokio::sync::Mutex::new(config))), None);
+    let static_modules = reloaded.config.lock().await.modman.static_modules.clone();
+    reloaded.modules.lock().await.extend(static_modules);
+
+    // The module is on the session it was rekeyed to, not the first one.
+    let module_keys = ModuleKeys { session: 2, ..keys };
+    let message = BusMessage::Content(sign_content(&module_keys, 1, b"hello".to_vec()).unwrap());
+    assert!(
+      verify_bus_message(&reloaded, &"module".to_string(), &message)
+        .await
+        .is_ok()
+    );
+  }
+
+  #[test]
+  fn window_evicts_oldest_nonce() {
+    let mut window = NonceWindow::default();
+    let nonce = |index: usize| (index as u64).to_be_bytes().to_vec();
+
+    for index in 0..NONCE_WINDOW {
+      assert!(window.insert(&nonce(index)));
+    }
+    // Still remembered while the window isn't full.
+    assert!(!window.insert(&nonce(0)));
+
+    assert!(window.insert(&nonce(NONCE_WINDOW)));
+    // The oldest nonce fell out of the window, the rest are still remembered.
+    assert!(window.insert(&nonce(0)));
+    assert!(!window.insert(&nonce(2)));
+    assert!(!window.insert(&nonce(NONCE_WINDOW)));
+  }
+
+  #[test]
+  fn l2_accepts_previous_session_only() {
+    let current = keys(SecurityLevel::L2, 2);
+    let message =
+      |session| sign_content(&keys(SecurityLevel::L2, session), 1, b"hi".to_vec()).unwrap();
+
+    assert!(verify_content(&current, &message(2)).is_ok());
+    assert!(verify_content(&current, &message(1)).is_ok());
+    assert!(matches!(
+      verify_content(&current, &message(0)),
+      Err(ContentError::InvalidHmac)
+    ));
+    assert!(matches!(
+      verify_content(&current, &message(3)),
+      Err(ContentError::InvalidHmac)
+    ));
+  }
+
+  fn provision(security_level: SecurityLevel) -> (ModuleKeys, ModuleKeys) {
+    let module_secret = StaticSecret::random_from_rng(OsRng);
+    let module_public_key = PublicKey::from(&module_secret).as_bytes().to_vec();
+
+    let (hub_keys, provision) = provision_keys(security_level, Some(&module_public_key)).unwrap();
+    let module_keys = accept_provision(&provision, Some(&module_secret)).unwrap();
+
+    (hub_keys, module_keys)
+  }
+
+  fn hello(keys: &ModuleKeys) -> AdoptionHello {
+    AdoptionHello {
+      protocol_version: 1,
+      module_type: "com.example.module".to_string(),
+      firmware_version: "1.0.0".to_string(),
+      serial: "0001".to_string(),
+      components: Default::default(),
+      security_level: keys.security_level,
+      public_key: None,
+      proof: None,
+    }
+  }
+
+  #[test]
+  fn provision_derives_same_root_key() {
+    for security_level in [SecurityLevel::L2, SecurityLevel::L3] {
+      let (hub_keys, module_keys) = provision(security_level);
+
+      assert_eq!(hub_keys.key, module_keys.key);
+    }
+  }
+
+  #[test]
+  fn l3_exchanges_keys_both_ways() {
+    let (hub_keys, module_keys) = provision(SecurityLevel::L3);
+
+    let to_module = sign_content(&hub_keys, 1, b"command".to_vec()).unwrap();
+    assert!(verify_content(&module_keys, &to_module).is_ok());
+
+    let to_hub = sign_content(&module_keys, 1, b"telemetry".to_vec()).unwrap();
+    assert!(verify_content(&hub_keys, &to_hub).is_ok());
+
+    // Neither side accepts its own messages, they're keyed to the other side's X25519 key.
+    assert!(verify_content(&hub_keys, &to_module).is_err());
+  }
+
+  #[test]
+  fn l3_uses_new_ephemeral_key_per_message() {
+    let (hub_keys, _) = provision(SecurityLevel::L3);
+
+    let first = sign_content(&hub_keys, 1, b"same".to_vec()).unwrap();
+    let second = sign_content(&hub_keys, 1, b"same".to_vec()).unwrap();
+
+    assert!(first.ephemeral_key.is_some());
+    assert_ne!(first.ephemeral_key, second.ephemeral_key);
+  }
+
+  #[test]
+  fn l3_requires_ephemeral_key() {
+    let (hub_keys, module_keys) = provision(SecurityLevel::L3);
+
+    let mut missing = sign_content(&hub_keys, 1, b"command".to_vec()).unwrap();
+    missing.ephemeral_key = None;
+    assert!(matches!(
+      verify_content(&module_keys, &missing),
+      Err(ContentError::MalformedMessage { .. })
+    ));
+
+    let mut swapped = sign_content(&hub_keys, 1, b"command".to_vec()).unwrap();
+    swapped.ephemeral_key = sign_content(&hub_keys, 1, b"other".to_vec())
+      .unwrap()
+      .ephemeral_key;
+    assert!(matches!(
+      verify_content(&module_keys, &swapped),
+      Err(ContentError::InvalidHmac)
+    ));
+  }
+
+  #[tokio::test]
+  async fn readoption_requires_challenge() {
+    let keys = keys(SecurityLevel::L1, 0);
+    let store = store_with_module("module", &keys).await;
+    let module_id = "module".to_string();
+
+    let challenge = match check_adoption_proof(&store, &module_id, &hello(&keys)).await {
+      Err(AdoptionError::ChallengeRequired { challenge }) => challenge,
+      result => panic!("expected a challenge, got: {result:?}"),
+    };
+
+    let mut answer = hello(&keys);
+    answer.proof = Some(adoption_proof(&keys, &challenge, &answer).unwrap());
+    assert!(check_adoption_proof(&store, &module_id, &answer)
+      .await
+      .is_ok());
+
+    // Challenges can only be answered once.
+    assert!(matches!(
+      check_adoption_proof(&store, &module_id, &answer).await,
+      Err(AdoptionError::ChallengeRequired { .. })
+    ));
+  }
+
+  #[tokio::test]
+  async fn readoption_rejects_wrong_key() {
+    let keys = keys(SecurityLevel::L1, 0);
+    let store = store_with_module("module", &keys).await;
+    let module_id = "module".to_string();
+
+    let challenge = match check_adoption_proof(&store, &module_id, &hello(&keys)).await {
+      Err(AdoptionError::ChallengeRequired { challenge }) => challenge,
+      result => panic!("expected a challenge, got: {result:?}"),
+    };
+
+    let wrong_keys = ModuleKeys {
+      key: vec![8u8; KEY_LEN],
+      ..keys.clone()
+    };
+    let mut answer = hello(&keys);
+    answer.proof = Some(adoption_proof(&wrong_keys, &challenge, &answer).unwrap());
+    assert!(matches!(
+      check_adoption_proof(&store, &module_id, &answer).await,
+      Err(AdoptionError::InvalidProof)
+    ));
+  }
+
+  #[tokio::test]
+  async fn readoption_proof_is_bound_to_hello() {
+    let keys = keys(SecurityLevel::L1, 0);
+    let store = store_with_module("module", &keys).await;
+    let module_id = "module".to_string();
+
+    let challenge = match check_adoption_proof(&store, &module_id, &hello(&keys)).await {
+      Err(AdoptionError::ChallengeRequired { challenge }) => challenge,
+      result => panic!("expected a challenge, got: {result:?}"),
+    };
+
+    let mut answer = hello(&keys);
+    answer.proof = Some(adoption_proof(&keys, &challenge, &answer).unwrap());
+    answer.public_key = Some(vec![1u8; 32]);
+    assert!(matches!(
+      check_adoption_proof(&store, &module_id, &answer).await,
+      Err(AdoptionError::InvalidProof)
+    ));
+  }
+}
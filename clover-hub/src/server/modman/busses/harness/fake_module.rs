@@ -0,0 +1,325 @@
+//! # Fake Modules
+//!
+//! A fake module speaks [`BusMessage`]s over a [`FakeLink`], following a script. It accepts key provisions and rekeys the same way module firmware would, so content can be signed and verified end to end.
+//!
+
+use std::{
+  collections::HashMap,
+  time::Duration,
+};
+
+use anyhow::anyhow;
+use rand::rngs::OsRng;
+use tracing::debug;
+use x25519_dalek::{
+  PublicKey,
+  StaticSecret,
+};
+
+#[cfg(feature = "bt_le")]
+use crate::server::modman::busses::harness::gatt::GattPeripheral;
+use crate::server::modman::{
+  busses::{
+    auth::{
+      accept_provision,
+      adoption_proof,
+      sign_content,
+      verify_content,
+      ModuleKeys,
+    },
+    harness::{
+      pty::PtyLink,
+      vcan::VcanLink,
+    },
+    models::{
+      AdoptionComponent,
+      AdoptionHello,
+      BusMessage,
+      ContentMessage,
+      KeyProvision,
+      ADOPTION_PROTOCOL_VERSION,
+    },
+  },
+  models::modules::SecurityLevel,
+};
+
+/// The module's end of a bus.
+pub enum FakeLink {
+  Pty(PtyLink),
+  Vcan(VcanLink),
+  #[cfg(feature = "bt_le")]
+  Gatt(GattPeripheral),
+}
+
+impl FakeLink {
+  pub async fn send(&mut self, message: &BusMessage) -> Result<(), anyhow::Error> {
+    match self {
+      FakeLink::Pty(link) => link.send(message).await,
+      FakeLink::Vcan(link) => link.send(message).await,
+      #[cfg(feature = "bt_le")]
+      FakeLink::Gatt(link) => link.send(message).await,
+    }
+  }
+
+  pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+    match self {
+      FakeLink::Pty(link) => link.send_raw(bytes).await,
+      FakeLink::Vcan(link) => link.send_raw(bytes).await,
+      #[cfg(feature = "bt_le")]
+      FakeLink::Gatt(link) => link.send_raw(bytes).await,
+    }
+  }
+
+  /// Wait for the next message from the bus, framing errors on the pty are returned as errors.
+  pub async fn recv(&mut self, timeout: Duration) -> Result<Option<BusMessage>, anyhow::Error> {
+    match self {
+      FakeLink::Pty(link) => match link.recv(timeout).await? {
+        Some(Ok(message)) => Ok(Some(message)),
+        Some(Err(framing_error)) => Err(anyhow!("Framing error from the bus: {framing_error}")),
+        None => Ok(None),
+      },
+      FakeLink::Vcan(link) => link.recv(timeout).await,
+      #[cfg(feature = "bt_le")]
+      FakeLink::Gatt(link) => link.recv(timeout).await,
+    }
+  }
+}
+
+/// Which message a [`ScriptStep::Expect`] waits for.
+#[derive(Debug, Clone)]
+pub enum ExpectedMessage {
+  Any,
+  Probe,
+  /// Also accepted by the fake module, so later steps can sign content.
+  Provision,
+  /// Verified with the fake module's keys, optionally matching this data.
+  Content(Option<Vec<u8>>),
+}
+
+#[derive(Debug, Clone)]
+pub enum ScriptStep {
+  /// Send the fake module's [`AdoptionHello`].
+  Hello,
+  /// Send a message as-is.
+  Send(BusMessage),
+  /// Sign data with the fake module's keys, and send it as content.
+  SendContent(Vec<u8>),
+  /// Send content signed with the wrong key.
+  SendForgedContent(Vec<u8>),
+  /// Send the content message that was last sent again, with the same nonce.
+  Replay,
+  /// Send bytes as-is, e.g. a corrupted frame.
+  SendRaw(Vec<u8>),
+  /// Wait for a message from the bus, failing the script if a different one (or none) arrives. Rekeys are handled on the way.
+  Expect {
+    message: ExpectedMessage,
+    timeout: Duration,
+  },
+  /// Fail the script if the bus sends anything (other than rekeys) in this time.
+  ExpectSilence(Duration),
+  Sleep(Duration),
+}
+
+pub struct FakeModule {
+  pub module_type: String,
+  pub serial: String,
+  pub firmware_version: String,
+  pub security_level: SecurityLevel,
+  pub components: HashMap<String, AdoptionComponent>,
+  secret: StaticSecret,
+  keys: Option<ModuleKeys>,
+  /// Counter of the last content message that was sent, like firmware would keep across restarts.
+  counter: u64,
+  /// Challenge from the last probe, answered in the next hello.
+  challenge: Option<Vec<u8>>,
+  last_content: Option<ContentMessage>,
+  /// Every message received from the bus while running scripts.
+  pub received: Vec<BusMessage>,
+}
+
+impl FakeModule {
+  pub fn new(module_type: &str, serial: &str, security_level: SecurityLevel) -> Self {
+    FakeModule {
+      module_type: module_type.to_string(),
+      serial: serial.to_string(),
+      firmware_version: "0.0.0-harness".to_string(),
+      security_level,
+      components: HashMap::new(),
+      secret: StaticSecret::random_from_rng(OsRng),
+      keys: None,
+      counter: 0,
+      challenge: None,
+      last_content: None,
+      received: vec![],
+    }
+  }
+
+  pub fn with_component(mut self, component_id: &str, component: AdoptionComponent) -> Self {
+    self.components.insert(component_id.to_string(), component);
+    self
+  }
+
+  /// The fake module's hello, with a proof if it's been provisioned and probed with a challenge.
+  pub fn hello(&self) -> AdoptionHello {
+    let mut hello = AdoptionHello {
+      protocol_version: ADOPTION_PROTOCOL_VERSION,
+      module_type: self.module_type.clone(),
+      firmware_version: self.firmware_version.clone(),
+      serial: self.serial.clone(),
+      components: self.components.clone(),
+      security_level: self.security_level,
+      public_key: match self.security_level {
+        SecurityLevel::L1 => None,
+        SecurityLevel::L2 | SecurityLevel::L3 => {
+          Some(PublicKey::from(&self.secret).as_bytes().to_vec())
+        }
+      },
+      proof: None,
+    };
+
+    if let (Some(keys), Some(challenge)) = (&self.keys, &self.challenge) {
+      hello.proof = adoption_proof(keys, challenge, &hello).ok();
+    }
+
+    hello
+  }
+
+  /// Derive keys from a provision, like firmware would after adoption.
+  pub fn accept_provision(&mut self, provision: &KeyProvision) -> Result<(), anyhow::Error> {
+    self.keys =
+      Some(accept_provision(provision, Some(&self.secret)).map_err(|err| anyhow!("{err}"))?);
+    Ok(())
+  }
+
+  pub fn keys(&self) -> Option<&ModuleKeys> {
+    self.keys.as_ref()
+  }
+
+  fn signing_keys(&self) -> Result<&ModuleKeys, anyhow::Error> {
+    self
+      .keys
+      .as_ref()
+      .ok_or(anyhow!("Fake module hasn't been provisioned yet."))
+  }
+
+  /// Check a rekey, then switch to the new session.
+  fn rekey(&mut self, rekey: &ContentMessage) -> Result<(), anyhow::Error> {
+    let keys = self.signing_keys()?;
+    verify_content(keys, rekey).map_err(|err| anyhow!("{err}"))?;
+
+    let session: [u8; 4] = rekey
+      .data
+      .as_slice()
+      .try_into()
+      .map_err(|_| anyhow!("Rekey data isn't a session number."))?;
+
+    if let Some(keys) = self.keys.as_mut() {
+      keys.session = u32::from_be_bytes(session);
+      debug!("Fake module switched to session: {}.", keys.session);
+    }
+
+    Ok(())
+  }
+
+  /// Wait for a message that isn't a rekey.
+  async fn next_message(
+    &mut self,
+    link: &mut FakeLink,
+    timeout: Duration,
+  ) -> Result<Option<BusMessage>, anyhow::Error> {
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+      let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+      match link.recv(remaining).await? {
+        Some(BusMessage::Rekey(rekey)) => {
+          self.received.push(BusMessage::Rekey(rekey.clone()));
+          self.rekey(&rekey)?;
+        }
+        Some(message) => {
+          self.received.push(message.clone());
+          return Ok(Some(message));
+        }
+        None => return Ok(None),
+      }
+    }
+  }
+
+  /// Run a script against the bus, stopping at the first step that fails.
+  pub async fn run(
+    &mut self,
+    link: &mut FakeLink,
+    script: &[ScriptStep],
+  ) -> Result<(), anyhow::Error> {
+    for (index, step) in script.iter().enumerate() {
+      debug!("Fake module: {}, step {index}: {step:?}", self.serial);
+
+      let result = match step {
+        ScriptStep::Hello => link.send(&BusMessage::Hello(self.hello())).await,
+        ScriptStep::Send(message) => link.send(message).await,
+        ScriptStep::SendContent(data) => {
+          self.counter += 1;
+          let content = sign_content(self.signing_keys()?, self.counter, data.clone())
+            .map_err(|err| anyhow!("{err}"))?;
+          self.last_content = Some(content.clone());
+          link.send(&BusMessage::Content(content)).await
+        }
+        ScriptStep::SendForgedContent(data) => {
+          let mut forged_keys = self.signing_keys()?.clone();
+          forged_keys.key = forged_keys.key.iter().map(|byte| !byte).collect();
+
+          self.counter += 1;
+          let content = sign_content(&forged_keys, self.counter, data.clone())
+            .map_err(|err| anyhow!("{err}"))?;
+          link.send(&BusMessage::Content(content)).await
+        }
+        ScriptStep::Replay => match self.last_content.clone() {
+          Some(content) => link.send(&BusMessage::Content(content)).await,
+          None => Err(anyhow!("No content has been sent yet, nothing to replay.")),
+        },
+        ScriptStep::SendRaw(bytes) => link.send_raw(bytes).await,
+        ScriptStep::Expect { message, timeout } => match self.next_message(link, *timeout).await? {
+          Some(received) => match (message, received) {
+            (ExpectedMessage::Any, _) => Ok(()),
+            (ExpectedMessage::Probe, BusMessage::Probe(probe)) => {
+              self.challenge = probe.challenge;
+              Ok(())
+            }
+            (ExpectedMessage::Provision, BusMessage::Provision(provision)) => {
+              self.accept_provision(&provision)
+            }
+            (ExpectedMessage::Content(expected_data), BusMessage::Content(content)) => {
+              verify_content(self.signing_keys()?, &content).map_err(|err| anyhow!("{err}"))?;
+
+              match expected_data {
+                Some(expected_data) if expected_data != &content.data => Err(anyhow!(
+                  "Expected content: {expected_data:?}, got: {:?}",
+                  content.data
+                )),
+                _ => Ok(()),
+              }
+            }
+            (expected, received) => Err(anyhow!("Expected: {expected:?}, got: {received:?}")),
+          },
+          None => Err(anyhow!(
+            "Expected: {message:?}, but nothing arrived in time."
+          )),
+        },
+        ScriptStep::ExpectSilence(duration) => match self.next_message(link, *duration).await? {
+          Some(received) => Err(anyhow!("Expected silence, got: {received:?}")),
+          None => Ok(()),
+        },
+        ScriptStep::Sleep(duration) => {
+          tokio::time::sleep(*duration).await;
+          Ok(())
+        }
+      };
+
+      if let Err(err) = result {
+        return Err(anyhow!("Step {index} ({step:?}) failed, due to:\n{err}"));
+      }
+    }
+
+    Ok(())
+  }
+}
@@ -0,0 +1,180 @@
+//! # GATT Peripheral Links
+//!
+//! Serves the [Clover GATT service](crate::server::modman::busses::proxies::individual::bt_le::gatt) from a second Bluetooth adapter, and advertises it so the [BLE bus](crate::server::modman::busses::proxies::individual::bt_le::BluetoothLEBus) finds it, the fake module talks through the service's characteristics.
+//!
+//! BlueZ can't connect an adapter to itself, so this needs two adapters, e.g. a pair of virtual controllers from `btvirt -L -l2`, with the bus configured to scan on the other one.
+//!
+
+use std::{
+  collections::BTreeSet,
+  sync::Arc,
+  time::Duration,
+};
+
+use anyhow::anyhow;
+use bluer::{
+  adv::{
+    Advertisement,
+    AdvertisementHandle,
+  },
+  gatt::local::{
+    Application,
+    ApplicationHandle,
+    Characteristic,
+    CharacteristicNotifier,
+    CharacteristicNotify,
+    CharacteristicNotifyMethod,
+    CharacteristicWrite,
+    CharacteristicWriteMethod,
+    Service,
+  },
+  Address,
+};
+use tokio::sync::{
+  mpsc::{
+    unbounded_channel,
+    UnboundedReceiver,
+  },
+  Mutex,
+};
+use tracing::debug;
+
+use crate::server::modman::busses::{
+  models::BusMessage,
+  proxies::individual::bt_le::gatt::{
+    chunk_message,
+    decode_message,
+    ChunkBuffer,
+    CLOVER_RX_CHARACTERISTIC_UUID,
+    CLOVER_SERVICE_UUID,
+    CLOVER_TX_CHARACTERISTIC_UUID,
+  },
+};
+
+/// Notifications are kept to the default ATT MTU, so they never need to be negotiated.
+pub const PERIPHERAL_CHUNK_LEN: usize = 20;
+
+pub struct GattPeripheral {
+  address: Address,
+  notifier: Arc<Mutex<Option<CharacteristicNotifier>>>,
+  writes: UnboundedReceiver<Vec<u8>>,
+  chunks: ChunkBuffer,
+  _session: bluer::Session,
+  _application: ApplicationHandle,
+  _advertisement: AdvertisementHandle,
+}
+
+impl GattPeripheral {
+  /// Serve and advertise the Clover service on an adapter, e.g. `hci1`.
+  pub async fn serve(adapter_name: &str, local_name: &str) -> Result<Self, anyhow::Error> {
+    let session = bluer::Session::new().await?;
+    let adapter = session.adapter(adapter_name)?;
+    adapter.set_powered(true).await?;
+
+    let notifier: Arc<Mutex<Option<CharacteristicNotifier>>> = Arc::new(Mutex::new(None));
+    let (write_tx, writes) = unbounded_channel();
+
+    let notify_slot = notifier.clone();
+    let application = Application {
+      services: vec![Service {
+        uuid: CLOVER_SERVICE_UUID,
+        primary: true,
+        characteristics: vec![
+          Characteristic {
+            uuid: CLOVER_RX_CHARACTERISTIC_UUID,
+            notify: Some(CharacteristicNotify {
+              notify: true,
+              method: CharacteristicNotifyMethod::Fun(Box::new(move |new_notifier| {
+                let notify_slot = notify_slot.clone();
+                Box::pin(async move {
+                  debug!("Hub subscribed to notifications.");
+                  *notify_slot.lock().await = Some(new_notifier);
+                })
+              })),
+              ..Default::default()
+            }),
+            ..Default::default()
+          },
+          Characteristic {
+            uuid: CLOVER_TX_CHARACTERISTIC_UUID,
+            write: Some(CharacteristicWrite {
+              write: true,
+              method: CharacteristicWriteMethod::Fun(Box::new(move |value, _request| {
+                let write_tx = write_tx.clone();
+                Box::pin(async move {
+                  let _ = write_tx.send(value);
+                  Ok(())
+                })
+              })),
+              ..Default::default()
+            }),
+            ..Default::default()
+          },
+        ],
+        ..Default::default()
+      }],
+      ..Default::default()
+    };
+    let application = adapter.serve_gatt_application(application).await?;
+
+    let advertisement = adapter
+      .advertise(Advertisement {
+        service_uuids: BTreeSet::from([CLOVER_SERVICE_UUID]),
+        discoverable: Some(true),
+        local_name: Some(local_name.to_string()),
+        ..Default::default()
+      })
+      .await?;
+
+    Ok(GattPeripheral {
+      address: adapter.address().await?,
+      notifier,
+      writes,
+      chunks: ChunkBuffer::default(),
+      _session: session,
+      _application: application,
+      _advertisement: advertisement,
+    })
+  }
+
+  /// Address the bus will see the fake module at.
+  pub fn address(&self) -> Address {
+    self.address
+  }
+
+  /// Notify the hub with bytes as-is, split into chunks. Fails if the hub hasn't subscribed yet.
+  pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+    let mut notifier = self.notifier.lock().await;
+    let notifier = match notifier.as_mut() {
+      Some(notifier) if !notifier.is_stopped() => notifier,
+      _ => return Err(anyhow!("The hub isn't subscribed to notifications.")),
+    };
+
+    for chunk in chunk_message(bytes, PERIPHERAL_CHUNK_LEN) {
+      notifier.notify(chunk).await?;
+    }
+
+    Ok(())
+  }
+
+  pub async fn send(&mut self, message: &BusMessage) -> Result<(), anyhow::Error> {
+    self.send_raw(&rmp_serde::to_vec(message)?).await
+  }
+
+  /// Wait for the next message written by the hub.
+  pub async fn recv(&mut self, timeout: Duration) -> Result<Option<BusMessage>, anyhow::Error> {
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+      let chunk = match tokio::time::timeout_at(deadline, self.writes.recv()).await {
+        Ok(Some(chunk)) => chunk,
+        Ok(None) => return Err(anyhow!("The GATT application was stopped.")),
+        Err(_) => return Ok(None),
+      };
+
+      if let Some(message) = self.chunks.push(&chunk) {
+        return Ok(Some(decode_message(&message?)?));
+      }
+    }
+  }
+}
@@ -0,0 +1,338 @@
+//! # Bus Proxy Harness
+//!
+//! Drives the bus proxies against [fake modules](fake_module) without any hardware, so adoption, rx/tx proxying, and error paths can be exercised on a headless Linux box. Enabled with the `bus_harness` feature.
+//!
+//! - UART: the [UART bus](super::proxies::individual::uart::UARTBus) binds the slave side of a [pty pair](pty), and the fake module talks on the master side. To exercise [adoption](super::proxies::individual::uart::adoption) instead, leave the module unregistered, and use [`register_module_spec`] and [`start_adoption`] with `uart.adopt_new_modules` turned on.
+//! - CAN 2: a [CAN 2 bus manager](super::proxies::group::can_2::bus_manager::can_bus_manager) is run on a [`vcan` interface](vcan), and the fake module opens an ISO-TP socket on it with the IDs swapped.
+//! - BLE (with the `bt_le` feature): the fake module serves the Clover GATT service from a [second adapter](gatt), and the [BLE bus](super::proxies::individual::bt_le::BluetoothLEBus) scans for it on the first. Leave it unregistered, with `adopt_new_modules` turned on, to exercise pairing and adoption, it's adopted once it's approved at `.../modules/adopt/approve`.
+//!
+//! A typical run:
+//!
+//! 1. Open a link, and [register](register_module) a module on it with the fake module's keys, this requests the port like initializing the module would.
+//! 2. [Start the bus](start_uart_bus), and wait for the port to be [bound](wait_for_port).
+//! 3. Run a [script](fake_module::ScriptStep) on the fake module, while sending to it with [`send_to_module`] and watching `.../modules/by-id/{module_id}/recv` and `.../error` on the Zenoh side.
+//!
+
+pub mod fake_module;
+#[cfg(feature = "bt_le")]
+pub mod gatt;
+pub mod pty;
+pub mod vcan;
+
+use std::{
+  sync::Arc,
+  time::Duration,
+};
+
+use anyhow::anyhow;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::server::{
+  modman::{
+    busses::{
+      auth::{
+        provision_keys,
+        reset_module_keys,
+        store_module_keys,
+      },
+      harness::fake_module::FakeModule,
+      models::{
+        Bus,
+        BusMessage,
+        KeyProvision,
+      },
+      proxies::{
+        group::can_2::{
+          bus_manager::can_bus_manager,
+          CAN2Bus,
+        },
+        individual::uart::UARTBus,
+      },
+    },
+    connections::{
+      CAN2Connection,
+      ModuleConnection,
+      UARTConnection,
+    },
+    ipc::modules::{
+      module_adoption_approval_queryable,
+      module_adoption_queryable,
+    },
+    models::{
+      modules::Module,
+      store::ModManStore,
+      PortStatus,
+    },
+    modules::connections::{
+      can_2::setup_can_2_connection,
+      uart::setup_uart_connection,
+    },
+    MODULE_EVT_ID,
+  },
+  warehouse::repos::{
+    builtin_rfqdn,
+    models::Manifest,
+  },
+};
+
+/// Register a module for the fake module without going through adoption (which needs a Warehouse spec), then request its port.
+///
+/// The fake module is given its keys directly, use [`send_to_module`] with the returned provision to provision it over the bus instead.
+pub async fn register_module(
+  store: &Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  module_id: &str,
+  connection: ModuleConnection,
+  fake_module: &mut FakeModule,
+) -> Result<KeyProvision, anyhow::Error> {
+  let module_id = module_id.to_string();
+  let hello = fake_module.hello();
+  let (keys, provision) = provision_keys(hello.security_level, hello.public_key.as_ref())
+    .map_err(|err| anyhow!("{err}"))?;
+
+  let mut module = Module {
+    module_type: hello.module_type.clone(),
+    module_name: hello.module_type.clone(),
+    custom_name: None,
+    initialized: false,
+    components: vec![],
+    registered_by: format!("{}.hub", builtin_rfqdn(false)),
+    connection: connection.clone(),
+    firmware_version: Some(hello.firmware_version.clone()),
+    serial: Some(hello.serial.clone()),
+    security_level: hello.security_level,
+    key: None,
+    exchange_secret: None,
+    public_key: None,
+    insecure_movement: false,
+    session: 0,
+    last_counter: 0,
+  };
+  store_module_keys(&mut module, &keys);
+
+  store
+    .modules
+    .lock()
+    .await
+    .insert(module_id.clone(), module.clone());
+  reset_module_keys(store, &module_id).await;
+  fake_module.accept_provision(&provision)?;
+
+  match connection {
+    ModuleConnection::UART(uart_connection) => {
+      let mut config = store.config.lock().await;
+      if !config.modman.uart_ports.contains(&uart_connection.port) {
+        config.modman.uart_ports.push(uart_connection.port.clone());
+      }
+      drop(config);
+
+      setup_uart_connection(store, &module_id, uart_connection).await?;
+    }
+    ModuleConnection::CAN2(can_2_connection) => {
+      let mut config = store.config.lock().await;
+      let permitted_interfaces = &mut config.modman.group_busses.can_2.permitted_interfaces;
+      if !permitted_interfaces.contains(&can_2_connection.bus_id) {
+        permitted_interfaces.push(can_2_connection.bus_id.clone());
+      }
+      drop(config);
+
+      setup_can_2_connection(store, &module, &module_id, can_2_connection, session).await?;
+    }
+    #[cfg(feature = "bt_le")]
+    ModuleConnection::BTLE(address) => {
+      use crate::server::modman::modules::connections::bt_le::setup_bt_le_connection;
+
+      setup_bt_le_connection(store, &module_id, &address).await?;
+    }
+    _ => return Err(anyhow!("The harness doesn't support this connection type.")),
+  }
+
+  debug!("Registered harness module: {module_id}.");
+  Ok(provision)
+}
+
+/// Add a manifest entry for the fake module's type, with its components, so it can be adopted.
+pub async fn register_module_spec(
+  store: &ModManStore,
+  fake_module: &FakeModule,
+) -> Result<(), anyhow::Error> {
+  let components: serde_json::Map<String, serde_json::Value> = fake_module
+    .components
+    .iter()
+    .map(|(component_id, component)| {
+      (
+        component_id.clone(),
+        serde_json::json!({ "type": component.component_type }),
+      )
+    })
+    .collect();
+
+  let manifest: Manifest = serde_json::from_value(serde_json::json!({
+    "version": "0.0.0-harness",
+    "directory": {
+      "modules": {
+        fake_module.module_type.clone(): {
+          "name": "Harness Module",
+          "components": components,
+        },
+      },
+    },
+  }))?;
+
+  store
+    .repos
+    .lock()
+    .await
+    .insert(format!("{}.harness", builtin_rfqdn(false)), manifest);
+  Ok(())
+}
+
+/// Serve the adoption queryables, like ModMan's IPC would.
+pub fn start_adoption(
+  store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+) {
+  let adoption_store = (*store).clone();
+  let adoption_session = session.clone();
+  let adoption_token = cancellation_token.clone();
+  tokio::task::spawn(async move {
+    module_adoption_queryable(adoption_store, adoption_token, adoption_session).await;
+  });
+
+  let approval_store = (*store).clone();
+  tokio::task::spawn(async move {
+    module_adoption_approval_queryable(approval_store, cancellation_token, session).await;
+  });
+}
+
+pub fn uart_connection(path: &str) -> ModuleConnection {
+  ModuleConnection::UART(UARTConnection {
+    port: path.to_string(),
+    baud: 115_200,
+  })
+}
+
+pub fn can_2_connection(interface: &str, hub_rx_id: u16, hub_tx_id: u16) -> ModuleConnection {
+  ModuleConnection::CAN2(CAN2Connection {
+    bus_id: interface.to_string(),
+    device_id: format!("0x{hub_tx_id:03X}"),
+    reply_id: format!("0x{hub_rx_id:03X}"),
+  })
+}
+
+#[cfg(feature = "bt_le")]
+pub fn bt_le_connection(address: bluer::Address) -> ModuleConnection {
+  ModuleConnection::BTLE(address.to_string())
+}
+
+pub async fn start_uart_bus(
+  store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+) -> Result<tokio::task::JoinHandle<()>, anyhow::Error> {
+  UARTBus {
+    store,
+    cancellation_token,
+  }
+  .subscribe_to_bus(session)
+  .await
+}
+
+/// Start the BLE bus, scanning on an adapter, e.g. `hci0`.
+#[cfg(feature = "bt_le")]
+pub async fn start_bt_le_bus(
+  store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+  adapter_name: &str,
+) -> Result<tokio::task::JoinHandle<()>, anyhow::Error> {
+  use crate::server::modman::busses::proxies::individual::bt_le::BluetoothLEBus;
+
+  store.config.lock().await.modman.bt_le.adapter = Some(adapter_name.to_string());
+
+  BluetoothLEBus {
+    store,
+    cancellation_token,
+  }
+  .subscribe_to_bus(session)
+  .await
+}
+
+/// Run a bus manager on one interface, skipping the lookout.
+pub fn start_can_bus_manager(
+  store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+  interface: &str,
+) -> tokio::task::JoinHandle<()> {
+  let ctx = Arc::new(CAN2Bus {
+    session,
+    store,
+    cancellation_token: cancellation_token.clone(),
+  });
+  let iface_name = interface.to_string();
+
+  tokio::task::spawn(async move {
+    can_bus_manager(ctx, cancellation_token, iface_name).await;
+  })
+}
+
+/// Wait until a port has the expected status, returns the last status seen if it didn't in time.
+pub async fn wait_for_port(
+  store: &ModManStore,
+  port: &str,
+  expected: PortStatus,
+  timeout: Duration,
+) -> Result<(), Option<PortStatus>> {
+  let deadline = tokio::time::Instant::now() + timeout;
+
+  loop {
+    let status = match store.port_statuses.uart.lock().await.get(port) {
+      Some(status) => Some(status.clone()),
+      None => store.port_statuses.can_2.lock().await.get(port).cloned(),
+    };
+    #[cfg(feature = "bt_le")]
+    let status = match status {
+      Some(status) => Some(status),
+      None => store.port_statuses.bt_le.lock().await.get(port).cloned(),
+    };
+
+    if status.as_ref() == Some(&expected) {
+      return Ok(());
+    }
+    if tokio::time::Instant::now() >= deadline {
+      return Err(status);
+    }
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+}
+
+/// Send a message to a module through its bus proxy, like an app would. Returns the proxy's reply.
+pub async fn send_to_module(
+  session: &zenoh::Session,
+  module_id: &String,
+  message: &BusMessage,
+) -> Result<String, anyhow::Error> {
+  let key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/send");
+
+  let replies = session
+    .get(&key_expr)
+    .payload(serde_json::to_string(message)?)
+    .timeout(Duration::from_secs(5))
+    .await
+    .map_err(|err| anyhow!("Failed to query: {key_expr}, due to:\n{err}"))?;
+
+  match replies.recv_async().await {
+    Ok(reply) => match reply.result() {
+      Ok(sample) => Ok(sample.payload().try_to_string()?.to_string()),
+      Err(reply_err) => Err(anyhow!(
+        "Error reply from: {key_expr}: {}",
+        reply_err.payload().try_to_string()?
+      )),
+    },
+    Err(err) => Err(anyhow!("No reply from: {key_expr}, due to:\n{err}")),
+  }
+}
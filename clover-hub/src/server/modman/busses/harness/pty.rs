@@ -0,0 +1,144 @@
+//! # Pseudo-terminal Links
+//!
+//! A pty pair stands in for a USB serial adapter, the [UART bus](crate::server::modman::busses::proxies::individual::uart::UARTBus) opens the slave side by its path (e.g. `/dev/pts/4`), and the fake module talks on the master side.
+//!
+//! Dropping the link closes the master, which the UART bus sees the same way as an adapter being unplugged.
+//!
+
+use std::{
+  fs::{
+    File,
+    OpenOptions,
+  },
+  io::{
+    Read,
+    Write,
+  },
+  os::unix::fs::OpenOptionsExt,
+  time::Duration,
+};
+
+use nix::{
+  fcntl::OFlag,
+  pty::{
+    grantpt,
+    posix_openpt,
+    ptsname_r,
+    unlockpt,
+    PtyMaster,
+  },
+  sys::termios::{
+    cfmakeraw,
+    tcgetattr,
+    tcsetattr,
+    SetArg,
+  },
+};
+use tokio::io::unix::AsyncFd;
+use tracing::debug;
+
+use crate::server::modman::busses::{
+  models::BusMessage,
+  proxies::individual::uart::framing::{
+    encode_frame,
+    FrameBuffer,
+    FramingError,
+  },
+};
+
+pub struct PtyLink {
+  master: AsyncFd<PtyMaster>,
+  /// Held open so the line discipline stays raw, and reads don't fail while the bus has the port closed.
+  _slave: File,
+  path: String,
+  frames: FrameBuffer,
+  pending: Vec<Result<Vec<u8>, FramingError>>,
+}
+
+impl PtyLink {
+  /// Open a new pty pair, with the slave in raw mode.
+  pub fn open() -> Result<Self, anyhow::Error> {
+    let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_NONBLOCK)?;
+    grantpt(&master)?;
+    unlockpt(&master)?;
+    let path = ptsname_r(&master)?;
+
+    let slave = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .custom_flags(OFlag::O_NOCTTY.bits())
+      .open(&path)?;
+
+    let mut termios = tcgetattr(&slave)?;
+    cfmakeraw(&mut termios);
+    tcsetattr(&slave, SetArg::TCSANOW, &termios)?;
+
+    debug!("Opened pty pair, slave: {path}.");
+
+    Ok(PtyLink {
+      master: AsyncFd::new(master)?,
+      _slave: slave,
+      path,
+      frames: FrameBuffer::default(),
+      pending: vec![],
+    })
+  }
+
+  /// Path of the slave side, to be used as the module's UART port.
+  pub fn path(&self) -> &String {
+    &self.path
+  }
+
+  /// Write bytes as-is, without framing.
+  pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+    let mut written = 0;
+
+    while written < bytes.len() {
+      let mut guard = self.master.writable_mut().await?;
+
+      match guard.try_io(|master| master.get_mut().write(&bytes[written..])) {
+        Ok(Ok(len)) => written += len,
+        Ok(Err(err)) => return Err(err.into()),
+        Err(_would_block) => continue,
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Frame and write a message, the same way a module would.
+  pub async fn send(&mut self, message: &BusMessage) -> Result<(), anyhow::Error> {
+    let payload = rmp_serde::to_vec(message)?;
+    self.send_raw(&encode_frame(&payload)).await
+  }
+
+  /// Wait for the next frame from the bus, `None` if nothing arrived in time.
+  pub async fn recv(
+    &mut self,
+    timeout: Duration,
+  ) -> Result<Option<Result<BusMessage, FramingError>>, anyhow::Error> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut read_buf = [0u8; 256];
+
+    while self.pending.is_empty() {
+      let mut guard = match tokio::time::timeout_at(deadline, self.master.readable_mut()).await {
+        Ok(guard) => guard?,
+        Err(_elapsed) => return Ok(None),
+      };
+
+      match guard.try_io(|master| master.get_mut().read(&mut read_buf)) {
+        Ok(Ok(0)) => return Err(anyhow::anyhow!("pty: {} was closed.", self.path)),
+        Ok(Ok(len)) => self.pending = self.frames.push(&read_buf[..len]),
+        Ok(Err(err)) => return Err(err.into()),
+        Err(_would_block) => continue,
+      }
+    }
+
+    let frame = self.pending.remove(0);
+    Ok(Some(frame.and_then(|payload| {
+      rmp_serde::from_slice::<BusMessage>(&payload).map_err(|err| FramingError::InvalidMessage {
+        reason: err.to_string(),
+      })
+    })))
+  }
+}
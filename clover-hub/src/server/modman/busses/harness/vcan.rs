@@ -0,0 +1,156 @@
+//! # Virtual CAN Links
+//!
+//! A `vcan` interface stands in for a CAN adapter, the [CAN 2 bus manager](crate::server::modman::busses::proxies::group::can_2::bus_manager::can_bus_manager) binds it like any other interface, and the fake module opens an ISO-TP socket on it with the IDs swapped.
+//!
+//! Creating interfaces needs `CAP_NET_ADMIN` (and the `vcan` kernel module), interfaces that already exist are used as-is.
+//!
+
+use std::{
+  process::Command,
+  time::Duration,
+};
+
+use anyhow::anyhow;
+use can_isotp_interface::{
+  IsoTpAsyncEndpoint,
+  RecvControl,
+  RecvStatus,
+};
+use embedded_can::{
+  Id,
+  StandardId,
+};
+use linux_socketcan_iso_tp::{
+  IsoTpKernelOptions,
+  TokioSocketCanIsoTp,
+};
+use nix::net::if_::if_nametoindex;
+use tracing::{
+  debug,
+  error,
+};
+
+use crate::server::modman::busses::models::BusMessage;
+
+/// A `vcan` interface, deleted on drop if it was created by the harness.
+pub struct VcanInterface {
+  name: String,
+  created: bool,
+}
+
+fn ip_link(args: &[&str]) -> Result<(), anyhow::Error> {
+  let output = Command::new("ip").arg("link").args(args).output()?;
+
+  if output.status.success() {
+    Ok(())
+  } else {
+    Err(anyhow!(
+      "`ip link {}` failed: {}",
+      args.join(" "),
+      String::from_utf8_lossy(&output.stderr).trim()
+    ))
+  }
+}
+
+impl VcanInterface {
+  /// Create (and bring up) a `vcan` interface, or use the existing interface with that name.
+  pub fn create(name: &str) -> Result<Self, anyhow::Error> {
+    if if_nametoindex(name).is_ok() {
+      debug!("Using existing interface: {name}.");
+      return Ok(VcanInterface {
+        name: name.to_string(),
+        created: false,
+      });
+    }
+
+    ip_link(&["add", "dev", name, "type", "vcan"])?;
+    let interface = VcanInterface {
+      name: name.to_string(),
+      created: true,
+    };
+    ip_link(&["set", "dev", name, "up"])?;
+
+    debug!("Created interface: {name}.");
+    Ok(interface)
+  }
+
+  pub fn name(&self) -> &String {
+    &self.name
+  }
+
+  /// Port identifier for a module on this interface, see [`PortStatuses::can_2`](crate::server::modman::models::store::PortStatuses::can_2).
+  pub fn port(&self, hub_rx_id: u16, hub_tx_id: u16) -> String {
+    format!("{}/0x{hub_rx_id:03X}:0x{hub_tx_id:03X}", self.name)
+  }
+}
+
+impl Drop for VcanInterface {
+  fn drop(&mut self) {
+    if self.created {
+      if let Err(err) = ip_link(&["delete", "dev", &self.name]) {
+        error!("Failed to delete interface: {}, due to:\n{err}", self.name);
+      }
+    }
+  }
+}
+
+/// The module's end of a CAN 2 port.
+pub struct VcanLink {
+  socket: TokioSocketCanIsoTp,
+}
+
+impl VcanLink {
+  /// Open the module's side of the port that ModMan binds with `hub_rx_id` and `hub_tx_id`.
+  pub fn open(
+    interface: &VcanInterface,
+    hub_rx_id: u16,
+    hub_tx_id: u16,
+  ) -> Result<Self, anyhow::Error> {
+    let rx_id = StandardId::new(hub_tx_id).ok_or(anyhow!("Invalid CAN ID: {hub_tx_id:#X}"))?;
+    let tx_id = StandardId::new(hub_rx_id).ok_or(anyhow!("Invalid CAN ID: {hub_rx_id:#X}"))?;
+
+    let socket = TokioSocketCanIsoTp::open(
+      interface.name(),
+      Id::Standard(rx_id),
+      Id::Standard(tx_id),
+      &IsoTpKernelOptions::default(),
+    )?;
+
+    Ok(VcanLink { socket })
+  }
+
+  /// Send bytes as-is, without encoding them.
+  pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+    match self
+      .socket
+      .send_to(0, bytes, Duration::from_millis(100))
+      .await
+    {
+      Ok(_) => Ok(()),
+      Err(err) => Err(anyhow!("Failed to send on vcan link, due to:\n{err:#?}")),
+    }
+  }
+
+  pub async fn send(&mut self, message: &BusMessage) -> Result<(), anyhow::Error> {
+    let payload = rmp_serde::to_vec(message)?;
+    self.send_raw(&payload).await
+  }
+
+  /// Wait for the next message from the bus, `None` if nothing arrived in time.
+  pub async fn recv(&mut self, timeout: Duration) -> Result<Option<BusMessage>, anyhow::Error> {
+    let mut payload: Vec<u8> = Vec::new();
+
+    match self
+      .socket
+      .recv_one(timeout, |_meta, p| {
+        payload.extend_from_slice(p);
+        Ok(RecvControl::Continue)
+      })
+      .await
+    {
+      Ok(RecvStatus::DeliveredOne) => Ok(Some(rmp_serde::from_slice::<BusMessage>(&payload)?)),
+      Ok(RecvStatus::TimedOut) => Ok(None),
+      Err(err) => Err(anyhow!("Failed to receive on vcan link, due to:\n{err:#?}")),
+    }
+  }
+}
@@ -3,8 +3,14 @@
 //! A.k.a. `busses`, Proxies allow Modules to access Zenoh securely without needing a network bridge. [Each bus](proxies) is compiled into ModMan, and enabled via features.
 //!
 
+#[cfg(any(feature = "uart", feature = "bt_le"))]
+pub mod adoption;
+pub mod auth;
+#[cfg(feature = "bus_harness")]
+pub mod harness;
 pub mod models;
 pub mod proxies;
+pub mod tx;
 
 use super::models::store::ModManStore;
 use log::info;
@@ -20,10 +26,54 @@ pub async fn start_busses(
   let mut handles = vec![];
 
   // TODO: Add config options for each bus!
-  handles.push(tokio::task::spawn(async move {
+  {
+    use log::error;
+    use models::Bus;
+    use proxies::individual::app::AppBus;
+
+    let app_session = session.clone();
     info!("Starting App Bus...");
-  }));
+    match (AppBus {
+      store: store.clone(),
+      cancellation_token: cancellation_token.clone(),
+    })
+    .subscribe_to_bus(app_session)
+    .await
+    {
+      Ok(handle) => {
+        handles.push(handle);
+      }
+      Err(err) => {
+        error!("Failed to start App Bus, due to:\n{err}");
+      }
+    }
+  }
+
+  {
+    use log::error;
+    use models::Bus;
+    use proxies::individual::simulated::SimulatedBus;
 
+    let simulated_session = session.clone();
+    info!("Starting Simulated Bus...");
+    match (SimulatedBus {
+      store: store.clone(),
+      cancellation_token: cancellation_token.clone(),
+    })
+    .subscribe_to_bus(simulated_session)
+    .await
+    {
+      Ok(handle) => {
+        handles.push(handle);
+      }
+      Err(err) => {
+        error!("Failed to start Simulated Bus, due to:\n{err}");
+      }
+    }
+  }
+
+  #[cfg(feature = "can_2")]
+  let can_2_ctx = (session.clone(), store.clone(), cancellation_token.clone());
   #[cfg(feature = "can_2")]
   handles.push(tokio::task::spawn(async move {
     use crate::server::modman::busses::proxies::group::can_2::{
@@ -31,44 +81,125 @@ pub async fn start_busses(
       CAN2Bus,
     };
 
+    let (session, store, cancellation_token) = can_2_ctx;
     let ctx = Arc::new(CAN2Bus {
-      session: session.clone(),
-      store: store.clone(),
-      cancellation_token: cancellation_token.clone(),
+      session,
+      store,
+      cancellation_token,
     });
 
     info!("Starting CAN 2 A/B Bus...");
     can_lookout_thread(ctx).await;
   }));
 
+  #[cfg(feature = "can_fd")]
+  let can_fd_ctx = (session.clone(), store.clone(), cancellation_token.clone());
+  #[cfg(feature = "can_fd")]
+  handles.push(tokio::task::spawn(async move {
+    use crate::server::modman::busses::proxies::group::can_fd::{
+      interface_lookout::can_fd_lookout_thread,
+      CANFDBus,
+    };
+
+    let (session, store, cancellation_token) = can_fd_ctx;
+    let ctx = Arc::new(CANFDBus {
+      session,
+      store,
+      cancellation_token,
+    });
+
+    info!("Starting CAN FD Bus...");
+    can_fd_lookout_thread(ctx).await;
+  }));
+
   // #[cfg(feature = "bt_classic")]
   // handles.push(tokio::task::spawn(async move {
   //   info!("Starting Bluetooth Classic Bus...");
   // }));
 
   #[cfg(feature = "bt_le")]
-  handles.push(tokio::task::spawn(async move {
+  {
+    use log::error;
+    use models::Bus;
+    use proxies::individual::bt_le::BluetoothLEBus;
+
+    let bt_le_session = session.clone();
     info!("Starting Bluetooth LE Bus...");
-  }));
+    match (BluetoothLEBus {
+      store: store.clone(),
+      cancellation_token: cancellation_token.clone(),
+    })
+    .subscribe_to_bus(bt_le_session)
+    .await
+    {
+      Ok(handle) => {
+        handles.push(handle);
+      }
+      Err(err) => {
+        error!("Failed to start Bluetooth LE Bus, due to:\n{err}");
+      }
+    }
+  }
 
-  // #[cfg(feature = "spi")]
-  // handles.push(tokio::task::spawn(async move {
-  //   info!("Starting SPI Bus...");
-  // }));
+  #[cfg(feature = "spi")]
+  {
+    use log::error;
+    use models::Bus;
+    use proxies::group::spi::SPIBus;
 
-  // #[cfg(feature = "i2c")]
-  // handles.push(tokio::task::spawn(async move {
-  //   info!("Starting I2C Bus...");
-  // }));
+    let spi_session = session.clone();
+    info!("Starting SPI Bus...");
+    match (SPIBus {
+      store: store.clone(),
+      cancellation_token: cancellation_token.clone(),
+    })
+    .subscribe_to_bus(spi_session)
+    .await
+    {
+      Ok(handle) => {
+        handles.push(handle);
+      }
+      Err(err) => {
+        error!("Failed to start SPI Bus, due to:\n{err}");
+      }
+    }
+  }
+
+  #[cfg(feature = "i2c")]
+  {
+    use log::error;
+    use models::Bus;
+    use proxies::group::i2c::I2CBus;
+
+    let i2c_session = session.clone();
+    info!("Starting I2C Bus...");
+    match (I2CBus {
+      store: store.clone(),
+      cancellation_token: cancellation_token.clone(),
+    })
+    .subscribe_to_bus(i2c_session)
+    .await
+    {
+      Ok(handle) => {
+        handles.push(handle);
+      }
+      Err(err) => {
+        error!("Failed to start I2C Bus, due to:\n{err}");
+      }
+    }
+  }
 
   #[cfg(feature = "uart")]
   {
+    use log::error;
+    use models::Bus;
     use proxies::individual::uart::UARTBus;
 
     let uart_session = session.clone();
     info!("Starting UART Bus...");
     match (UARTBus {
       store: store.clone(),
+      cancellation_token: cancellation_token.clone(),
     })
     .subscribe_to_bus(uart_session)
     .await
@@ -76,7 +207,9 @@ pub async fn start_busses(
       Ok(handle) => {
         handles.push(handle);
       }
-      Err(_) => todo!(),
+      Err(err) => {
+        error!("Failed to start UART Bus, due to:\n{err}");
+      }
     }
   }
 
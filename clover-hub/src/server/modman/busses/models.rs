@@ -1,13 +1,20 @@
-use std::sync::Arc;
+use std::{
+  collections::HashMap,
+  sync::Arc,
+};
 
 use serde::{
   Deserialize,
   Serialize,
 };
+use tracing::error;
+
+use crate::server::modman::models::modules::SecurityLevel;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BusTypes {
   App,
+  Simulated,
   #[cfg(feature = "can_fd")]
   CANFD,
   #[cfg(feature = "can_2")]
@@ -43,20 +50,159 @@ pub enum BusMessage {
   Hello(AdoptionHello),
   #[serde(rename = "c")]
   Content(ContentMessage),
+  #[serde(rename = "k")]
+  Provision(KeyProvision),
+  /// Switch to the next L2 session key, `data` is the new session number (big-endian `u32`), signed with the current session key.
+  #[serde(rename = "r")]
+  Rekey(ContentMessage),
+}
+
+/// Version of the adoption handshake that ModMan speaks.
+pub const ADOPTION_PROTOCOL_VERSION: u8 = 1;
+
+/// Sent by a bus proxy to an un-adopted module (e.g. on a newly connected port) to ask it to identify itself with an [`AdoptionHello`].
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AdoptionProbe {
+  #[serde(rename = "v")]
+  pub protocol_version: u8,
+  /// Sent to a module that's already adopted, it must answer with a hello that carries a [`proof`](AdoptionHello::proof) for this challenge before it's given new keys.
+  #[serde(rename = "c", default, with = "serde_bytes")]
+  pub challenge: Option<Vec<u8>>,
 }
 
+impl Default for AdoptionProbe {
+  fn default() -> Self {
+    AdoptionProbe {
+      protocol_version: ADOPTION_PROTOCOL_VERSION,
+      challenge: None,
+    }
+  }
+}
+
+/// Sent by a module in reply to an [`AdoptionProbe`] (or when it powers on) to announce what it is, see [adoption](crate::server::modman::modules::adoption).
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct AdoptionProbe {} // TODO: this.
+pub struct AdoptionHello {
+  #[serde(rename = "v")]
+  pub protocol_version: u8,
+  /// RFQDN of the module's entry in a Warehouse manifest.
+  #[serde(rename = "m")]
+  pub module_type: String,
+  #[serde(rename = "f")]
+  pub firmware_version: String,
+  /// Unique to this unit (e.g. a serial number), so the module keeps its ID when it's adopted again.
+  #[serde(rename = "s")]
+  pub serial: String,
+  /// Every component on the module, keyed by its ID in the module's manifest entry.
+  #[serde(rename = "c")]
+  pub components: HashMap<String, AdoptionComponent>,
+  #[serde(rename = "l", default)]
+  pub security_level: SecurityLevel,
+  /// The module's X25519 public key, required for L2 and L3.
+  #[serde(rename = "k", default, with = "serde_bytes")]
+  pub public_key: Option<Vec<u8>>,
+  /// HMAC of the probe's challenge and this hello with the module's current key, see [`adoption_proof`](crate::server::modman::busses::auth::adoption_proof).
+  #[serde(rename = "a", default, with = "serde_bytes")]
+  pub proof: Option<Vec<u8>>,
+}
 
+/// Sent by a bus proxy to a module once it's adopted, so it can sign and verify content messages.
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct AdoptionHello {} // TODO: this.
+pub struct KeyProvision {
+  #[serde(rename = "l")]
+  pub security_level: SecurityLevel,
+  /// The static key, only for L1.
+  #[serde(rename = "k", default, with = "serde_bytes")]
+  pub key: Option<Vec<u8>>,
+  /// ModMan's X25519 public key to complete the exchange with, only for L2 and L3.
+  #[serde(rename = "p", default, with = "serde_bytes")]
+  pub public_key: Option<Vec<u8>>,
+}
 
+/// A component announced in an [`AdoptionHello`].
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AdoptionComponent {
+  /// Component type, e.g. `movement`, `indicator`, or `sensor`.
+  #[serde(rename = "t")]
+  pub component_type: String,
+  /// Data type the component receives, e.g. `vec2d`.
+  #[serde(rename = "i", default)]
+  pub input: Option<String>,
+  /// Data type the component reports.
+  #[serde(rename = "o", default)]
+  pub output: Option<String>,
+}
+
+/// Data sent to or from a module, authenticated with the module's key, see [`auth`](super::auth).
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ContentMessage {
+  /// Increases with every message from the sender, so old messages can't be replayed.
+  #[serde(rename = "c")]
+  pub counter: u64,
   #[serde(rename = "n", with = "serde_bytes")]
   pub nonce: Vec<u8>,
   #[serde(rename = "d", with = "serde_bytes")]
   pub data: Vec<u8>,
+  /// HMAC-SHA256 of `counter || nonce || data`, with the counter as a big-endian `u64`.
   #[serde(rename = "h", with = "serde_bytes")]
   pub hmac: Vec<u8>,
+  /// The sender's ephemeral X25519 public key for this message, only for L3.
+  #[serde(
+    rename = "e",
+    default,
+    skip_serializing_if = "Option::is_none",
+    with = "serde_bytes"
+  )]
+  pub ephemeral_key: Option<Vec<u8>>,
+}
+
+/// Component values reported by a module, the (MessagePack encoded) `data` of a [`ContentMessage`] sent on `.../modules/by-id/{module_id}/recv`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ComponentTelemetry {
+  #[serde(rename = "c")]
+  pub component_id: String,
+  /// Current (raw, not intensity) values keyed by gesture parameter, or axis for movement components.
+  #[serde(rename = "v")]
+  pub values: HashMap<String, f64>,
+}
+
+/// Why a bus proxy couldn't send a message from `.../modules/by-id/{module_id}/send`, replied to the querier as `error:{error}`.
+#[derive(Serialize, Deserialize, Debug, Clone, strum_macros::Display)]
+pub enum BusTXError {
+  #[strum(to_string = "missing-payload")]
+  MissingPayload,
+  #[strum(to_string = "payload-is-not-string")]
+  PayloadIsNotString,
+  /// The payload isn't a JSON encoded [`BusMessage`].
+  #[strum(to_string = "malformed-payload")]
+  MalformedPayload,
+  #[strum(to_string = "encoding-failed")]
+  EncodingFailed,
+  #[strum(to_string = "signing-failed")]
+  SigningFailed,
+  #[strum(to_string = "tx-failed")]
+  TXFailed,
+  /// Simulated modules only accept [`BusMessage::Content`].
+  #[strum(to_string = "unsupported-message")]
+  UnsupportedMessage,
+  /// The content isn't MessagePack encoded [`ComponentTelemetry`].
+  #[strum(to_string = "malformed-command")]
+  MalformedCommand,
+  /// The app didn't reply, it's probably not running.
+  #[strum(to_string = "app-unreachable")]
+  AppUnreachable,
+  /// The app replied with an error, it's logged by ModMan.
+  #[strum(to_string = "app-error")]
+  AppError,
+}
+
+impl BusTXError {
+  /// Reply to the query that asked for the message to be sent.
+  pub async fn reply(self, query: &zenoh::query::Query, key_expr: &str) {
+    match query.reply(key_expr, format!("error:{self}")).await {
+      Ok(_) => {}
+      Err(err) => {
+        error!("Failed to reply to query with error message due to:\n{err}");
+      }
+    }
+  }
 }
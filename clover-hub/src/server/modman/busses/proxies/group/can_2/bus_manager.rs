@@ -5,12 +5,13 @@ use std::{
 };
 
 use anyhow::anyhow;
-use embedded_can::Id;
+use embedded_can::{
+  ExtendedId,
+  Id,
+  StandardId,
+};
 use linux_socketcan_iso_tp::{
   self,
-  flags,
-  IsoTpKernelOptions,
-  IsoTpSocketOptions,
   TokioSocketCanIsoTp,
 };
 use regex::Regex;
@@ -30,24 +31,51 @@ use crate::server::modman::{
       can_module_rx,
       can_module_tx,
     },
+    set_bitrate,
     CAN2Bus,
+    CAN2IsoTpConfig,
   },
   models::PortStatus,
 };
 
 #[instrument]
-pub fn parse_id_str(id_str: &str) -> Result<u16, anyhow::Error> {
+pub fn parse_id_str(id_str: &str) -> Result<u32, anyhow::Error> {
   let digits = id_str
     .strip_prefix("0x")
     .or_else(|| id_str.strip_prefix("0X"))
     .unwrap_or(id_str);
 
-  match u16::from_str_radix(digits, 16) {
+  match u32::from_str_radix(digits, 16) {
     Ok(val) => Ok(val),
     Err(err) => Err(err.into()),
   }
 }
 
+/// Turn a parsed ID into a CAN ID, IDs that don't fit in 11 bits are always extended.
+pub fn can_id(raw_id: u32, extended: bool) -> Result<Id, anyhow::Error> {
+  if extended || raw_id > StandardId::MAX.as_raw() as u32 {
+    match ExtendedId::new(raw_id) {
+      Some(id) => Ok(Id::Extended(id)),
+      None => Err(anyhow!(
+        "We expect that an ID of {raw_id:#X}, is a valid extended ID. Check your config or there's a bug in manifest validation!"
+      )),
+    }
+  } else {
+    // Known good value, checked against the max above.
+    Ok(Id::Standard(StandardId::new(raw_id as u16).unwrap()))
+  }
+}
+
+/// Matches against strings like: `"can0/0xFFF:0xFFF"`, or `"can0/0x1FFFFFFF:0x1FFFFFFF"` for extended IDs.
+/// This regex does not validate if the specified IDs are within the CAN range though.
+/// That's done later by `embedded_can`.
+pub fn port_specifier_re() -> Regex {
+  Regex::new(
+    r"^(?<iface>\w+)\/(?<rx_id>0[xX](?:[0-9a-fA-F]{8}|[0-9a-fA-F]{3})):(?<tx_id>0[xX](?:[0-9a-fA-F]{8}|[0-9a-fA-F]{3}))$",
+  )
+  .unwrap()
+}
+
 pub fn match_to_str(match_struct: regex::Match<'_>) -> &str {
   <regex::Match<'_> as Into<&str>>::into(match_struct)
 }
@@ -62,18 +90,40 @@ pub async fn can_bus_manager(
   let listener_registry: Arc<Mutex<HashMap<String, CancellationToken>>> =
     Arc::new(Mutex::new(HashMap::new()));
 
+  let can_2_config = ctx
+    .store
+    .config
+    .lock()
+    .await
+    .modman
+    .group_busses
+    .can_2
+    .clone();
+  if let Some(bitrate) = can_2_config
+    .interfaces
+    .get(&iface_name)
+    .and_then(|iface_config| iface_config.bitrate)
+  {
+    match set_bitrate(&iface_name, bitrate) {
+      Ok(true) => {
+        info!("Set bit-rate of interface: {iface_name}, to: {bitrate}.");
+      }
+      Ok(false) => {
+        warn!("Interface: {iface_name}, isn't a CAN interface, so it has no bit-rate to set, using it as-is.");
+      }
+      Err(err) => {
+        error!("Failed to set bit-rate of interface: {iface_name}, to: {bitrate}, using it as-is. Due to:\n{err}");
+      }
+    }
+  }
+
   debug!("Now listening for requests for bus: {iface_name}...");
 
   while !cancellation_token.is_cancelled() {
     let port_statuses = can_2_port_status_mutex.lock().await;
     let mut port_statuses_snapshot = Vec::new();
 
-    // Matches against strings like: `"can0/0xFFF:0xFFF"`.
-    // This regex does not validate if the specified IDs are within the CAN range though.
-    // That's done later by `embedded_can`.
-    let port_specifier_re =
-      Regex::new(r"^(?<iface>\w+)\/(?<rx_id>0[xX][0-9a-fA-F]{3}):(?<tx_id>0[xX][0-9a-fA-F]{3})$")
-        .unwrap();
+    let port_specifier_re = port_specifier_re();
 
     // We need to make sure that we're not leaving that mutex locked for too long.
     for port_status in port_statuses.iter() {
@@ -95,25 +145,36 @@ pub async fn can_bus_manager(
           if requested_iface == &iface_name {
             match port_status {
               PortStatus::Requested(module_id) => {
+                let iso_tp_config = can_2_config.iso_tp_config(&iface_name, &module_id);
+
                 setup_listener(
                   ctx.clone(),
                   iface_name.clone(),
                   module_id,
                   (rx_id, tx_id),
+                  iso_tp_config,
                   listener_registry.clone(),
                 )
                 .await;
               }
               PortStatus::Unrequested(module_id) => {
-                match listener_registry.lock().await.get(&module_id) {
+                match listener_registry.lock().await.remove(&module_id) {
                   Some(listener_token) => {
                     info!("Shutting down CAN 2 listener for Module: {module_id}...");
                     listener_token.cancel();
                   }
                   None => {
-                    error!("Listener for Module: {module_id}, was asked to be unbound, but there's no listener for that module; this is a bug and should be reported!");
+                    debug!("Listener for Module: {module_id}, was already shut down.");
                   }
                 }
+
+                ctx
+                  .store
+                  .port_statuses
+                  .can_2
+                  .lock()
+                  .await
+                  .insert(port_path, PortStatus::Available);
               }
               _ => {}
             }
@@ -139,86 +200,67 @@ pub async fn setup_listener(
   iface_name: String,
   module_id: String,
   id_tuple: (&str, &str),
+  iso_tp_config: CAN2IsoTpConfig,
   listener_registry: Arc<Mutex<HashMap<String, CancellationToken>>>,
 ) {
   let mut bound_port = false;
+  let extended = iso_tp_config.extended_ids.unwrap_or(false);
 
-  match parse_id_str(id_tuple.0) {
-    Ok(raw_rx_id) => match parse_id_str(id_tuple.1) {
-      Ok(raw_tx_id) => {
-        let rx_options = IsoTpKernelOptions::default();
-        let tx_options = IsoTpKernelOptions {
-          socket: IsoTpSocketOptions {
-            flags: flags::CAN_ISOTP_LISTEN_MODE,
-            ..Default::default()
-          },
-          ..Default::default()
-        };
-
-        match embedded_can::StandardId::new(raw_rx_id).ok_or(anyhow!(
-          "We expect that an RX ID of {}, is valid. Check your config or there's a bug in manifest validation!",
-          raw_rx_id
-        )) {
-          Ok(rx_id) => {
-            match embedded_can::StandardId::new(raw_tx_id).ok_or(anyhow!(
-              "We expect that a TX ID of {}, is valid. Check your config or there's a bug in manifest validation!",
-              raw_tx_id
-            )) {
-              Ok(tx_id) => {
-                match TokioSocketCanIsoTp::open(
-                  &iface_name,
-                  Id::Standard(rx_id),
-                  Id::Standard(tx_id),
-                  &rx_options,
-                ) {
-                  Ok(rx_socket) => {
-                    match TokioSocketCanIsoTp::open(
-                      &iface_name,
-                      Id::Standard(rx_id),
-                      Id::Standard(tx_id),
-                      &tx_options
-                    ) {
-                      Ok(tx_socket) => {
-                        info!("Bound port: {}, for module: {module_id}!", format!("{iface_name}/{}:{}", id_tuple.0, id_tuple.1));
-
-                        let listener_token = CancellationToken::new();
-
-                        listener_registry.lock().await.insert(module_id.clone(), listener_token.clone());
-
-                        let rx_session = ctx.session.clone();
-                        let rx_token = listener_token.clone();
-                        let rx_id = module_id.clone();
-                        tokio::task::spawn(async move {
-                          can_module_rx(rx_session, rx_token, rx_socket, rx_id).await;
-                        });
-
-                        let tx_session = ctx.session.clone();
-                        let tx_token = listener_token.clone();
-                        let tx_id = module_id.clone();
-                        tokio::task::spawn(async move {
-                          can_module_tx(tx_session, tx_token, tx_socket, tx_id).await;
-                        });
-
-                        bound_port = true;
-                      },
-                      Err(err) => {
-                        error!("Error while binding socketcan port: {} for TX, due to:\n{err}", format!("{iface_name}/{}:{}", id_tuple.0, id_tuple.1));
-                      },
-                    }
-                  },
-                  Err(err) => {
-                    error!("Error while binding socketcan port: {} for RX, due to:\n{err}", format!("{iface_name}/{}:{}", id_tuple.0, id_tuple.1));
-                  },
-                }
-              },
+  match parse_id_str(id_tuple.0).and_then(|raw_rx_id| can_id(raw_rx_id, extended)) {
+    Ok(rx_id) => match parse_id_str(id_tuple.1).and_then(|raw_tx_id| can_id(raw_tx_id, extended)) {
+      Ok(tx_id) => {
+        // The TX socket can't send in listen mode, so it answers flow control for both, and the RX socket only listens.
+        let rx_options = iso_tp_config.kernel_options(true);
+        let tx_options = iso_tp_config.kernel_options(false);
+
+        match TokioSocketCanIsoTp::open(&iface_name, rx_id, tx_id, &rx_options) {
+          Ok(rx_socket) => {
+            match TokioSocketCanIsoTp::open(&iface_name, rx_id, tx_id, &tx_options) {
+              Ok(tx_socket) => {
+                info!(
+                  "Bound port: {}, for module: {module_id}!",
+                  format!("{iface_name}/{}:{}", id_tuple.0, id_tuple.1)
+                );
+
+                let listener_token = CancellationToken::new();
+
+                listener_registry
+                  .lock()
+                  .await
+                  .insert(module_id.clone(), listener_token.clone());
+
+                let rx_store = ctx.store.clone();
+                let rx_session = ctx.session.clone();
+                let rx_token = listener_token.clone();
+                let rx_id = module_id.clone();
+                tokio::task::spawn(async move {
+                  can_module_rx(rx_store, rx_session, rx_token, rx_socket, rx_id).await;
+                });
+
+                let tx_store = ctx.store.clone();
+                let tx_session = ctx.session.clone();
+                let tx_token = listener_token.clone();
+                let tx_id = module_id.clone();
+                tokio::task::spawn(async move {
+                  can_module_tx(tx_store, tx_session, tx_token, tx_socket, tx_id).await;
+                });
+
+                bound_port = true;
+              }
               Err(err) => {
-                error!("{err}");
-              },
+                error!(
+                  "Error while binding socketcan port: {} for TX, due to:\n{err}",
+                  format!("{iface_name}/{}:{}", id_tuple.0, id_tuple.1)
+                );
+              }
             }
-          },
+          }
           Err(err) => {
-            error!("{err}");
-          },
+            error!(
+              "Error while binding socketcan port: {} for RX, due to:\n{err}",
+              format!("{iface_name}/{}:{}", id_tuple.0, id_tuple.1)
+            );
+          }
         }
       }
       Err(err) => {
@@ -256,3 +298,51 @@ pub async fn setup_listener(
     );
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn port_specifier_accepts_standard_and_extended_ids() {
+    let re = port_specifier_re();
+
+    let captures = re.captures("can0/0x7FF:0x123").unwrap();
+    assert_eq!(match_to_str(captures.name("iface").unwrap()), "can0");
+    assert_eq!(match_to_str(captures.name("rx_id").unwrap()), "0x7FF");
+    assert_eq!(match_to_str(captures.name("tx_id").unwrap()), "0x123");
+
+    let captures = re.captures("can0/0x1FFFFFFF:0X00000800").unwrap();
+    assert_eq!(match_to_str(captures.name("rx_id").unwrap()), "0x1FFFFFFF");
+    assert_eq!(match_to_str(captures.name("tx_id").unwrap()), "0X00000800");
+  }
+
+  #[test]
+  fn port_specifier_rejects_other_lengths() {
+    let re = port_specifier_re();
+
+    for id in ["0x0800", "0x10000", "0x100000", "0x1000000"] {
+      assert!(!re.is_match(&format!("can0/{id}:0x123")), "{id}");
+      assert!(!re.is_match(&format!("can0/0x123:{id}")), "{id}");
+    }
+    assert!(!re.is_match("can0/0x12:0x123"));
+    assert!(!re.is_match("can0/0x123"));
+  }
+
+  #[test]
+  fn can_id_extends_large_or_configured_ids() {
+    assert_eq!(
+      can_id(0x7FF, false).unwrap(),
+      Id::Standard(StandardId::new(0x7FF).unwrap())
+    );
+    assert_eq!(
+      can_id(0x800, false).unwrap(),
+      Id::Extended(ExtendedId::new(0x800).unwrap())
+    );
+    assert_eq!(
+      can_id(0x123, true).unwrap(),
+      Id::Extended(ExtendedId::new(0x123).unwrap())
+    );
+    assert!(can_id(0x2000_0000, false).is_err());
+  }
+}
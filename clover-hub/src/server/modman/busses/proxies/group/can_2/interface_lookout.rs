@@ -33,21 +33,26 @@ pub async fn can_lookout_thread(ctx: Arc<CAN2Bus>) {
   let (lookout_tx, lookout_rx) = unbounded_channel::<CanLookoutEvent>();
 
   let registrar_ctx = ctx.clone();
-  let lookout_ctx = ctx.clone();
+  let lookout_token = ctx.cancellation_token.clone();
 
   // Something something tokio task pool is limited.
-  std::thread::spawn(|| can_interface_lookout(lookout_ctx, lookout_tx));
+  std::thread::spawn(|| can_interface_lookout(lookout_token, lookout_tx));
   let _ =
     tokio::task::spawn(async move { can_bus_registrar(registrar_ctx, lookout_rx).await }).await;
 }
 
 /// Detects network interfaces to try and bind.
-#[instrument(skip(ctx, channel))]
-pub fn can_interface_lookout(ctx: Arc<CAN2Bus>, channel: UnboundedSender<CanLookoutEvent>) {
+///
+/// Shared by every CAN bus, they each decide which interfaces to bind when they're created.
+#[instrument(skip(cancellation_token, channel))]
+pub fn can_interface_lookout(
+  cancellation_token: CancellationToken,
+  channel: UnboundedSender<CanLookoutEvent>,
+) {
   let mut known_ifaces: HashMap<String, u32> = HashMap::new();
   let mut retries = 0;
 
-  while !ctx.cancellation_token.is_cancelled() {
+  while !cancellation_token.is_cancelled() {
     match if_nameindex() {
       Ok(detected_ifaces) => {
         let mut ifaces_to_save = Vec::new();
@@ -117,7 +122,7 @@ pub fn can_interface_lookout(ctx: Arc<CAN2Bus>, channel: UnboundedSender<CanLook
         if retries == 5 {
           error!("Continously failed to get network interfaces even after initial check, did something happen to the network manager?");
           debug!("{err}");
-          ctx.cancellation_token.cancel();
+          cancellation_token.cancel();
           break;
         } else {
           std_sleep(Duration::from_millis(500));
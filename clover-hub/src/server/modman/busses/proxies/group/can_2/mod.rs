@@ -8,6 +8,8 @@ pub mod interface_lookout;
 pub mod module_listener;
 
 use std::{
+  collections::HashMap,
+  process::Command,
   sync::Arc,
   time::Duration,
 };
@@ -18,6 +20,11 @@ use crate::server::modman::{
 };
 
 use anyhow::anyhow;
+use linux_socketcan_iso_tp::{
+  flags,
+  IsoTpFlowControlOptions,
+  IsoTpKernelOptions,
+};
 use nix::net::if_::if_nameindex;
 use serde::{
   Deserialize,
@@ -68,4 +75,277 @@ pub async fn spawn_lookout_thread(
 pub struct CAN2Config {
   /// Interfaces that we should bind to.
   pub permitted_interfaces: Vec<String>,
+  /// Settings for each interface, keyed by interface name.
+  #[serde(default)]
+  pub interfaces: HashMap<String, CAN2InterfaceConfig>,
+  /// ISO-TP overrides for each module, keyed by module ID. Unset fields fall back to the interface's settings.
+  #[serde(default)]
+  pub modules: HashMap<String, CAN2IsoTpConfig>,
+}
+
+impl CAN2Config {
+  /// ISO-TP settings for a module on an interface, with the module's overrides applied.
+  pub fn iso_tp_config(&self, iface_name: &String, module_id: &String) -> CAN2IsoTpConfig {
+    let iface_config = match self.interfaces.get(iface_name) {
+      Some(iface_config) => iface_config.iso_tp.clone(),
+      None => CAN2IsoTpConfig::default(),
+    };
+
+    match self.modules.get(module_id) {
+      Some(module_config) => iface_config.with_overrides(module_config),
+      None => iface_config,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CAN2InterfaceConfig {
+  /// Bit-rate to configure the interface with (in bits per second) before binding it, left as-is if unset.
+  #[serde(default)]
+  pub bitrate: Option<u32>,
+  /// ISO-TP settings for every module on this interface.
+  #[serde(flatten)]
+  pub iso_tp: CAN2IsoTpConfig,
+}
+
+/// ISO-TP settings, unset fields use the kernel's defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CAN2IsoTpConfig {
+  /// Consecutive frames the module may send before waiting for flow control (0 = unlimited.)
+  #[serde(default)]
+  pub block_size: Option<u8>,
+  /// Minimum separation time between consecutive frames, in the raw ISO-TP encoding (0x00-0x7F milliseconds, 0xF1-0xF9 100-900 microseconds.)
+  #[serde(default)]
+  pub st_min: Option<u8>,
+  /// Pad frames to 8 bytes with this byte.
+  #[serde(default)]
+  pub padding: Option<u8>,
+  /// Use extended (29-bit) IDs, even if the IDs in the port fit in 11 bits. IDs that don't fit are always extended.
+  #[serde(default)]
+  pub extended_ids: Option<bool>,
+}
+
+impl CAN2IsoTpConfig {
+  /// Fields set in `overrides` replace ours.
+  pub fn with_overrides(&self, overrides: &CAN2IsoTpConfig) -> CAN2IsoTpConfig {
+    CAN2IsoTpConfig {
+      block_size: overrides.block_size.or(self.block_size),
+      st_min: overrides.st_min.or(self.st_min),
+      padding: overrides.padding.or(self.padding),
+      extended_ids: overrides.extended_ids.or(self.extended_ids),
+    }
+  }
+
+  /// Kernel options for a socket with these settings, `listen_only` sockets never send (including flow control.)
+  pub fn kernel_options(&self, listen_only: bool) -> IsoTpKernelOptions {
+    let mut options = IsoTpKernelOptions::default();
+
+    if listen_only {
+      options.socket.flags |= flags::CAN_ISOTP_LISTEN_MODE;
+    }
+    if let Some(padding) = self.padding {
+      options.socket.tx_padding = Some(padding);
+      options.socket.rx_padding = Some(padding);
+    }
+    if self.block_size.is_some() || self.st_min.is_some() {
+      options.flow_control = Some(IsoTpFlowControlOptions::new(
+        self.block_size.unwrap_or(0),
+        self.st_min.unwrap_or(0),
+        0,
+      ));
+    }
+
+    options
+  }
+}
+
+fn ip_link(args: &[&str]) -> Result<(), anyhow::Error> {
+  let output = Command::new("ip").arg("link").args(args).output()?;
+
+  if output.status.success() {
+    Ok(())
+  } else {
+    Err(anyhow!(
+      "`ip link {}` failed: {}",
+      args.join(" "),
+      String::from_utf8_lossy(&output.stderr).trim()
+    ))
+  }
+}
+
+/// What changing an interface's bit-rate has to respect, from `ip -details link show`.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct LinkDetails {
+  /// e.g. `can`, or `vcan` for virtual interfaces, which have no bit-rate.
+  kind: Option<String>,
+  /// In FD mode, e.g. when it's shared with the [CAN FD bus](super::can_fd).
+  fd: bool,
+  data_bitrate: Option<u32>,
+}
+
+impl LinkDetails {
+  fn parse(json: &[u8]) -> Result<Self, anyhow::Error> {
+    let links: serde_json::Value = serde_json::from_slice(json)?;
+    let link_info = &links[0]["linkinfo"];
+
+    Ok(LinkDetails {
+      kind: link_info["info_kind"].as_str().map(|kind| kind.to_string()),
+      fd: link_info["info_data"]["ctrlmode"]
+        .as_array()
+        .is_some_and(|modes| modes.iter().any(|mode| mode == "FD")),
+      data_bitrate: link_info["info_data"]["data_bittiming"]["bitrate"]
+        .as_u64()
+        .map(|bitrate| bitrate as u32),
+    })
+  }
+
+  fn get(iface_name: &str) -> Result<Self, anyhow::Error> {
+    let output = Command::new("ip")
+      .args(["-details", "-json", "link", "show", "dev", iface_name])
+      .output()?;
+
+    if output.status.success() {
+      LinkDetails::parse(&output.stdout)
+    } else {
+      Err(anyhow!(
+        "`ip -details -json link show dev {iface_name}` failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+      ))
+    }
+  }
+
+  /// Arguments to keep the interface in FD mode when changing its bit-rate.
+  fn fd_args(&self) -> Vec<String> {
+    match (self.fd, self.data_bitrate) {
+      (true, Some(data_bitrate)) => vec![
+        "dbitrate".to_string(),
+        data_bitrate.to_string(),
+        "fd".to_string(),
+        "on".to_string(),
+      ],
+      (true, None) => vec!["fd".to_string(), "on".to_string()],
+      (false, _) => vec![],
+    }
+  }
+}
+
+/// Set an interface's bit-rate, it has to be taken down to do so.
+///
+/// Returns `false` if the interface isn't a CAN interface (e.g. `vcan`), which is left as-is. Interfaces in FD mode stay in FD mode, with the same data bit-rate. The interface is always brought back up, even if the bit-rate was refused.
+#[instrument]
+pub fn set_bitrate(iface_name: &str, bitrate: u32) -> Result<bool, anyhow::Error> {
+  let details = LinkDetails::get(iface_name)?;
+  if details.kind.as_deref() != Some("can") {
+    return Ok(false);
+  }
+
+  let bitrate_str = bitrate.to_string();
+  let fd_args = details.fd_args();
+  let mut type_args = vec![
+    "set",
+    "dev",
+    iface_name,
+    "type",
+    "can",
+    "bitrate",
+    &bitrate_str,
+  ];
+  type_args.extend(fd_args.iter().map(|arg| arg.as_str()));
+
+  ip_link(&["set", "dev", iface_name, "down"])?;
+  let set_result = ip_link(&type_args);
+  // Bring it back up even if the bit-rate was refused, rather than leaving the interface down.
+  let up_result = ip_link(&["set", "dev", iface_name, "up"]);
+
+  set_result.and(up_result).map(|_| true)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn link_details_of_virtual_interface() {
+    let details =
+      LinkDetails::parse(br#"[{"ifname":"vcan0","linkinfo":{"info_kind":"vcan"}}]"#).unwrap();
+
+    assert_eq!(details.kind.as_deref(), Some("vcan"));
+    assert!(!details.fd);
+    assert!(details.fd_args().is_empty());
+  }
+
+  #[test]
+  fn link_details_of_classic_interface() {
+    let details = LinkDetails::parse(
+      br#"[{"ifname":"can0","linkinfo":{"info_kind":"can","info_data":{"ctrlmode":["ONE-SHOT"],"bittiming":{"bitrate":500000}}}}]"#,
+    )
+    .unwrap();
+
+    assert_eq!(details.kind.as_deref(), Some("can"));
+    assert!(!details.fd);
+    assert!(details.fd_args().is_empty());
+  }
+
+  #[test]
+  fn link_details_keep_fd_mode() {
+    let details = LinkDetails::parse(
+      br#"[{"ifname":"can0","linkinfo":{"info_kind":"can","info_data":{"ctrlmode":["FD"],"bittiming":{"bitrate":500000},"data_bittiming":{"bitrate":2000000}}}}]"#,
+    )
+    .unwrap();
+
+    assert!(details.fd);
+    assert_eq!(details.fd_args(), vec!["dbitrate", "2000000", "fd", "on"]);
+  }
+
+  #[test]
+  fn link_details_of_missing_linkinfo() {
+    let details = LinkDetails::parse(br#"[{"ifname":"eth0"}]"#).unwrap();
+
+    assert_eq!(details, LinkDetails::default());
+  }
+
+  #[test]
+  fn module_overrides_win() {
+    let config = CAN2Config {
+      permitted_interfaces: vec!["can0".to_string()],
+      interfaces: HashMap::from([(
+        "can0".to_string(),
+        CAN2InterfaceConfig {
+          bitrate: None,
+          iso_tp: CAN2IsoTpConfig {
+            block_size: Some(8),
+            st_min: Some(0x0A),
+            padding: Some(0xCC),
+            extended_ids: None,
+          },
+        },
+      )]),
+      modules: HashMap::from([(
+        "module".to_string(),
+        CAN2IsoTpConfig {
+          block_size: Some(0),
+          st_min: None,
+          padding: Some(0xAA),
+          extended_ids: Some(true),
+        },
+      )]),
+    };
+
+    let iso_tp_config = config.iso_tp_config(&"can0".to_string(), &"module".to_string());
+    assert_eq!(iso_tp_config.block_size, Some(0));
+    assert_eq!(iso_tp_config.st_min, Some(0x0A));
+    assert_eq!(iso_tp_config.padding, Some(0xAA));
+    assert_eq!(iso_tp_config.extended_ids, Some(true));
+
+    // Modules without overrides use the interface's settings.
+    let iso_tp_config = config.iso_tp_config(&"can0".to_string(), &"other".to_string());
+    assert_eq!(iso_tp_config.block_size, Some(8));
+    assert_eq!(iso_tp_config.padding, Some(0xCC));
+    assert_eq!(iso_tp_config.extended_ids, None);
+
+    // Interfaces without settings only get the module's overrides.
+    let iso_tp_config = config.iso_tp_config(&"can1".to_string(), &"module".to_string());
+    assert_eq!(iso_tp_config.st_min, None);
+    assert_eq!(iso_tp_config.padding, Some(0xAA));
+  }
 }
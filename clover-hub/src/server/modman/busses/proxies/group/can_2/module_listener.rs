@@ -3,6 +3,7 @@ use std::{
   time::Duration,
 };
 
+use anyhow::anyhow;
 use can_isotp_interface::{
   IsoTpAsyncEndpoint,
   RecvControl,
@@ -23,12 +24,25 @@ use zenoh_ext::{
 };
 
 use crate::server::modman::{
-  busses::models::BusMessage,
+  busses::{
+    auth::{
+      report_content_error,
+      verify_bus_message,
+      ContentError,
+    },
+    models::BusMessage,
+    tx::{
+      handle_send_query,
+      ModuleTX,
+    },
+  },
+  models::store::ModManStore,
   MODULE_EVT_ID,
 };
 
-#[instrument(skip(session, cancellation_token, socket))]
+#[instrument(skip(store, session, cancellation_token, socket))]
 pub async fn can_module_rx(
+  store: Arc<ModManStore>,
   session: Arc<zenoh::Session>,
   cancellation_token: CancellationToken,
   mut socket: TokioSocketCanIsoTp,
@@ -58,22 +72,37 @@ pub async fn can_module_rx(
           Ok(_) => {
             if payload.len() > 0 {
               match rmp_serde::from_slice::<BusMessage>(&payload) {
-                Ok(decoded_payload) => match serde_json::to_string(&decoded_payload) {
-                  Ok(json_payload) => match publisher.put(json_payload).await {
-                    Ok(_) => {
-                      debug!("Successfully proxied CAN 2 message from module: {module_id}!");
-                    }
-                    Err(err) => {
-                      error!("Failed to publish message to Zenoh, at this stage, we've either lost connectivity, or there's a massive problem. (Might be a bug) Due to:\n{err}");
+                Ok(decoded_payload) => {
+                  match verify_bus_message(&store, &module_id, &decoded_payload).await {
+                    Ok(_) => match serde_json::to_string(&decoded_payload) {
+                      Ok(json_payload) => match publisher.put(json_payload).await {
+                        Ok(_) => {
+                          debug!("Successfully proxied CAN 2 message from module: {module_id}!");
+                        }
+                        Err(err) => {
+                          error!("Failed to publish message to Zenoh, at this stage, we've either lost connectivity, or there's a massive problem. (Might be a bug) Due to:\n{err}");
+                        }
+                      },
+                      Err(err) => {
+                        error!("Failed to produce a JSON payload from the decoded message, this is a bug and should be reported! Due to:\n{err}");
+                      }
+                    },
+                    Err(content_error) => {
+                      report_content_error(&session, &module_id, &content_error).await;
                     }
-                  },
-                  Err(err) => {
-                    error!("Failed to produce a JSON payload from the decoded message, this is a bug and should be reported! Due to:\n{err}");
                   }
-                },
+                }
                 Err(err) => {
                   // TODO: Do we want to tell the module that it fucked up?
                   error!("Invalid message from module: {module_id}, this is a bug (or bad connection) and should (probably) be reported to the module maintainer! Happened due to:\n{err}");
+                  report_content_error(
+                    &session,
+                    &module_id,
+                    &ContentError::MalformedMessage {
+                      reason: err.to_string(),
+                    },
+                  )
+                  .await;
                 }
               }
             }
@@ -108,110 +137,82 @@ pub async fn can_module_rx(
   };
 }
 
-#[instrument(skip(session, cancellation_token, socket))]
+/// Send a message over ISO-TP, after dropping anything the module sent to this socket (the RX socket handles those.)
+async fn send_to_module(
+  socket: &mut TokioSocketCanIsoTp,
+  msg_bytes: &[u8],
+) -> Result<(), anyhow::Error> {
+  debug!("Dumping everything in the recv buffer so we don't get a memory leak.");
+  loop {
+    match socket
+      .recv_one(Duration::ZERO, |_meta, _payload| {
+        // just discard whatever showed up
+        Ok(RecvControl::Continue)
+      })
+      .await
+    {
+      Ok(RecvStatus::DeliveredOne) => continue,
+      Ok(RecvStatus::TimedOut) => break,
+      Err(RecvError::BufferTooSmall { needed, got }) => {
+        error!(
+          needed,
+          got, "drain buffer undersized, this is a bug and should be reported!"
+        );
+        break;
+      }
+      Err(RecvError::Backend(e)) => {
+        warn!(?e, "Isotp drain failed, socket likely dead!!");
+        break;
+      }
+    }
+  }
+
+  match socket
+    .send_to(0, msg_bytes, Duration::from_millis(100))
+    .await
+  {
+    Ok(_) => Ok(()),
+    Err(err) => Err(anyhow!("{err:#?}")),
+  }
+}
+
+impl ModuleTX for TokioSocketCanIsoTp {
+  async fn send(&mut self, msg_bytes: Vec<u8>) -> Result<(), anyhow::Error> {
+    send_to_module(self, &msg_bytes).await
+  }
+}
+
+#[instrument(skip(store, session, cancellation_token, socket))]
 pub async fn can_module_tx(
+  store: Arc<ModManStore>,
   session: Arc<zenoh::Session>,
   cancellation_token: CancellationToken,
   mut socket: TokioSocketCanIsoTp,
   module_id: String,
 ) {
-  let key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{}/recv", &module_id);
+  let key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/send");
 
   match session.declare_queryable(&key_expr).await {
     Ok(queryable) => {
-      while !cancellation_token.is_cancelled() {
-        if let Ok(query) = queryable.recv_async().await {
-          match query.payload() {
-            Some(query_payload) => match query_payload.try_to_string() {
-              Ok(payload_str) => {
-                match serde_json_lenient::from_str::<BusMessage>(&payload_str.to_owned()) {
-                  Ok(message) => match rmp_serde::to_vec(&message) {
-                    Ok(msg_bytes) => {
-                      debug!(
-                        "Dumping everything in the recv buffer so we don't get a memory leak."
-                      );
-                      loop {
-                        match socket
-                          .recv_one(Duration::ZERO, |_meta, _payload| {
-                            // just discard whatever showed up
-                            Ok(RecvControl::Continue)
-                          })
-                          .await
-                        {
-                          Ok(RecvStatus::DeliveredOne) => continue,
-                          Ok(RecvStatus::TimedOut) => break,
-                          Err(RecvError::BufferTooSmall { needed, got }) => {
-                            error!(
-                              needed,
-                              got, "drain buffer undersized, this is a bug and should be reported!"
-                            );
-                            break;
-                          }
-                          Err(RecvError::Backend(e)) => {
-                            warn!(?e, "Isotp drain failed, socket likely dead!!");
-                            break;
-                          }
-                        }
-                      }
-
-                      debug!("Sending CAN 2 message to module: {module_id}...");
-                      match socket
-                        .send_to(0, &msg_bytes, Duration::from_millis(100))
-                        .await
-                      {
-                        Ok(_) => {
-                          debug!("Successfully sent CAN 2 message to module: {module_id}!");
-                        }
-                        Err(err) => {
-                          error!("Unable to send message to CAN 2 socket due to:\n{err:#?}");
-                        }
-                      }
-                    }
-                    Err(err) => {
-                      error!("Could not turn the BusMessage into a valid MSGPack byte array, this is a bug and should be reported! Due to:\n{err}");
-                    }
-                  },
-                  Err(err) => {
-                    // TODO: do we tell the querier that they fucked up?
-                    let querier_str = match query.source_info() {
-                      Some(source_info) => {
-                        format!(" from source: {}", source_info.source_id().zid())
-                      }
-                      None => "".to_string(),
-                    };
-
-                    error!(
-                      "Invalid query payload{querier_str}, due to:\n{err}\nPayload:\n{query_payload:#?}"
-                    );
-                  }
-                }
-              }
-              Err(err) => {
-                // TODO: do we tell the querier that they fucked up?
-                let querier_str = match query.source_info() {
-                  Some(source_info) => {
-                    format!(" from source: {}", source_info.source_id().zid())
-                  }
-                  None => "".to_string(),
-                };
-
-                error!(
-                  "Query is not string{querier_str}, due to:\n{err}\nPayload:\n{query_payload:#?}"
-                );
-              }
-            },
-            None => {
-              let querier_str = match query.source_info() {
-                Some(source_info) => {
-                  format!(" talking to you, {}...", source_info.source_id().zid())
-                }
-                None => "".to_string(),
-              };
+      loop {
+        let query = tokio::select! {
+          _ = cancellation_token.cancelled() => break,
+          query = queryable.recv_async() => match query {
+            Ok(query) => query,
+            Err(_) => break,
+          },
+        };
 
-              warn!("Empty queries are ignored...{querier_str}");
-            }
-          }
-        }
+        handle_send_query(
+          &store,
+          &session,
+          &module_id,
+          &query,
+          &key_expr,
+          "CAN 2",
+          &mut socket,
+        )
+        .await;
       }
 
       debug!("Shutting down CAN 2 TX thread.");
@@ -0,0 +1,212 @@
+use std::{
+  collections::HashMap,
+  time::Duration,
+};
+
+use anyhow::anyhow;
+use linux_socketcan_iso_tp::{
+  flags,
+  IsoTpKernelOptions,
+  IsoTpLinkLayerOptions,
+  TokioSocketCanIsoTp,
+};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  error,
+  info,
+  instrument,
+  warn,
+};
+
+use crate::server::modman::{
+  busses::proxies::group::{
+    can_2::{
+      bus_manager::{
+        can_id,
+        match_to_str,
+        parse_id_str,
+        port_specifier_re,
+      },
+      module_listener::{
+        can_module_rx,
+        can_module_tx,
+      },
+    },
+    can_fd::{
+      CANFDBus,
+      CANFDConfig,
+      CANFD_BRS,
+      CANFD_DATA_LENGTHS,
+      CANFD_MTU,
+    },
+  },
+  models::PortStatus,
+};
+
+/// Kernel options for CAN FD sockets on this bus.
+pub fn can_fd_socket_options(config: &CANFDConfig) -> Result<IsoTpKernelOptions, anyhow::Error> {
+  if !CANFD_DATA_LENGTHS.contains(&config.data_length) {
+    return Err(anyhow!(
+      "CAN FD data length: {}, is not one of: {CANFD_DATA_LENGTHS:?}. Check your config!",
+      config.data_length
+    ));
+  }
+
+  Ok(IsoTpKernelOptions {
+    link_layer: Some(IsoTpLinkLayerOptions::new(
+      CANFD_MTU,
+      config.data_length,
+      if config.bit_rate_switching {
+        CANFD_BRS
+      } else {
+        0
+      },
+    )),
+    ..Default::default()
+  })
+}
+
+#[instrument(skip(ctx, cancellation_token, config))]
+pub async fn can_fd_bus_manager(
+  ctx: Arc<CANFDBus>,
+  cancellation_token: CancellationToken,
+  iface_name: String,
+  config: CANFDConfig,
+) {
+  let listener_registry: Arc<tokio::sync::Mutex<HashMap<String, CancellationToken>>> =
+    Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+
+  let socket_options = match can_fd_socket_options(&config) {
+    Ok(socket_options) => socket_options,
+    Err(err) => {
+      error!("Not managing CAN FD interface: {iface_name}, due to:\n{err}");
+      return;
+    }
+  };
+
+  // Same port specifiers as CAN 2.
+  let port_specifier_re = port_specifier_re();
+
+  debug!("Now listening for requests for bus: {iface_name}...");
+
+  while !cancellation_token.is_cancelled() {
+    // We need to make sure that we're not leaving that mutex locked for too long.
+    let port_statuses_snapshot = ctx.store.port_statuses.can_fd.lock().await.clone();
+
+    for (port_path, port_status) in port_statuses_snapshot {
+      if let Some(re_captures) = port_specifier_re.captures(&port_path) {
+        // Known good value since the regex is static, and the haystack matched.
+        let requested_iface = match_to_str(re_captures.get(1).unwrap());
+        let rx_id = match_to_str(re_captures.get(2).unwrap());
+        let tx_id = match_to_str(re_captures.get(3).unwrap());
+
+        if requested_iface != iface_name {
+          continue;
+        }
+
+        match port_status {
+          PortStatus::Requested(module_id) => {
+            let bound = match setup_fd_listener(
+              ctx.clone(),
+              &iface_name,
+              &module_id,
+              (rx_id, tx_id),
+              &socket_options,
+            ) {
+              Ok(listener_token) => {
+                info!("Bound CAN FD port: {port_path}, for module: {module_id}!");
+                listener_registry
+                  .lock()
+                  .await
+                  .insert(module_id.clone(), listener_token);
+                PortStatus::Bound(module_id)
+              }
+              Err(err) => {
+                warn!("Unable to bind CAN FD port: {port_path}, for module: {module_id}, due to:\n{err}");
+                PortStatus::Unavailable(module_id)
+              }
+            };
+
+            ctx
+              .store
+              .port_statuses
+              .can_fd
+              .lock()
+              .await
+              .insert(port_path, bound);
+          }
+          PortStatus::Unrequested(module_id) => {
+            match listener_registry.lock().await.remove(&module_id) {
+              Some(listener_token) => {
+                info!("Shutting down CAN FD listener for Module: {module_id}...");
+                listener_token.cancel();
+              }
+              None => {
+                debug!("Listener for Module: {module_id}, was already shut down.");
+              }
+            }
+
+            ctx
+              .store
+              .port_statuses
+              .can_fd
+              .lock()
+              .await
+              .insert(port_path, PortStatus::Available);
+          }
+          _ => {}
+        }
+      }
+    }
+
+    // We don't wanna obliterate the CPU.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+  }
+
+  for (module_id, listener_token) in listener_registry.lock().await.iter() {
+    debug!("Shutting down CAN FD listener for module: {module_id}...");
+    listener_token.cancel();
+  }
+}
+
+/// Open the module's sockets, and start the listeners that expose them over Zenoh.
+#[instrument(skip(ctx, socket_options))]
+fn setup_fd_listener(
+  ctx: Arc<CANFDBus>,
+  iface_name: &String,
+  module_id: &String,
+  id_tuple: (&str, &str),
+  socket_options: &IsoTpKernelOptions,
+) -> Result<CancellationToken, anyhow::Error> {
+  let rx_id = can_id(parse_id_str(id_tuple.0)?, false)?;
+  let tx_id = can_id(parse_id_str(id_tuple.1)?, false)?;
+
+  // Same as CAN 2, the TX socket answers flow control for both, and the RX socket only listens.
+  let mut rx_options = socket_options.clone();
+  rx_options.socket.flags |= flags::CAN_ISOTP_LISTEN_MODE;
+
+  let rx_socket = TokioSocketCanIsoTp::open(iface_name, rx_id, tx_id, &rx_options)?;
+  let tx_socket = TokioSocketCanIsoTp::open(iface_name, rx_id, tx_id, socket_options)?;
+
+  let listener_token = CancellationToken::new();
+
+  let rx_store = ctx.store.clone();
+  let rx_session = ctx.session.clone();
+  let rx_token = listener_token.clone();
+  let rx_id = module_id.clone();
+  tokio::task::spawn(async move {
+    can_module_rx(rx_store, rx_session, rx_token, rx_socket, rx_id).await;
+  });
+
+  let tx_store = ctx.store.clone();
+  let tx_session = ctx.session.clone();
+  let tx_token = listener_token.clone();
+  let tx_id = module_id.clone();
+  tokio::task::spawn(async move {
+    can_module_tx(tx_store, tx_session, tx_token, tx_socket, tx_id).await;
+  });
+
+  Ok(listener_token)
+}
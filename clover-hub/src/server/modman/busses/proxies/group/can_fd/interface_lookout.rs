@@ -0,0 +1,87 @@
+use std::{
+  collections::HashMap,
+  sync::Arc,
+};
+
+use crate::server::modman::busses::proxies::group::{
+  can_2::interface_lookout::{
+    can_interface_lookout,
+    CanLookoutEvent,
+  },
+  can_fd::{
+    bus_manager::can_fd_bus_manager,
+    CANFDBus,
+  },
+};
+
+use tokio::sync::mpsc::{
+  unbounded_channel,
+  UnboundedReceiver,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  info,
+  instrument,
+};
+
+#[instrument(skip(ctx))]
+pub async fn can_fd_lookout_thread(ctx: Arc<CANFDBus>) {
+  let (lookout_tx, lookout_rx) = unbounded_channel::<CanLookoutEvent>();
+
+  let registrar_ctx = ctx.clone();
+  let lookout_token = ctx.cancellation_token.clone();
+
+  // Something something tokio task pool is limited.
+  std::thread::spawn(|| can_interface_lookout(lookout_token, lookout_tx));
+  let _ =
+    tokio::task::spawn(async move { can_fd_bus_registrar(registrar_ctx, lookout_rx).await }).await;
+}
+
+#[instrument(skip(ctx, channel))]
+pub async fn can_fd_bus_registrar(
+  ctx: Arc<CANFDBus>,
+  mut channel: UnboundedReceiver<CanLookoutEvent>,
+) {
+  let mut bus_registry: HashMap<String, CancellationToken> = HashMap::new();
+  // Prevent the mutex from being locked for the entire time we run the bus proxy.
+  let config = ctx.store.config.lock().await;
+  let can_fd_config = config.modman.group_busses.can_fd.clone();
+  drop(config);
+
+  while !ctx.cancellation_token.is_cancelled() {
+    if let Some(lookout_event) = channel.recv().await {
+      match lookout_event {
+        CanLookoutEvent::IFaceCreate((iface_name, _iface_index)) => {
+          if can_fd_config.permitted_interfaces.contains(&iface_name)
+            && !bus_registry.contains_key(&iface_name)
+          {
+            info!("Found configured interface: {iface_name}, starting up a CAN FD bus manager...");
+
+            let manager_token = CancellationToken::new();
+            let manager_ctx = ctx.clone();
+            let manager_iface_name = iface_name.clone();
+            let manager_config = can_fd_config.clone();
+
+            bus_registry.insert(iface_name, manager_token.clone());
+
+            tokio::task::spawn(async move {
+              can_fd_bus_manager(
+                manager_ctx,
+                manager_token,
+                manager_iface_name,
+                manager_config,
+              )
+              .await;
+            });
+          }
+        }
+        CanLookoutEvent::IFaceDestroy(iface_name) => {
+          if let Some(manager_token) = bus_registry.remove(&iface_name) {
+            info!("Shutting down CAN FD bus manager for interface: {iface_name}...");
+            manager_token.cancel();
+          }
+        }
+      }
+    }
+  }
+}
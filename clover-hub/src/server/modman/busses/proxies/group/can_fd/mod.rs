@@ -0,0 +1,61 @@
+//! # CAN FD Bus Proxy
+//!
+//! Works the same way as the [CAN 2 proxy](super::can_2), and shares its lookout and module listeners, but opens ISO-TP sockets with the CAN FD MTU. Frames carry up to 64 bytes, and the data phase can use bit-rate switching (BRS) if the interface's data bit-rate is configured (e.g. `ip link set can0 type can bitrate 500000 dbitrate 2000000 fd on`).
+//!
+
+pub mod bus_manager;
+pub mod interface_lookout;
+
+use std::sync::Arc;
+
+use crate::server::modman::models::store::ModManStore;
+
+use serde::{
+  Deserialize,
+  Serialize,
+};
+use tokio_util::sync::CancellationToken;
+
+/// `CANFD_MTU` from `linux/can.h`.
+pub const CANFD_MTU: u8 = 72;
+/// `CANFD_BRS` from `linux/can.h`.
+pub const CANFD_BRS: u8 = 0x01;
+/// Valid CAN FD data lengths.
+pub const CANFD_DATA_LENGTHS: [u8; 8] = [8, 12, 16, 20, 24, 32, 48, 64];
+
+#[derive(Debug, Clone)]
+pub struct CANFDBus {
+  pub session: Arc<zenoh::Session>,
+  pub store: Arc<ModManStore>,
+  pub cancellation_token: CancellationToken,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CANFDConfig {
+  /// Interfaces that we should bind to, must be configured for CAN FD.
+  pub permitted_interfaces: Vec<String>,
+  /// Bytes per frame, one of 8, 12, 16, 20, 24, 32, 48, or 64.
+  #[serde(default = "default_data_length")]
+  pub data_length: u8,
+  /// Send the data phase of frames at the interface's data bit-rate.
+  #[serde(default = "default_bit_rate_switching")]
+  pub bit_rate_switching: bool,
+}
+
+fn default_data_length() -> u8 {
+  64
+}
+
+fn default_bit_rate_switching() -> bool {
+  true
+}
+
+impl Default for CANFDConfig {
+  fn default() -> Self {
+    CANFDConfig {
+      permitted_interfaces: Default::default(),
+      data_length: default_data_length(),
+      bit_rate_switching: default_bit_rate_switching(),
+    }
+  }
+}
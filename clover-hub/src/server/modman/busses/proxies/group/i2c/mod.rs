@@ -0,0 +1,275 @@
+//! # I2C Bus Proxy
+//!
+//! Binds modules on the I2C busses (`/dev/i2c-*`) allowed in the config. Each module is an SMBus target, polled for messages at its address, see the [register protocol](protocol).
+//!
+//! Ports are identified as `$BUS/$ADDR`, e.g. `i2c-1/0x42`.
+//!
+
+pub mod protocol;
+
+use std::{
+  collections::HashMap,
+  path::Path,
+  sync::Arc,
+  time::Duration,
+};
+
+use anyhow::anyhow;
+use i2cdev::linux::LinuxI2CDevice;
+use serde::{
+  Deserialize,
+  Serialize,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  info,
+  instrument,
+  warn,
+};
+
+use crate::server::modman::{
+  busses::{
+    models::{
+      Bus,
+      BusTypes,
+    },
+    proxies::{
+      group::{
+        i2c::protocol::{
+          read_message,
+          write_message,
+        },
+        polled_listener::{
+          polled_module_listener,
+          PolledDevice,
+        },
+      },
+      Backoff,
+    },
+  },
+  models::{
+    store::ModManStore,
+    PortStatus,
+  },
+};
+
+#[derive(Debug, Clone)]
+pub struct I2CBus {
+  pub store: Arc<ModManStore>,
+  pub cancellation_token: CancellationToken,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct I2CConfig {
+  /// Busses that we should bind to, e.g. `i2c-1` for `/dev/i2c-1`.
+  pub permitted_busses: Vec<String>,
+  /// Milliseconds between polls of each module.
+  #[serde(default = "default_poll_interval")]
+  pub poll_interval: u64,
+  /// Polls that can fail in a row before a module is released, and bound again with backoff.
+  #[serde(default = "default_max_poll_failures")]
+  pub max_poll_failures: u32,
+}
+
+fn default_poll_interval() -> u64 {
+  20
+}
+
+fn default_max_poll_failures() -> u32 {
+  10
+}
+
+impl Default for I2CConfig {
+  fn default() -> Self {
+    I2CConfig {
+      permitted_busses: Default::default(),
+      poll_interval: default_poll_interval(),
+      max_poll_failures: default_max_poll_failures(),
+    }
+  }
+}
+
+/// Split a port into its bus, and the device's address.
+pub fn parse_i2c_port(port: &String) -> Result<(String, u16), anyhow::Error> {
+  let (bus_id, address) = port.split_once('/').ok_or(anyhow!(
+    "I2C port: {port}, is not in the `$BUS/$ADDR` format."
+  ))?;
+  let digits = address
+    .strip_prefix("0x")
+    .or_else(|| address.strip_prefix("0X"))
+    .unwrap_or(address);
+
+  match u16::from_str_radix(digits, 16) {
+    // 7-bit addresses, 10-bit addressing isn't supported by most adapters.
+    Ok(address) if address <= 0x7F => Ok((bus_id.to_string(), address)),
+    Ok(address) => Err(anyhow!(
+      "I2C address: {address:#X}, for port: {port}, is out of range."
+    )),
+    Err(err) => Err(err.into()),
+  }
+}
+
+impl Bus for I2CBus {
+  #[instrument(name = "i2c_bus", skip(self, session))]
+  async fn subscribe_to_bus(
+    self,
+    session: Arc<zenoh::Session>,
+  ) -> Result<tokio::task::JoinHandle<()>, anyhow::Error> {
+    Ok(tokio::task::spawn(async move {
+      let mut listeners: HashMap<String, CancellationToken> = HashMap::new();
+      let mut backoffs: HashMap<String, Backoff> = HashMap::new();
+
+      while !self.cancellation_token.is_cancelled() {
+        let i2c_config = self
+          .store
+          .config
+          .lock()
+          .await
+          .modman
+          .group_busses
+          .i2c
+          .clone();
+        let port_statuses_snapshot = self.store.port_statuses.i2c.lock().await.clone();
+
+        for (port, port_status) in port_statuses_snapshot {
+          let (bus_id, address) = match parse_i2c_port(&port) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+              warn!("{err}");
+              continue;
+            }
+          };
+          let bus_path = format!("/dev/{bus_id}");
+          let bus_present = Path::new(&bus_path).exists();
+
+          let next_status = match port_status.clone() {
+            PortStatus::Requested(module_id) | PortStatus::Unavailable(module_id) => {
+              if !i2c_config.permitted_busses.contains(&bus_id) {
+                warn!("Module: {module_id}, requested I2C port: {port}, but bus: {bus_id}, isn't permitted in the config.");
+                PortStatus::Unavailable(module_id)
+              } else if !bus_present || backoffs.get(&port).is_some_and(|backoff| !backoff.ready())
+              {
+                PortStatus::Unavailable(module_id)
+              } else {
+                match LinuxI2CDevice::new(&bus_path, address) {
+                  Ok(device) => {
+                    info!("Bound I2C port: {port}, for module: {module_id}!");
+
+                    let listener_token = CancellationToken::new();
+                    listeners.insert(port.clone(), listener_token.clone());
+
+                    let listener_store = self.store.clone();
+                    let listener_session = session.clone();
+                    let listener_id = module_id.clone();
+                    let poll_interval = Duration::from_millis(i2c_config.poll_interval);
+                    tokio::task::spawn(async move {
+                      polled_module_listener(
+                        listener_store,
+                        listener_session,
+                        listener_token,
+                        PolledDevice {
+                          bus_name: "I2C",
+                          device,
+                          read_message: read_message::<LinuxI2CDevice>,
+                          write_message: write_message::<LinuxI2CDevice>,
+                        },
+                        listener_id,
+                        poll_interval,
+                        i2c_config.max_poll_failures,
+                      )
+                      .await;
+                    });
+
+                    PortStatus::Bound(module_id)
+                  }
+                  Err(err) => {
+                    warn!(
+                      "Unable to bind I2C port: {port}, for module: {module_id}, due to:\n{err}"
+                    );
+                    PortStatus::Unavailable(module_id)
+                  }
+                }
+              }
+            }
+            PortStatus::Bound(module_id) => {
+              // The listener cancels itself once the module stops answering polls.
+              let listener_stopped = listeners
+                .get(&port)
+                .is_some_and(|listener_token| listener_token.is_cancelled());
+              if bus_present && !listener_stopped {
+                continue;
+              }
+
+              if let Some(listener_token) = listeners.remove(&port) {
+                listener_token.cancel();
+              }
+              if listener_stopped {
+                let backoff = backoffs.entry(port.clone()).or_default();
+                backoff.failed();
+                warn!("I2C module: {module_id}, on port: {port}, stopped answering polls, will bind it again (attempt {})...", backoff.attempts);
+              } else {
+                warn!("I2C bus: {bus_id}, disappeared, will bind port: {port}, for module: {module_id}, again once it's available...");
+              }
+              PortStatus::Unavailable(module_id)
+            }
+            PortStatus::Unrequested(module_id) => {
+              if let Some(listener_token) = listeners.remove(&port) {
+                info!("Releasing I2C port: {port}, from module: {module_id}...");
+                listener_token.cancel();
+              }
+              backoffs.remove(&port);
+              PortStatus::Available
+            }
+            PortStatus::Available => continue,
+          };
+
+          let mut port_statuses = self.store.port_statuses.i2c.lock().await;
+          // Don't clobber a module being (de)initialized while we were binding.
+          if port_statuses.get(&port) == Some(&port_status) {
+            port_statuses.insert(port, next_status);
+          }
+        }
+
+        tokio::select! {
+          _ = self.cancellation_token.cancelled() => {},
+          _ = tokio::time::sleep(Duration::from_millis(500)) => {},
+        }
+      }
+
+      for (port, listener_token) in listeners.drain() {
+        debug!("Shutting down I2C listener for port: {port}...");
+        listener_token.cancel();
+      }
+    }))
+  }
+
+  fn get_type() -> BusTypes {
+    BusTypes::I2C
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ports_are_split_into_bus_and_address() {
+    assert_eq!(
+      parse_i2c_port(&"i2c-1/0x42".to_string()).unwrap(),
+      ("i2c-1".to_string(), 0x42)
+    );
+    assert_eq!(
+      parse_i2c_port(&"i2c-0/7f".to_string()).unwrap(),
+      ("i2c-0".to_string(), 0x7F)
+    );
+  }
+
+  #[test]
+  fn malformed_ports_are_refused() {
+    // 10-bit addresses aren't supported.
+    assert!(parse_i2c_port(&"i2c-1/0x80".to_string()).is_err());
+    assert!(parse_i2c_port(&"i2c-1/0xZZ".to_string()).is_err());
+    assert!(parse_i2c_port(&"i2c-1".to_string()).is_err());
+  }
+}
@@ -0,0 +1,247 @@
+//! # I2C Register Protocol
+//!
+//! Modules are SMBus targets, [`BusMessage`]s are MessagePack encoded, and moved in 32 byte chunks over these registers:
+//!
+//! | Register | Access | Contents |
+//! | --- | --- | --- |
+//! | `0x00` | Word read | Length of the message the module has queued for ModMan, `0` if there isn't one. |
+//! | `0x02` | Word write | Length of the message ModMan just wrote, the module decodes it and clears its buffer. |
+//! | `0x40` | I2C block write | Next chunk of a message to the module. |
+//! | `0x80` | I2C block read | Next chunk of the queued message, the module dequeues it once the last chunk is read. |
+//!
+//! Module firmware treats `0x40` and `0x80` as FIFOs. The registers are spaced so messages up to 32 bytes can be exercised against the kernel's `i2c-stub` (which treats every register as plain memory), e.g. by setting `0x00` and `0x80..` with `i2cset`, and checking `0x40..` with `i2cdump`.
+//!
+
+use i2cdev::core::I2CDevice;
+
+pub const REG_RX_LEN: u8 = 0x00;
+pub const REG_TX_END: u8 = 0x02;
+pub const REG_TX_DATA: u8 = 0x40;
+pub const REG_RX_DATA: u8 = 0x80;
+/// Largest SMBus I2C block transfer.
+pub const CHUNK_LEN: usize = 32;
+/// Largest message either side will send, anything bigger is treated as a corrupted length.
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Read the module's queued message, if there is one.
+pub fn read_message<D: I2CDevice>(device: &mut D) -> Result<Option<Vec<u8>>, anyhow::Error>
+where
+  D::Error: Send + Sync + 'static,
+{
+  let len = device.smbus_read_word_data(REG_RX_LEN)? as usize;
+
+  if len == 0 {
+    return Ok(None);
+  }
+  if len > MAX_MESSAGE_LEN {
+    return Err(anyhow::anyhow!(
+      "Module queued a message of {len} bytes, more than the max of {MAX_MESSAGE_LEN} bytes."
+    ));
+  }
+
+  let mut message = Vec::with_capacity(len);
+  while message.len() < len {
+    let chunk_len = (len - message.len()).min(CHUNK_LEN);
+    let chunk = device.smbus_read_i2c_block_data(REG_RX_DATA, chunk_len as u8)?;
+
+    if chunk.len() != chunk_len {
+      return Err(anyhow::anyhow!(
+        "Short read from module, expected {chunk_len} bytes, got {} bytes.",
+        chunk.len()
+      ));
+    }
+    message.extend_from_slice(&chunk);
+  }
+
+  Ok(Some(message))
+}
+
+/// Write a message to the module, then tell it how long it was.
+pub fn write_message<D: I2CDevice>(device: &mut D, message: &[u8]) -> Result<(), anyhow::Error>
+where
+  D::Error: Send + Sync + 'static,
+{
+  if message.len() > MAX_MESSAGE_LEN {
+    return Err(anyhow::anyhow!(
+      "Message of {} bytes, is more than the max of {MAX_MESSAGE_LEN} bytes.",
+      message.len()
+    ));
+  }
+
+  for chunk in message.chunks(CHUNK_LEN) {
+    device.smbus_write_i2c_block_data(REG_TX_DATA, chunk)?;
+  }
+  device.smbus_write_word_data(REG_TX_END, message.len() as u16)?;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    fs,
+    io,
+  };
+
+  use i2cdev::linux::LinuxI2CDevice;
+
+  use super::*;
+
+  /// Records the transfers it's asked to do, and answers reads from a queued message.
+  #[derive(Default)]
+  struct RecordingDevice {
+    queued: Vec<u8>,
+    block_reads: Vec<(u8, u8)>,
+    block_writes: Vec<(u8, Vec<u8>)>,
+    word_writes: Vec<(u8, u16)>,
+  }
+
+  impl I2CDevice for RecordingDevice {
+    type Error = io::Error;
+
+    fn read(&mut self, _data: &mut [u8]) -> Result<(), io::Error> {
+      unimplemented!()
+    }
+
+    fn write(&mut self, _data: &[u8]) -> Result<(), io::Error> {
+      unimplemented!()
+    }
+
+    fn smbus_write_quick(&mut self, _bit: bool) -> Result<(), io::Error> {
+      unimplemented!()
+    }
+
+    fn smbus_read_word_data(&mut self, register: u8) -> Result<u16, io::Error> {
+      assert_eq!(register, REG_RX_LEN);
+      Ok(self.queued.len() as u16)
+    }
+
+    fn smbus_write_word_data(&mut self, register: u8, value: u16) -> Result<(), io::Error> {
+      self.word_writes.push((register, value));
+      Ok(())
+    }
+
+    fn smbus_read_block_data(&mut self, _register: u8) -> Result<Vec<u8>, io::Error> {
+      unimplemented!()
+    }
+
+    fn smbus_read_i2c_block_data(&mut self, register: u8, len: u8) -> Result<Vec<u8>, io::Error> {
+      self.block_reads.push((register, len));
+      let offset = self.block_reads[..self.block_reads.len() - 1]
+        .iter()
+        .map(|(_, len)| *len as usize)
+        .sum::<usize>();
+      Ok(self.queued[offset..offset + len as usize].to_vec())
+    }
+
+    fn smbus_write_block_data(&mut self, _register: u8, _values: &[u8]) -> Result<(), io::Error> {
+      unimplemented!()
+    }
+
+    fn smbus_write_i2c_block_data(&mut self, register: u8, values: &[u8]) -> Result<(), io::Error> {
+      self.block_writes.push((register, values.to_vec()));
+      Ok(())
+    }
+
+    fn smbus_process_block(&mut self, _register: u8, _values: &[u8]) -> Result<Vec<u8>, io::Error> {
+      unimplemented!()
+    }
+  }
+
+  #[test]
+  fn messages_are_read_in_chunks() {
+    let queued: Vec<u8> = (0..70).collect();
+    let mut device = RecordingDevice {
+      queued: queued.clone(),
+      ..Default::default()
+    };
+
+    assert_eq!(read_message(&mut device).unwrap(), Some(queued));
+    assert_eq!(
+      device.block_reads,
+      vec![(REG_RX_DATA, 32), (REG_RX_DATA, 32), (REG_RX_DATA, 6)]
+    );
+  }
+
+  #[test]
+  fn nothing_queued_is_none() {
+    let mut device = RecordingDevice::default();
+
+    assert_eq!(read_message(&mut device).unwrap(), None);
+    assert!(device.block_reads.is_empty());
+  }
+
+  #[test]
+  fn oversized_length_is_refused() {
+    let mut device = RecordingDevice {
+      queued: vec![0; MAX_MESSAGE_LEN + 1],
+      ..Default::default()
+    };
+
+    assert!(read_message(&mut device).is_err());
+    assert!(write_message(&mut device, &[0; MAX_MESSAGE_LEN + 1]).is_err());
+    assert!(device.block_reads.is_empty());
+    assert!(device.block_writes.is_empty());
+  }
+
+  #[test]
+  fn messages_are_written_in_chunks_then_ended() {
+    let message: Vec<u8> = (0..64).collect();
+    let mut device = RecordingDevice::default();
+
+    write_message(&mut device, &message).unwrap();
+    assert_eq!(
+      device.block_writes,
+      vec![
+        (REG_TX_DATA, message[..32].to_vec()),
+        (REG_TX_DATA, message[32..].to_vec()),
+      ]
+    );
+    assert_eq!(device.word_writes, vec![(REG_TX_END, 64)]);
+  }
+
+  /// The bus the `i2c-stub` kernel module added, if it's loaded.
+  fn stub_bus() -> Option<String> {
+    fs::read_dir("/sys/bus/i2c/devices")
+      .ok()?
+      .find_map(|entry| {
+        let entry = entry.ok()?;
+        let name = fs::read_to_string(entry.path().join("name")).ok()?;
+
+        match name.trim() == "SMBus stub driver" {
+          true => Some(format!("/dev/{}", entry.file_name().to_string_lossy())),
+          false => None,
+        }
+      })
+  }
+
+  #[test]
+  #[ignore = "needs the i2c-stub kernel module, e.g. `modprobe i2c-stub chip_addr=0x42`"]
+  fn messages_round_trip_through_i2c_stub() {
+    let bus_path = stub_bus().expect("i2c-stub isn't loaded.");
+    let mut device = LinuxI2CDevice::new(&bus_path, 0x42).expect("Failed to open i2c-stub.");
+    let message: Vec<u8> = (0..CHUNK_LEN as u8).collect();
+
+    // i2c-stub is plain memory, so queue a message like module firmware would.
+    device
+      .smbus_write_i2c_block_data(REG_RX_DATA, &message)
+      .unwrap();
+    device
+      .smbus_write_word_data(REG_RX_LEN, message.len() as u16)
+      .unwrap();
+    assert_eq!(read_message(&mut device).unwrap(), Some(message.clone()));
+
+    let reply: Vec<u8> = message.iter().rev().copied().collect();
+    write_message(&mut device, &reply).unwrap();
+    assert_eq!(
+      device
+        .smbus_read_i2c_block_data(REG_TX_DATA, reply.len() as u8)
+        .unwrap(),
+      reply
+    );
+    assert_eq!(
+      device.smbus_read_word_data(REG_TX_END).unwrap(),
+      reply.len() as u16
+    );
+  }
+}
@@ -24,6 +24,12 @@ use serde::{
 
 #[cfg(feature = "can_2")]
 use crate::server::modman::busses::proxies::group::can_2::CAN2Config;
+#[cfg(feature = "can_fd")]
+use crate::server::modman::busses::proxies::group::can_fd::CANFDConfig;
+#[cfg(feature = "i2c")]
+use crate::server::modman::busses::proxies::group::i2c::I2CConfig;
+#[cfg(feature = "spi")]
+use crate::server::modman::busses::proxies::group::spi::SPIConfig;
 
 #[cfg(feature = "can_2")]
 pub mod can_2;
@@ -31,6 +37,8 @@ pub mod can_2;
 pub mod can_fd;
 #[cfg(feature = "i2c")]
 pub mod i2c;
+#[cfg(any(feature = "i2c", feature = "spi"))]
+pub mod polled_listener;
 #[cfg(feature = "spi")]
 pub mod spi;
 
@@ -38,4 +46,13 @@ pub mod spi;
 pub struct GroupBusConfigs {
   #[cfg(feature = "can_2")]
   pub can_2: CAN2Config,
+  #[cfg(feature = "can_fd")]
+  #[serde(default)]
+  pub can_fd: CANFDConfig,
+  #[cfg(feature = "i2c")]
+  #[serde(default)]
+  pub i2c: I2CConfig,
+  #[cfg(feature = "spi")]
+  #[serde(default)]
+  pub spi: SPIConfig,
 }
@@ -0,0 +1,221 @@
+//! # Polled Module Listener
+//!
+//! Modules on busses driven entirely by ModMan ([I2C](super::i2c) and [SPI](super::spi)) can't tell us when they have something to say, so they're polled on an interval. The listener is the same for both, only the [`PolledDevice`]'s `read_message` and `write_message` are bus specific.
+//!
+
+use std::{
+  sync::{
+    Arc,
+    Mutex,
+  },
+  time::Duration,
+};
+
+use anyhow::anyhow;
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  error,
+  instrument,
+  warn,
+};
+use zenoh_ext::{
+  AdvancedPublisherBuilderExt,
+  CacheConfig,
+};
+
+use crate::server::modman::{
+  busses::{
+    auth::{
+      report_content_error,
+      verify_bus_message,
+      ContentError,
+    },
+    models::BusMessage,
+    tx::{
+      handle_send_query,
+      ModuleTX,
+    },
+  },
+  models::store::ModManStore,
+  MODULE_EVT_ID,
+};
+
+/// A bound device, and the blocking transfers of its bus's protocol.
+pub struct PolledDevice<D, R, W> {
+  /// e.g. `I2C`, for logs.
+  pub bus_name: &'static str,
+  pub device: D,
+  /// Read the message the module has queued, `None` if there isn't one.
+  pub read_message: R,
+  pub write_message: W,
+}
+
+/// Run a blocking transfer on the device without holding up the runtime.
+async fn transfer<D: Send + 'static, T: Send + 'static>(
+  device: &Arc<Mutex<D>>,
+  bus_name: &'static str,
+  transfer_fn: impl FnOnce(&mut D) -> Result<T, anyhow::Error> + Send + 'static,
+) -> Result<T, anyhow::Error> {
+  let device = device.clone();
+
+  tokio::task::spawn_blocking(move || match device.lock() {
+    Ok(mut device) => transfer_fn(&mut device),
+    Err(_) => Err(anyhow!("{bus_name} device lock was poisoned.")),
+  })
+  .await?
+}
+
+/// Writes to a polled device, see [`PolledDevice::write_message`].
+struct PolledTX<'a, D, W> {
+  bus_name: &'static str,
+  device: &'a Arc<Mutex<D>>,
+  write_message: &'a Arc<W>,
+}
+
+impl<D, W> ModuleTX for PolledTX<'_, D, W>
+where
+  D: Send + 'static,
+  W: Fn(&mut D, &[u8]) -> Result<(), anyhow::Error> + Send + Sync + 'static,
+{
+  async fn send(&mut self, msg_bytes: Vec<u8>) -> Result<(), anyhow::Error> {
+    let write = self.write_message.clone();
+    transfer(self.device, self.bus_name, move |device| {
+      write(device, &msg_bytes)
+    })
+    .await
+  }
+}
+
+/// Poll a module for messages, and send messages from `.../send` to it.
+///
+/// Both happen on this task, so transfers to the module never interleave. After `max_poll_failures` polls fail in a row the module is assumed to be gone, and the listener cancels its own token, so the bus can bind it again.
+#[instrument(skip(store, session, cancellation_token, polled_device))]
+pub async fn polled_module_listener<D, R, W>(
+  store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+  polled_device: PolledDevice<D, R, W>,
+  module_id: String,
+  poll_interval: Duration,
+  max_poll_failures: u32,
+) where
+  D: Send + 'static,
+  R: Fn(&mut D) -> Result<Option<Vec<u8>>, anyhow::Error> + Send + Sync + 'static,
+  W: Fn(&mut D, &[u8]) -> Result<(), anyhow::Error> + Send + Sync + 'static,
+{
+  let bus_name = polled_device.bus_name;
+  let device = Arc::new(Mutex::new(polled_device.device));
+  let read_message = Arc::new(polled_device.read_message);
+  let write_message = Arc::new(polled_device.write_message);
+
+  let recv_key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/recv");
+  let send_key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/send");
+
+  let publisher = match session
+    .declare_publisher(&recv_key_expr)
+    .cache(CacheConfig::default().max_samples(1))
+    .await
+  {
+    Ok(publisher) => publisher,
+    Err(err) => {
+      error!("Unable to create a zenoh broadcaster at: {recv_key_expr}, there's probably an error in your configuration. Due to:\n{err}");
+      return;
+    }
+  };
+
+  let queryable = match session.declare_queryable(&send_key_expr).await {
+    Ok(queryable) => queryable,
+    Err(err) => {
+      error!("Unable to create a zenoh queryable at: {send_key_expr}, there's probably an error in your configuration. Due to:\n{err}");
+      return;
+    }
+  };
+
+  let mut poll_failures = 0;
+  let mut poll = tokio::time::interval(poll_interval);
+  poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+  loop {
+    tokio::select! {
+      _ = cancellation_token.cancelled() => break,
+      _ = poll.tick() => {
+        let read = read_message.clone();
+        let polled = transfer(&device, bus_name, move |device| read(device)).await;
+        if polled.is_ok() {
+          poll_failures = 0;
+        }
+
+        match polled {
+          Ok(Some(message)) => match rmp_serde::from_slice::<BusMessage>(&message) {
+            Ok(decoded_message) => {
+              match verify_bus_message(&store, &module_id, &decoded_message).await {
+                Ok(_) => match serde_json::to_string(&decoded_message) {
+                  Ok(json_payload) => match publisher.put(json_payload).await {
+                    Ok(_) => {
+                      debug!("Successfully proxied {bus_name} message from module: {module_id}!");
+                    }
+                    Err(err) => {
+                      error!("Failed to publish message to Zenoh, at this stage, we've either lost connectivity, or there's a massive problem. (Might be a bug) Due to:\n{err}");
+                    }
+                  },
+                  Err(err) => {
+                    error!("Failed to produce a JSON payload from the decoded message, this is a bug and should be reported! Due to:\n{err}");
+                  }
+                },
+                Err(content_error) => {
+                  report_content_error(&session, &module_id, &content_error).await;
+                }
+              }
+            }
+            Err(err) => {
+              error!("Invalid message from module: {module_id}, this is a bug (or bad connection) and should (probably) be reported to the module maintainer! Happened due to:\n{err}");
+              report_content_error(
+                &session,
+                &module_id,
+                &ContentError::MalformedMessage {
+                  reason: err.to_string(),
+                },
+              )
+              .await;
+            }
+          },
+          Ok(None) => {}
+          Err(err) => {
+            poll_failures += 1;
+            error!("Failed to poll module: {module_id} ({poll_failures}/{max_poll_failures}), due to:\n{err}");
+
+            if poll_failures >= max_poll_failures {
+              warn!("Module: {module_id}, failed {poll_failures} polls in a row, releasing it...");
+              cancellation_token.cancel();
+              break;
+            }
+          }
+        }
+      },
+      query = queryable.recv_async() => {
+        let query = match query {
+          Ok(query) => query,
+          Err(_) => break,
+        };
+
+        handle_send_query(
+          &store,
+          &session,
+          &module_id,
+          &query,
+          &send_key_expr,
+          bus_name,
+          &mut PolledTX {
+            bus_name,
+            device: &device,
+            write_message: &write_message,
+          },
+        )
+        .await;
+      },
+    }
+  }
+
+  debug!("Shutting down {bus_name} listener for module: {module_id}.");
+}
@@ -0,0 +1,364 @@
+//! # SPI Bus Proxy
+//!
+//! Binds modules on the SPI busses configured in `busses`. Each module's `device_id` is mapped to a chip-select on its bus, so a module at device `imu` on bus `0`, mapped to chip-select `1`, is reached through `/dev/spidev0.1`. Modules are polled for messages with the [data-ready protocol](protocol).
+//!
+//! Ports are identified as `$BUS/$DEVICE`, e.g. `0/imu`.
+//!
+
+pub mod protocol;
+
+use std::{
+  collections::HashMap,
+  path::Path,
+  sync::Arc,
+  time::Duration,
+};
+
+use anyhow::anyhow;
+use serde::{
+  Deserialize,
+  Serialize,
+};
+use spidev::{
+  SpiModeFlags,
+  Spidev,
+  SpidevOptions,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  info,
+  instrument,
+  warn,
+};
+
+use crate::server::modman::{
+  busses::{
+    models::{
+      Bus,
+      BusTypes,
+    },
+    proxies::{
+      group::{
+        polled_listener::{
+          polled_module_listener,
+          PolledDevice,
+        },
+        spi::protocol::{
+          read_message,
+          write_message,
+        },
+      },
+      Backoff,
+    },
+  },
+  models::{
+    store::ModManStore,
+    PortStatus,
+  },
+};
+
+#[derive(Debug, Clone)]
+pub struct SPIBus {
+  pub store: Arc<ModManStore>,
+  pub cancellation_token: CancellationToken,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SPIConfig {
+  /// Busses that we should bind to, keyed by bus number (`B` in `/dev/spidevB.C`).
+  pub busses: HashMap<String, SPIBusConfig>,
+  /// Milliseconds between polls of each module.
+  #[serde(default = "default_poll_interval")]
+  pub poll_interval: u64,
+  /// Polls that can fail in a row before a module is released, and bound again with backoff.
+  #[serde(default = "default_max_poll_failures")]
+  pub max_poll_failures: u32,
+}
+
+fn default_poll_interval() -> u64 {
+  20
+}
+
+fn default_max_poll_failures() -> u32 {
+  10
+}
+
+impl Default for SPIConfig {
+  fn default() -> Self {
+    SPIConfig {
+      busses: Default::default(),
+      poll_interval: default_poll_interval(),
+      max_poll_failures: default_max_poll_failures(),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SPIBusConfig {
+  /// Devices on this bus, keyed by the `device_id` modules are registered with.
+  pub devices: HashMap<String, SPIDeviceConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SPIDeviceConfig {
+  /// Chip-select line the device is wired to (`C` in `/dev/spidevB.C`).
+  pub chip_select: u8,
+  /// SPI mode, `0` to `3`.
+  #[serde(default)]
+  pub mode: u8,
+  /// Clock speed in hertz.
+  #[serde(default = "default_max_speed_hz")]
+  pub max_speed_hz: u32,
+  #[serde(default = "default_bits_per_word")]
+  pub bits_per_word: u8,
+  #[serde(default)]
+  pub lsb_first: bool,
+}
+
+fn default_max_speed_hz() -> u32 {
+  1_000_000
+}
+
+fn default_bits_per_word() -> u8 {
+  8
+}
+
+impl SPIDeviceConfig {
+  pub fn device_path(&self, bus_id: &String) -> String {
+    format!("/dev/spidev{bus_id}.{}", self.chip_select)
+  }
+
+  pub fn spidev_options(&self) -> Result<SpidevOptions, anyhow::Error> {
+    let mode = match self.mode {
+      0 => SpiModeFlags::SPI_MODE_0,
+      1 => SpiModeFlags::SPI_MODE_1,
+      2 => SpiModeFlags::SPI_MODE_2,
+      3 => SpiModeFlags::SPI_MODE_3,
+      mode => {
+        return Err(anyhow!(
+          "SPI mode: {mode}, is not one of 0, 1, 2, or 3. Check your config!"
+        ));
+      }
+    };
+
+    Ok(
+      SpidevOptions::new()
+        .mode(mode)
+        .max_speed_hz(self.max_speed_hz)
+        .bits_per_word(self.bits_per_word)
+        .lsb_first(self.lsb_first)
+        .build(),
+    )
+  }
+}
+
+/// Split a port into its bus, and the device's ID.
+pub fn parse_spi_port(port: &String) -> Result<(String, String), anyhow::Error> {
+  match port.split_once('/') {
+    Some((bus_id, device_id)) => Ok((bus_id.to_string(), device_id.to_string())),
+    None => Err(anyhow!(
+      "SPI port: {port}, is not in the `$BUS/$DEVICE` format."
+    )),
+  }
+}
+
+/// Open the device's chip-select, and apply its mode and clock settings.
+fn open_spi_device(
+  device_config: &SPIDeviceConfig,
+  device_path: &String,
+) -> Result<Spidev, anyhow::Error> {
+  let options = device_config.spidev_options()?;
+  let mut device = Spidev::open(device_path)?;
+  device.configure(&options)?;
+
+  Ok(device)
+}
+
+impl Bus for SPIBus {
+  #[instrument(name = "spi_bus", skip(self, session))]
+  async fn subscribe_to_bus(
+    self,
+    session: Arc<zenoh::Session>,
+  ) -> Result<tokio::task::JoinHandle<()>, anyhow::Error> {
+    Ok(tokio::task::spawn(async move {
+      let mut listeners: HashMap<String, CancellationToken> = HashMap::new();
+      let mut backoffs: HashMap<String, Backoff> = HashMap::new();
+
+      while !self.cancellation_token.is_cancelled() {
+        let spi_config = self
+          .store
+          .config
+          .lock()
+          .await
+          .modman
+          .group_busses
+          .spi
+          .clone();
+        let port_statuses_snapshot = self.store.port_statuses.spi.lock().await.clone();
+
+        for (port, port_status) in port_statuses_snapshot {
+          let (bus_id, device_id) = match parse_spi_port(&port) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+              warn!("{err}");
+              continue;
+            }
+          };
+          let device_config = spi_config
+            .busses
+            .get(&bus_id)
+            .and_then(|bus_config| bus_config.devices.get(&device_id));
+          let device_present = device_config
+            .map(|device_config| Path::new(&device_config.device_path(&bus_id)).exists())
+            .unwrap_or(false);
+
+          let next_status = match port_status.clone() {
+            PortStatus::Requested(module_id) | PortStatus::Unavailable(module_id) => {
+              match device_config {
+                None => {
+                  warn!("Module: {module_id}, requested SPI port: {port}, but device: {device_id}, isn't mapped to a chip-select on bus: {bus_id}, in the config.");
+                  PortStatus::Unavailable(module_id)
+                }
+                Some(_)
+                  if !device_present
+                    || backoffs.get(&port).is_some_and(|backoff| !backoff.ready()) =>
+                {
+                  PortStatus::Unavailable(module_id)
+                }
+                Some(device_config) => {
+                  let device_path = device_config.device_path(&bus_id);
+
+                  match open_spi_device(device_config, &device_path) {
+                    Ok(device) => {
+                      info!("Bound SPI port: {port} ({device_path}), for module: {module_id}!");
+
+                      let listener_token = CancellationToken::new();
+                      listeners.insert(port.clone(), listener_token.clone());
+
+                      let listener_store = self.store.clone();
+                      let listener_session = session.clone();
+                      let listener_id = module_id.clone();
+                      let poll_interval = Duration::from_millis(spi_config.poll_interval);
+                      tokio::task::spawn(async move {
+                        polled_module_listener(
+                          listener_store,
+                          listener_session,
+                          listener_token,
+                          PolledDevice {
+                            bus_name: "SPI",
+                            device,
+                            read_message: read_message::<Spidev>,
+                            write_message: write_message::<Spidev>,
+                          },
+                          listener_id,
+                          poll_interval,
+                          spi_config.max_poll_failures,
+                        )
+                        .await;
+                      });
+
+                      PortStatus::Bound(module_id)
+                    }
+                    Err(err) => {
+                      warn!("Unable to bind SPI port: {port} ({device_path}), for module: {module_id}, due to:\n{err}");
+                      PortStatus::Unavailable(module_id)
+                    }
+                  }
+                }
+              }
+            }
+            PortStatus::Bound(module_id) => {
+              // The listener cancels itself once the module stops answering polls.
+              let listener_stopped = listeners
+                .get(&port)
+                .is_some_and(|listener_token| listener_token.is_cancelled());
+              if device_present && !listener_stopped {
+                continue;
+              }
+
+              if let Some(listener_token) = listeners.remove(&port) {
+                listener_token.cancel();
+              }
+              if listener_stopped {
+                let backoff = backoffs.entry(port.clone()).or_default();
+                backoff.failed();
+                warn!("SPI module: {module_id}, on port: {port}, stopped answering polls, will bind it again (attempt {})...", backoff.attempts);
+              } else {
+                warn!("SPI device for port: {port}, disappeared, will bind it for module: {module_id}, again once it's available...");
+              }
+              PortStatus::Unavailable(module_id)
+            }
+            PortStatus::Unrequested(module_id) => {
+              if let Some(listener_token) = listeners.remove(&port) {
+                info!("Releasing SPI port: {port}, from module: {module_id}...");
+                listener_token.cancel();
+              }
+              backoffs.remove(&port);
+              PortStatus::Available
+            }
+            PortStatus::Available => continue,
+          };
+
+          let mut port_statuses = self.store.port_statuses.spi.lock().await;
+          // Don't clobber a module being (de)initialized while we were binding.
+          if port_statuses.get(&port) == Some(&port_status) {
+            port_statuses.insert(port, next_status);
+          }
+        }
+
+        tokio::select! {
+          _ = self.cancellation_token.cancelled() => {},
+          _ = tokio::time::sleep(Duration::from_millis(500)) => {},
+        }
+      }
+
+      for (port, listener_token) in listeners.drain() {
+        debug!("Shutting down SPI listener for port: {port}...");
+        listener_token.cancel();
+      }
+    }))
+  }
+
+  fn get_type() -> BusTypes {
+    BusTypes::SPI
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn device_config(mode: u8) -> SPIDeviceConfig {
+    SPIDeviceConfig {
+      chip_select: 0,
+      mode,
+      max_speed_hz: default_max_speed_hz(),
+      bits_per_word: default_bits_per_word(),
+      lsb_first: false,
+    }
+  }
+
+  #[test]
+  fn modes_map_to_spidev_flags() {
+    for (mode, flags) in [
+      (0, SpiModeFlags::SPI_MODE_0),
+      (1, SpiModeFlags::SPI_MODE_1),
+      (2, SpiModeFlags::SPI_MODE_2),
+      (3, SpiModeFlags::SPI_MODE_3),
+    ] {
+      let options = device_config(mode).spidev_options().unwrap();
+
+      assert_eq!(options.spi_mode, Some(flags));
+      assert_eq!(options.max_speed_hz, Some(1_000_000));
+      assert_eq!(options.bits_per_word, Some(8));
+      assert_eq!(options.lsb_first, Some(false));
+    }
+  }
+
+  #[test]
+  fn invalid_modes_are_refused() {
+    assert!(device_config(4).spidev_options().is_err());
+    assert!(device_config(u8::MAX).spidev_options().is_err());
+  }
+}
@@ -0,0 +1,219 @@
+//! # SPI Data-Ready Protocol
+//!
+//! SPI is driven entirely by ModMan, so modules can't tell us when they have something to say. Instead, ModMan polls each module with a status transfer, and only clocks a message out once the module reports that one is ready.
+//!
+//! Every transfer starts with a command byte, the module answers in the bytes clocked after it:
+//!
+//! | Command | ModMan sends | Module answers |
+//! | --- | --- | --- |
+//! | `0x01` Status | `[0x01, 0x00, 0x00, 0x00]` | `[_, STATUS, LEN_HI, LEN_LO]`, `STATUS` is `0x01` when a message of `LEN` bytes is ready. |
+//! | `0x02` Read | `[0x02]`, followed by `LEN` zero bytes | `[_, ..message]`, the module dequeues the message once it's clocked out. |
+//! | `0x03` Write | `[0x03, LEN_HI, LEN_LO, ..message]` | Nothing. |
+//!
+//! [`BusMessage`]s are MessagePack encoded, and lengths are big-endian. A module that isn't responding leaves MISO floating (usually reading `0xFF`), which is never a valid status.
+//!
+
+use std::io;
+
+use spidev::{
+  Spidev,
+  SpidevTransfer,
+};
+
+pub const CMD_STATUS: u8 = 0x01;
+pub const CMD_READ: u8 = 0x02;
+pub const CMD_WRITE: u8 = 0x03;
+pub const STATUS_DATA_READY: u8 = 0x01;
+/// Default buffer size of the `spidev` driver, no single transfer can be bigger than this.
+pub const MAX_TRANSFER_LEN: usize = 4096;
+/// Largest message either side will send, leaves room for the write header.
+pub const MAX_MESSAGE_LEN: usize = MAX_TRANSFER_LEN - 3;
+
+/// A device that can clock bytes in and out, with its chip-select held for the whole transfer.
+pub trait SPITransfer {
+  /// Clock `tx_buf` out, and what the module answers into `rx_buf` (which is as long as `tx_buf`), if there is one.
+  fn exchange(&mut self, tx_buf: &[u8], rx_buf: Option<&mut [u8]>) -> io::Result<()>;
+}
+
+impl SPITransfer for Spidev {
+  fn exchange(&mut self, tx_buf: &[u8], rx_buf: Option<&mut [u8]>) -> io::Result<()> {
+    match rx_buf {
+      Some(rx_buf) => self.transfer(&mut SpidevTransfer::read_write(tx_buf, rx_buf)),
+      None => self.transfer(&mut SpidevTransfer::write(tx_buf)),
+    }
+  }
+}
+
+/// Ask the module if it has a message ready, returning its length if it does.
+pub fn poll_status<D: SPITransfer>(device: &mut D) -> Result<Option<usize>, anyhow::Error> {
+  let tx_buf = [CMD_STATUS, 0x00, 0x00, 0x00];
+  let mut rx_buf = [0u8; 4];
+  device.exchange(&tx_buf, Some(&mut rx_buf))?;
+
+  if rx_buf[1] != STATUS_DATA_READY {
+    return Ok(None);
+  }
+
+  let len = u16::from_be_bytes([rx_buf[2], rx_buf[3]]) as usize;
+  if len == 0 {
+    return Ok(None);
+  }
+  if len > MAX_MESSAGE_LEN {
+    return Err(anyhow::anyhow!(
+      "Module has a message of {len} bytes ready, more than the max of {MAX_MESSAGE_LEN} bytes."
+    ));
+  }
+
+  Ok(Some(len))
+}
+
+/// Read the module's pending message, if there is one.
+pub fn read_message<D: SPITransfer>(device: &mut D) -> Result<Option<Vec<u8>>, anyhow::Error> {
+  let len = match poll_status(device)? {
+    Some(len) => len,
+    None => return Ok(None),
+  };
+
+  let mut tx_buf = vec![0u8; len + 1];
+  tx_buf[0] = CMD_READ;
+  let mut rx_buf = vec![0u8; len + 1];
+  device.exchange(&tx_buf, Some(&mut rx_buf))?;
+
+  Ok(Some(rx_buf.split_off(1)))
+}
+
+/// Write a message to the module in a single transfer.
+pub fn write_message<D: SPITransfer>(device: &mut D, message: &[u8]) -> Result<(), anyhow::Error> {
+  if message.len() > MAX_MESSAGE_LEN {
+    return Err(anyhow::anyhow!(
+      "Message of {} bytes, is more than the max of {MAX_MESSAGE_LEN} bytes.",
+      message.len()
+    ));
+  }
+
+  let mut tx_buf = Vec::with_capacity(message.len() + 3);
+  tx_buf.push(CMD_WRITE);
+  tx_buf.extend_from_slice(&(message.len() as u16).to_be_bytes());
+  tx_buf.extend_from_slice(message);
+  device.exchange(&tx_buf, None)?;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::VecDeque;
+
+  use super::*;
+
+  /// Answers each transfer with the next queued reply, and records what was clocked out.
+  #[derive(Default)]
+  struct MockDevice {
+    replies: VecDeque<Vec<u8>>,
+    sent: Vec<Vec<u8>>,
+  }
+
+  impl SPITransfer for MockDevice {
+    fn exchange(&mut self, tx_buf: &[u8], rx_buf: Option<&mut [u8]>) -> io::Result<()> {
+      self.sent.push(tx_buf.to_vec());
+
+      if let Some(rx_buf) = rx_buf {
+        // Nothing queued reads like a floating MISO line.
+        let mut reply = self
+          .replies
+          .pop_front()
+          .unwrap_or_else(|| vec![0xFF; rx_buf.len()]);
+        reply.resize(rx_buf.len(), 0xFF);
+        rx_buf.copy_from_slice(&reply);
+      }
+
+      Ok(())
+    }
+  }
+
+  impl MockDevice {
+    fn with_replies(replies: Vec<Vec<u8>>) -> Self {
+      MockDevice {
+        replies: replies.into(),
+        ..Default::default()
+      }
+    }
+  }
+
+  #[test]
+  fn status_reports_ready_length() {
+    let mut device = MockDevice::with_replies(vec![vec![0x00, STATUS_DATA_READY, 0x01, 0x02]]);
+
+    assert_eq!(poll_status(&mut device).unwrap(), Some(0x0102));
+    assert_eq!(device.sent, vec![vec![CMD_STATUS, 0x00, 0x00, 0x00]]);
+  }
+
+  #[test]
+  fn status_without_message_is_none() {
+    // Not ready, ready with nothing, and a module that isn't responding.
+    let mut device = MockDevice::with_replies(vec![
+      vec![0x00, 0x00, 0x00, 0x10],
+      vec![0x00, STATUS_DATA_READY, 0x00, 0x00],
+    ]);
+
+    assert_eq!(poll_status(&mut device).unwrap(), None);
+    assert_eq!(poll_status(&mut device).unwrap(), None);
+    assert_eq!(poll_status(&mut device).unwrap(), None);
+  }
+
+  #[test]
+  fn oversized_status_is_refused() {
+    let len = (MAX_MESSAGE_LEN as u16 + 1).to_be_bytes();
+    let mut device = MockDevice::with_replies(vec![vec![0x00, STATUS_DATA_READY, len[0], len[1]]]);
+
+    assert!(poll_status(&mut device).is_err());
+  }
+
+  #[test]
+  fn messages_are_read_after_their_status() {
+    let mut device = MockDevice::with_replies(vec![
+      vec![0x00, STATUS_DATA_READY, 0x00, 0x03],
+      vec![0x00, 0xAA, 0xBB, 0xCC],
+    ]);
+
+    assert_eq!(
+      read_message(&mut device).unwrap(),
+      Some(vec![0xAA, 0xBB, 0xCC])
+    );
+    assert_eq!(
+      device.sent,
+      vec![
+        vec![CMD_STATUS, 0x00, 0x00, 0x00],
+        vec![CMD_READ, 0x00, 0x00, 0x00],
+      ]
+    );
+  }
+
+  #[test]
+  fn nothing_is_read_when_not_ready() {
+    let mut device = MockDevice::with_replies(vec![vec![0x00, 0x00, 0x00, 0x00]]);
+
+    assert_eq!(read_message(&mut device).unwrap(), None);
+    assert_eq!(device.sent.len(), 1);
+  }
+
+  #[test]
+  fn messages_are_written_with_their_length() {
+    let mut device = MockDevice::default();
+    let message = vec![0x42; 0x0105];
+
+    write_message(&mut device, &message).unwrap();
+
+    let mut expected = vec![CMD_WRITE, 0x01, 0x05];
+    expected.extend_from_slice(&message);
+    assert_eq!(device.sent, vec![expected]);
+  }
+
+  #[test]
+  fn oversized_messages_are_not_written() {
+    let mut device = MockDevice::default();
+
+    assert!(write_message(&mut device, &[0; MAX_MESSAGE_LEN + 1]).is_err());
+    assert!(device.sent.is_empty());
+  }
+}
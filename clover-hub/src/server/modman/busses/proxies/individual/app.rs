@@ -1,30 +1,490 @@
-//! # UART Bus Proxy
+//! # App Bus Proxy
 //!
-//! <div class="warning">
+//! Lets AppD applications drive modules, e.g. modules that are reached through a network protocol, or that are simulated entirely in software.
 //!
-//! To meet a deadline of 2027-01-01, UART support is currently left as broken.
+//! Applications claim module types in their [manifest entry](crate::server::warehouse::repos::models::ApplicationSpec) with `modules`. A module with an [App connection](crate::server::modman::connections::ModuleConnection::App) is only bound if:
 //!
-//! </div>
+//! - the app is declared in a manifest loaded by Warehouse,
+//! - the app is the module's `registered_by` owner,
+//! - and the app claims the module's type.
+//!
+//! Once bound, the module's `/send` and `/recv` are routed through the app's namespace, at `{APPD_EVT_ID}/apps/{app_id}/modules/{module_id}/`:
+//!
+//! - `Q:send`: declared by the app, queries on the module's `/send` are forwarded to it, and its reply is relayed back.
+//! - `B:recv`: published to by the app, messages are republished on the module's `/recv`.
+//!
+//! The app is trusted as the module's firmware, so content isn't signed or verified on this bus.
 //!
 
-use std::sync::Arc;
+use std::{
+  collections::HashMap,
+  sync::Arc,
+  time::Duration,
+};
 
-use crate::server::modman::busses::models::{
-  Bus,
-  BusTypes,
+use serde::{
+  Deserialize,
+  Serialize,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  error,
+  info,
+  instrument,
+  warn,
 };
+use zenoh_ext::{
+  AdvancedPublisherBuilderExt,
+  CacheConfig,
+};
+
+use crate::server::{
+  appd::MODULE_EVT_ID as APPD_EVT_ID,
+  modman::{
+    busses::{
+      auth::{
+        report_content_error,
+        ContentError,
+      },
+      models::{
+        Bus,
+        BusMessage,
+        BusTXError,
+        BusTypes,
+      },
+    },
+    connections::ModuleConnection,
+    models::{
+      modules::Module,
+      store::ModManStore,
+      PortStatus,
+    },
+    MODULE_EVT_ID,
+  },
+  warehouse::repos::models::{
+    ApplicationSpec,
+    Optional,
+    OptionalStrTHashMap,
+    OptionalStringList,
+  },
+};
+
+/// How long the app has to reply to a forwarded `/send` query.
+const APP_SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct AppBus {
+  pub store: Arc<ModManStore>,
+  pub cancellation_token: CancellationToken,
+}
+
+/// Why an app can't claim a module.
+#[derive(Serialize, Deserialize, Clone, Debug, strum_macros::Display)]
+#[serde(tag = "error")]
+pub enum AppClaimError {
+  /// No manifest loaded by Warehouse declares the app.
+  #[serde(rename = "unknown-app")]
+  #[strum(to_string = "unknown-app: {app_id}")]
+  UnknownApp { app_id: String },
+  /// The module was registered by something other than the app.
+  #[serde(rename = "not-owner")]
+  #[strum(to_string = "not-owner: {app_id}, module is registered by: {registered_by}")]
+  NotOwner {
+    app_id: String,
+    registered_by: String,
+  },
+  /// The app's manifest entry doesn't list the module's type in `modules`.
+  #[serde(rename = "unclaimed-module-type")]
+  #[strum(to_string = "unclaimed-module-type: {module_type}, by app: {app_id}")]
+  UnclaimedModuleType { app_id: String, module_type: String },
+}
+
+/// Key expression prefix for a module's routes in its app's namespace.
+pub fn app_module_key_expr(app_id: &String, module_id: &String) -> String {
+  format!("{APPD_EVT_ID}/apps/{app_id}/modules/{module_id}")
+}
+
+/// Find the manifest entry for an app in any repo loaded by Warehouse.
+pub async fn find_app_spec(store: &ModManStore, app_id: &str) -> Option<ApplicationSpec> {
+  store
+    .repos
+    .lock()
+    .await
+    .values()
+    .find_map(|manifest| match &manifest.directory {
+      Optional::Some(directory) => match &directory.applications {
+        OptionalStrTHashMap::Some(applications) => applications.get(app_id).cloned(),
+        OptionalStrTHashMap::None => None,
+      },
+      _ => None,
+    })
+}
+
+/// Check that an app may drive a module, see the [module docs](self).
+pub async fn check_app_claim(
+  store: &ModManStore,
+  module: &Module,
+  app_id: &String,
+) -> Result<(), AppClaimError> {
+  let app_spec = match find_app_spec(store, app_id).await {
+    Some(app_spec) => app_spec,
+    None => {
+      return Err(AppClaimError::UnknownApp {
+        app_id: app_id.clone(),
+      })
+    }
+  };
+
+  if &module.registered_by != app_id {
+    return Err(AppClaimError::NotOwner {
+      app_id: app_id.clone(),
+      registered_by: module.registered_by.clone(),
+    });
+  }
+
+  let claimed = match &app_spec.modules {
+    OptionalStringList::Some(module_types) => module_types.contains(&module.module_type),
+    OptionalStringList::None => false,
+  };
+  if !claimed {
+    return Err(AppClaimError::UnclaimedModuleType {
+      app_id: app_id.clone(),
+      module_type: module.module_type.clone(),
+    });
+  }
+
+  Ok(())
+}
+
+/// Route a module's `/send` and `/recv` through its app's namespace.
+#[instrument(skip(session, cancellation_token))]
+pub async fn app_module_router(
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+  app_id: String,
+  module_id: String,
+) {
+  let recv_key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/recv");
+  let send_key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/send");
+  let app_key_expr = app_module_key_expr(&app_id, &module_id);
+  let app_recv_key_expr = format!("{app_key_expr}/recv");
+  let app_send_key_expr = format!("{app_key_expr}/send");
+
+  let publisher = match session
+    .declare_publisher(&recv_key_expr)
+    .cache(CacheConfig::default().max_samples(1))
+    .await
+  {
+    Ok(publisher) => publisher,
+    Err(err) => {
+      error!("Unable to create a zenoh broadcaster at: {recv_key_expr}, there's probably an error in your configuration. Due to:\n{err}");
+      return;
+    }
+  };
+
+  let queryable = match session.declare_queryable(&send_key_expr).await {
+    Ok(queryable) => queryable,
+    Err(err) => {
+      error!("Unable to create a zenoh queryable at: {send_key_expr}, there's probably an error in your configuration. Due to:\n{err}");
+      return;
+    }
+  };
+
+  let subscriber = match session.declare_subscriber(&app_recv_key_expr).await {
+    Ok(subscriber) => subscriber,
+    Err(err) => {
+      error!("Unable to create a zenoh subscriber at: {app_recv_key_expr}, there's probably an error in your configuration. Due to:\n{err}");
+      return;
+    }
+  };
+
+  loop {
+    tokio::select! {
+      _ = cancellation_token.cancelled() => break,
+      sample = subscriber.recv_async() => {
+        let sample = match sample {
+          Ok(sample) => sample,
+          Err(_) => break,
+        };
+
+        let message = sample
+          .payload()
+          .try_to_string()
+          .map_err(|err| err.to_string())
+          .and_then(|payload_str| {
+            serde_json_lenient::from_str::<BusMessage>(&payload_str).map_err(|err| err.to_string())
+          });
+
+        match message {
+          Ok(message) => match serde_json::to_string(&message) {
+            Ok(json_payload) => match publisher.put(json_payload).await {
+              Ok(_) => {
+                debug!("Successfully proxied message from app: {app_id}, for module: {module_id}!");
+              }
+              Err(err) => {
+                error!("Failed to publish message to Zenoh, at this stage, we've either lost connectivity, or there's a massive problem. (Might be a bug) Due to:\n{err}");
+              }
+            },
+            Err(err) => {
+              error!("Failed to produce a JSON payload from the message, this is a bug and should be reported! Due to:\n{err}");
+            }
+          },
+          Err(reason) => {
+            report_content_error(&session, &module_id, &ContentError::MalformedMessage { reason }).await;
+          }
+        }
+      },
+      query = queryable.recv_async() => {
+        let query = match query {
+          Ok(query) => query,
+          Err(_) => break,
+        };
+
+        let payload_str = match query.payload() {
+          Some(query_payload) => match query_payload.try_to_string() {
+            Ok(payload_str) => payload_str.to_string(),
+            Err(err) => {
+              error!("Query's payload could not be decoded into a string, due to:\n{err}");
+              BusTXError::PayloadIsNotString.reply(&query, &send_key_expr).await;
+              continue;
+            }
+          },
+          None => {
+            error!("Query was sent to the module endpoint without a payload!");
+            BusTXError::MissingPayload.reply(&query, &send_key_expr).await;
+            continue;
+          }
+        };
+
+        if let Err(err) = serde_json_lenient::from_str::<BusMessage>(&payload_str) {
+          error!("Invalid query payload, due to:\n{err}");
+          BusTXError::MalformedPayload.reply(&query, &send_key_expr).await;
+          continue;
+        }
 
-pub struct AppBus {}
+        let replies = match session
+          .get(&app_send_key_expr)
+          .payload(payload_str)
+          .timeout(APP_SEND_TIMEOUT)
+          .await
+        {
+          Ok(replies) => replies,
+          Err(err) => {
+            error!("Failed to forward query to: {app_send_key_expr}, due to:\n{err}");
+            BusTXError::AppUnreachable.reply(&query, &send_key_expr).await;
+            continue;
+          }
+        };
+
+        match replies.recv_async().await {
+          Ok(reply) => match reply.result() {
+            Ok(sample) => {
+              if let Err(err) = query.reply(&send_key_expr, sample.payload().clone()).await {
+                error!("Failed to relay reply from app: {app_id}, due to:\n{err}");
+              }
+            }
+            Err(reply_err) => {
+              warn!(
+                "App: {app_id}, replied with an error for module: {module_id}: {}",
+                reply_err.payload().try_to_string().unwrap_or_default()
+              );
+              BusTXError::AppError.reply(&query, &send_key_expr).await;
+            }
+          },
+          Err(_) => {
+            warn!("App: {app_id}, did not reply for module: {module_id}, is it running?");
+            BusTXError::AppUnreachable.reply(&query, &send_key_expr).await;
+          }
+        }
+      },
+    }
+  }
+
+  debug!("Shutting down app route for module: {module_id}.");
+}
 
 impl Bus for AppBus {
+  #[instrument(name = "app_bus", skip(self, session))]
   async fn subscribe_to_bus(
-    mut self,
+    self,
     session: Arc<zenoh::Session>,
   ) -> Result<tokio::task::JoinHandle<()>, anyhow::Error> {
-    todo!()
+    Ok(tokio::task::spawn(async move {
+      let mut routes: HashMap<String, CancellationToken> = HashMap::new();
+
+      while !self.cancellation_token.is_cancelled() {
+        let port_statuses_snapshot = self.store.port_statuses.app.lock().await.clone();
+
+        for (module_id, port_status) in port_statuses_snapshot {
+          let next_status = match port_status.clone() {
+            PortStatus::Requested(_) | PortStatus::Unavailable(_) => {
+              let app_id = match self.store.modules.lock().await.get(&module_id) {
+                Some(Module {
+                  connection: ModuleConnection::App(app_id),
+                  ..
+                }) => app_id.clone(),
+                _ => {
+                  warn!("Module: {module_id}, requested an app route, but doesn't have an app connection.");
+                  continue;
+                }
+              };
+
+              info!("Routing module: {module_id}, through app: {app_id}!");
+
+              let route_token = CancellationToken::new();
+              routes.insert(module_id.clone(), route_token.clone());
+
+              let route_session = session.clone();
+              let route_id = module_id.clone();
+              tokio::task::spawn(async move {
+                app_module_router(route_session, route_token, app_id, route_id).await;
+              });
+
+              PortStatus::Bound(module_id.clone())
+            }
+            PortStatus::Bound(_) => continue,
+            PortStatus::Unrequested(_) => {
+              if let Some(route_token) = routes.remove(&module_id) {
+                info!("Removing app route for module: {module_id}...");
+                route_token.cancel();
+              }
+              PortStatus::Available
+            }
+            PortStatus::Available => continue,
+          };
+
+          let mut port_statuses = self.store.port_statuses.app.lock().await;
+          // Don't clobber a module being (de)initialized while we were routing.
+          if port_statuses.get(&module_id) == Some(&port_status) {
+            port_statuses.insert(module_id, next_status);
+          }
+        }
+
+        tokio::select! {
+          _ = self.cancellation_token.cancelled() => {},
+          _ = tokio::time::sleep(Duration::from_millis(500)) => {},
+        }
+      }
+
+      for (module_id, route_token) in routes.drain() {
+        debug!("Shutting down app route for module: {module_id}...");
+        route_token.cancel();
+      }
+    }))
   }
 
   fn get_type() -> BusTypes {
     BusTypes::App
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::server::{
+    modman::models::modules::SecurityLevel,
+    warehouse::repos::models::Manifest,
+  };
+
+  const APP_ID: &str = "com.example.app";
+
+  async fn store_with_app() -> ModManStore {
+    let store = ModManStore::new(None, None);
+    let manifest: Manifest = serde_json::from_value(serde_json::json!({
+      "version": "1.0.0",
+      "directory": {
+        "applications": {
+          APP_ID: {
+            "name": "Example App",
+            "version": "1.0.0",
+            "modules": ["claimed-module"],
+          },
+        },
+      },
+    }))
+    .unwrap();
+
+    store
+      .repos
+      .lock()
+      .await
+      .insert("com.example".to_string(), manifest);
+    store
+  }
+
+  fn module(registered_by: &str, module_type: &str) -> Module {
+    Module {
+      module_type: module_type.to_string(),
+      module_name: module_type.to_string(),
+      custom_name: None,
+      initialized: false,
+      components: vec![],
+      registered_by: registered_by.to_string(),
+      connection: ModuleConnection::App(registered_by.to_string()),
+      firmware_version: None,
+      serial: None,
+      security_level: SecurityLevel::L1,
+      key: None,
+      exchange_secret: None,
+      public_key: None,
+      insecure_movement: false,
+      session: 0,
+      last_counter: 0,
+    }
+  }
+
+  #[tokio::test]
+  async fn unknown_app_is_refused() {
+    let store = store_with_app().await;
+    let app_id = "com.example.missing".to_string();
+
+    assert!(matches!(
+      check_app_claim(&store, &module(&app_id, "claimed-module"), &app_id).await,
+      Err(AppClaimError::UnknownApp { .. })
+    ));
+  }
+
+  #[tokio::test]
+  async fn other_apps_modules_are_refused() {
+    let store = store_with_app().await;
+
+    assert!(matches!(
+      check_app_claim(
+        &store,
+        &module("com.example.other", "claimed-module"),
+        &APP_ID.to_string()
+      )
+      .await,
+      Err(AppClaimError::NotOwner { registered_by, .. }) if registered_by == "com.example.other"
+    ));
+  }
+
+  #[tokio::test]
+  async fn unclaimed_module_types_are_refused() {
+    let store = store_with_app().await;
+
+    assert!(matches!(
+      check_app_claim(
+        &store,
+        &module(APP_ID, "other-module"),
+        &APP_ID.to_string()
+      )
+      .await,
+      Err(AppClaimError::UnclaimedModuleType { module_type, .. }) if module_type == "other-module"
+    ));
+  }
+
+  #[tokio::test]
+  async fn claimed_module_is_accepted() {
+    let store = store_with_app().await;
+
+    assert!(check_app_claim(
+      &store,
+      &module(APP_ID, "claimed-module"),
+      &APP_ID.to_string()
+    )
+    .await
+    .is_ok());
+  }
+}
@@ -0,0 +1,113 @@
+//! # BLE Adoption
+//!
+//! Pairing is part of adopting a module: ModMan pairs with a new module before the adoption handshake, so the hello and key provision are sent over an encrypted link. The bond is kept (and the device trusted) only once the module is adopted, refused modules are unpaired again.
+//!
+//! New modules have to be [approved](crate::server::modman::modules::adoption) before they're adopted, the module stays connected while it waits, and is refused if it isn't approved within `approval_timeout`.
+//!
+
+use std::{
+  sync::Arc,
+  time::Duration,
+};
+
+use bluer::{
+  Adapter,
+  Address,
+  Device,
+};
+use tracing::{
+  debug,
+  info,
+  instrument,
+  warn,
+};
+
+use crate::server::modman::{
+  busses::{
+    adoption::{
+      adopt_over_link,
+      AdoptionLink,
+    },
+    models::BusMessage,
+    proxies::individual::bt_le::gatt::{
+      decode_message,
+      GattLink,
+    },
+  },
+  connections::ModuleConnection,
+};
+
+/// Pair with, and connect to a device, if it isn't already.
+pub async fn pair_and_connect(device: &Device) -> Result<(), anyhow::Error> {
+  if !device.is_paired().await? {
+    debug!("Pairing with: {}...", device.address());
+    device.pair().await?;
+  }
+
+  if !device.is_connected().await? {
+    debug!("Connecting to: {}...", device.address());
+    device.connect().await?;
+  }
+
+  Ok(())
+}
+
+impl AdoptionLink for GattLink {
+  async fn send(&mut self, message: &BusMessage) -> Result<(), anyhow::Error> {
+    GattLink::send(self, message).await
+  }
+
+  async fn recv(&mut self) -> Option<Result<BusMessage, anyhow::Error>> {
+    GattLink::recv(self)
+      .await
+      .map(|message| message.and_then(|message| Ok(decode_message(&message)?)))
+  }
+}
+
+async fn adopt(
+  session: &zenoh::Session,
+  device: &Device,
+  hello_timeout: Duration,
+  approval_timeout: Duration,
+) -> Result<String, anyhow::Error> {
+  pair_and_connect(device).await?;
+
+  let mut link = GattLink::open(device).await?;
+  let module_id = adopt_over_link(
+    session,
+    &mut link,
+    ModuleConnection::BTLE(device.address().to_string()),
+    hello_timeout,
+    approval_timeout,
+  )
+  .await?;
+  device.set_trusted(true).await?;
+
+  Ok(module_id)
+}
+
+/// Pair with a module advertising the Clover service, and adopt it once it's approved. Returns the module's ID.
+#[instrument(skip(session, adapter))]
+pub async fn adopt_bt_le_module(
+  session: Arc<zenoh::Session>,
+  adapter: Adapter,
+  address: Address,
+  hello_timeout: Duration,
+  approval_timeout: Duration,
+) -> Result<String, anyhow::Error> {
+  let device = adapter.device(address)?;
+
+  match adopt(&session, &device, hello_timeout, approval_timeout).await {
+    Ok(module_id) => {
+      info!("Paired with, and adopted BLE module: {module_id} ({address})!");
+      Ok(module_id)
+    }
+    Err(err) => {
+      // Only adopted modules stay paired.
+      if let Err(remove_err) = adapter.remove_device(address).await {
+        warn!("Unable to unpair: {address}, after it wasn't adopted, due to:\n{remove_err}");
+      }
+      Err(err)
+    }
+  }
+}
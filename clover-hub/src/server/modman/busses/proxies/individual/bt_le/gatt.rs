@@ -0,0 +1,217 @@
+//! # Clover GATT Service
+//!
+//! Modules advertise the Clover service, which has two characteristics:
+//!
+//! - RX (`...0002`): the module notifies ModMan with chunks of messages.
+//! - TX (`...0003`): ModMan writes chunks of messages to the module.
+//!
+//! [`BusMessage`]s are MessagePack encoded, then split into chunks that fit in an attribute. Each chunk starts with a flags byte, [`CHUNK_FINAL`] is set on the last chunk of a message. Modules should size their chunks to the negotiated MTU, ModMan writes chunks up to [`MAX_ATTRIBUTE_LEN`] and leaves it to BlueZ to use long writes where needed.
+//!
+
+use bluer::{
+  gatt::remote::Characteristic,
+  Device,
+  Uuid,
+};
+use tokio::sync::mpsc::{
+  channel,
+  Receiver,
+};
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  error,
+};
+
+use crate::server::modman::busses::models::BusMessage;
+
+pub const CLOVER_SERVICE_UUID: Uuid = Uuid::from_u128(0x9f3c0001_6c6f_7665_722d_687562000000);
+/// Module to ModMan, notify.
+pub const CLOVER_RX_CHARACTERISTIC_UUID: Uuid =
+  Uuid::from_u128(0x9f3c0002_6c6f_7665_722d_687562000000);
+/// ModMan to module, write.
+pub const CLOVER_TX_CHARACTERISTIC_UUID: Uuid =
+  Uuid::from_u128(0x9f3c0003_6c6f_7665_722d_687562000000);
+pub const CHUNK_FINAL: u8 = 0x01;
+/// Largest value of a GATT attribute.
+pub const MAX_ATTRIBUTE_LEN: usize = 512;
+/// Largest message either side will send.
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Split a message into chunks of at most `chunk_len` bytes, flags included.
+pub fn chunk_message(message: &[u8], chunk_len: usize) -> Vec<Vec<u8>> {
+  let payload_len = chunk_len.saturating_sub(1).max(1);
+  let chunk_count = message.len().div_ceil(payload_len).max(1);
+
+  (0..chunk_count)
+    .map(|index| {
+      let payload = &message
+        [(index * payload_len).min(message.len())..((index + 1) * payload_len).min(message.len())];
+      let flags = if index + 1 == chunk_count {
+        CHUNK_FINAL
+      } else {
+        0x00
+      };
+
+      let mut chunk = Vec::with_capacity(payload.len() + 1);
+      chunk.push(flags);
+      chunk.extend_from_slice(payload);
+      chunk
+    })
+    .collect()
+}
+
+/// Reassembles messages from chunks.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkBuffer {
+  buffer: Vec<u8>,
+}
+
+impl ChunkBuffer {
+  /// Add a chunk, returning the message once its last chunk arrives.
+  pub fn push(&mut self, chunk: &[u8]) -> Option<Result<Vec<u8>, anyhow::Error>> {
+    let (flags, payload) = match chunk.split_first() {
+      Some(split) => split,
+      None => return Some(Err(anyhow::anyhow!("Received an empty chunk."))),
+    };
+
+    if self.buffer.len() + payload.len() > MAX_MESSAGE_LEN {
+      self.buffer.clear();
+      return Some(Err(anyhow::anyhow!(
+        "Message is more than the max of {MAX_MESSAGE_LEN} bytes, dropped it."
+      )));
+    }
+    self.buffer.extend_from_slice(payload);
+
+    if flags & CHUNK_FINAL == CHUNK_FINAL {
+      Some(Ok(std::mem::take(&mut self.buffer)))
+    } else {
+      None
+    }
+  }
+}
+
+pub fn decode_message(message: &[u8]) -> Result<BusMessage, rmp_serde::decode::Error> {
+  rmp_serde::from_slice::<BusMessage>(message)
+}
+
+/// ModMan's end of a module's Clover service.
+pub struct GattLink {
+  tx_characteristic: Characteristic,
+  notifications: Receiver<Vec<u8>>,
+  notify_token: CancellationToken,
+  buffer: ChunkBuffer,
+}
+
+impl GattLink {
+  /// Find the Clover service on a connected device, and subscribe to its notifications.
+  pub async fn open(device: &Device) -> Result<Self, anyhow::Error> {
+    let mut rx_characteristic = None;
+    let mut tx_characteristic = None;
+
+    for service in device.services().await? {
+      if service.uuid().await? != CLOVER_SERVICE_UUID {
+        continue;
+      }
+
+      for characteristic in service.characteristics().await? {
+        match characteristic.uuid().await? {
+          uuid if uuid == CLOVER_RX_CHARACTERISTIC_UUID => {
+            rx_characteristic = Some(characteristic);
+          }
+          uuid if uuid == CLOVER_TX_CHARACTERISTIC_UUID => {
+            tx_characteristic = Some(characteristic);
+          }
+          _ => {}
+        }
+      }
+    }
+
+    let (rx_characteristic, tx_characteristic) = match (rx_characteristic, tx_characteristic) {
+      (Some(rx), Some(tx)) => (rx, tx),
+      _ => {
+        return Err(anyhow::anyhow!(
+          "Device: {}, does not have the Clover GATT service (or it's missing characteristics).",
+          device.address()
+        ));
+      }
+    };
+
+    // The notification stream borrows the characteristic, so it lives on its own task.
+    let (notification_tx, notifications) = channel(32);
+    let notify_token = CancellationToken::new();
+    let forwarder_token = notify_token.clone();
+    let address = device.address();
+    tokio::task::spawn(async move {
+      let mut stream = match rx_characteristic.notify().await {
+        Ok(stream) => Box::pin(stream),
+        Err(err) => {
+          error!("Unable to subscribe to notifications from: {address}, due to:\n{err}");
+          return;
+        }
+      };
+
+      loop {
+        tokio::select! {
+          _ = forwarder_token.cancelled() => break,
+          value = stream.next() => match value {
+            Some(value) => {
+              if notification_tx.send(value).await.is_err() {
+                break;
+              }
+            }
+            None => {
+              debug!("Notifications from: {address}, stopped.");
+              break;
+            }
+          },
+        }
+      }
+    });
+
+    Ok(GattLink {
+      tx_characteristic,
+      notifications,
+      notify_token,
+      buffer: ChunkBuffer::default(),
+    })
+  }
+
+  /// Write an encoded message to the module.
+  pub async fn send_bytes(&self, message: &[u8]) -> Result<(), anyhow::Error> {
+    if message.len() > MAX_MESSAGE_LEN {
+      return Err(anyhow::anyhow!(
+        "Message of {} bytes, is more than the max of {MAX_MESSAGE_LEN} bytes.",
+        message.len()
+      ));
+    }
+
+    for chunk in chunk_message(message, MAX_ATTRIBUTE_LEN) {
+      self.tx_characteristic.write(&chunk).await?;
+    }
+
+    Ok(())
+  }
+
+  pub async fn send(&self, message: &BusMessage) -> Result<(), anyhow::Error> {
+    self.send_bytes(&rmp_serde::to_vec(message)?).await
+  }
+
+  /// Wait for the next whole message, `None` once the module stops notifying (e.g. it disconnected).
+  pub async fn recv(&mut self) -> Option<Result<Vec<u8>, anyhow::Error>> {
+    loop {
+      let chunk = self.notifications.recv().await?;
+
+      if let Some(message) = self.buffer.push(&chunk) {
+        return Some(message);
+      }
+    }
+  }
+}
+
+impl Drop for GattLink {
+  fn drop(&mut self) {
+    self.notify_token.cancel();
+  }
+}
@@ -0,0 +1,414 @@
+//! # Bluetooth LE Proxy Bus
+//!
+//! Scans for modules advertising the [Clover GATT service](gatt), and proxies their messages over its characteristics, one connection per module.
+//!
+//! Modules that haven't been adopted yet are only paired with and [adopted](adoption) if `adopt_new_modules` is turned on, and each one still has to be approved through the [adoption queryables](crate::server::modman::modules::adoption) before it's adopted. Adopted modules have a [BLE connection](crate::server::modman::connections::ModuleConnection::BTLE) to their address, and their ports move through the same [statuses](PortStatus) as UART ports, becoming `Unavailable` while they're out of range or disconnected.
+//!
+//! Ports are identified by the module's address, e.g. `AA:BB:CC:DD:EE:FF`.
+//!
+
+pub mod adoption;
+pub mod gatt;
+pub mod module_listener;
+
+use std::{
+  collections::{
+    HashMap,
+    HashSet,
+  },
+  sync::Arc,
+  time::Duration,
+};
+
+use bluer::{
+  Adapter,
+  AdapterEvent,
+  Address,
+  DiscoveryFilter,
+  DiscoveryTransport,
+};
+use serde::{
+  Deserialize,
+  Serialize,
+};
+use tokio::sync::Mutex;
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  error,
+  info,
+  instrument,
+  warn,
+};
+
+use crate::server::modman::{
+  busses::{
+    models::{
+      Bus,
+      BusTypes,
+    },
+    proxies::individual::bt_le::{
+      adoption::{
+        adopt_bt_le_module,
+        pair_and_connect,
+      },
+      gatt::{
+        GattLink,
+        CLOVER_SERVICE_UUID,
+      },
+      module_listener::bt_le_module_listener,
+    },
+  },
+  connections::ModuleConnection,
+  models::{
+    store::ModManStore,
+    PortStatus,
+  },
+};
+
+#[derive(Debug, Clone)]
+pub struct BluetoothLEBus {
+  pub store: Arc<ModManStore>,
+  pub cancellation_token: CancellationToken,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BTLEConfig {
+  /// Adapter to scan with, e.g. `hci0`, the default adapter is used if unset.
+  #[serde(default)]
+  pub adapter: Option<String>,
+  /// Pair with modules advertising the Clover service that haven't been adopted yet, and adopt them once they're approved.
+  #[serde(default = "default_adopt_new_modules")]
+  pub adopt_new_modules: bool,
+  /// Seconds to wait for a new module's hello.
+  #[serde(default = "default_hello_timeout")]
+  pub hello_timeout: u64,
+  /// Seconds a new module stays paired while it waits to be approved.
+  #[serde(default = "default_approval_timeout")]
+  pub approval_timeout: u64,
+  /// Seconds to wait for a module to pair and connect before it's marked unavailable.
+  #[serde(default = "default_connect_timeout")]
+  pub connect_timeout: u64,
+}
+
+fn default_adopt_new_modules() -> bool {
+  false
+}
+
+fn default_hello_timeout() -> u64 {
+  10
+}
+
+fn default_approval_timeout() -> u64 {
+  300
+}
+
+fn default_connect_timeout() -> u64 {
+  30
+}
+
+impl Default for BTLEConfig {
+  fn default() -> Self {
+    BTLEConfig {
+      adapter: None,
+      adopt_new_modules: default_adopt_new_modules(),
+      hello_timeout: default_hello_timeout(),
+      approval_timeout: default_approval_timeout(),
+      connect_timeout: default_connect_timeout(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AdoptionState {
+  Adopting,
+  /// Not retried until the bus restarts, so refused modules aren't paired over and over.
+  Refused,
+}
+
+/// Whether a device advertises the Clover service.
+async fn has_clover_service(adapter: &Adapter, address: Address) -> Result<bool, anyhow::Error> {
+  Ok(
+    adapter
+      .device(address)?
+      .uuids()
+      .await?
+      .map(|uuids| uuids.contains(&CLOVER_SERVICE_UUID))
+      .unwrap_or(false),
+  )
+}
+
+/// Whether a module with a BLE connection to this address is registered.
+async fn is_registered(store: &ModManStore, address: Address) -> bool {
+  store
+    .modules
+    .lock()
+    .await
+    .values()
+    .any(|module| match &module.connection {
+      ModuleConnection::BTLE(module_address) => {
+        module_address.parse::<Address>().ok() == Some(address)
+      }
+      _ => false,
+    })
+}
+
+/// Result of connecting to a module for a port, sent back to the bus once it's done.
+struct ConnectResult {
+  port: String,
+  module_id: String,
+  link: Result<GattLink, anyhow::Error>,
+}
+
+/// Connect to a module, and open its Clover service.
+async fn connect_module(
+  adapter: &Adapter,
+  address: Address,
+  connect_timeout: Duration,
+) -> Result<GattLink, anyhow::Error> {
+  let device = adapter.device(address)?;
+
+  tokio::time::timeout(connect_timeout, async {
+    pair_and_connect(&device).await?;
+    GattLink::open(&device).await
+  })
+  .await
+  .map_err(|_| anyhow::anyhow!("Timed out after {connect_timeout:?}."))?
+}
+
+impl Bus for BluetoothLEBus {
+  #[instrument(name = "bt_le_bus", skip(self, session))]
+  async fn subscribe_to_bus(
+    self,
+    session: Arc<zenoh::Session>,
+  ) -> Result<tokio::task::JoinHandle<()>, anyhow::Error> {
+    let bt_le_config = self.store.config.lock().await.modman.bt_le.clone();
+
+    let bluer_session = bluer::Session::new().await?;
+    let adapter = match &bt_le_config.adapter {
+      Some(adapter_name) => bluer_session.adapter(adapter_name)?,
+      None => bluer_session.default_adapter().await?,
+    };
+    adapter.set_powered(true).await?;
+    adapter
+      .set_discovery_filter(DiscoveryFilter {
+        uuids: HashSet::from([CLOVER_SERVICE_UUID]),
+        transport: DiscoveryTransport::Le,
+        ..Default::default()
+      })
+      .await?;
+
+    info!("Scanning for BLE modules on adapter: {}...", adapter.name());
+
+    Ok(tokio::task::spawn(async move {
+      // Keeps the connection to BlueZ open while the bus is running.
+      let _bluer_session = bluer_session;
+
+      let mut discovery = match adapter.discover_devices().await {
+        Ok(discovery) => Box::pin(discovery),
+        Err(err) => {
+          error!("Unable to start discovering BLE modules, due to:\n{err}");
+          return;
+        }
+      };
+
+      let mut discovered: HashSet<Address> = HashSet::new();
+      let mut listeners: HashMap<String, CancellationToken> = HashMap::new();
+      let adoptions: Arc<Mutex<HashMap<Address, AdoptionState>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+      // Ports being connected to, connecting can take up to `connect_timeout`, so it's done off of the bus' task.
+      let mut connecting: HashSet<String> = HashSet::new();
+      let (connect_tx, mut connect_rx) = tokio::sync::mpsc::unbounded_channel::<ConnectResult>();
+      let mut poll = tokio::time::interval(Duration::from_millis(500));
+      poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+      loop {
+        tokio::select! {
+          _ = self.cancellation_token.cancelled() => break,
+          event = discovery.next() => match event {
+            Some(AdapterEvent::DeviceAdded(address)) => {
+              match has_clover_service(&adapter, address).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => {
+                  debug!("Unable to check services of: {address}, due to:\n{err}");
+                  continue;
+                }
+              }
+
+              debug!("Found BLE module: {address}.");
+              discovered.insert(address);
+
+              if !bt_le_config.adopt_new_modules || is_registered(&self.store, address).await {
+                continue;
+              }
+
+              let mut adoptions_guard = adoptions.lock().await;
+              if adoptions_guard.contains_key(&address) {
+                continue;
+              }
+              adoptions_guard.insert(address, AdoptionState::Adopting);
+              drop(adoptions_guard);
+
+              let adoption_session = session.clone();
+              let adoption_adapter = adapter.clone();
+              let adoption_states = adoptions.clone();
+              let hello_timeout = Duration::from_secs(bt_le_config.hello_timeout);
+              let approval_timeout = Duration::from_secs(bt_le_config.approval_timeout);
+              tokio::task::spawn(async move {
+                match adopt_bt_le_module(adoption_session, adoption_adapter, address, hello_timeout, approval_timeout).await {
+                  Ok(_) => {
+                    adoption_states.lock().await.remove(&address);
+                  }
+                  Err(err) => {
+                    warn!("Unable to adopt BLE module: {address}, due to:\n{err}");
+                    adoption_states.lock().await.insert(address, AdoptionState::Refused);
+                  }
+                }
+              });
+            }
+            Some(AdapterEvent::DeviceRemoved(address)) => {
+              discovered.remove(&address);
+            }
+            Some(_) => {}
+            None => {
+              error!("BLE discovery stopped, is bluetoothd still running?");
+              break;
+            }
+          },
+          Some(ConnectResult { port, module_id, link }) = connect_rx.recv() => {
+            connecting.remove(&port);
+
+            let mut port_statuses = self.store.port_statuses.bt_le.lock().await;
+            // Don't clobber a module being (de)initialized while we were connecting.
+            match port_statuses.get(&port) {
+              Some(PortStatus::Requested(status_id)) | Some(PortStatus::Unavailable(status_id)) if status_id == &module_id => {}
+              _ => {
+                debug!("BLE port: {port}, changed while connecting to it, dropping the connection.");
+                continue;
+              }
+            }
+
+            let next_status = match link {
+              Ok(link) => {
+                info!("Bound BLE port: {port}, for module: {module_id}!");
+
+                let listener_token = CancellationToken::new();
+                listeners.insert(port.clone(), listener_token.clone());
+
+                let listener_store = self.store.clone();
+                let listener_session = session.clone();
+                let listener_id = module_id.clone();
+                tokio::task::spawn(async move {
+                  bt_le_module_listener(
+                    listener_store,
+                    listener_session,
+                    listener_token,
+                    link,
+                    listener_id,
+                  )
+                  .await;
+                });
+
+                PortStatus::Bound(module_id)
+              }
+              Err(err) => {
+                warn!("Unable to bind BLE port: {port}, for module: {module_id}, due to:\n{err}");
+                PortStatus::Unavailable(module_id)
+              }
+            };
+
+            port_statuses.insert(port, next_status);
+          },
+          _ = poll.tick() => {
+            let port_statuses_snapshot = self.store.port_statuses.bt_le.lock().await.clone();
+
+            for (port, port_status) in port_statuses_snapshot {
+              let address = match port.parse::<Address>() {
+                Ok(address) => address,
+                Err(err) => {
+                  warn!("BLE port: {port}, is not a valid address, due to:\n{err}");
+                  continue;
+                }
+              };
+
+              let next_status = match port_status.clone() {
+                PortStatus::Requested(module_id) | PortStatus::Unavailable(module_id) => {
+                  // The adoption handshake still has the module's service open.
+                  if adoptions.lock().await.get(&address) == Some(&AdoptionState::Adopting) {
+                    continue;
+                  }
+
+                  if connecting.contains(&port) {
+                    continue;
+                  }
+
+                  if !discovered.contains(&address) {
+                    PortStatus::Unavailable(module_id)
+                  } else {
+                    connecting.insert(port.clone());
+
+                    let connect_adapter = adapter.clone();
+                    let connect_tx = connect_tx.clone();
+                    let connect_timeout = Duration::from_secs(bt_le_config.connect_timeout);
+                    tokio::task::spawn(async move {
+                      let link = connect_module(&connect_adapter, address, connect_timeout).await;
+                      // The bus has stopped if the receiver is gone, and the link is dropped with the result.
+                      let _ = connect_tx.send(ConnectResult {
+                        port,
+                        module_id,
+                        link,
+                      });
+                    });
+                    continue;
+                  }
+                }
+                PortStatus::Bound(module_id) => {
+                  // Listeners cancel their own token when the module disconnects.
+                  match listeners.get(&port) {
+                    Some(listener_token) if !listener_token.is_cancelled() => continue,
+                    _ => {}
+                  }
+
+                  listeners.remove(&port);
+                  warn!("BLE module: {module_id}, disconnected from port: {port}, will bind it again once it's available...");
+                  PortStatus::Unavailable(module_id)
+                }
+                PortStatus::Unrequested(module_id) => {
+                  if let Some(listener_token) = listeners.remove(&port) {
+                    info!("Releasing BLE port: {port}, from module: {module_id}...");
+                    listener_token.cancel();
+                  }
+
+                  if let Ok(device) = adapter.device(address) {
+                    if let Err(err) = device.disconnect().await {
+                      debug!("Unable to disconnect from: {address}, due to:\n{err}");
+                    }
+                  }
+                  PortStatus::Available
+                }
+                PortStatus::Available => continue,
+              };
+
+              let mut port_statuses = self.store.port_statuses.bt_le.lock().await;
+              // Don't clobber a module being (de)initialized while we were binding.
+              if port_statuses.get(&port) == Some(&port_status) {
+                port_statuses.insert(port, next_status);
+              }
+            }
+          },
+        }
+      }
+
+      for (port, listener_token) in listeners.drain() {
+        debug!("Shutting down BLE listener for port: {port}...");
+        listener_token.cancel();
+      }
+    }))
+  }
+
+  fn get_type() -> BusTypes {
+    BusTypes::BTLE
+  }
+}
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  error,
+  instrument,
+  warn,
+};
+use zenoh_ext::{
+  AdvancedPublisherBuilderExt,
+  CacheConfig,
+};
+
+use crate::server::modman::{
+  busses::{
+    auth::{
+      report_content_error,
+      verify_bus_message,
+      ContentError,
+    },
+    models::BusMessage,
+    proxies::individual::bt_le::gatt::{
+      decode_message,
+      GattLink,
+    },
+    tx::{
+      handle_send_query,
+      ModuleTX,
+    },
+  },
+  models::store::ModManStore,
+  MODULE_EVT_ID,
+};
+
+impl ModuleTX for GattLink {
+  async fn send(&mut self, msg_bytes: Vec<u8>) -> Result<(), anyhow::Error> {
+    self.send_bytes(&msg_bytes).await
+  }
+}
+
+/// Proxy notifications from a module to `.../recv`, and write messages from `.../send` to it.
+///
+/// Cancels its own token when the module disconnects, so the bus can bind it again.
+#[instrument(skip(store, session, cancellation_token, link))]
+pub async fn bt_le_module_listener(
+  store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+  mut link: GattLink,
+  module_id: String,
+) {
+  let recv_key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/recv");
+  let send_key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/send");
+
+  let publisher = match session
+    .declare_publisher(&recv_key_expr)
+    .cache(CacheConfig::default().max_samples(1))
+    .await
+  {
+    Ok(publisher) => publisher,
+    Err(err) => {
+      error!("Unable to create a zenoh broadcaster at: {recv_key_expr}, there's probably an error in your configuration. Due to:\n{err}");
+      return;
+    }
+  };
+
+  let queryable = match session.declare_queryable(&send_key_expr).await {
+    Ok(queryable) => queryable,
+    Err(err) => {
+      error!("Unable to create a zenoh queryable at: {send_key_expr}, there's probably an error in your configuration. Due to:\n{err}");
+      return;
+    }
+  };
+
+  loop {
+    tokio::select! {
+      _ = cancellation_token.cancelled() => break,
+      message = link.recv() => {
+        match message {
+          Some(Ok(message)) => match decode_message(&message) {
+            Ok(decoded_message) => {
+              match verify_bus_message(&store, &module_id, &decoded_message).await {
+                Ok(_) => match serde_json::to_string(&decoded_message) {
+                  Ok(json_payload) => match publisher.put(json_payload).await {
+                    Ok(_) => {
+                      debug!("Successfully proxied BLE message from module: {module_id}!");
+                    }
+                    Err(err) => {
+                      error!("Failed to publish message to Zenoh, at this stage, we've either lost connectivity, or there's a massive problem. (Might be a bug) Due to:\n{err}");
+                    }
+                  },
+                  Err(err) => {
+                    error!("Failed to produce a JSON payload from the decoded message, this is a bug and should be reported! Due to:\n{err}");
+                  }
+                },
+                Err(content_error) => {
+                  report_content_error(&session, &module_id, &content_error).await;
+                }
+              }
+            }
+            Err(err) => {
+              error!("Invalid message from module: {module_id}, this is a bug (or bad connection) and should (probably) be reported to the module maintainer! Happened due to:\n{err}");
+              report_content_error(
+                &session,
+                &module_id,
+                &ContentError::MalformedMessage {
+                  reason: err.to_string(),
+                },
+              )
+              .await;
+            }
+          },
+          Some(Err(err)) => {
+            error!("Dropped a message from module: {module_id}, due to:\n{err}");
+          }
+          None => {
+            warn!("Module: {module_id}, disconnected.");
+            cancellation_token.cancel();
+            break;
+          }
+        }
+      },
+      query = queryable.recv_async() => {
+        let query = match query {
+          Ok(query) => query,
+          Err(_) => break,
+        };
+
+        handle_send_query(
+          &store,
+          &session,
+          &module_id,
+          &query,
+          &send_key_expr,
+          "BLE",
+          &mut link,
+        )
+        .await;
+      },
+    }
+  }
+
+  debug!("Shutting down BLE listener for module: {module_id}.");
+}
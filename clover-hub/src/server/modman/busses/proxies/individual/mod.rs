@@ -3,5 +3,6 @@ pub mod app;
 pub mod bt_classic;
 #[cfg(feature = "bt_le")]
 pub mod bt_le;
+pub mod simulated;
 #[cfg(feature = "uart")]
 pub mod uart;
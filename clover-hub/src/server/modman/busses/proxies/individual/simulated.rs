@@ -0,0 +1,562 @@
+//! # Simulated Bus Proxy
+//!
+//! Drives modules with a [Simulated connection](crate::server::modman::connections::ModuleConnection::Simulated) entirely in software, so gestures, apps, and UIs can be developed (and CI can run the whole ModMan pipeline) without hardware.
+//!
+//! A module's [simulation](ModuleSimulation) is looked up by the ID in its connection in the `simulations` config, then in the `simulation` of the module's manifest entry. Simulated modules without one (e.g. the debug displays) don't need the bus, their components are driven directly.
+//!
+//! Once bound, the module publishes [`ComponentTelemetry`] for each simulated component on its `/recv` every `report_interval`. Telemetry sent to its `/send` is echoed back as the module's values (if `echo_commands` is on), until the next command for the same parameter.
+//!
+//! Telemetry is signed with the module's key if it has one, otherwise it's sent with an empty HMAC. Content sent to the module isn't verified, as nothing leaves ModMan.
+//!
+
+use std::{
+  collections::HashMap,
+  sync::Arc,
+  time::Duration,
+};
+
+use rand::{
+  rngs::OsRng,
+  RngCore,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  error,
+  info,
+  instrument,
+  warn,
+};
+use zenoh_ext::{
+  AdvancedPublisherBuilderExt,
+  CacheConfig,
+};
+
+use crate::server::{
+  modman::{
+    busses::{
+      auth::{
+        module_keys,
+        next_counter,
+        sign_content,
+        ContentError,
+        NONCE_LEN,
+      },
+      models::{
+        Bus,
+        BusMessage,
+        BusTXError,
+        BusTypes,
+        ComponentTelemetry,
+        ContentMessage,
+      },
+    },
+    connections::ModuleConnection,
+    models::{
+      modules::Module,
+      simulation::ModuleSimulation,
+      store::ModManStore,
+      PortStatus,
+    },
+    modules::adoption::find_module_spec,
+    MODULE_EVT_ID,
+  },
+  warehouse::repos::models::Optional,
+};
+
+#[derive(Debug, Clone)]
+pub struct SimulatedBus {
+  pub store: Arc<ModManStore>,
+  pub cancellation_token: CancellationToken,
+}
+
+/// Find the simulation for a module, from config first, then its manifest entry.
+pub async fn find_simulation(
+  store: &ModManStore,
+  module: &Module,
+  simulation_id: &String,
+) -> Option<ModuleSimulation> {
+  if let Some(simulation) = store
+    .config
+    .lock()
+    .await
+    .modman
+    .simulations
+    .get(simulation_id)
+  {
+    return Some(simulation.clone());
+  }
+
+  match find_module_spec(store, &module.module_type).await {
+    Some(spec) => match spec.simulation {
+      Optional::Some(simulation) => Some(simulation),
+      _ => None,
+    },
+    None => None,
+  }
+}
+
+/// Component ID for a key in a simulation, either the ID itself or the key of an adopted module's component.
+fn simulated_component_id(module_id: &String, module: &Module, key: &String) -> String {
+  if module
+    .components
+    .iter()
+    .any(|(component_id, _)| component_id == key)
+  {
+    key.clone()
+  } else {
+    format!("{module_id}.{key}")
+  }
+}
+
+/// Wrap telemetry in a [`ContentMessage`], signed if the module has a key.
+async fn telemetry_message(
+  store: &ModManStore,
+  module_id: &String,
+  telemetry: &ComponentTelemetry,
+) -> Result<BusMessage, anyhow::Error> {
+  let data = rmp_serde::to_vec(telemetry)?;
+  let counter = next_counter(store, module_id).await;
+
+  match module_keys(store, module_id).await {
+    Ok(keys) => match sign_content(&keys, counter, data) {
+      Ok(content) => Ok(BusMessage::Content(content)),
+      Err(err) => Err(anyhow::anyhow!("{err}")),
+    },
+    Err(ContentError::MissingKey) => {
+      let mut nonce = vec![0u8; NONCE_LEN];
+      OsRng.fill_bytes(&mut nonce);
+
+      Ok(BusMessage::Content(ContentMessage {
+        counter,
+        nonce,
+        data,
+        hmac: vec![],
+        ephemeral_key: None,
+      }))
+    }
+    Err(err) => Err(anyhow::anyhow!("{err}")),
+  }
+}
+
+/// Report a simulated module's telemetry, and accept commands for it, until cancelled.
+#[instrument(skip(store, session, cancellation_token, simulation))]
+pub async fn simulated_module_listener(
+  store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+  module_id: String,
+  simulation: ModuleSimulation,
+) {
+  let recv_key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/recv");
+  let send_key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/send");
+
+  let module = match store.modules.lock().await.get(&module_id) {
+    Some(module) => module.clone(),
+    None => {
+      error!("Module: {module_id}, was removed before its simulation started!");
+      return;
+    }
+  };
+  let components: Vec<(String, HashMap<String, _>)> = simulation
+    .components
+    .iter()
+    .map(|(key, parameters)| {
+      (
+        simulated_component_id(&module_id, &module, key),
+        parameters.clone(),
+      )
+    })
+    .collect();
+
+  let publisher = match session
+    .declare_publisher(&recv_key_expr)
+    .cache(CacheConfig::default().max_samples(1))
+    .await
+  {
+    Ok(publisher) => publisher,
+    Err(err) => {
+      error!("Unable to create a zenoh broadcaster at: {recv_key_expr}, there's probably an error in your configuration. Due to:\n{err}");
+      return;
+    }
+  };
+
+  let queryable = match session.declare_queryable(&send_key_expr).await {
+    Ok(queryable) => queryable,
+    Err(err) => {
+      error!("Unable to create a zenoh queryable at: {send_key_expr}, there's probably an error in your configuration. Due to:\n{err}");
+      return;
+    }
+  };
+
+  let started_at = tokio::time::Instant::now();
+  let mut echoed: HashMap<String, HashMap<String, f64>> = HashMap::new();
+  let mut report = tokio::time::interval(Duration::from_millis(simulation.report_interval.max(1)));
+  report.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+  loop {
+    tokio::select! {
+      _ = cancellation_token.cancelled() => break,
+      _ = report.tick() => {
+        let elapsed = started_at.elapsed().as_secs_f64() * 1000.0;
+
+        let mut reports: HashMap<String, HashMap<String, f64>> = components
+          .iter()
+          .map(|(component_id, parameters)| {
+            (
+              component_id.clone(),
+              parameters
+                .iter()
+                .map(|(parameter, value)| (parameter.clone(), value.sample(elapsed)))
+                .collect(),
+            )
+          })
+          .collect();
+        for (component_id, values) in echoed.iter() {
+          reports.entry(component_id.clone()).or_default().extend(values.clone());
+        }
+
+        for (component_id, values) in reports {
+          let message = match telemetry_message(&store, &module_id, &ComponentTelemetry { component_id, values }).await {
+            Ok(message) => message,
+            Err(err) => {
+              error!("Failed to produce telemetry for simulated module: {module_id}, due to:\n{err}");
+              continue;
+            }
+          };
+
+          match serde_json::to_string(&message) {
+            Ok(json_payload) => {
+              if let Err(err) = publisher.put(json_payload).await {
+                error!("Failed to publish message to Zenoh, at this stage, we've either lost connectivity, or there's a massive problem. (Might be a bug) Due to:\n{err}");
+              }
+            }
+            Err(err) => {
+              error!("Failed to produce a JSON payload from the message, this is a bug and should be reported! Due to:\n{err}");
+            }
+          }
+        }
+      },
+      query = queryable.recv_async() => {
+        let query = match query {
+          Ok(query) => query,
+          Err(_) => break,
+        };
+
+        let payload_str = match query.payload() {
+          Some(query_payload) => match query_payload.try_to_string() {
+            Ok(payload_str) => payload_str.to_string(),
+            Err(err) => {
+              error!("Query's payload could not be decoded into a string, due to:\n{err}");
+              BusTXError::PayloadIsNotString.reply(&query, &send_key_expr).await;
+              continue;
+            }
+          },
+          None => {
+            error!("Query was sent to the module endpoint without a payload!");
+            BusTXError::MissingPayload.reply(&query, &send_key_expr).await;
+            continue;
+          }
+        };
+
+        let content = match serde_json_lenient::from_str::<BusMessage>(&payload_str) {
+          Ok(BusMessage::Content(content)) => content,
+          Ok(_) => {
+            BusTXError::UnsupportedMessage.reply(&query, &send_key_expr).await;
+            continue;
+          }
+          Err(err) => {
+            error!("Invalid query payload, due to:\n{err}");
+            BusTXError::MalformedPayload.reply(&query, &send_key_expr).await;
+            continue;
+          }
+        };
+
+        let command = match rmp_serde::from_slice::<ComponentTelemetry>(&content.data) {
+          Ok(command) => command,
+          Err(err) => {
+            warn!("Simulated module: {module_id}, can't understand command, due to:\n{err}");
+            BusTXError::MalformedCommand.reply(&query, &send_key_expr).await;
+            continue;
+          }
+        };
+
+        if simulation.echo_commands {
+          echoed.entry(command.component_id).or_default().extend(command.values);
+        }
+
+        if let Err(err) = query.reply(&send_key_expr, format!("{}", content.data.len())).await {
+          error!("Failed to reply to query, due to:\n{err}");
+        }
+      },
+    }
+  }
+
+  debug!("Stopped simulating module: {module_id}.");
+}
+
+impl Bus for SimulatedBus {
+  #[instrument(name = "simulated_bus", skip(self, session))]
+  async fn subscribe_to_bus(
+    self,
+    session: Arc<zenoh::Session>,
+  ) -> Result<tokio::task::JoinHandle<()>, anyhow::Error> {
+    Ok(tokio::task::spawn(async move {
+      let mut listeners: HashMap<String, CancellationToken> = HashMap::new();
+
+      while !self.cancellation_token.is_cancelled() {
+        let port_statuses_snapshot = self.store.port_statuses.simulated.lock().await.clone();
+
+        for (module_id, port_status) in port_statuses_snapshot {
+          let next_status = match port_status.clone() {
+            PortStatus::Requested(_) | PortStatus::Unavailable(_) => {
+              let module = match self.store.modules.lock().await.get(&module_id) {
+                Some(module) => module.clone(),
+                None => continue,
+              };
+              let simulation = match &module.connection {
+                ModuleConnection::Simulated(simulation_id) => {
+                  find_simulation(&self.store, &module, simulation_id).await
+                }
+                _ => {
+                  warn!("Module: {module_id}, requested a simulation, but doesn't have a simulated connection.");
+                  continue;
+                }
+              };
+
+              match simulation {
+                Some(simulation) => {
+                  info!("Simulating module: {module_id}!");
+
+                  let listener_token = CancellationToken::new();
+                  listeners.insert(module_id.clone(), listener_token.clone());
+
+                  let listener_store = self.store.clone();
+                  let listener_session = session.clone();
+                  let listener_id = module_id.clone();
+                  tokio::task::spawn(async move {
+                    simulated_module_listener(
+                      listener_store,
+                      listener_session,
+                      listener_token,
+                      listener_id,
+                      simulation,
+                    )
+                    .await;
+                  });
+
+                  PortStatus::Bound(module_id.clone())
+                }
+                None => {
+                  warn!(
+                    "Simulation for module: {module_id}, is gone, will bind it once it's back..."
+                  );
+                  PortStatus::Unavailable(module_id.clone())
+                }
+              }
+            }
+            PortStatus::Bound(_) => continue,
+            PortStatus::Unrequested(_) => {
+              if let Some(listener_token) = listeners.remove(&module_id) {
+                info!("Stopping simulation of module: {module_id}...");
+                listener_token.cancel();
+              }
+              PortStatus::Available
+            }
+            PortStatus::Available => continue,
+          };
+
+          let mut port_statuses = self.store.port_statuses.simulated.lock().await;
+          // Don't clobber a module being (de)initialized while we were binding.
+          if port_statuses.get(&module_id) == Some(&port_status) {
+            port_statuses.insert(module_id, next_status);
+          }
+        }
+
+        tokio::select! {
+          _ = self.cancellation_token.cancelled() => {},
+          _ = tokio::time::sleep(Duration::from_millis(500)) => {},
+        }
+      }
+
+      for (module_id, listener_token) in listeners.drain() {
+        debug!("Shutting down simulation of module: {module_id}...");
+        listener_token.cancel();
+      }
+    }))
+  }
+
+  fn get_type() -> BusTypes {
+    BusTypes::Simulated
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    server::modman::models::{
+      gestures::GestureTrack,
+      modules::SecurityLevel,
+      simulation::SimulatedValue,
+    },
+    utils::configure_zenoh,
+  };
+
+  const TIMEOUT: Duration = Duration::from_secs(5);
+
+  async fn start_simulation(
+    module_id: &str,
+  ) -> (Arc<ModManStore>, Arc<zenoh::Session>, CancellationToken) {
+    let store = Arc::new(ModManStore::new(None, None));
+    store.modules.lock().await.insert(
+      module_id.to_string(),
+      Module {
+        module_type: "com.example.sensor".to_string(),
+        module_name: "Sensor".to_string(),
+        custom_name: None,
+        initialized: false,
+        components: vec![],
+        registered_by: "com.reboot-codes.clover.hub".to_string(),
+        connection: ModuleConnection::Simulated(module_id.to_string()),
+        firmware_version: None,
+        serial: None,
+        security_level: SecurityLevel::L1,
+        key: None,
+        exchange_secret: None,
+        public_key: None,
+        insecure_movement: false,
+        session: 0,
+        last_counter: 0,
+      },
+    );
+
+    let zenoh_config = configure_zenoh(vec![
+      ("mode", "\"peer\""),
+      ("listen/endpoints", "[]"),
+      ("scouting/multicast/enabled", "false"),
+      // Needed by the publisher's cache.
+      (
+        "timestamping/enabled",
+        r#"{ router: true, peer: true, client: true }"#,
+      ),
+    ])
+    .unwrap();
+    let session = Arc::new(zenoh::open(zenoh_config).await.unwrap());
+    let cancellation_token = CancellationToken::new();
+
+    let simulation = ModuleSimulation {
+      report_interval: 20,
+      echo_commands: true,
+      components: HashMap::from([(
+        "sensor".to_string(),
+        HashMap::from([(
+          "value".to_string(),
+          SimulatedValue::Scripted(GestureTrack::Constant(1.0)),
+        )]),
+      )]),
+    };
+
+    tokio::task::spawn(simulated_module_listener(
+      store.clone(),
+      session.clone(),
+      cancellation_token.clone(),
+      module_id.to_string(),
+      simulation,
+    ));
+
+    (store, session, cancellation_token)
+  }
+
+  /// Wait for telemetry from a module's `/recv` that matches `predicate`.
+  async fn wait_for_telemetry(
+    session: &zenoh::Session,
+    module_id: &str,
+    predicate: impl Fn(&ComponentTelemetry) -> bool,
+  ) -> Option<ComponentTelemetry> {
+    let subscriber = session
+      .declare_subscriber(format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/recv"))
+      .await
+      .unwrap();
+
+    tokio::time::timeout(TIMEOUT, async {
+      loop {
+        let sample = subscriber.recv_async().await.unwrap();
+        let message: BusMessage =
+          serde_json::from_str(&sample.payload().try_to_string().unwrap()).unwrap();
+
+        if let BusMessage::Content(content) = message {
+          let telemetry: ComponentTelemetry = rmp_serde::from_slice(&content.data).unwrap();
+          if predicate(&telemetry) {
+            return telemetry;
+          }
+        }
+      }
+    })
+    .await
+    .ok()
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn bound_module_publishes_telemetry() {
+    let (_store, session, cancellation_token) = start_simulation("sim-telemetry").await;
+
+    let telemetry = wait_for_telemetry(&session, "sim-telemetry", |_| true)
+      .await
+      .expect("No telemetry was published.");
+    assert_eq!(telemetry.component_id, "sim-telemetry.sensor");
+    assert_eq!(telemetry.values["value"], 1.0);
+
+    cancellation_token.cancel();
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn commands_are_echoed() {
+    let (_store, session, cancellation_token) = start_simulation("sim-echo").await;
+
+    let command = ComponentTelemetry {
+      component_id: "sim-echo.sensor".to_string(),
+      values: HashMap::from([("value".to_string(), 5.0)]),
+    };
+    let content = BusMessage::Content(ContentMessage {
+      counter: 0,
+      nonce: vec![],
+      data: rmp_serde::to_vec(&command).unwrap(),
+      hmac: vec![],
+      ephemeral_key: None,
+    });
+
+    // The queryable is declared once the listener starts.
+    let reply = tokio::time::timeout(TIMEOUT, async {
+      loop {
+        let replies = session
+          .get(format!("{MODULE_EVT_ID}/modules/by-id/sim-echo/send"))
+          .payload(serde_json::to_string(&content).unwrap())
+          .await
+          .unwrap();
+
+        if let Ok(reply) = replies.recv_async().await {
+          return reply
+            .result()
+            .unwrap()
+            .payload()
+            .try_to_string()
+            .unwrap()
+            .to_string();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+      }
+    })
+    .await
+    .expect("The module didn't reply to the command.");
+    assert!(!reply.starts_with("error:"), "{reply}");
+
+    let telemetry = wait_for_telemetry(&session, "sim-echo", |telemetry| {
+      telemetry.values.get("value") == Some(&5.0)
+    })
+    .await;
+    assert!(telemetry.is_some(), "The command wasn't echoed.");
+
+    cancellation_token.cancel();
+  }
+}
@@ -0,0 +1,119 @@
+//! # UART Adoption
+//!
+//! Ports that are present, but haven't been requested by a module, are opened at `adoption_baud` and [probed](crate::server::modman::busses::adoption) if `adopt_new_modules` is turned on. The port stays open while the module waits to be approved, and is refused if it isn't approved within `approval_timeout`.
+//!
+//! Adopted modules are given a [UART connection](crate::server::modman::connections::UARTConnection) to the port at the same baud rate, and initializing them requests the port like any other module.
+//!
+
+use std::{
+  collections::VecDeque,
+  sync::Arc,
+  time::Duration,
+};
+
+use anyhow::anyhow;
+use tokio::io::{
+  AsyncReadExt,
+  AsyncWriteExt,
+};
+use tokio_serial::SerialStream;
+use tracing::{
+  debug,
+  info,
+  instrument,
+};
+
+use crate::server::modman::{
+  busses::{
+    adoption::{
+      adopt_over_link,
+      AdoptionLink,
+    },
+    models::BusMessage,
+    proxies::individual::uart::framing::{
+      encode_frame,
+      FrameBuffer,
+      FramingError,
+    },
+  },
+  connections::{
+    ModuleConnection,
+    UARTConnection,
+  },
+};
+
+/// A port opened to adopt the module on it.
+struct UARTLink {
+  port: SerialStream,
+  frames: FrameBuffer,
+  /// Frames completed by the last read, that haven't been received yet.
+  pending: VecDeque<Result<Vec<u8>, FramingError>>,
+}
+
+impl AdoptionLink for UARTLink {
+  async fn send(&mut self, message: &BusMessage) -> Result<(), anyhow::Error> {
+    self
+      .port
+      .write_all(&encode_frame(&rmp_serde::to_vec(message)?))
+      .await?;
+    Ok(())
+  }
+
+  async fn recv(&mut self) -> Option<Result<BusMessage, anyhow::Error>> {
+    let mut read_buf = [0u8; 256];
+
+    loop {
+      if let Some(frame) = self.pending.pop_front() {
+        return Some(match frame {
+          Ok(payload) => rmp_serde::from_slice::<BusMessage>(&payload).map_err(|err| err.into()),
+          Err(framing_error) => Err(anyhow!("{framing_error}")),
+        });
+      }
+
+      match self.port.read(&mut read_buf).await {
+        Ok(0) => return None,
+        Ok(len) => self.pending.extend(self.frames.push(&read_buf[..len])),
+        Err(err) => match err.kind() {
+          std::io::ErrorKind::Interrupted
+          | std::io::ErrorKind::TimedOut
+          | std::io::ErrorKind::WouldBlock => {}
+          _ => {
+            debug!("Failed to read from UART port while adopting, due to:\n{err}");
+            return None;
+          }
+        },
+      }
+    }
+  }
+}
+
+/// Open a port, and adopt the module on it once it's approved. Returns the module's ID.
+#[instrument(skip(session))]
+pub async fn adopt_uart_module(
+  session: Arc<zenoh::Session>,
+  path: String,
+  baud: u32,
+  hello_timeout: Duration,
+  approval_timeout: Duration,
+) -> Result<String, anyhow::Error> {
+  let mut link = UARTLink {
+    port: SerialStream::open(&tokio_serial::new(path.clone(), baud))?,
+    frames: FrameBuffer::default(),
+    pending: VecDeque::new(),
+  };
+
+  let module_id = adopt_over_link(
+    &session,
+    &mut link,
+    ModuleConnection::UART(UARTConnection {
+      port: path.clone(),
+      baud,
+    }),
+    hello_timeout,
+    approval_timeout,
+  )
+  .await?;
+
+  info!("Adopted UART module: {module_id} ({path})!");
+  Ok(module_id)
+}
@@ -0,0 +1,264 @@
+//! # UART Framing
+//!
+//! Serial links don't have message boundaries, so every [`BusMessage`](crate::server::modman::busses::models::BusMessage) is sent as a frame:
+//!
+//! ```text
+//! COBS(msgpack || CRC-32(msgpack) as little-endian u32) || 0x00
+//! ```
+//!
+//! COBS removes every `0x00` from the frame, so the delimiter always marks the end of a frame. After a corrupted or truncated frame, the reader drops everything up to the next delimiter and carries on from there.
+//!
+
+use serde::{
+  Deserialize,
+  Serialize,
+};
+
+/// Marks the end of every frame.
+pub const FRAME_DELIMITER: u8 = 0x00;
+/// Largest encoded frame that will be buffered before it's dropped, excluding the delimiter.
+pub const MAX_FRAME_LEN: usize = 4096;
+const CRC_LEN: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, strum_macros::Display)]
+#[serde(tag = "error")]
+pub enum FramingError {
+  /// The frame isn't valid COBS.
+  #[serde(rename = "invalid-encoding")]
+  #[strum(serialize = "invalid-encoding")]
+  InvalidEncoding,
+  /// The frame is too short to contain a CRC.
+  #[serde(rename = "truncated")]
+  #[strum(serialize = "truncated")]
+  Truncated,
+  /// No delimiter was found within [`MAX_FRAME_LEN`] bytes.
+  #[serde(rename = "oversized")]
+  #[strum(serialize = "oversized")]
+  Oversized,
+  #[serde(rename = "crc-mismatch")]
+  #[strum(serialize = "crc-mismatch")]
+  CrcMismatch { expected: u32, actual: u32 },
+  /// The frame was intact, but isn't a valid message.
+  #[serde(rename = "invalid-message")]
+  #[strum(serialize = "invalid-message")]
+  InvalidMessage { reason: String },
+}
+
+/// COBS encode `data`, without the trailing delimiter.
+pub fn cobs_encode(data: &[u8]) -> Vec<u8> {
+  let mut encoded = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+  let mut code_index = 0;
+  let mut code = 1u8;
+  encoded.push(0);
+
+  for byte in data {
+    if *byte == 0 {
+      encoded[code_index] = code;
+      code_index = encoded.len();
+      encoded.push(0);
+      code = 1;
+    } else {
+      encoded.push(*byte);
+      code += 1;
+
+      if code == 0xFF {
+        encoded[code_index] = code;
+        code_index = encoded.len();
+        encoded.push(0);
+        code = 1;
+      }
+    }
+  }
+
+  encoded[code_index] = code;
+  encoded
+}
+
+/// Decode a COBS encoded frame, without the trailing delimiter.
+pub fn cobs_decode(encoded: &[u8]) -> Result<Vec<u8>, FramingError> {
+  let mut decoded = Vec::with_capacity(encoded.len());
+  let mut index = 0;
+
+  while index < encoded.len() {
+    let code = encoded[index] as usize;
+    if code == 0 || index + code > encoded.len() {
+      return Err(FramingError::InvalidEncoding);
+    }
+
+    let block = &encoded[index + 1..index + code];
+    if block.contains(&0) {
+      return Err(FramingError::InvalidEncoding);
+    }
+    decoded.extend_from_slice(block);
+    index += code;
+
+    // Blocks of 254 bytes don't imply a zero after them, nor does the last block.
+    if code != 0xFF && index < encoded.len() {
+      decoded.push(0);
+    }
+  }
+
+  Ok(decoded)
+}
+
+/// Build a frame from an encoded message, including the trailing delimiter.
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+  let mut data = payload.to_vec();
+  data.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+
+  let mut frame = cobs_encode(&data);
+  frame.push(FRAME_DELIMITER);
+  frame
+}
+
+/// Get the encoded message from a frame, without the trailing delimiter.
+pub fn decode_frame(frame: &[u8]) -> Result<Vec<u8>, FramingError> {
+  let mut data = cobs_decode(frame)?;
+  if data.len() < CRC_LEN {
+    return Err(FramingError::Truncated);
+  }
+
+  let crc_bytes = data.split_off(data.len() - CRC_LEN);
+  // Known good length from the split above.
+  let expected = u32::from_le_bytes(crc_bytes.try_into().unwrap());
+  let actual = crc32fast::hash(&data);
+
+  if expected == actual {
+    Ok(data)
+  } else {
+    Err(FramingError::CrcMismatch { expected, actual })
+  }
+}
+
+/// Splits a byte stream into frames, resynchronising on the next delimiter after an oversized frame.
+#[derive(Debug, Default)]
+pub struct FrameBuffer {
+  buffer: Vec<u8>,
+  overflowed: bool,
+}
+
+impl FrameBuffer {
+  /// Add bytes read from the port, returns every frame (or error) that was completed by them.
+  pub fn push(&mut self, bytes: &[u8]) -> Vec<Result<Vec<u8>, FramingError>> {
+    let mut frames = vec![];
+
+    for byte in bytes {
+      if *byte == FRAME_DELIMITER {
+        if self.overflowed {
+          self.overflowed = false;
+        } else if !self.buffer.is_empty() {
+          frames.push(decode_frame(&self.buffer));
+        }
+
+        self.buffer.clear();
+      } else if !self.overflowed {
+        self.buffer.push(*byte);
+
+        if self.buffer.len() > MAX_FRAME_LEN {
+          frames.push(Err(FramingError::Oversized));
+          self.buffer.clear();
+          self.overflowed = true;
+        }
+      }
+    }
+
+    frames
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn round_trip(payload: &[u8]) {
+    let frame = encode_frame(payload);
+
+    assert_eq!(frame.last(), Some(&FRAME_DELIMITER));
+    assert!(!frame[..frame.len() - 1].contains(&FRAME_DELIMITER));
+    assert_eq!(
+      decode_frame(&frame[..frame.len() - 1]),
+      Ok(payload.to_vec())
+    );
+  }
+
+  #[test]
+  fn round_trip_empty() {
+    round_trip(&[]);
+  }
+
+  #[test]
+  fn round_trip_254_non_zero_bytes() {
+    let run: Vec<u8> = (0..254).map(|index| (index % 255 + 1) as u8).collect();
+    assert_eq!(cobs_decode(&cobs_encode(&run)), Ok(run.clone()));
+
+    // The CRC pushes the run over a block boundary too.
+    round_trip(&run);
+  }
+
+  #[test]
+  fn round_trip_trailing_zero() {
+    assert_eq!(cobs_decode(&cobs_encode(&[1, 2, 0])), Ok(vec![1, 2, 0]));
+    round_trip(&[1, 2, 0]);
+    round_trip(&[0, 0, 0]);
+  }
+
+  #[test]
+  fn crc_mismatch() {
+    let mut data = b"hello".to_vec();
+    data.extend_from_slice(&(crc32fast::hash(b"hello") ^ 1).to_le_bytes());
+
+    assert!(matches!(
+      decode_frame(&cobs_encode(&data)),
+      Err(FramingError::CrcMismatch { .. })
+    ));
+  }
+
+  #[test]
+  fn truncated_frame() {
+    assert_eq!(
+      decode_frame(&cobs_encode(&[1, 2, 3])),
+      Err(FramingError::Truncated)
+    );
+  }
+
+  #[test]
+  fn invalid_encoding() {
+    // The first block claims more bytes than there are.
+    assert_eq!(cobs_decode(&[5, 1, 2]), Err(FramingError::InvalidEncoding));
+  }
+
+  #[test]
+  fn buffer_splits_frames() {
+    let mut buffer = FrameBuffer::default();
+    let stream = [encode_frame(b"first"), encode_frame(b"second")].concat();
+    let (start, end) = stream.split_at(4);
+
+    assert!(buffer.push(start).is_empty());
+    assert_eq!(
+      buffer.push(end),
+      vec![Ok(b"first".to_vec()), Ok(b"second".to_vec())]
+    );
+  }
+
+  #[test]
+  fn buffer_ignores_empty_frames() {
+    let mut buffer = FrameBuffer::default();
+
+    assert!(buffer.push(&[FRAME_DELIMITER, FRAME_DELIMITER]).is_empty());
+  }
+
+  #[test]
+  fn buffer_resyncs_after_oversized_frame() {
+    let mut buffer = FrameBuffer::default();
+
+    let frames = buffer.push(&vec![1u8; MAX_FRAME_LEN + 10]);
+    assert_eq!(frames, vec![Err(FramingError::Oversized)]);
+
+    // The rest of the oversized frame is dropped up to its delimiter, then frames are read again.
+    assert!(buffer.push(&[1, 1, 1]).is_empty());
+    assert_eq!(
+      buffer.push(&[[FRAME_DELIMITER].as_slice(), &encode_frame(b"after")].concat()),
+      vec![Ok(b"after".to_vec())]
+    );
+  }
+}
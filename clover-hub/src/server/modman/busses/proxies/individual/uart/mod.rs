@@ -2,12 +2,32 @@
 //!
 //! The UART proxy bus is designed bind one serial port per module, then expose I/O over Zenoh.
 //!
+//! Ports allowed in the config are polled continuously, so adapters can be plugged in (and unplugged) at any time. Each port moves through these [statuses](PortStatus):
+//!
+//! - `Available`: the port is present, but no module has requested it. If `adopt_new_modules` is turned on, the module on it is [adopted](adoption) once it's approved.
+//! - `Requested`: a module with a [UART connection](crate::server::modman::connections::UARTConnection) was initialized, the port is bound as soon as it's present.
+//! - `Bound`: the port is open at the connection's baud rate, and proxied over Zenoh.
+//! - `Unavailable`: the port was unplugged or failed to open, it's bound again once it's present, with exponential backoff between attempts.
+//! - `Unrequested`: the module was deinitialized, the port is closed and becomes `Available` again.
+//!
+//! The current port table is available at `{MODULE_EVT_ID}/busses/uart/ports`.
+//!
 
+pub mod adoption;
+pub mod framing;
 pub mod reader;
 pub mod rx;
 pub mod tx;
 
-use std::sync::Arc;
+use std::{
+  collections::{
+    HashMap,
+    HashSet,
+  },
+  path::Path,
+  sync::Arc,
+  time::Duration,
+};
 
 use crate::server::modman::{
   busses::{
@@ -16,22 +36,28 @@ use crate::server::modman::{
       BusMessage,
       BusTypes,
     },
-    proxies::uart::{
-      reader::uart_reader,
-      rx::uart_rx_thread,
-      tx::uart_tx_thread,
+    proxies::{
+      individual::uart::{
+        adoption::adopt_uart_module,
+        framing::FramingError,
+        reader::uart_reader,
+        rx::uart_rx_thread,
+        tx::uart_tx_thread,
+      },
+      Backoff,
     },
   },
   connections::ModuleConnection,
   models::{
-    ModManStore,
+    store::ModManStore,
     PortStatus,
   },
+  MODULE_EVT_ID,
 };
 use anyhow::anyhow;
-use serialport::{
-  self,
-  SerialPortInfo,
+use serde::{
+  Deserialize,
+  Serialize,
 };
 use tokio::{
   io::split,
@@ -41,15 +67,65 @@ use tokio_serial::{
   self,
   SerialStream,
 };
-use tokio_util::io::SyncIoBridge;
+use tokio_util::sync::CancellationToken;
 use tracing::{
   debug,
   error,
+  info,
   instrument,
+  warn,
 };
+
+/// How often ports are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 #[derive(Debug, Clone)]
 pub struct UARTBus {
   pub store: Arc<ModManStore>,
+  pub cancellation_token: CancellationToken,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UARTConfig {
+  /// Probe ports that aren't requested by a module, and adopt the modules on them once they're approved.
+  #[serde(default = "default_adopt_new_modules")]
+  pub adopt_new_modules: bool,
+  /// Baud rate that new modules are probed at, and bound at once they're adopted.
+  #[serde(default = "default_adoption_baud")]
+  pub adoption_baud: u32,
+  /// Seconds to wait for a new module's hello.
+  #[serde(default = "default_hello_timeout")]
+  pub hello_timeout: u64,
+  /// Seconds a new module's port stays open while it waits to be approved.
+  #[serde(default = "default_approval_timeout")]
+  pub approval_timeout: u64,
+}
+
+fn default_adopt_new_modules() -> bool {
+  false
+}
+
+fn default_adoption_baud() -> u32 {
+  115_200
+}
+
+fn default_hello_timeout() -> u64 {
+  10
+}
+
+fn default_approval_timeout() -> u64 {
+  300
+}
+
+impl Default for UARTConfig {
+  fn default() -> Self {
+    UARTConfig {
+      adopt_new_modules: default_adopt_new_modules(),
+      adoption_baud: default_adoption_baud(),
+      hello_timeout: default_hello_timeout(),
+      approval_timeout: default_approval_timeout(),
+    }
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -58,96 +134,249 @@ pub struct PortToBind {
   path: String,
 }
 
+/// An entry in the UART port table.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UARTPortInfo {
+  /// `None` if the port isn't present, and no module has requested it.
+  pub status: Option<PortStatus>,
+  pub present: bool,
+  /// Framing errors since the port was last bound.
+  pub framing_errors: u64,
+}
+
+/// Proxy threads of a port that's currently open.
+struct BoundPort {
+  token: CancellationToken,
+  handles: Vec<JoinHandle<()>>,
+}
+
+impl BoundPort {
+  /// Have any of the proxy threads stopped? (e.g. the reader when the port is unplugged.)
+  fn is_closed(&self) -> bool {
+    self.handles.iter().any(|handle| handle.is_finished())
+  }
+
+  async fn close(self) {
+    self.token.cancel();
+    futures::future::join_all(self.handles).await;
+  }
+}
+
+/// Allowed ports that are currently present.
+fn present_ports(allowed_ports: &[String]) -> HashSet<String> {
+  let mut present = HashSet::new();
+
+  match serialport::available_ports() {
+    Ok(ports) => {
+      for port in ports {
+        if allowed_ports.contains(&port.port_name) {
+          present.insert(port.port_name);
+        }
+      }
+    }
+    Err(err) => {
+      debug!("Failed to enumerate serial ports, due to:\n{err}");
+    }
+  }
+
+  // Not every port is enumerated (e.g. symlinks and pseudo-terminals), so check the paths too.
+  for allowed_port in allowed_ports {
+    if Path::new(allowed_port).exists() {
+      present.insert(allowed_port.clone());
+    }
+  }
+
+  present
+}
+
 impl Bus for UARTBus {
   #[instrument(name = "uart_bus", skip(self, session))]
   async fn subscribe_to_bus(
     self,
     session: Arc<zenoh::Session>,
   ) -> Result<tokio::task::JoinHandle<()>, anyhow::Error> {
-    match serialport::available_ports() {
-      Ok(port_info) => {
-        return Ok(tokio::task::spawn(async move {
-          loop {
-            let mut handles = vec![];
-
-            for port in port_info.clone() {
-              let mut attempt_bind: Option<PortToBind> = None;
-              let config = self.store.config.lock().await;
-              let mut port_statuses = self.store.port_statuses.uart.lock().await;
-              let allowed_ports = config.modman.uart_ports.clone();
-              drop(config);
-
-              for allowed_port in allowed_ports {
-                if allowed_port == port.port_name.clone() {
-                  let mut bound = false;
-                  let port_path = allowed_port.clone();
-
-                  for port_status in port_statuses.iter() {
-                    match port_status.1 {
-                      PortStatus::Available => {}
-                      PortStatus::Requested(module_id) => {
-                        debug!("Port: {allowed_port}, requested for Module ID: {module_id}");
-
-                        if port_status.0 == &port_path {
-                          attempt_bind = Some(PortToBind {
-                            module_id: module_id.clone(),
-                            path: allowed_port,
-                          });
-                        }
-
-                        break;
-                      }
-                      PortStatus::Bound(_port_status) => {
-                        bound = true;
-                      }
-                      PortStatus::Unavailable(module_id) => {
-                        if port_status.0 == &port_path {
-                          attempt_bind = Some(PortToBind {
-                            module_id: module_id.clone(),
-                            path: allowed_port,
-                          });
-                        }
-                        break;
-                      }
-                    }
-                  }
+    let table_store = self.store.clone();
+    let table_session = session.clone();
+    let table_token = self.cancellation_token.clone();
+    tokio::task::spawn(async move {
+      uart_port_table_queryable(table_store, table_session, table_token).await;
+    });
 
-                  match attempt_bind.clone() {
-                    Some(_bind_info) => {}
-                    Option::None => {
-                      if !bound {
-                        port_statuses.insert(port_path.clone(), PortStatus::Available);
-                      }
-                    }
-                  }
+    Ok(tokio::task::spawn(async move {
+      let mut bound_ports: HashMap<String, BoundPort> = HashMap::new();
+      let mut backoffs: HashMap<String, Backoff> = HashMap::new();
+      // Ports being adopted, they're open until the adoption is done, so they aren't bound in the meantime.
+      let mut adoptions: HashMap<String, JoinHandle<Result<String, anyhow::Error>>> =
+        HashMap::new();
+      // Not probed again until they're unplugged, so refused modules aren't probed over and over.
+      let mut refused: HashSet<String> = HashSet::new();
+
+      while !self.cancellation_token.is_cancelled() {
+        let (allowed_ports, uart_config) = {
+          let config = self.store.config.lock().await;
+          (config.modman.uart_ports.clone(), config.modman.uart.clone())
+        };
+        let present = present_ports(&allowed_ports);
+
+        let finished_adoptions: Vec<String> = adoptions
+          .iter()
+          .filter(|(_, handle)| handle.is_finished())
+          .map(|(path, _)| path.clone())
+          .collect();
+        for path in finished_adoptions {
+          if let Some(handle) = adoptions.remove(&path) {
+            match handle.await {
+              Ok(Ok(_)) => {}
+              Ok(Err(err)) => {
+                warn!("Unable to adopt the module on port: {path}, due to:\n{err}");
+                refused.insert(path);
+              }
+              Err(err) => {
+                error!("Adoption on port: {path}, panicked; this is a bug and should be reported! This happened due to:\n{err}");
+                refused.insert(path);
+              }
+            }
+          }
+        }
+        refused.retain(|path| present.contains(path));
+
+        // Proxy threads stop on their own when a port disappears.
+        let closed_ports: Vec<String> = bound_ports
+          .iter()
+          .filter(|(_, bound_port)| bound_port.is_closed())
+          .map(|(path, _)| path.clone())
+          .collect();
+        for path in closed_ports {
+          if let Some(bound_port) = bound_ports.remove(&path) {
+            bound_port.close().await;
+          }
+
+          let mut port_statuses = self.store.port_statuses.uart.lock().await;
+          if let Some(PortStatus::Bound(module_id)) = port_statuses.get(&path).cloned() {
+            warn!("Port: {path}, bound to module: {module_id}, was closed, will bind it again once it's available...");
+            port_statuses.insert(path.clone(), PortStatus::Unavailable(module_id));
+          }
+        }
+
+        let port_statuses_snapshot = self.store.port_statuses.uart.lock().await.clone();
 
-                  break;
+        for path in allowed_ports {
+          let is_present = present.contains(&path);
+
+          let next_status = match port_statuses_snapshot.get(&path).cloned() {
+            None | Some(PortStatus::Available) => match is_present {
+              true => {
+                if uart_config.adopt_new_modules
+                  && !adoptions.contains_key(&path)
+                  && !refused.contains(&path)
+                {
+                  debug!("Probing port: {path}, for a new module...");
+
+                  let adoption_session = session.clone();
+                  let adoption_path = path.clone();
+                  let hello_timeout = Duration::from_secs(uart_config.hello_timeout);
+                  let approval_timeout = Duration::from_secs(uart_config.approval_timeout);
+                  adoptions.insert(
+                    path.clone(),
+                    tokio::task::spawn(adopt_uart_module(
+                      adoption_session,
+                      adoption_path,
+                      uart_config.adoption_baud,
+                      hello_timeout,
+                      approval_timeout,
+                    )),
+                  );
                 }
+
+                Some(PortStatus::Available)
               }
+              false => None,
+            },
+            // The adoption still has the port open, it's bound once it's done.
+            Some(PortStatus::Requested(_)) if adoptions.contains_key(&path) => continue,
+            Some(PortStatus::Requested(module_id)) | Some(PortStatus::Unavailable(module_id)) => {
+              let backoff = backoffs.entry(path.clone()).or_default();
 
-              std::mem::drop(port_statuses);
-
-              match attempt_bind.clone() {
-                Some(bind_info) => {
-                  match bind_uart_port(bind_info, port, self.store.clone(), session.clone()).await {
-                    Ok(mut sub_handles) => {
-                      handles.append(&mut sub_handles);
-                    }
-                    Err(err) => {
-                      error!("Failed to bind port due to:\n{err}");
-                    }
+              if !is_present || !backoff.ready() {
+                Some(PortStatus::Unavailable(module_id))
+              } else {
+                let bind_info = PortToBind {
+                  module_id: module_id.clone(),
+                  path: path.clone(),
+                };
+
+                match bind_uart_port(bind_info, self.store.clone(), session.clone()).await {
+                  Ok(bound_port) => {
+                    bound_ports.insert(path.clone(), bound_port);
+                    backoffs.remove(&path);
+                    Some(PortStatus::Bound(module_id))
+                  }
+                  Err(err) => {
+                    backoff.failed();
+                    error!(
+                      "Failed to bind port: {path}, for module: {module_id} (attempt {}), due to:\n{err}",
+                      backoff.attempts
+                    );
+                    Some(PortStatus::Unavailable(module_id))
                   }
                 }
-                None => {}
               }
             }
+            Some(PortStatus::Bound(module_id)) => match bound_ports.get(&path) {
+              Some(_) if is_present => Some(PortStatus::Bound(module_id)),
+              _ => {
+                if let Some(bound_port) = bound_ports.remove(&path) {
+                  bound_port.close().await;
+                }
+
+                warn!("Port: {path}, bound to module: {module_id}, disappeared, will bind it again once it's available...");
+                Some(PortStatus::Unavailable(module_id))
+              }
+            },
+            Some(PortStatus::Unrequested(module_id)) => {
+              if let Some(bound_port) = bound_ports.remove(&path) {
+                info!("Releasing port: {path}, from module: {module_id}...");
+                bound_port.close().await;
+              }
+              backoffs.remove(&path);
+
+              match is_present {
+                true => Some(PortStatus::Available),
+                false => None,
+              }
+            }
+          };
 
-            futures::future::join_all(handles).await;
+          // Only update ports that weren't changed (e.g. by a module being deinitialized) while we were binding.
+          let mut port_statuses = self.store.port_statuses.uart.lock().await;
+          if port_statuses.get(&path) == port_statuses_snapshot.get(&path) {
+            match next_status {
+              Some(status) => {
+                port_statuses.insert(path.clone(), status);
+              }
+              None => {
+                port_statuses.remove(&path);
+              }
+            }
           }
-        }));
+        }
+
+        tokio::select! {
+          _ = self.cancellation_token.cancelled() => {},
+          _ = tokio::time::sleep(POLL_INTERVAL) => {},
+        }
       }
-      Err(err) => Err(anyhow!("Failed to get available ports due to:\n{err}")),
-    }
+
+      for (path, handle) in adoptions.drain() {
+        debug!("Stopping adoption on port: {path}...");
+        handle.abort();
+      }
+
+      for (path, bound_port) in bound_ports.drain() {
+        debug!("Closing port: {path}...");
+        bound_port.close().await;
+      }
+    }))
   }
 
   fn get_type() -> BusTypes {
@@ -158,16 +387,15 @@ impl Bus for UARTBus {
 /// Reusable code to bind a serial port for a function, and then run Zenoh endpoints for that binding.
 /// Should be called on startup for static modules, and then dynamically for app modules.
 #[instrument(skip(store, session))]
-pub async fn bind_uart_port(
+async fn bind_uart_port(
   bind_info: PortToBind,
-  port: SerialPortInfo,
   store: Arc<ModManStore>,
   session: Arc<zenoh::Session>,
-) -> Result<Vec<JoinHandle<()>>, anyhow::Error> {
+) -> Result<BoundPort, anyhow::Error> {
   debug!(
     "Module: {}, Attempting to bind to: {}...",
     bind_info.module_id.clone(),
-    port.port_name.clone()
+    bind_info.path.clone()
   );
 
   let modules_mutex = store.modules.lock().await;
@@ -192,40 +420,58 @@ pub async fn bind_uart_port(
         Ok(bound_port) => {
           let port_session = session.clone();
           let (port_read, port_write) = split(bound_port);
+          let port_token = CancellationToken::new();
 
-          store.port_statuses.uart.lock().await.insert(
-            bind_info.path.clone(),
-            PortStatus::Bound(bind_info.module_id.clone()),
-          );
+          store
+            .port_statuses
+            .uart_framing_errors
+            .lock()
+            .await
+            .insert(bind_info.path.clone(), 0);
 
           debug!(
-            "Module: {}, Port: {}, bound!",
-            bind_info.module_id, port.port_name
+            "Module: {}, Port: {}, bound at {} baud!",
+            bind_info.module_id, bind_info.path, connection_config.baud
           );
 
           let mut sub_handles = vec![];
 
-          let tx_port_ctx = (bind_info.module_id.clone(), port.port_name.clone());
+          let tx_port_ctx = (bind_info.module_id.clone(), bind_info.path.clone());
           let tx_bind_info = bind_info.clone();
           let tx_session = port_session.clone();
+          let tx_store = store.clone();
+          let tx_token = port_token.clone();
           sub_handles.push(tokio::task::spawn(async move {
-            uart_tx_thread(tx_bind_info, tx_session, tx_port_ctx, port_write).await;
+            uart_tx_thread(
+              tx_store,
+              tx_bind_info,
+              tx_session,
+              tx_port_ctx,
+              port_write,
+              tx_token,
+            )
+            .await;
           }));
 
-          let (read_channel, rx_channel) = tokio::sync::mpsc::unbounded_channel::<BusMessage>();
+          let (read_channel, rx_channel) =
+            tokio::sync::mpsc::unbounded_channel::<Result<BusMessage, FramingError>>();
 
-          let rx_port_ctx = (bind_info.module_id.clone(), port.port_name.clone());
+          let rx_port_ctx = (bind_info.module_id.clone(), bind_info.path.clone());
           let rx_session = port_session.clone();
+          let rx_store = store.clone();
           sub_handles.push(tokio::task::spawn(async move {
-            uart_rx_thread(rx_port_ctx, rx_channel, rx_session).await;
+            uart_rx_thread(rx_store, rx_port_ctx, rx_channel, rx_session).await;
           }));
 
-          let bridge = SyncIoBridge::new(port_read);
-          sub_handles.push(tokio::task::spawn_blocking(move || {
-            uart_reader(bridge, read_channel);
+          let reader_token = port_token.clone();
+          sub_handles.push(tokio::task::spawn(async move {
+            uart_reader(port_read, read_channel, reader_token).await;
           }));
 
-          Ok(sub_handles)
+          Ok(BoundPort {
+            token: port_token,
+            handles: sub_handles,
+          })
         }
         Err(err) => {
           return Err(err.into());
@@ -238,3 +484,68 @@ pub async fn bind_uart_port(
     }
   }
 }
+
+/// Reply with the status of every allowed port, and any other ports requested by modules.
+#[instrument(skip(store, session, cancellation_token))]
+pub async fn uart_port_table_queryable(
+  store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+) {
+  let key_expr = format!("{MODULE_EVT_ID}/busses/uart/ports");
+
+  let queryable = match session.declare_queryable(&key_expr).await {
+    Ok(queryable) => queryable,
+    Err(err) => {
+      error!("Unable to create a zenoh queryable at: {key_expr}, due to:\n{err}");
+      return;
+    }
+  };
+
+  debug!("Listening on {key_expr}!");
+  loop {
+    let query = tokio::select! {
+      _ = cancellation_token.cancelled() => break,
+      query = queryable.recv_async() => match query {
+        Ok(query) => query,
+        Err(err) => {
+          error!("{err}");
+          break;
+        }
+      },
+    };
+
+    let allowed_ports = store.config.lock().await.modman.uart_ports.clone();
+    let present = present_ports(&allowed_ports);
+    let port_statuses = store.port_statuses.uart.lock().await.clone();
+    let framing_errors = store.port_statuses.uart_framing_errors.lock().await.clone();
+
+    let mut paths: HashSet<String> = allowed_ports.into_iter().collect();
+    paths.extend(port_statuses.keys().cloned());
+
+    let port_table: HashMap<String, UARTPortInfo> = paths
+      .into_iter()
+      .map(|path| {
+        let info = UARTPortInfo {
+          status: port_statuses.get(&path).cloned(),
+          present: present.contains(&path),
+          framing_errors: framing_errors.get(&path).cloned().unwrap_or(0),
+        };
+
+        (path, info)
+      })
+      .collect();
+
+    match serde_json::to_string(&port_table) {
+      Ok(port_table_str) => match query.reply(&key_expr, port_table_str).await {
+        Ok(_) => {}
+        Err(err) => {
+          error!("Failed to reply to UART port table query, due to:\n{err}");
+        }
+      },
+      Err(err) => {
+        error!("Failed to serialize UART port table; this is a bug and should be reported! This happened due to:\n{err}");
+      }
+    }
+  }
+}
@@ -1,23 +1,70 @@
 use serde::de::DeserializeOwned;
 use tokio::{
-  io::ReadHalf,
+  io::{
+    AsyncRead,
+    AsyncReadExt,
+  },
   sync::mpsc::UnboundedSender,
 };
-use tokio_serial::SerialStream;
-use tokio_util::io::SyncIoBridge;
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  error,
+};
+
+use super::framing::{
+  FrameBuffer,
+  FramingError,
+};
 
-pub fn uart_reader<Msg>(
-  mut bridge: SyncIoBridge<ReadHalf<SerialStream>>,
-  channel: UnboundedSender<Msg>,
+/// Read frames from a serial port (see [framing](super::framing)), and forward the decoded messages (or framing errors) to the rx thread.
+///
+/// Runs until the port is closed (e.g. unplugged), the rx thread stops listening, or the port is released.
+pub async fn uart_reader<R, Msg>(
+  mut reader: R,
+  channel: UnboundedSender<Result<Msg, FramingError>>,
+  cancellation_token: CancellationToken,
 ) where
+  R: AsyncRead + Unpin,
   Msg: DeserializeOwned,
 {
-  let mut should_read = true;
+  let mut frames = FrameBuffer::default();
+  let mut read_buf = [0u8; 256];
+
+  loop {
+    let read = tokio::select! {
+      _ = cancellation_token.cancelled() => break,
+      read = reader.read(&mut read_buf) => read,
+    };
+
+    match read {
+      Ok(0) => {
+        debug!("UART port closed, stopping reader.");
+        break;
+      }
+      Ok(len) => {
+        for frame in frames.push(&read_buf[..len]) {
+          let msg = frame.and_then(|payload| {
+            rmp_serde::from_slice::<Msg>(&payload).map_err(|err| FramingError::InvalidMessage {
+              reason: err.to_string(),
+            })
+          });
 
-  while should_read {
-    match rmp_serde::from_read::<_, Msg>(&mut bridge) {
-      Ok(msg) => {}
-      Err(err) => todo!(),
+          if channel.send(msg).is_err() {
+            debug!("UART rx thread stopped, stopping reader.");
+            return;
+          }
+        }
+      }
+      Err(err) => match err.kind() {
+        std::io::ErrorKind::Interrupted
+        | std::io::ErrorKind::TimedOut
+        | std::io::ErrorKind::WouldBlock => {}
+        _ => {
+          error!("Failed to read from UART port, stopping reader. Due to:\n{err}");
+          break;
+        }
+      },
     }
   }
 }
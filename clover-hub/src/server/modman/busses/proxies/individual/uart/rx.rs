@@ -1,7 +1,16 @@
 use std::sync::Arc;
 
 use crate::server::modman::{
-  busses::models::BusMessage,
+  busses::{
+    auth::{
+      report_content_error,
+      verify_bus_message,
+      ContentError,
+    },
+    models::BusMessage,
+    proxies::individual::uart::framing::FramingError,
+  },
+  models::store::ModManStore,
   MODULE_EVT_ID,
 };
 use tokio::sync::mpsc::UnboundedReceiver;
@@ -15,13 +24,14 @@ use zenoh_ext::{
   CacheConfig,
 };
 
-#[instrument(skip(port_session))]
+#[instrument(skip(store, port_session))]
 pub async fn uart_rx_thread(
+  store: Arc<ModManStore>,
   rx_port_ctx: (String, String),
-  mut rx_channel: UnboundedReceiver<BusMessage>,
+  mut rx_channel: UnboundedReceiver<Result<BusMessage, FramingError>>,
   port_session: Arc<zenoh::Session>,
 ) {
-  let (module_id, _port_name) = rx_port_ctx;
+  let (module_id, port_name) = rx_port_ctx;
 
   let key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{}/recv", &module_id);
 
@@ -34,6 +44,35 @@ pub async fn uart_rx_thread(
       // If we don't fail out, this block should be the one that gets put in that new thread.
 
       while let Some(msg) = rx_channel.recv().await {
+        let msg = match msg {
+          Ok(msg) => msg,
+          Err(framing_error) => {
+            let count = {
+              let mut framing_errors = store.port_statuses.uart_framing_errors.lock().await;
+              let count = framing_errors.entry(port_name.clone()).or_insert(0);
+              *count += 1;
+              *count
+            };
+
+            report_content_error(
+              &port_session,
+              &module_id,
+              &ContentError::Framing {
+                port: port_name.clone(),
+                reason: framing_error.to_string(),
+                count,
+              },
+            )
+            .await;
+            continue;
+          }
+        };
+
+        if let Err(content_error) = verify_bus_message(&store, &module_id, &msg).await {
+          report_content_error(&port_session, &module_id, &content_error).await;
+          continue;
+        }
+
         match serde_json::to_string(&msg) {
           Ok(msg_str) => match publisher.put(&msg_str).await {
             Ok(_) => {
@@ -13,3 +13,42 @@
 
 pub mod group;
 pub mod individual;
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// First delay before a port that failed is retried, doubled after every failure.
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Retry schedule for a port that couldn't be bound, or failed once it was.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+  pub attempts: u32,
+  next_attempt: Instant,
+}
+
+impl Default for Backoff {
+  fn default() -> Self {
+    Backoff {
+      attempts: 0,
+      next_attempt: Instant::now(),
+    }
+  }
+}
+
+impl Backoff {
+  pub fn ready(&self) -> bool {
+    Instant::now() >= self.next_attempt
+  }
+
+  pub fn failed(&mut self) {
+    let delay = BACKOFF_BASE
+      .saturating_mul(2u32.saturating_pow(self.attempts))
+      .min(BACKOFF_MAX);
+
+    self.attempts += 1;
+    self.next_attempt = Instant::now() + delay;
+  }
+}
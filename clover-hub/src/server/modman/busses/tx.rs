@@ -0,0 +1,128 @@
+//! # Bus TX
+//!
+//! Every bus proxy answers queries on `.../modules/by-id/{module_id}/send` the same way, only writing the encoded message to the module ([`ModuleTX`]) is bus specific.
+//!
+
+use std::sync::Arc;
+
+use tracing::{
+  debug,
+  error,
+};
+
+use crate::server::modman::{
+  busses::{
+    auth::{
+      report_content_error,
+      rotate_session_if_due,
+      sign_for_module,
+    },
+    models::{
+      BusMessage,
+      BusTXError,
+    },
+  },
+  models::store::ModManStore,
+};
+
+/// A bus' way of writing an encoded [`BusMessage`] to a bound module.
+pub trait ModuleTX {
+  fn send(
+    &mut self,
+    msg_bytes: Vec<u8>,
+  ) -> impl std::future::Future<Output = Result<(), anyhow::Error>> + Send;
+}
+
+/// Send the JSON encoded [`BusMessage`] in a query's payload to a module, over its [`ModuleTX`], and reply with the number of bytes sent (or a [`BusTXError`].)
+///
+/// Content is (re-)signed with the module's key, since queriers don't have it. If the module's session is due to rotate, the rekey is sent first.
+pub async fn handle_send_query(
+  store: &ModManStore,
+  session: &Arc<zenoh::Session>,
+  module_id: &String,
+  query: &zenoh::query::Query,
+  key_expr: &str,
+  bus_name: &str,
+  module_tx: &mut impl ModuleTX,
+) {
+  let payload_str = match query.payload() {
+    Some(query_payload) => match query_payload.try_to_string() {
+      Ok(payload_str) => payload_str.to_string(),
+      Err(err) => {
+        error!("Query's payload could not be decoded into a string, due to:\n{err}");
+        BusTXError::PayloadIsNotString.reply(query, key_expr).await;
+        return;
+      }
+    },
+    None => {
+      error!("Query was sent to the module endpoint without a payload!");
+      BusTXError::MissingPayload.reply(query, key_expr).await;
+      return;
+    }
+  };
+
+  let message = match serde_json_lenient::from_str::<BusMessage>(&payload_str) {
+    Ok(message) => message,
+    Err(err) => {
+      error!("Invalid query payload, due to:\n{err}");
+      BusTXError::MalformedPayload.reply(query, key_expr).await;
+      return;
+    }
+  };
+
+  // The module has to switch sessions before anything is signed with the next session's key.
+  match rotate_session_if_due(store, module_id).await {
+    Ok(Some(rekey)) => match rmp_serde::to_vec(&rekey) {
+      Ok(rekey_bytes) => {
+        if let Err(err) = module_tx.send(rekey_bytes).await {
+          error!("Unable to send session rekey to {bus_name} module due to:\n{err}");
+        }
+      }
+      Err(err) => {
+        error!(
+          "Could not encode session rekey, this is a bug and should be reported! Due to:\n{err}"
+        );
+      }
+    },
+    Ok(None) => {}
+    Err(content_error) => {
+      report_content_error(session, module_id, &content_error).await;
+    }
+  }
+
+  let message = match message {
+    BusMessage::Content(content) => match sign_for_module(store, module_id, content.data).await {
+      Ok(signed) => signed,
+      Err(content_error) => {
+        report_content_error(session, module_id, &content_error).await;
+        BusTXError::SigningFailed.reply(query, key_expr).await;
+        return;
+      }
+    },
+    message => message,
+  };
+
+  let msg_bytes = match rmp_serde::to_vec(&message) {
+    Ok(msg_bytes) => msg_bytes,
+    Err(err) => {
+      error!("Could not turn the BusMessage into a valid MSGPack byte array, this is a bug and should be reported! Due to:\n{err}");
+      BusTXError::EncodingFailed.reply(query, key_expr).await;
+      return;
+    }
+  };
+
+  debug!("Sending {bus_name} message to module: {module_id}...");
+  let msg_len = msg_bytes.len();
+  match module_tx.send(msg_bytes).await {
+    Ok(_) => {
+      debug!("Successfully sent {bus_name} message to module: {module_id}!");
+      if let Err(err) = query.reply(key_expr, format!("{msg_len}")).await {
+        error!("Failed to reply to client that we were able to send the message, due to:\n{err}");
+      }
+    }
+    Err(err) => {
+      error!("Unable to send message to {bus_name} module due to:\n{err}");
+      BusTXError::TXFailed.reply(query, key_expr).await;
+    }
+  }
+}
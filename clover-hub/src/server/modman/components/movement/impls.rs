@@ -1,9 +1,25 @@
-use super::models::MovementComponent;
+use super::models::{
+  DegreesOfFreedom,
+  DegreesOfFreedomGestureParams,
+  MovementComponent,
+  Position2D,
+  Position3D,
+  Position4D,
+  Position5D,
+  Position6D,
+};
 use crate::server::modman::{
   components::models::CloverComponentTrait,
-  models::store::ModManStore,
+  models::{
+    gestures::GestureParameters,
+    store::ModManStore,
+  },
+};
+use std::{
+  collections::HashMap,
+  sync::Arc,
 };
-use std::sync::Arc;
+use tracing::warn;
 
 impl CloverComponentTrait for MovementComponent {
   async fn init(&mut self, store: Arc<ModManStore>) -> Result<(), anyhow::Error> {
@@ -14,3 +30,338 @@ impl CloverComponentTrait for MovementComponent {
     todo!()
   }
 }
+
+impl DegreesOfFreedom {
+  /// A position at the origin with the given number of degrees of freedom (1 to 6).
+  pub fn zeroed(degrees: usize) -> Option<Self> {
+    let position = match degrees {
+      1 => DegreesOfFreedom::OneDegree(0.0),
+      2 => DegreesOfFreedom::TwoDegrees(Position2D { x: 0.0, y: 0.0 }),
+      3 => DegreesOfFreedom::ThreeDegrees(Position3D {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+      }),
+      4 => DegreesOfFreedom::FourDegrees(Position4D {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        u: 0.0,
+      }),
+      5 => DegreesOfFreedom::FiveDegrees(Position5D {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        u: 0.0,
+        v: 0.0,
+      }),
+      6 => DegreesOfFreedom::SixDegrees(Position6D {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        u: 0.0,
+        v: 0.0,
+        w: 0.0,
+      }),
+      _ => return None,
+    };
+
+    Some(position)
+  }
+
+  /// Every axis of this position and its value, a single degree of freedom is the `x` axis.
+  pub fn axes(&self) -> Vec<(&'static str, f64)> {
+    match self {
+      DegreesOfFreedom::OneDegree(x) => vec![("x", *x)],
+      DegreesOfFreedom::TwoDegrees(pos) => vec![("x", pos.x), ("y", pos.y)],
+      DegreesOfFreedom::ThreeDegrees(pos) => vec![("x", pos.x), ("y", pos.y), ("z", pos.z)],
+      DegreesOfFreedom::FourDegrees(pos) => {
+        vec![("x", pos.x), ("y", pos.y), ("z", pos.z), ("u", pos.u)]
+      }
+      DegreesOfFreedom::FiveDegrees(pos) => vec![
+        ("x", pos.x),
+        ("y", pos.y),
+        ("z", pos.z),
+        ("u", pos.u),
+        ("v", pos.v),
+      ],
+      DegreesOfFreedom::SixDegrees(pos) => vec![
+        ("x", pos.x),
+        ("y", pos.y),
+        ("z", pos.z),
+        ("u", pos.u),
+        ("v", pos.v),
+        ("w", pos.w),
+      ],
+    }
+  }
+
+  /// Set a single axis, returns false if this position doesn't have that axis.
+  pub fn set_axis(&mut self, axis: &str, value: f64) -> bool {
+    let target = match (self, axis) {
+      (DegreesOfFreedom::OneDegree(x), "x") => x,
+      (DegreesOfFreedom::TwoDegrees(pos), "x") => &mut pos.x,
+      (DegreesOfFreedom::TwoDegrees(pos), "y") => &mut pos.y,
+      (DegreesOfFreedom::ThreeDegrees(pos), "x") => &mut pos.x,
+      (DegreesOfFreedom::ThreeDegrees(pos), "y") => &mut pos.y,
+      (DegreesOfFreedom::ThreeDegrees(pos), "z") => &mut pos.z,
+      (DegreesOfFreedom::FourDegrees(pos), "x") => &mut pos.x,
+      (DegreesOfFreedom::FourDegrees(pos), "y") => &mut pos.y,
+      (DegreesOfFreedom::FourDegrees(pos), "z") => &mut pos.z,
+      (DegreesOfFreedom::FourDegrees(pos), "u") => &mut pos.u,
+      (DegreesOfFreedom::FiveDegrees(pos), "x") => &mut pos.x,
+      (DegreesOfFreedom::FiveDegrees(pos), "y") => &mut pos.y,
+      (DegreesOfFreedom::FiveDegrees(pos), "z") => &mut pos.z,
+      (DegreesOfFreedom::FiveDegrees(pos), "u") => &mut pos.u,
+      (DegreesOfFreedom::FiveDegrees(pos), "v") => &mut pos.v,
+      (DegreesOfFreedom::SixDegrees(pos), "x") => &mut pos.x,
+      (DegreesOfFreedom::SixDegrees(pos), "y") => &mut pos.y,
+      (DegreesOfFreedom::SixDegrees(pos), "z") => &mut pos.z,
+      (DegreesOfFreedom::SixDegrees(pos), "u") => &mut pos.u,
+      (DegreesOfFreedom::SixDegrees(pos), "v") => &mut pos.v,
+      (DegreesOfFreedom::SixDegrees(pos), "w") => &mut pos.w,
+      _ => return false,
+    };
+
+    *target = value;
+    true
+  }
+}
+
+impl DegreesOfFreedomGestureParams {
+  /// Gesture parameters for every axis, a single degree of freedom is the `x` axis.
+  pub fn axes(&self) -> Vec<(&'static str, &GestureParameters)> {
+    match self {
+      DegreesOfFreedomGestureParams::OneDegree(x) => vec![("x", x)],
+      DegreesOfFreedomGestureParams::TwoDegrees(params) => {
+        vec![("x", &params.x), ("y", &params.y)]
+      }
+      DegreesOfFreedomGestureParams::ThreeDegrees(params) => {
+        vec![("x", &params.x), ("y", &params.y), ("z", &params.z)]
+      }
+      DegreesOfFreedomGestureParams::FourDegrees(params) => vec![
+        ("x", &params.x),
+        ("y", &params.y),
+        ("z", &params.z),
+        ("u", &params.u),
+      ],
+      DegreesOfFreedomGestureParams::FiveDegrees(params) => vec![
+        ("x", &params.x),
+        ("y", &params.y),
+        ("z", &params.z),
+        ("u", &params.u),
+        ("v", &params.v),
+      ],
+      DegreesOfFreedomGestureParams::SixDegrees(params) => vec![
+        ("x", &params.x),
+        ("y", &params.y),
+        ("z", &params.z),
+        ("u", &params.u),
+        ("v", &params.v),
+        ("w", &params.w),
+      ],
+    }
+  }
+}
+
+impl MovementComponent {
+  /// Target position for gesture intensities (-1.0 to 1.0) keyed by axis, each axis is clamped to its limits.
+  ///
+  /// Axes that the gesture doesn't drive are held at the initial position, returns `None` if the gesture doesn't drive any axes. If `parameters_override` is set (from the component's [gesture config](crate::server::modman::models::gestures::GestureConfig)), it's used for every axis instead of the per-axis parameters.
+  pub fn gesture_position(
+    &self,
+    intensities: &HashMap<String, f64>,
+    parameters_override: Option<&GestureParameters>,
+  ) -> Option<DegreesOfFreedom> {
+    let axis_parameters: Vec<(&str, &GestureParameters)> = match parameters_override {
+      Some(parameters) => self
+        .initial_position
+        .axes()
+        .into_iter()
+        .map(|(axis, _)| (axis, parameters))
+        .collect(),
+      None => self.gesture_parameters.as_ref()?.axes(),
+    };
+    let mut position = self.initial_position.clone();
+    let mut driven = false;
+
+    for (axis, parameters) in axis_parameters {
+      if let Some(intensity) = intensities.get(axis) {
+        let mut target = parameters.calculate_intensity(*intensity);
+
+        if let Some(limits) = self.axis_limits.get(axis) {
+          target = target.clamp(limits.min.min(limits.max), limits.max.max(limits.min));
+        }
+
+        if position.set_axis(axis, target) {
+          driven = true;
+        } else {
+          warn!("Gesture parameters define axis: {axis}, which isn't part of the component's initial position, ignoring it!");
+        }
+      }
+    }
+
+    if driven {
+      Some(position)
+    } else {
+      None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::server::modman::{
+    components::movement::models::{
+      AxisLimits,
+      ConnectionType,
+      GestureParamsPosition2D,
+      GestureParamsPosition5D,
+    },
+    models::gestures::GestureConfig,
+  };
+
+  /// Maps intensities -1.0, 0.0, and 1.0 onto `min`, `(min + max) / 2`, and `max`.
+  fn parameters(min: f64, max: f64) -> GestureParameters {
+    GestureParameters {
+      min,
+      max: (min + max) / 2.0,
+      multiplier: 1.0,
+      offset: 0.0,
+    }
+  }
+
+  fn movement_component(
+    initial_position: DegreesOfFreedom,
+    gesture_parameters: Option<DegreesOfFreedomGestureParams>,
+  ) -> MovementComponent {
+    MovementComponent {
+      initial_position,
+      gesture_config: GestureConfig::default(),
+      gesture_parameters,
+      axis_limits: HashMap::new(),
+      connection: ConnectionType::ModManProxy,
+    }
+  }
+
+  #[test]
+  fn targets_are_clamped_to_axis_limits() {
+    let mut component = movement_component(
+      DegreesOfFreedom::OneDegree(0.0),
+      Some(DegreesOfFreedomGestureParams::OneDegree(parameters(
+        -90.0, 90.0,
+      ))),
+    );
+    component.axis_limits.insert(
+      "x".to_string(),
+      AxisLimits {
+        min: -45.0,
+        max: 45.0,
+      },
+    );
+
+    let intensities = |x: f64| HashMap::from([("x".to_string(), x)]);
+    assert_eq!(
+      component.gesture_position(&intensities(1.0), None),
+      Some(DegreesOfFreedom::OneDegree(45.0))
+    );
+    assert_eq!(
+      component.gesture_position(&intensities(0.25), None),
+      Some(DegreesOfFreedom::OneDegree(22.5))
+    );
+
+    // Limits are a range, whichever way around they're written.
+    component.axis_limits.insert(
+      "x".to_string(),
+      AxisLimits {
+        min: 45.0,
+        max: -45.0,
+      },
+    );
+    assert_eq!(
+      component.gesture_position(&intensities(-1.0), None),
+      Some(DegreesOfFreedom::OneDegree(-45.0))
+    );
+  }
+
+  #[test]
+  fn five_degrees_hold_undriven_axes() {
+    let component = movement_component(
+      DegreesOfFreedom::FiveDegrees(Position5D {
+        x: 1.0,
+        y: 2.0,
+        z: 3.0,
+        u: 4.0,
+        v: 5.0,
+      }),
+      Some(DegreesOfFreedomGestureParams::FiveDegrees(
+        GestureParamsPosition5D {
+          x: parameters(-1.0, 1.0),
+          y: parameters(-1.0, 1.0),
+          z: parameters(-1.0, 1.0),
+          u: parameters(-10.0, 10.0),
+          v: parameters(-20.0, 20.0),
+        },
+      )),
+    );
+
+    let intensities = HashMap::from([
+      ("u".to_string(), 1.0),
+      ("v".to_string(), -0.5),
+      // Not an axis of this component.
+      ("w".to_string(), 1.0),
+    ]);
+    assert_eq!(
+      component.gesture_position(&intensities, None),
+      Some(DegreesOfFreedom::FiveDegrees(Position5D {
+        x: 1.0,
+        y: 2.0,
+        z: 3.0,
+        u: 10.0,
+        v: -10.0,
+      }))
+    );
+
+    assert_eq!(
+      component.gesture_position(&HashMap::from([("w".to_string(), 1.0)]), None),
+      None
+    );
+  }
+
+  #[test]
+  fn parameters_override_applies_to_every_axis() {
+    let component = movement_component(
+      DegreesOfFreedom::TwoDegrees(Position2D { x: 0.0, y: 0.0 }),
+      Some(DegreesOfFreedomGestureParams::TwoDegrees(
+        GestureParamsPosition2D {
+          x: parameters(-1.0, 1.0),
+          y: parameters(-1.0, 1.0),
+        },
+      )),
+    );
+    let intensities = HashMap::from([("x".to_string(), 1.0), ("y".to_string(), -1.0)]);
+
+    assert_eq!(
+      component.gesture_position(&intensities, Some(&parameters(-30.0, 30.0))),
+      Some(DegreesOfFreedom::TwoDegrees(Position2D {
+        x: 30.0,
+        y: -30.0
+      }))
+    );
+
+    // Components without per-axis parameters can still be driven through an override.
+    let component = movement_component(
+      DegreesOfFreedom::TwoDegrees(Position2D { x: 0.0, y: 0.0 }),
+      None,
+    );
+    assert_eq!(component.gesture_position(&intensities, None), None);
+    assert_eq!(
+      component.gesture_position(&intensities, Some(&parameters(-30.0, 30.0))),
+      Some(DegreesOfFreedom::TwoDegrees(Position2D {
+        x: 30.0,
+        y: -30.0
+      }))
+    );
+  }
+}
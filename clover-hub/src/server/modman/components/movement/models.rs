@@ -6,6 +6,7 @@ use serde::{
   Deserialize,
   Serialize,
 };
+use std::collections::HashMap;
 use strum::VariantNames;
 
 #[derive(Debug, Clone, Serialize, Deserialize, VariantNames)]
@@ -15,99 +16,100 @@ pub enum ConnectionType {
   ModManProxy,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Position2D {
-  x: f64,
-  y: f64,
+  pub x: f64,
+  pub y: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Position3D {
-  x: f64,
-  y: f64,
-  z: f64,
+  pub x: f64,
+  pub y: f64,
+  pub z: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Position4D {
-  x: f64,
-  y: f64,
-  z: f64,
+  pub x: f64,
+  pub y: f64,
+  pub z: f64,
 
-  u: f64,
+  pub u: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Position5D {
-  x: f64,
-  y: f64,
-  z: f64,
+  pub x: f64,
+  pub y: f64,
+  pub z: f64,
 
-  u: f64,
-  v: f64,
+  pub u: f64,
+  pub v: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Position6D {
-  x: f64,
-  y: f64,
-  z: f64,
+  pub x: f64,
+  pub y: f64,
+  pub z: f64,
 
-  u: f64,
-  v: f64,
-  w: f64,
+  pub u: f64,
+  pub v: f64,
+  pub w: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum DegreesOfFreedom {
   OneDegree(f64),
   TwoDegrees(Position2D),
   ThreeDegrees(Position3D),
   FourDegrees(Position4D),
+  FiveDegrees(Position5D),
   SixDegrees(Position6D),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GestureParamsPosition2D {
-  x: GestureParameters,
-  y: GestureParameters,
+  pub x: GestureParameters,
+  pub y: GestureParameters,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GestureParamsPosition3D {
-  x: GestureParameters,
-  y: GestureParameters,
-  z: GestureParameters,
+  pub x: GestureParameters,
+  pub y: GestureParameters,
+  pub z: GestureParameters,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GestureParamsPosition4D {
-  x: GestureParameters,
-  y: GestureParameters,
-  z: GestureParameters,
+  pub x: GestureParameters,
+  pub y: GestureParameters,
+  pub z: GestureParameters,
 
-  u: GestureParameters,
+  pub u: GestureParameters,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GestureParamsPosition5D {
-  x: GestureParameters,
-  y: GestureParameters,
-  z: GestureParameters,
+  pub x: GestureParameters,
+  pub y: GestureParameters,
+  pub z: GestureParameters,
 
-  u: GestureParameters,
-  v: GestureParameters,
+  pub u: GestureParameters,
+  pub v: GestureParameters,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GestureParamsPosition6D {
-  x: GestureParameters,
-  y: GestureParameters,
-  z: GestureParameters,
+  pub x: GestureParameters,
+  pub y: GestureParameters,
+  pub z: GestureParameters,
 
-  u: GestureParameters,
-  v: GestureParameters,
-  w: GestureParameters,
+  pub u: GestureParameters,
+  pub v: GestureParameters,
+  pub w: GestureParameters,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -116,13 +118,27 @@ pub enum DegreesOfFreedomGestureParams {
   TwoDegrees(GestureParamsPosition2D),
   ThreeDegrees(GestureParamsPosition3D),
   FourDegrees(GestureParamsPosition4D),
+  FiveDegrees(GestureParamsPosition5D),
   SixDegrees(GestureParamsPosition6D),
 }
 
+/// Range that an axis is physically allowed to move within, gesture targets are clamped to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AxisLimits {
+  pub min: f64,
+  pub max: f64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MovementComponent {
   /// The initial position of this component, also determines how many degrees of freedom it uses. **NON OPTIONAL!**
   pub initial_position: DegreesOfFreedom,
   pub gesture_config: GestureConfig,
+  /// How gesture parameters named after each axis (`x`, `y`, `z`, `u`, `v`, `w`; `x` for a single degree of freedom) map onto this component's position, gestures are ignored if unset.
+  #[serde(default)]
+  pub gesture_parameters: Option<DegreesOfFreedomGestureParams>,
+  /// Per-axis limits, keyed by axis name.
+  #[serde(default)]
+  pub axis_limits: HashMap<String, AxisLimits>,
   pub connection: ConnectionType,
 }
@@ -19,7 +19,7 @@ pub enum ModuleConnection {
   /// Device ID
   #[cfg(feature = "bt_classic")]
   BT(String),
-  /// Device ID
+  /// Module's address, e.g. `AA:BB:CC:DD:EE:FF`
   #[cfg(feature = "bt_le")]
   BTLE(String),
   /// Bus path and Device ID
@@ -37,6 +37,7 @@ pub enum ModuleConnection {
 pub struct CANFDConnection {
   pub bus_id: String,
   pub device_id: String,
+  pub reply_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
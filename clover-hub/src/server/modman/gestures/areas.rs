@@ -0,0 +1,528 @@
+//! # Body Areas
+//!
+//! Areas are dot-separated paths on/in the user that [component locations](crate::server::modman::models::components::CloverComponentMeta::location) and gestures refer to, e.g. `com.reboot-codes.clover.CORE.humanoid.torso.head.face.eyes.left.eye`.
+//!
+//! Every area belongs to a body, which is identified by its RFQDN (`@core.humanoid` is shorthand for the built-in humanoid body). Warehouse manifests can define supplemental bodies that extend other bodies, like a plantigrade canine body extending `@core.humanoid` with a tail and paws, which are then layered together based on [`ModManConfig::bodies`](crate::server::modman::models::config::ModManConfig::bodies).
+//!
+//! Area queries support globs, where `*` matches any run of characters (including `.`) and `?` matches any single character. Queries that start with neither `@`, `*`, nor a known body RFQDN are relative to the configured bodies. A query also matches everything nested under the areas it matches, so `*.head.face.eyes` will select both eyes, their eyelids, and eyebrows.
+//!
+//! ## Default Humanoid Areas
+//!
+//! - `torso`
+//!   - `core`: `back.spine.{upper,middle,bottom}`, `front`
+//!   - `chest`: `{front,back}.{right,center,left}`
+//!   - `arms.{right,left}`: `shoulder`, `biceps`, `forearm`, `hand.fingers.{thumb,index,middle,ring,pinky}`
+//!   - `head`: `neck`, `face.{jaw,mouth,nose}`, `face.eyes.{right,left}.{eye,eyelids.{upper,lower},eyebrow}`, `ears.{right,left}`, `top`, `back`
+//! - `pelvis`: `waist.{left,right,upper,lower}`, `front.{upper,lower}`, `bottom`, `rear.{right,left}`
+//! - `legs.{right,left}`: `thigh`, `shin`, `foot.toes.{hallux,index,middle,ring,pinky}`
+//!
+
+use std::collections::{
+  BTreeSet,
+  HashMap,
+};
+
+use tracing::{
+  debug,
+  info,
+  instrument,
+  warn,
+};
+
+use crate::server::{
+  modman::models::store::ModManStore,
+  warehouse::repos::{
+    builtin_rfqdn,
+    models::{
+      BodySpec,
+      Optional,
+      OptionalStrTHashMap,
+      OptionalString,
+      OptionalStringList,
+    },
+  },
+};
+
+/// Relative areas of the built-in humanoid body, `{a,b}` expands to both alternatives.
+const HUMANOID_AREAS: &[&str] = &[
+  "torso.core.back.spine.{upper,middle,bottom}",
+  "torso.core.front",
+  "torso.chest.{front,back}.{right,center,left}",
+  "torso.arms.{right,left}.{shoulder,biceps,forearm}",
+  "torso.arms.{right,left}.hand.fingers.{thumb,index,middle,ring,pinky}",
+  "torso.head.neck",
+  "torso.head.face.{jaw,mouth,nose}",
+  "torso.head.face.eyes.{right,left}.{eye,eyebrow}",
+  "torso.head.face.eyes.{right,left}.eyelids.{upper,lower}",
+  "torso.head.ears.{right,left}",
+  "torso.head.{top,back}",
+  "pelvis.waist.{left,right,upper,lower}",
+  "pelvis.front.{upper,lower}",
+  "pelvis.bottom",
+  "pelvis.rear.{right,left}",
+  "legs.{right,left}.{thigh,shin}",
+  "legs.{right,left}.foot.toes.{hallux,index,middle,ring,pinky}",
+];
+
+/// RFQDN of the built-in humanoid body.
+pub fn humanoid_body_id() -> String {
+  format!("{}.humanoid", builtin_rfqdn(true))
+}
+
+/// Replace the `@core` and `@clover` shorthands at the start of an area or body RFQDN.
+pub fn normalize_area(area: &str) -> String {
+  if let Some(rest) = area.strip_prefix("@core") {
+    format!("{}{rest}", builtin_rfqdn(true))
+  } else if let Some(rest) = area.strip_prefix("@clover") {
+    format!("{}{rest}", builtin_rfqdn(false))
+  } else {
+    area.to_string()
+  }
+}
+
+/// Expand `{a,b}` alternatives in an area path, e.g. `arms.{right,left}` becomes `arms.right` and `arms.left`.
+pub fn expand_alternatives(area: &str) -> Vec<String> {
+  match (area.find('{'), area.find('}')) {
+    (Some(start), Some(end)) if start < end => {
+      let (prefix, suffix) = (&area[..start], &area[end + 1..]);
+
+      area[start + 1..end]
+        .split(',')
+        .flat_map(|alternative| expand_alternatives(&format!("{prefix}{alternative}{suffix}")))
+        .collect()
+    }
+    _ => vec![area.to_string()],
+  }
+}
+
+/// Match `candidate` against a glob `pattern`, where `*` matches any run of characters and `?` matches any single character.
+pub fn glob_match(pattern: &str, candidate: &str) -> bool {
+  let pattern: Vec<char> = pattern.chars().collect();
+  let candidate: Vec<char> = candidate.chars().collect();
+  let (mut p, mut c) = (0, 0);
+  let mut backtrack: Option<(usize, usize)> = None;
+
+  while c < candidate.len() {
+    if p < pattern.len() && (pattern[p] == '?' || pattern[p] == candidate[c]) {
+      p += 1;
+      c += 1;
+    } else if p < pattern.len() && pattern[p] == '*' {
+      backtrack = Some((p, c));
+      p += 1;
+    } else if let Some((star_p, star_c)) = backtrack {
+      p = star_p + 1;
+      c = star_c + 1;
+      backtrack = Some((star_p, star_c + 1));
+    } else {
+      return false;
+    }
+  }
+
+  pattern[p..].iter().all(|ch| *ch == '*')
+}
+
+/// Does `pattern` select `location`, or any area that `location` is nested under?
+pub fn location_matches(pattern: &str, location: &str) -> bool {
+  let pattern = normalize_area(pattern);
+  let location = normalize_area(location);
+  let segments: Vec<&str> = location.split('.').collect();
+
+  (1..=segments.len()).any(|len| glob_match(&pattern, &segments[..len].join(".")))
+}
+
+/// A body definition, either built-in or from a Warehouse manifest.
+#[derive(Debug, Clone, Default)]
+pub struct Body {
+  /// Friendly name to show in any UI.
+  pub name: Option<String>,
+  /// RFQDNs of the bodies this body supplements.
+  pub extends: Vec<String>,
+  /// Relative area paths defined by this body, including every parent area.
+  pub areas: BTreeSet<String>,
+}
+
+impl Body {
+  pub fn new(name: Option<String>, extends: Vec<String>, areas: Vec<String>) -> Self {
+    let mut body = Body {
+      name,
+      extends: extends
+        .iter()
+        .map(|body_id| normalize_area(body_id))
+        .collect(),
+      areas: BTreeSet::new(),
+    };
+
+    for area in areas.iter().flat_map(|area| expand_alternatives(area)) {
+      body.add_area(&area);
+    }
+
+    body
+  }
+
+  /// Add an area and all of its parents.
+  pub fn add_area(&mut self, area: &str) {
+    let segments: Vec<&str> = area.split('.').filter(|seg| !seg.is_empty()).collect();
+
+    for len in 1..=segments.len() {
+      self.areas.insert(segments[..len].join("."));
+    }
+  }
+}
+
+impl From<BodySpec> for Body {
+  fn from(spec: BodySpec) -> Self {
+    let list = |list: OptionalStringList| match list {
+      OptionalStringList::Some(values) => values,
+      OptionalStringList::None => vec![],
+    };
+
+    Body::new(
+      match spec.name {
+        OptionalString::Some(name) => Some(name),
+        OptionalString::None => None,
+      },
+      list(spec.extends),
+      list(spec.areas),
+    )
+  }
+}
+
+/// All known bodies and their areas.
+#[derive(Debug, Clone)]
+pub struct AreaRegistry {
+  pub bodies: HashMap<String, Body>,
+}
+
+impl Default for AreaRegistry {
+  /// Only contains the built-in humanoid body.
+  fn default() -> Self {
+    let mut bodies = HashMap::new();
+
+    bodies.insert(
+      humanoid_body_id(),
+      Body::new(
+        Some("Humanoid".to_string()),
+        vec![],
+        HUMANOID_AREAS.iter().map(|area| area.to_string()).collect(),
+      ),
+    );
+
+    AreaRegistry { bodies }
+  }
+}
+
+impl AreaRegistry {
+  /// Register a (supplemental) body, replacing any body with the same RFQDN.
+  pub fn register_body(&mut self, body_id: &str, body: Body) {
+    let body_id = normalize_area(body_id);
+
+    for parent_id in body.extends.iter() {
+      if !self.bodies.contains_key(parent_id) {
+        warn!("Body: {body_id}, extends unknown body: {parent_id}; its areas will not be inherited until that body is registered.");
+      }
+    }
+
+    self.bodies.insert(body_id, body);
+  }
+
+  /// The body and every body it extends, in order of precedence.
+  pub fn lineage(&self, body_id: &str) -> Vec<String> {
+    let mut lineage = vec![];
+    let mut queue = vec![normalize_area(body_id)];
+
+    while let Some(current) = queue.pop() {
+      if lineage.contains(&current) {
+        continue;
+      }
+
+      if let Some(body) = self.bodies.get(&current) {
+        queue.extend(body.extends.iter().rev().cloned());
+      }
+
+      lineage.push(current);
+    }
+
+    lineage
+  }
+
+  /// The given bodies and every body they extend, without duplicates.
+  pub fn lineages(&self, body_ids: &[String]) -> Vec<String> {
+    let mut lineages: Vec<String> = vec![];
+
+    for body_id in body_ids.iter().flat_map(|body_id| self.lineage(body_id)) {
+      if !lineages.contains(&body_id) {
+        lineages.push(body_id);
+      }
+    }
+
+    lineages
+  }
+
+  /// Every fully qualified area known for the given bodies (and the bodies they extend).
+  pub fn areas(&self, body_ids: &[String]) -> BTreeSet<String> {
+    let mut areas = BTreeSet::new();
+
+    for body_id in body_ids.iter().flat_map(|body_id| self.lineage(body_id)) {
+      if let Some(body) = self.bodies.get(&body_id) {
+        areas.insert(body_id.clone());
+
+        for area in body.areas.iter() {
+          areas.insert(format!("{body_id}.{area}"));
+        }
+      }
+    }
+
+    areas
+  }
+
+  /// Is this area defined by any registered body?
+  pub fn contains(&self, area: &str) -> bool {
+    let area = normalize_area(area);
+
+    self.bodies.iter().any(|(body_id, body)| {
+      area == *body_id
+        || match area.strip_prefix(&format!("{body_id}.")) {
+          Some(relative) => body.areas.contains(relative),
+          None => false,
+        }
+    })
+  }
+
+  /// Split a location into the most specific registered body it's on and the area relative to that body.
+  pub fn split_location(&self, location: &str) -> Option<(String, String)> {
+    let location = normalize_area(location);
+
+    self
+      .bodies
+      .keys()
+      .filter_map(|body_id| {
+        location
+          .strip_prefix(&format!("{body_id}."))
+          .map(|area| (body_id.clone(), area.to_string()))
+      })
+      .max_by_key(|(body_id, _)| body_id.len())
+  }
+
+  /// Turn area queries into absolute glob patterns, expanding relative queries against the given bodies.
+  pub fn resolve_patterns(&self, body_ids: &[String], patterns: &[String]) -> Vec<String> {
+    let mut resolved = vec![];
+
+    for pattern in patterns
+      .iter()
+      .flat_map(|pattern| expand_alternatives(pattern))
+    {
+      let normalized = normalize_area(&pattern);
+      let is_absolute = pattern.starts_with('@')
+        || pattern.starts_with('*')
+        || self
+          .bodies
+          .keys()
+          .any(|body_id| location_matches(body_id, &normalized));
+
+      if is_absolute {
+        resolved.push(normalized);
+      } else {
+        for body_id in body_ids.iter().flat_map(|body_id| self.lineage(body_id)) {
+          let body_pattern = format!("{body_id}.{normalized}");
+
+          if !resolved.contains(&body_pattern) {
+            resolved.push(body_pattern);
+          }
+        }
+      }
+    }
+
+    resolved
+  }
+
+  /// Every known area for the given bodies that matches the glob query.
+  pub fn select(&self, body_ids: &[String], pattern: &str) -> Vec<String> {
+    let patterns = self.resolve_patterns(body_ids, &[pattern.to_string()]);
+
+    self
+      .areas(body_ids)
+      .into_iter()
+      .filter(|area| patterns.iter().any(|pattern| glob_match(pattern, area)))
+      .collect()
+  }
+}
+
+/// Rebuild the [area registry](AreaRegistry) in the store with the built-in bodies and every body defined in Warehouse manifests.
+#[instrument(skip(store))]
+pub async fn load_bodies(store: &ModManStore) {
+  let mut registry = AreaRegistry::default();
+
+  for (repo_id, manifest) in store.repos.lock().await.iter() {
+    if let Optional::Some(directory) = &manifest.directory {
+      if let OptionalStrTHashMap::Some(bodies) = &directory.bodies {
+        for (body_id, body_spec) in bodies.iter() {
+          debug!("Registering body: {body_id}, from repo: {repo_id}...");
+          registry.register_body(body_id, Body::from(body_spec.clone()));
+        }
+      }
+    }
+  }
+
+  for body_id in store.config.lock().await.modman.bodies.iter() {
+    if !registry.bodies.contains_key(&normalize_area(body_id)) {
+      warn!("Configured body: {body_id}, is not defined by any repo, gestures will not be able to use its areas!");
+    }
+  }
+
+  info!("Loaded {} bodies!", registry.bodies.len());
+  *store.areas.lock().await = registry;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn humanoid_area(area: &str) -> String {
+    format!("{}.{area}", humanoid_body_id())
+  }
+
+  #[test]
+  fn glob_match_star() {
+    assert!(glob_match("torso.*", "torso.chest"));
+    assert!(glob_match("torso.*.left", "torso.arms.left"));
+    assert!(glob_match("*", "torso"));
+    assert!(!glob_match("torso.*", "pelvis.bottom"));
+    assert!(!glob_match("torso.*.left", "torso.arms.right"));
+    assert!(glob_match("torso.arms.?ight", "torso.arms.right"));
+  }
+
+  #[test]
+  fn glob_match_double_star() {
+    assert!(glob_match("torso.**", "torso.arms.left.hand.fingers.thumb"));
+    assert!(glob_match("**.thumb", "torso.arms.left.hand.fingers.thumb"));
+    assert!(glob_match(
+      "torso.**.thumb",
+      "torso.arms.left.hand.fingers.thumb"
+    ));
+    assert!(!glob_match(
+      "torso.**.thumb",
+      "torso.arms.left.hand.fingers.index"
+    ));
+    assert!(!glob_match("legs.**", "torso.arms"));
+  }
+
+  #[test]
+  fn expand_alternatives_single() {
+    assert_eq!(
+      expand_alternatives("arms.{right,left}"),
+      vec!["arms.right".to_string(), "arms.left".to_string()]
+    );
+    assert_eq!(
+      expand_alternatives("arms.right"),
+      vec!["arms.right".to_string()]
+    );
+  }
+
+  #[test]
+  fn expand_alternatives_nested() {
+    assert_eq!(
+      expand_alternatives("{a,b}.{c,d}"),
+      vec![
+        "a.c".to_string(),
+        "a.d".to_string(),
+        "b.c".to_string(),
+        "b.d".to_string()
+      ]
+    );
+  }
+
+  #[test]
+  fn body_includes_parent_areas() {
+    let registry = AreaRegistry::default();
+
+    assert!(registry.contains(&humanoid_area("torso")));
+    assert!(registry.contains(&humanoid_area("torso.arms.left")));
+    assert!(registry.contains(&humanoid_area("torso.arms.left.hand.fingers.thumb")));
+    assert!(registry.contains("@core.humanoid.legs.right.shin"));
+    assert!(!registry.contains(&humanoid_area("torso.tail")));
+  }
+
+  #[test]
+  fn split_location_most_specific_body() {
+    let mut registry = AreaRegistry::default();
+    registry.register_body(
+      "@core.humanoid.tail",
+      Body::new(None, vec![], vec!["base".to_string()]),
+    );
+
+    assert_eq!(
+      registry.split_location("@core.humanoid.torso.chest"),
+      Some((humanoid_body_id(), "torso.chest".to_string()))
+    );
+    assert_eq!(
+      registry.split_location("@core.humanoid.tail.base"),
+      Some((humanoid_area("tail"), "base".to_string()))
+    );
+    assert_eq!(registry.split_location("com.example.robot.arm"), None);
+  }
+
+  #[test]
+  fn resolve_patterns_relative_to_bodies() {
+    let mut registry = AreaRegistry::default();
+    registry.register_body(
+      "com.example.robot",
+      Body::new(
+        None,
+        vec!["@core.humanoid".to_string()],
+        vec!["tail".to_string()],
+      ),
+    );
+
+    assert_eq!(
+      registry.resolve_patterns(&["com.example.robot".to_string()], &["tail.*".to_string()]),
+      vec![
+        "com.example.robot.tail.*".to_string(),
+        humanoid_area("tail.*")
+      ]
+    );
+  }
+
+  #[test]
+  fn resolve_patterns_absolute() {
+    let registry = AreaRegistry::default();
+    let bodies = [humanoid_body_id()];
+
+    assert_eq!(
+      registry.resolve_patterns(&bodies, &["@core.humanoid.torso".to_string()]),
+      vec![humanoid_area("torso")]
+    );
+    assert_eq!(
+      registry.resolve_patterns(&bodies, &["*.thumb".to_string()]),
+      vec!["*.thumb".to_string()]
+    );
+  }
+
+  #[test]
+  fn resolve_patterns_expands_alternatives() {
+    let registry = AreaRegistry::default();
+
+    assert_eq!(
+      registry.resolve_patterns(
+        &[humanoid_body_id()],
+        &["legs.{right,left}.shin".to_string()]
+      ),
+      vec![
+        humanoid_area("legs.right.shin"),
+        humanoid_area("legs.left.shin")
+      ]
+    );
+  }
+
+  #[test]
+  fn select_relative_glob() {
+    let registry = AreaRegistry::default();
+
+    assert_eq!(
+      registry.select(&[humanoid_body_id()], "torso.head.ears.*"),
+      vec![
+        humanoid_area("torso.head.ears.left"),
+        humanoid_area("torso.head.ears.right")
+      ]
+    );
+  }
+}
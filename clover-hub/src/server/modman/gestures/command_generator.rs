@@ -1,40 +1,362 @@
+use std::collections::HashMap;
+
+use tokio::time::Instant;
 use tracing::{
   debug,
   instrument,
 };
 
-use crate::server::modman::models::gestures::{
-  GestureState,
-  GestureStates,
+use super::{
+  areas::{
+    glob_match,
+    location_matches,
+    normalize_area,
+  },
+  packs::Gesture,
+};
+use crate::server::modman::models::{
+  components::{
+    CloverComponent,
+    CloverComponentMeta,
+  },
+  gestures::{
+    ComponentGestureCommand,
+    GestureParameters,
+    GestureState,
+    GestureStates,
+    GestureTrack,
+  },
+  store::ModManStore,
 };
 
-#[instrument]
-pub async fn gesture_command_generator(gesture_id: &String, gesture_state: &GestureStates) {
+/// Area patterns (`body_id.area_glob`) and the tracks a gesture applies to them.
+type GestureTargets = Vec<(String, HashMap<String, GestureTrack>)>;
+
+/// [Targets](GestureTargets) of a gesture for every body in the lineage that matches the gesture's body globs.
+///
+/// Less specific patterns come first, so more specific ones override them.
+fn gesture_targets(gesture: &Gesture, lineage: &[String]) -> GestureTargets {
+  let mut targets: GestureTargets = vec![];
+
+  for (body_glob, area_configs) in gesture.configs.0.iter() {
+    let body_glob = normalize_area(body_glob);
+
+    for body_id in lineage
+      .iter()
+      .filter(|body_id| glob_match(&body_glob, body_id))
+    {
+      for (area_glob, tracks) in area_configs.iter() {
+        targets.push((format!("{body_id}.{area_glob}"), tracks.clone()));
+      }
+    }
+  }
+
+  targets.sort_by_key(|(pattern, _)| pattern.len());
+  targets
+}
+
+/// Calculate the command for a single component, returns `None` if the gesture doesn't drive any of its parameters.
+///
+/// `parameters_override` replaces the component's base parameters for every parameter the gesture drives.
+fn component_gesture_command(
+  gesture_id: &str,
+  component_entry: &(CloverComponentMeta, CloverComponent),
+  targets: &[(String, HashMap<String, GestureTrack>)],
+  elapsed: f64,
+  intensity: f64,
+  speed: f64,
+  parameters_override: Option<&GestureParameters>,
+) -> Option<ComponentGestureCommand> {
+  let component_meta = &component_entry.0;
+
+  let mut tracks: HashMap<String, GestureTrack> = HashMap::new();
+  for (pattern, area_tracks) in targets.iter() {
+    if location_matches(pattern, &component_meta.location) {
+      tracks.extend(area_tracks.clone());
+    }
+  }
+
+  let intensities: HashMap<String, f64> = tracks
+    .iter()
+    .map(|(parameter, track)| {
+      (
+        parameter.clone(),
+        (track.sample(elapsed) * intensity).clamp(-1.0, 1.0),
+      )
+    })
+    .collect();
+
+  let (values, position) = match &component_entry.1 {
+    // Movement components get per-axis position targets instead.
+    CloverComponent::MovementComponent(movement) => {
+      match movement.gesture_position(&intensities, parameters_override) {
+        Some(position) => (
+          position
+            .axes()
+            .into_iter()
+            .map(|(axis, value)| (axis.to_string(), value))
+            .collect(),
+          Some(position),
+        ),
+        None => (HashMap::new(), None),
+      }
+    }
+    // Calculate intensity, components without parameters used by the gesture don't support it.
+    _ => (
+      intensities
+        .iter()
+        .filter_map(|(parameter, intensity)| {
+          parameters_override
+            .or_else(|| component_meta.base_gesture_parameters.get(parameter))
+            .map(|parameters| {
+              (
+                parameter.clone(),
+                parameters.calculate_intensity(*intensity),
+              )
+            })
+        })
+        .collect::<HashMap<String, f64>>(),
+      None,
+    ),
+  };
+
+  if !values.is_empty() {
+    // Speed is also passed through for components that smooth between samples themselves.
+    Some(ComponentGestureCommand {
+      gesture_id: gesture_id.to_string(),
+      values,
+      speed,
+      position,
+    })
+  } else {
+    None
+  }
+}
+
+/// Every body configured for the user, and the bodies they extend.
+async fn configured_lineage(store: &ModManStore) -> (Vec<String>, Vec<String>) {
+  let bodies = store.config.lock().await.modman.bodies.clone();
+  let lineage = store.areas.lock().await.lineages(&bodies);
+
+  (bodies, lineage)
+}
+
+/// Calculate the commands for every component affected by a gesture, keyed by component ID.
+///
+/// Each component resolves the gesture through its own [gesture config](crate::server::modman::models::gestures::GestureConfig), so it can use its primary pack or an override instead.
+#[instrument(skip(store))]
+pub async fn gesture_command_generator(
+  store: &ModManStore,
+  gesture_id: &String,
+  gesture_state: &GestureStates,
+) -> HashMap<String, ComponentGestureCommand> {
   debug!(
     "Cacluating gesture: {}, with: {:?}",
     gesture_id, gesture_state
   );
 
+  let mut commands = HashMap::new();
+
   match gesture_state.current_state {
     GestureState::Begin {
       intensity,
       speed,
-      background,
+      background: _,
     } => {
-      // Get supplemental configuration areas. (E.g. areas from @core.humanoid.furry.canine.domestic-dog.plantigrade in addition to @core, @core.humanoid.pentadactal, @core.humanoid.diopical)
+      // Milliseconds along the curves' time axis, frozen while paused.
+      let elapsed = match gesture_state.began_at {
+        Some(began_at) => {
+          let until = gesture_state.paused_at.unwrap_or_else(Instant::now);
+          until.saturating_duration_since(began_at).as_secs_f64() * 1000.0 * speed
+        }
+        None => 0.0,
+      };
 
-      // Get Union of the gesture's supported areas, the instance's defined areas, and command requested areas.
+      let requested = match gesture_state.areas.clone() {
+        Some(areas) => areas,
+        None => vec!["*".to_string()],
+      };
+      let (bodies, lineage) = configured_lineage(store).await;
+      let areas = store
+        .areas
+        .lock()
+        .await
+        .resolve_patterns(&bodies, &requested);
 
       // Find all modules under applicable areas.
+      let component_ids: Vec<String> = {
+        let modules = store.modules.lock().await;
+        modules
+          .values()
+          .filter(|module| module.initialized)
+          .flat_map(|module| module.components.iter().map(|(id, _)| id.clone()))
+          .collect()
+      };
+
+      let library = store.gesture_library.lock().await;
+      let components = store.components.lock().await;
+      // Targets per resolved gesture, most components will resolve to the same one.
+      let mut targets_cache: HashMap<String, GestureTargets> = HashMap::new();
 
-      // Calculate intensity
+      for component_id in component_ids {
+        match components.get(&component_id) {
+          Some(component_entry) => {
+            if !areas
+              .iter()
+              .any(|area| location_matches(area, &component_entry.0.location))
+            {
+              continue;
+            }
 
-      // Calculate speed
+            let (resolved_id, gesture, parameters_override) = match library
+              .resolve_for_component(gesture_id, component_entry.1.gesture_config())
+            {
+              Some(resolved) => resolved,
+              None => {
+                debug!("Gesture: {gesture_id}, does not resolve for component: {component_id}, skipping.");
+                continue;
+              }
+            };
+            let targets = targets_cache
+              .entry(resolved_id)
+              .or_insert_with(|| gesture_targets(gesture, &lineage));
 
-      todo!();
+            if let Some(command) = component_gesture_command(
+              gesture_id,
+              component_entry,
+              targets,
+              elapsed,
+              intensity,
+              speed,
+              parameters_override.as_ref(),
+            ) {
+              commands.insert(component_id.clone(), command);
+            }
+          }
+          None => {
+            debug!("Component: {component_id}, is not in the store, skipping.");
+          }
+        }
+      }
     }
     _ => {
       // Not covered by this function, see `super::ipc::gestures`!
     }
   }
+
+  commands
+}
+
+/// Calculate the default gesture for every initialized component that has one and isn't in `busy_components`, keyed by component ID.
+///
+/// `idle_since` tracks when each component became idle so its default gesture's curves start from the beginning, it's updated in place.
+#[instrument(skip(store, busy_components, idle_since))]
+pub async fn idle_gesture_commands(
+  store: &ModManStore,
+  busy_components: &HashMap<String, ComponentGestureCommand>,
+  idle_since: &mut HashMap<String, Instant>,
+) -> HashMap<String, ComponentGestureCommand> {
+  let mut commands = HashMap::new();
+
+  let component_ids: Vec<String> = {
+    let modules = store.modules.lock().await;
+    modules
+      .values()
+      .filter(|module| module.initialized)
+      .flat_map(|module| module.components.iter().map(|(id, _)| id.clone()))
+      .filter(|component_id| !busy_components.contains_key(component_id))
+      .collect()
+  };
+
+  idle_since.retain(|component_id, _| component_ids.contains(component_id));
+
+  let (_bodies, lineage) = configured_lineage(store).await;
+  let library = store.gesture_library.lock().await;
+  let components = store.components.lock().await;
+  let now = Instant::now();
+
+  for component_id in component_ids {
+    let component_entry = match components.get(&component_id) {
+      Some(component_entry) => component_entry,
+      None => continue,
+    };
+    let gesture_config = component_entry.1.gesture_config();
+    let default_gesture = match gesture_config.and_then(|config| config.default_gesture.as_ref()) {
+      Some(default_gesture) => default_gesture,
+      None => continue,
+    };
+
+    match library.resolve_for_component(default_gesture, gesture_config) {
+      Some((_resolved_id, gesture, parameters_override)) => {
+        let elapsed = now
+          .saturating_duration_since(*idle_since.entry(component_id.clone()).or_insert(now))
+          .as_secs_f64()
+          * 1000.0;
+
+        if let Some(command) = component_gesture_command(
+          default_gesture,
+          component_entry,
+          &gesture_targets(gesture, &lineage),
+          elapsed,
+          1.0,
+          1.0,
+          parameters_override.as_ref(),
+        ) {
+          commands.insert(component_id, command);
+        }
+      }
+      None => {
+        debug!("Default gesture: {default_gesture}, for component: {component_id}, does not resolve, skipping.");
+      }
+    }
+  }
+
+  commands
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::server::{
+    modman::gestures::{
+      areas::{
+        humanoid_body_id,
+        load_bodies,
+      },
+      packs::load_gesture_packs,
+    },
+    warehouse::repos::{
+      builtin_rfqdn,
+      load_core_manifest,
+    },
+  };
+
+  #[tokio::test]
+  async fn default_pack_happy_drives_tailed_humanoid() {
+    let store = ModManStore::new(None, None);
+    store
+      .repos
+      .lock()
+      .await
+      .insert(builtin_rfqdn(true), load_core_manifest().await);
+    load_bodies(&store).await;
+    load_gesture_packs(&store).await;
+
+    let body_id = format!("{}.humanoid.tailed", builtin_rfqdn(true));
+    let lineage = store.areas.lock().await.lineage(&body_id);
+    assert!(lineage.contains(&humanoid_body_id()));
+
+    let library = store.gesture_library.lock().await;
+    let (_pack_id, gesture) = library
+      .resolve(&format!("{}.happy", builtin_rfqdn(true)))
+      .expect("The default pack doesn't define happy.");
+
+    let targets = gesture_targets(gesture, &lineage);
+    assert!(targets.iter().any(|(pattern, tracks)| {
+      pattern == &format!("{body_id}.pelvis.rear.tail.*") && tracks.contains_key("angle")
+    }));
+    assert!(targets
+      .iter()
+      .any(|(pattern, _tracks)| pattern == &format!("{body_id}.torso.head.face.eyes.*.eyebrow")));
+  }
 }
@@ -0,0 +1,237 @@
+//! # Smoothing Curves
+//!
+//! Animated gesture parameters are defined as keyframes on a smoothing curve, where the time (x) axis is in milliseconds since the gesture began and the value (y) axis is the parameter's intensity (-1.0 to 1.0).
+//!
+//! A gesture's `speed` is a multiplier over the time axis and its `intensity` is a multiplier over the value axis. Every active gesture is sampled once per [tick](super::GESTURE_TICK_INTERVAL), and each sample is turned into a component value with [`GestureParameters::calculate_intensity`](crate::server::modman::models::gestures::GestureParameters::calculate_intensity).
+//!
+
+use crate::server::modman::models::gestures::{
+  GestureAnimation,
+  GestureCurve,
+  GestureKeyframe,
+  GestureRepeat,
+  GestureTrack,
+};
+
+/// Iterations used to invert the x axis of a cubic-bezier curve.
+const BEZIER_ITERATIONS: usize = 16;
+
+/// A single axis of a cubic-bezier curve that starts at 0 and ends at 1.
+fn bezier_axis(t: f64, p1: f64, p2: f64) -> f64 {
+  let inv = 1.0 - t;
+  (3.0 * inv * inv * t * p1) + (3.0 * inv * t * t * p2) + (t * t * t)
+}
+
+/// Eased progress (y) for linear progress (x) through a CSS style cubic-bezier timing function.
+pub fn cubic_bezier(progress: f64, [x1, y1, x2, y2]: [f64; 4]) -> f64 {
+  // x is monotonic when x1 and x2 are within 0..1, so bisect for the t that gives us our progress.
+  let (x1, x2) = (x1.clamp(0.0, 1.0), x2.clamp(0.0, 1.0));
+  let (mut low, mut high) = (0.0, 1.0);
+  let mut t = progress;
+
+  for _ in 0..BEZIER_ITERATIONS {
+    if bezier_axis(t, x1, x2) < progress {
+      low = t;
+    } else {
+      high = t;
+    }
+
+    t = (low + high) / 2.0;
+  }
+
+  bezier_axis(t, y1, y2)
+}
+
+impl GestureCurve {
+  /// Eased progress (0.0 to 1.0) between two keyframes.
+  pub fn ease(&self, progress: f64) -> f64 {
+    let progress = progress.clamp(0.0, 1.0);
+
+    match self {
+      GestureCurve::Linear => progress,
+      GestureCurve::CubicBezier(points) => cubic_bezier(progress, *points),
+      GestureCurve::Step => {
+        if progress >= 1.0 {
+          1.0
+        } else {
+          0.0
+        }
+      }
+    }
+  }
+}
+
+impl GestureAnimation {
+  /// Time from the first to the last keyframe, in milliseconds.
+  pub fn duration(&self) -> f64 {
+    match (self.keyframes.first(), self.keyframes.last()) {
+      (Some(first), Some(last)) => (last.x - first.x).max(0.0),
+      _ => 0.0,
+    }
+  }
+
+  /// Map time since the gesture began onto the keyframes' time axis, accounting for repetition.
+  fn local_time(&self, elapsed: f64) -> f64 {
+    let start = match self.keyframes.first() {
+      Some(first) => first.x,
+      None => 0.0,
+    };
+    let duration = self.duration();
+
+    if duration <= 0.0 || elapsed <= 0.0 {
+      return start + elapsed.clamp(0.0, duration);
+    }
+
+    start
+      + match self.repeat {
+        GestureRepeat::Once => elapsed.min(duration),
+        GestureRepeat::Loop => elapsed % duration,
+        GestureRepeat::PingPong => {
+          let cycle = elapsed % (duration * 2.0);
+
+          if cycle > duration {
+            (duration * 2.0) - cycle
+          } else {
+            cycle
+          }
+        }
+      }
+  }
+
+  /// Intensity at `elapsed` milliseconds since the gesture began (after `speed` is applied).
+  pub fn sample(&self, elapsed: f64) -> f64 {
+    let time = self.local_time(elapsed);
+
+    let (before, after): (Option<&GestureKeyframe>, Option<&GestureKeyframe>) = (
+      self
+        .keyframes
+        .iter()
+        .rev()
+        .find(|keyframe| keyframe.x <= time),
+      self.keyframes.iter().find(|keyframe| keyframe.x > time),
+    );
+
+    match (before, after) {
+      (Some(before), Some(after)) => {
+        let progress = (time - before.x) / (after.x - before.x);
+        before.y + ((after.y - before.y) * self.curve.ease(progress))
+      }
+      (Some(only), None) | (None, Some(only)) => only.y,
+      (None, None) => 0.0,
+    }
+  }
+}
+
+impl GestureTrack {
+  /// Intensity of this parameter at `elapsed` milliseconds since the gesture began (after `speed` is applied).
+  pub fn sample(&self, elapsed: f64) -> f64 {
+    match self {
+      GestureTrack::Constant(value) => *value,
+      GestureTrack::Animated { animation } => animation.sample(elapsed),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EPSILON: f64 = 1e-3;
+
+  fn animation(curve: GestureCurve, repeat: GestureRepeat) -> GestureAnimation {
+    GestureAnimation {
+      curve,
+      repeat,
+      keyframes: vec![
+        GestureKeyframe { x: 0.0, y: 0.0 },
+        GestureKeyframe { x: 1000.0, y: 1.0 },
+      ],
+    }
+  }
+
+  fn assert_close(actual: f64, expected: f64) {
+    assert!(
+      (actual - expected).abs() < EPSILON,
+      "expected {expected}, got {actual}"
+    );
+  }
+
+  #[test]
+  fn bezier_endpoints() {
+    for points in [
+      [0.42, 0.0, 0.58, 1.0],
+      [0.25, 0.1, 0.25, 1.0],
+      [0.0, 0.0, 1.0, 1.0],
+    ] {
+      assert_close(cubic_bezier(0.0, points), 0.0);
+      assert_close(cubic_bezier(1.0, points), 1.0);
+    }
+  }
+
+  #[test]
+  fn bezier_midpoint() {
+    // ease-in-out is symmetric around its midpoint.
+    assert_close(cubic_bezier(0.5, [0.42, 0.0, 0.58, 1.0]), 0.5);
+    // Control points on the diagonal are a linear curve.
+    assert_close(
+      cubic_bezier(0.25, [1.0 / 3.0, 1.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0]),
+      0.25,
+    );
+  }
+
+  #[test]
+  fn sample_endpoints() {
+    let anim = animation(
+      GestureCurve::CubicBezier([0.42, 0.0, 0.58, 1.0]),
+      GestureRepeat::Once,
+    );
+
+    assert_close(anim.sample(0.0), 0.0);
+    assert_close(anim.sample(500.0), 0.5);
+    assert_close(anim.sample(1000.0), 1.0);
+    assert_close(anim.sample(5000.0), 1.0);
+  }
+
+  #[test]
+  fn step_holds_until_next_keyframe() {
+    let anim = GestureAnimation {
+      curve: GestureCurve::Step,
+      repeat: GestureRepeat::Once,
+      keyframes: vec![
+        GestureKeyframe { x: 0.0, y: 0.25 },
+        GestureKeyframe { x: 500.0, y: -0.5 },
+        GestureKeyframe { x: 1000.0, y: 1.0 },
+      ],
+    };
+
+    assert_close(anim.sample(0.0), 0.25);
+    assert_close(anim.sample(499.0), 0.25);
+    assert_close(anim.sample(500.0), -0.5);
+    assert_close(anim.sample(999.0), -0.5);
+    assert_close(anim.sample(1000.0), 1.0);
+  }
+
+  #[test]
+  fn loop_wraps_past_last_keyframe() {
+    let anim = animation(GestureCurve::Linear, GestureRepeat::Loop);
+
+    assert_close(anim.sample(250.0), 0.25);
+    assert_close(anim.sample(1250.0), 0.25);
+    assert_close(anim.sample(3750.0), 0.75);
+  }
+
+  #[test]
+  fn ping_pong_reflects_past_last_keyframe() {
+    let anim = animation(GestureCurve::Linear, GestureRepeat::PingPong);
+
+    assert_close(anim.sample(250.0), 0.25);
+    assert_close(anim.sample(1250.0), 0.75);
+    assert_close(anim.sample(1750.0), 0.25);
+    assert_close(anim.sample(2250.0), 0.25);
+  }
+
+  #[test]
+  fn constant_track() {
+    assert_close(GestureTrack::Constant(0.6).sample(12345.0), 0.6);
+  }
+}
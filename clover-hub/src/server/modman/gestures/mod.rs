@@ -1,72 +1,29 @@
-// TODO: Manifests specify bodies. Allow globs.
+//! # Gestures
+//!
+//! Gestures are defined by [gesture packs](packs), applied to [areas](areas) of the user's body, and are turned into commands for every component in those areas.
+//!
+//! Components resolve each gesture through their own [gesture config](crate::server::modman::models::gestures::GestureConfig): their primary pack is preferred over the default pack, overrides can swap in another pack's gesture with different parameters, and a default gesture is played whenever no other gesture drives the component.
+//!
+//! Gestures can also be scheduled to switch states after a delay, see [transitions](transitions), background gestures survive restarts, see [persistence](persistence), and new gestures can be recorded from module telemetry, see [recording](recording).
+//!
+
 // TODO: Each area has default applicable gestures
-/*
-Default area specification
-  - com.reboot-codes.clover.CORE.humanoid
-    - torso
-      - core
-        - back
-          - spine
-            - upper/middle/bottom
-        - front
-      - chest
-        - font
-          - right/center/left
-        - back
-          - right/center/left
-      - arms
-        - right/left
-          - shoulder
-          - biceps
-          - forearm
-          - hand
-            - fingers
-              - thumb
-              - index
-              - middle
-              - ring
-              - pinky
-      - head
-        - neck
-        - face
-          - jaw
-          - mouth
-          - nose
-          - eyes
-            - right/left
-              - eye
-              - eyelids
-                - upper/lower
-              - eyebrow
-        - ears
-          right/left
-        - top
-        - back
-    - pelivs
-      - waist
-        - left/right/upper/lower
-      - front
-        - upper/lower
-      - bottom
-      - rear
-        - right/left
-    - legs
-      - right/left
-        - thigh
-        - shin
-        - foot
-          - toes
-            - hallux
-            - index
-            - middle
-            - ring
-            - pinky
-*/
 
+pub mod areas;
 pub mod command_generator;
+pub mod curves;
+pub mod packs;
+pub mod persistence;
+pub mod recording;
+pub mod transitions;
 
-use std::sync::Arc;
+use std::{
+  collections::HashMap,
+  sync::Arc,
+  time::Duration,
+};
 
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use tracing::{
   debug,
@@ -76,45 +33,145 @@ use tracing::{
 };
 
 use crate::server::modman::{
-  gestures::command_generator::gesture_command_generator,
-  models::store::ModManStore,
+  gestures::command_generator::{
+    gesture_command_generator,
+    idle_gesture_commands,
+  },
+  models::{
+    gestures::ComponentGestureCommand,
+    store::ModManStore,
+  },
+  MODULE_EVT_ID,
 };
 
-/// Thread to calculate
-#[instrument(skip(store, cancellation_token))]
+/// How often gesture commands are re-calculated, and so how often gesture [curves](curves) are sampled.
+pub const GESTURE_TICK_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Thread to calculate and publish module commands for all gestures in the background and foreground priority stacks.
+///
+/// Background gestures are calculated first, so foreground gestures always override them, and within each stack the most recently begun gesture wins. Components that aren't driven by any gesture fall back to their default gesture.
+#[instrument(skip(store, session, cancellation_token))]
 pub async fn gesture_command_generator_manager(
   store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
   cancellation_token: CancellationToken,
 ) {
+  // Last calculated commands per gesture, so paused gestures keep their values frozen.
+  let mut gesture_commands: HashMap<String, HashMap<String, ComponentGestureCommand>> =
+    HashMap::new();
+  let mut published_commands: HashMap<String, ComponentGestureCommand> = HashMap::new();
+  // When each idle component started playing its default gesture.
+  let mut idle_since: HashMap<String, Instant> = HashMap::new();
+  let mut interval = tokio::time::interval(GESTURE_TICK_INTERVAL);
+
   while !cancellation_token.is_cancelled() {
-    let gestures_store_guards = vec![
-      store.background_gesture_priority.lock().await,
-      store.foreground_gesture_priority.lock().await,
-    ];
-
-    for mut gestures_store_guard in gestures_store_guards {
-      let mut gestures_to_remove = vec![];
-      let mut gesture_index_plus_one: usize = 0;
-
-      for gesture_id in gestures_store_guard.iter() {
-        gesture_index_plus_one += 1;
-
-        match store.gesture_states.lock().await.get(gesture_id) {
-          Some(gesture_state) => gesture_command_generator(gesture_id, gesture_state).await,
-          None => {
-            error!("Gesture: {}, is not in state map, removing from the gesture array! This state de-sync is not permissable and should be reported as a bug!", gesture_id.clone());
-            gestures_to_remove.push((gesture_index_plus_one - 1, gesture_id.clone()));
-            continue;
+    tokio::select! {
+      _ = cancellation_token.cancelled() => { break; }
+      _ = interval.tick() => {}
+    }
+
+    // Snapshot the stacks so we don't hold their locks while calculating.
+    let gesture_ids: Vec<String> = {
+      let background = store.background_gesture_priority.lock().await;
+      let foreground = store.foreground_gesture_priority.lock().await;
+      background
+        .iter()
+        .chain(foreground.iter())
+        .cloned()
+        .collect()
+    };
+
+    let mut gestures_to_remove = vec![];
+    let mut component_commands: HashMap<String, ComponentGestureCommand> = HashMap::new();
+
+    for gesture_id in gesture_ids.iter() {
+      let gesture_state = store.gesture_states.lock().await.get(gesture_id).cloned();
+
+      match gesture_state {
+        Some(gesture_state) => {
+          if !gesture_state.paused {
+            let commands = gesture_command_generator(&store, gesture_id, &gesture_state).await;
+            gesture_commands.insert(gesture_id.clone(), commands);
+          }
+
+          if let Some(commands) = gesture_commands.get(gesture_id) {
+            // Later gestures override earlier ones.
+            for (component_id, command) in commands {
+              component_commands.insert(component_id.clone(), command.clone());
+            }
           }
         }
+        None => {
+          error!("Gesture: {}, is not in state map, removing from the gesture array! This state de-sync is not permissable and should be reported as a bug!", gesture_id.clone());
+          gestures_to_remove.push(gesture_id.clone());
+        }
       }
+    }
+
+    if !gestures_to_remove.is_empty() {
+      for priority_vec in [
+        &store.background_gesture_priority,
+        &store.foreground_gesture_priority,
+      ] {
+        let mut priority_vec = priority_vec.lock().await;
 
-      for (gesture_index, gesture_id) in gestures_to_remove {
-        debug!(
-          "Removing gesture: {} (@ index: {})...",
-          gesture_id, gesture_index
-        );
-        gestures_store_guard.remove(gesture_index);
+        for gesture_id in gestures_to_remove.iter() {
+          debug!("Removing gesture: {}...", gesture_id);
+          priority_vec.retain(|id| id != gesture_id);
+        }
+      }
+    }
+
+    // Components that no gesture drives play their default gesture.
+    let idle_commands = idle_gesture_commands(&store, &component_commands, &mut idle_since).await;
+    component_commands.extend(idle_commands);
+
+    gesture_commands.retain(|gesture_id, _| gesture_ids.contains(gesture_id));
+    published_commands.retain(|component_id, _| component_commands.contains_key(component_id));
+
+    for (component_id, command) in component_commands {
+      if published_commands.get(&component_id) == Some(&command) {
+        continue;
+      }
+
+      // Movement components also get their clamped target as a position command.
+      if let Some(position) = &command.position {
+        match serde_json::to_string(position) {
+          Ok(position_str) => {
+            if let Err(err) = session
+              .put(
+                format!("{MODULE_EVT_ID}/components/by-id/{component_id}/position"),
+                position_str,
+              )
+              .await
+            {
+              error!(
+                "Failed to publish position command for component: {component_id}, due to:\n{err}"
+              );
+            }
+          }
+          Err(err) => {
+            error!("Failed to serialize position command for component: {component_id}, this is a bug and should be reported! Due to:\n{err}");
+          }
+        }
+      }
+
+      let key_expr = format!("{MODULE_EVT_ID}/components/by-id/{component_id}/gesture");
+
+      match serde_json::to_string(&command) {
+        Ok(command_str) => match session.put(&key_expr, command_str).await {
+          Ok(_) => {
+            published_commands.insert(component_id, command);
+          }
+          Err(err) => {
+            error!(
+              "Failed to publish gesture command for component: {component_id}, due to:\n{err}"
+            );
+          }
+        },
+        Err(err) => {
+          error!("Failed to serialize gesture command for component: {component_id}, this is a bug and should be reported! Due to:\n{err}");
+        }
       }
     }
   }
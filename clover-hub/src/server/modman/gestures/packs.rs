@@ -0,0 +1,442 @@
+//! # Gesture Packs
+//!
+//! Gesture packs are defined in Warehouse manifests (see [`GesturePackSpec`]), and describe what each of their gestures does to every [area](super::areas) and parameter with [`GestureBodyConfigs`].
+//!
+//! Gesture IDs are in `gesture_RFQDN@gesture_pack_RFQDN` format, where `@` and everything after can be omitted to use the [default gesture pack](crate::server::warehouse::config::models::Config::default_gesture_pack).
+//!
+
+use std::collections::HashMap;
+
+use tracing::{
+  debug,
+  info,
+  instrument,
+  warn,
+};
+
+use crate::server::{
+  modman::models::{
+    gestures::{
+      GestureBodyConfigs,
+      GestureConfig,
+      GestureParameters,
+    },
+    store::ModManStore,
+  },
+  warehouse::repos::models::{
+    GesturePackSpec,
+    GestureSpec,
+    Optional,
+    OptionalStrTHashMap,
+    OptionalString,
+  },
+};
+
+/// A gesture that can be applied to components.
+#[derive(Debug, Clone)]
+pub struct Gesture {
+  pub name: Option<String>,
+  pub description: Option<String>,
+  pub configs: GestureBodyConfigs,
+}
+
+/// All gestures from a single gesture pack.
+#[derive(Debug, Clone, Default)]
+pub struct GesturePack {
+  pub name: Option<String>,
+  pub description: Option<String>,
+  pub gestures: HashMap<String, Gesture>,
+}
+
+impl GesturePack {
+  /// Convert a compiled gesture pack spec, skipping gestures that can't be used by ModMan.
+  pub fn from_spec(pack_id: &str, spec: GesturePackSpec) -> Self {
+    let optional_string = |val: OptionalString| match val {
+      OptionalString::Some(val) => Some(val),
+      OptionalString::None => None,
+    };
+
+    let mut gestures = HashMap::new();
+
+    if let OptionalStrTHashMap::Some(gesture_specs) = spec.gestures {
+      for (gesture_id, gesture_spec) in gesture_specs {
+        match gesture_spec {
+          GestureSpec::KeyframedGestureSpec(keyframed) => {
+            gestures.insert(
+              gesture_id,
+              Gesture {
+                name: optional_string(keyframed.name),
+                description: optional_string(keyframed.description),
+                configs: match keyframed.configs {
+                  Optional::Some(configs) => configs,
+                  _ => GestureBodyConfigs::default(),
+                },
+              },
+            );
+          }
+          GestureSpec::StaticGestureSpec(_) => {
+            warn!("Gesture: {gesture_id}, from pack: {pack_id}, is a static gesture, which is not supported yet; skipping!");
+          }
+        }
+      }
+    }
+
+    GesturePack {
+      name: optional_string(spec.name),
+      description: optional_string(spec.description),
+      gestures,
+    }
+  }
+}
+
+/// All loaded gesture packs.
+#[derive(Debug, Clone, Default)]
+pub struct GestureLibrary {
+  pub packs: HashMap<String, GesturePack>,
+  /// RFQDN of the pack to use for gesture IDs that don't specify one.
+  pub default_pack: String,
+}
+
+impl GestureLibrary {
+  /// Split a gesture ID into the gesture's RFQDN and the RFQDN of the pack it comes from.
+  pub fn split_id<'a>(&'a self, gesture_id: &'a str) -> (&'a str, &'a str) {
+    match gesture_id.rsplit_once('@') {
+      Some((gesture_rfqdn, pack_id)) if !gesture_rfqdn.is_empty() => (gesture_rfqdn, pack_id),
+      _ => (gesture_id, self.default_pack.as_str()),
+    }
+  }
+
+  /// Find a gesture by its ID, returning the RFQDN of the pack it was found in.
+  pub fn resolve(&self, gesture_id: &str) -> Option<(String, &Gesture)> {
+    let (gesture_rfqdn, pack_id) = self.split_id(gesture_id);
+
+    self
+      .packs
+      .get(pack_id)
+      .and_then(|pack| pack.gestures.get(gesture_rfqdn))
+      .map(|gesture| (pack_id.to_string(), gesture))
+  }
+
+  /// Find a gesture by its ID, checking the given pack before the default pack when the ID doesn't specify one.
+  pub fn resolve_with_pack(
+    &self,
+    gesture_id: &str,
+    primary_pack: Option<&String>,
+  ) -> Option<(String, &Gesture)> {
+    if !gesture_id.contains('@') {
+      if let Some(pack_id) = primary_pack {
+        if let Some(gesture) = self
+          .packs
+          .get(pack_id)
+          .and_then(|pack| pack.gestures.get(gesture_id))
+        {
+          return Some((pack_id.clone(), gesture));
+        }
+      }
+    }
+
+    self.resolve(gesture_id)
+  }
+
+  /// Is this gesture defined by the pack it refers to, or by any pack if it doesn't refer to one?
+  pub fn is_known(&self, gesture_id: &str) -> bool {
+    self.resolve(gesture_id).is_some()
+      || (!gesture_id.contains('@')
+        && self
+          .packs
+          .values()
+          .any(|pack| pack.gestures.contains_key(gesture_id)))
+  }
+
+  /// Find the gesture that a component should play for a gesture ID, applying the component's [gesture configuration](GestureConfig), returns the resolved gesture's full ID (`gesture_RFQDN@gesture_pack_RFQDN`).
+  ///
+  /// Overrides are looked up by the full gesture ID, then by the gesture's RFQDN, and can swap in a different gesture (from any pack) and/or parameters. Otherwise the component's primary pack is checked before the default pack. The parameters returned replace the component's base parameters if set.
+  pub fn resolve_for_component(
+    &self,
+    gesture_id: &str,
+    gesture_config: Option<&GestureConfig>,
+  ) -> Option<(String, &Gesture, Option<GestureParameters>)> {
+    let (gesture_rfqdn, _pack_id) = self.split_id(gesture_id);
+
+    match gesture_config {
+      Some(gesture_config) => {
+        let gesture_override = gesture_config
+          .gesture_overrides
+          .get(gesture_id)
+          .or_else(|| gesture_config.gesture_overrides.get(gesture_rfqdn));
+
+        match gesture_override {
+          Some(gesture_override) => {
+            let (override_rfqdn, _pack_id) = self.split_id(&gesture_override.gesture_preset_id);
+
+            self
+              .resolve_with_pack(
+                &gesture_override.gesture_preset_id,
+                gesture_config.primary_gesture_pack.as_ref(),
+              )
+              .map(|(pack_id, gesture)| {
+                (
+                  format!("{override_rfqdn}@{pack_id}"),
+                  gesture,
+                  gesture_override
+                    .gesture_parameters
+                    .clone()
+                    .or(gesture_config.gesture_parameters.clone()),
+                )
+              })
+          }
+          None => self
+            .resolve_with_pack(gesture_id, gesture_config.primary_gesture_pack.as_ref())
+            .map(|(pack_id, gesture)| {
+              (
+                format!("{gesture_rfqdn}@{pack_id}"),
+                gesture,
+                gesture_config.gesture_parameters.clone(),
+              )
+            }),
+        }
+      }
+      None => self
+        .resolve(gesture_id)
+        .map(|(pack_id, gesture)| (format!("{gesture_rfqdn}@{pack_id}"), gesture, None)),
+    }
+  }
+}
+
+/// Rebuild the [gesture library](GestureLibrary) in the store with every gesture pack defined in Warehouse manifests.
+#[instrument(skip(store))]
+pub async fn load_gesture_packs(store: &ModManStore) {
+  let mut library = GestureLibrary {
+    packs: HashMap::new(),
+    default_pack: store.config.lock().await.default_gesture_pack.clone(),
+  };
+
+  for (repo_id, manifest) in store.repos.lock().await.iter() {
+    if let Optional::Some(directory) = &manifest.directory {
+      if let OptionalStrTHashMap::Some(gesture_packs) = &directory.gesture_packs {
+        for (pack_id, pack_spec) in gesture_packs.iter() {
+          debug!("Loading gesture pack: {pack_id}, from repo: {repo_id}...");
+          library.packs.insert(
+            pack_id.clone(),
+            GesturePack::from_spec(pack_id, pack_spec.clone()),
+          );
+        }
+      }
+    }
+  }
+
+  if !library.packs.contains_key(&library.default_pack) {
+    warn!(
+      "Default gesture pack: {}, is not defined by any repo, gestures without a pack will not resolve!",
+      library.default_pack
+    );
+  }
+
+  info!(
+    "Loaded {} gesture(s) from {} gesture pack(s)!",
+    library
+      .packs
+      .values()
+      .map(|pack| pack.gestures.len())
+      .sum::<usize>(),
+    library.packs.len()
+  );
+  *store.gesture_library.lock().await = library;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::server::modman::models::gestures::GestureOverride;
+
+  fn pack(gestures: &[(&str, &str)]) -> GesturePack {
+    GesturePack {
+      name: None,
+      description: None,
+      gestures: gestures
+        .iter()
+        .map(|(gesture_id, name)| {
+          (
+            gesture_id.to_string(),
+            Gesture {
+              name: Some(name.to_string()),
+              description: None,
+              configs: GestureBodyConfigs::default(),
+            },
+          )
+        })
+        .collect(),
+    }
+  }
+
+  fn library() -> GestureLibrary {
+    GestureLibrary {
+      packs: HashMap::from([
+        (
+          "pack.default".to_string(),
+          pack(&[("wave", "Default wave"), ("blink", "Default blink")]),
+        ),
+        (
+          "pack.primary".to_string(),
+          pack(&[("wave", "Primary wave")]),
+        ),
+        ("pack.other".to_string(), pack(&[("grin", "Other grin")])),
+      ]),
+      default_pack: "pack.default".to_string(),
+    }
+  }
+
+  fn parameters(min: f64) -> GestureParameters {
+    GestureParameters {
+      min,
+      max: 1.0,
+      multiplier: 1.0,
+      offset: 0.0,
+    }
+  }
+
+  /// The resolved gesture's full ID and name, and the minimum of the parameters it resolved with.
+  fn resolved(
+    library: &GestureLibrary,
+    gesture_id: &str,
+    gesture_config: Option<&GestureConfig>,
+  ) -> Option<(String, String, Option<f64>)> {
+    library
+      .resolve_for_component(gesture_id, gesture_config)
+      .map(|(resolved_id, gesture, parameters)| {
+        (
+          resolved_id,
+          gesture.name.clone().unwrap(),
+          parameters.map(|parameters| parameters.min),
+        )
+      })
+  }
+
+  #[test]
+  fn primary_pack_is_checked_before_default_pack() {
+    let library = library();
+    let gesture_config = GestureConfig {
+      primary_gesture_pack: Some("pack.primary".to_string()),
+      gesture_parameters: Some(parameters(-1.0)),
+      ..Default::default()
+    };
+
+    assert_eq!(
+      resolved(&library, "wave", None),
+      Some((
+        "wave@pack.default".to_string(),
+        "Default wave".to_string(),
+        None
+      ))
+    );
+    assert_eq!(
+      resolved(&library, "wave", Some(&gesture_config)),
+      Some((
+        "wave@pack.primary".to_string(),
+        "Primary wave".to_string(),
+        Some(-1.0)
+      ))
+    );
+    // Falls back to the default pack, and an explicit pack is always used.
+    assert_eq!(
+      resolved(&library, "blink", Some(&gesture_config)),
+      Some((
+        "blink@pack.default".to_string(),
+        "Default blink".to_string(),
+        Some(-1.0)
+      ))
+    );
+    assert_eq!(
+      resolved(&library, "wave@pack.default", Some(&gesture_config)),
+      Some((
+        "wave@pack.default".to_string(),
+        "Default wave".to_string(),
+        Some(-1.0)
+      ))
+    );
+    assert_eq!(resolved(&library, "grin", Some(&gesture_config)), None);
+  }
+
+  #[test]
+  fn overrides_take_precedence_over_packs() {
+    let library = library();
+    let gesture_config = GestureConfig {
+      primary_gesture_pack: Some("pack.primary".to_string()),
+      gesture_parameters: Some(parameters(-1.0)),
+      gesture_overrides: HashMap::from([
+        (
+          "wave".to_string(),
+          GestureOverride {
+            gesture_preset_id: "grin@pack.other".to_string(),
+            gesture_parameters: Some(parameters(-0.5)),
+          },
+        ),
+        (
+          "wave@pack.default".to_string(),
+          GestureOverride {
+            gesture_preset_id: "blink".to_string(),
+            gesture_parameters: None,
+          },
+        ),
+      ]),
+      ..Default::default()
+    };
+
+    assert_eq!(
+      resolved(&library, "wave", Some(&gesture_config)),
+      Some((
+        "grin@pack.other".to_string(),
+        "Other grin".to_string(),
+        Some(-0.5)
+      ))
+    );
+    // The full ID is looked up before the RFQDN, and the component's parameters apply if the override has none.
+    assert_eq!(
+      resolved(&library, "wave@pack.default", Some(&gesture_config)),
+      Some((
+        "blink@pack.default".to_string(),
+        "Default blink".to_string(),
+        Some(-1.0)
+      ))
+    );
+  }
+
+  #[test]
+  fn idle_default_gesture_resolves_like_any_other() {
+    let library = library();
+    let gesture_config = GestureConfig {
+      primary_gesture_pack: Some("pack.primary".to_string()),
+      default_gesture: Some("wave".to_string()),
+      ..Default::default()
+    };
+    let default_gesture = gesture_config.default_gesture.clone().unwrap();
+
+    assert_eq!(
+      resolved(&library, &default_gesture, Some(&gesture_config)),
+      Some((
+        "wave@pack.primary".to_string(),
+        "Primary wave".to_string(),
+        None
+      ))
+    );
+
+    let gesture_config = GestureConfig {
+      gesture_overrides: HashMap::from([(
+        "wave".to_string(),
+        GestureOverride {
+          gesture_preset_id: "grin@pack.other".to_string(),
+          gesture_parameters: None,
+        },
+      )]),
+      ..gesture_config
+    };
+    assert_eq!(
+      resolved(&library, &default_gesture, Some(&gesture_config)),
+      Some((
+        "grin@pack.other".to_string(),
+        "Other grin".to_string(),
+        None
+      ))
+    );
+  }
+}
@@ -0,0 +1,146 @@
+//! # Gesture Persistence
+//!
+//! Background gestures are saved into the [configuration](crate::server::modman::models::config::ModManConfig) in the `Begin` state when ModMan shuts down, and resumed when it starts back up if `restart_gestures` is set. Foreground gestures are never saved.
+//!
+
+use std::{
+  collections::HashMap,
+  sync::Arc,
+};
+
+use tracing::{
+  debug,
+  error,
+  info,
+  instrument,
+  warn,
+};
+
+use crate::server::modman::{
+  ipc::gestures::handle_gesture_cmd,
+  models::{
+    gestures::{
+      GestureCommand,
+      GestureState,
+      GestureStates,
+    },
+    store::ModManStore,
+  },
+};
+
+/// Write the background gesture stack into the configuration, replacing whatever was saved before.
+#[instrument(skip(store))]
+pub async fn save_background_gestures(store: &ModManStore) {
+  let background_ids = store.background_gesture_priority.lock().await.clone();
+  let mut saved_states = HashMap::new();
+  let mut saved_ids = vec![];
+
+  {
+    let gesture_states = store.gesture_states.lock().await;
+
+    for gesture_id in background_ids {
+      match gesture_states.get(&gesture_id) {
+        Some(states) => match states.current_state {
+          GestureState::Begin {
+            intensity,
+            speed,
+            background: _,
+          } => {
+            saved_states.insert(
+              gesture_id.clone(),
+              GestureStates {
+                prev_state: None,
+                paused: false,
+                current_state: GestureState::Begin {
+                  intensity,
+                  speed,
+                  background: Some(true),
+                },
+                next_state: None,
+                next_state_at: None,
+                began_at: None,
+                paused_at: None,
+                areas: states.areas.clone(),
+              },
+            );
+            saved_ids.push(gesture_id);
+          }
+          _ => {
+            debug!("Background gesture: {gesture_id}, was not begun, not saving it.");
+          }
+        },
+        None => {
+          warn!("Background gesture: {gesture_id}, is not in the state map, not saving it.");
+        }
+      }
+    }
+  }
+
+  info!("Saving {} background gesture(s)...", saved_ids.len());
+
+  let mut config = store.config.lock().await;
+  config.modman.gesture_states = saved_states;
+  config.modman.background_gestures = saved_ids;
+}
+
+/// Begin every background gesture that was saved on shutdown, if `restart_gestures` is enabled.
+#[instrument(skip(store))]
+pub async fn restore_background_gestures(store: &Arc<ModManStore>) {
+  let (saved_ids, saved_states) = {
+    let config = store.config.lock().await;
+
+    if !config.modman.restart_gestures {
+      debug!("Not restarting gestures, restart_gestures is disabled.");
+      return;
+    }
+
+    let mut saved_ids = config.modman.background_gestures.clone();
+    // Gestures that were added to the config by hand may not be in the ordered list.
+    for gesture_id in config.modman.gesture_states.keys() {
+      if !saved_ids.contains(gesture_id) {
+        saved_ids.push(gesture_id.clone());
+      }
+    }
+
+    (saved_ids, config.modman.gesture_states.clone())
+  };
+
+  for gesture_id in saved_ids {
+    match saved_states.get(&gesture_id) {
+      Some(states) => match states.current_state {
+        GestureState::Begin {
+          intensity,
+          speed,
+          background: _,
+        } => {
+          match handle_gesture_cmd(
+            store,
+            gesture_id.clone(),
+            GestureCommand {
+              state: GestureState::Begin {
+                intensity,
+                speed,
+                background: Some(true),
+              },
+              auto_switch: None,
+              next_state: None,
+              is_from_system: true,
+              areas: states.areas.clone(),
+            },
+          )
+          .await
+          {
+            Ok(_) => info!("Resumed background gesture: {gesture_id}"),
+            Err(err) => error!("Failed to resume background gesture: {gesture_id}, due to: {err}"),
+          }
+        }
+        _ => {
+          warn!("Saved gesture: {gesture_id}, is not in the begin state, skipping!");
+        }
+      },
+      None => {
+        warn!("Saved gesture: {gesture_id}, has no saved state, skipping!");
+      }
+    }
+  }
+}
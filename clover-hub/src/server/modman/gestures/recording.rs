@@ -0,0 +1,708 @@
+//! # Gesture Recording
+//!
+//! Gestures can be authored by recording what a performer does live. While a recording is running, the [telemetry](crate::server::modman::busses::models::ComponentTelemetry) that modules send on `.../modules/by-id/{module_id}/recv` is sampled into a timed sequence per component parameter.
+//!
+//! When the recording is stopped, every sample is normalised back into intensity space with [`GestureParameters::inverse_intensity`] (using the same parameters that would be used to play the gesture back on that component), and the result is keyed by the body and area of each component's location.
+//!
+//! Recorded gestures are saved into a gesture pack in a local repo (see [`ModManConfig::recordings_repo`](crate::server::modman::models::config::ModManConfig::recordings_repo)) that is registered with Warehouse so it's loaded on the next startup, and into the live [gesture library](super::packs::GestureLibrary) so they can be played back right away.
+//!
+
+use std::{
+  collections::HashMap,
+  sync::Arc,
+};
+
+use anyhow::anyhow;
+use os_path::OsPath;
+use tokio::{
+  fs,
+  time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  error,
+  info,
+  instrument,
+  warn,
+};
+
+use super::{
+  areas::AreaRegistry,
+  packs::{
+    Gesture,
+    GesturePack,
+  },
+};
+use crate::{
+  server::{
+    modman::{
+      busses::models::{
+        BusMessage,
+        ComponentTelemetry,
+      },
+      models::{
+        components::{
+          CloverComponent,
+          CloverComponentMeta,
+        },
+        gestures::{
+          GestureAnimation,
+          GestureBodyConfigs,
+          GestureCurve,
+          GestureKeyframe,
+          GestureParameters,
+          GestureRecordingError,
+          GestureRecordingRequest,
+          GestureRecordingSuccess,
+          GestureRepeat,
+          GestureTrack,
+        },
+        store::ModManStore,
+      },
+      MODULE_EVT_ID,
+    },
+    warehouse::{
+      config::models::RepoSpec,
+      repos::models::{
+        ManifestSpec,
+        OptionalListManifestSpecEntry,
+        OptionalSingleManifestSpecEntry,
+        RawDirectorySpec,
+        RawGesturePackSpec,
+        RawGestureSpec,
+        RawKeyframedGestureSpec,
+        RequiredSingleManifestEntry,
+      },
+    },
+  },
+  utils::read_file,
+};
+
+/// Name used for the recordings repo and its gesture pack.
+const RECORDINGS_NAME: &str = "Recorded Gestures";
+
+/// Path of the recordings gesture pack, relative to the recordings repo.
+const RECORDINGS_PACK_PATH: &str = "gesture-packs/recorded.clover.json";
+
+/// A gesture that is being recorded.
+#[derive(Debug, Clone)]
+pub struct GestureRecording {
+  pub name: Option<String>,
+  pub description: Option<String>,
+  pub module_ids: Vec<String>,
+  /// Only record these components, every component of the modules is recorded if unset.
+  pub component_ids: Option<Vec<String>>,
+  pub started_at: Instant,
+  /// Raw values keyed by component ID, then parameter, where `x` is milliseconds since the recording started.
+  pub samples: HashMap<String, HashMap<String, Vec<GestureKeyframe>>>,
+  pub cancellation_token: CancellationToken,
+}
+
+/// Start recording telemetry from modules into a new gesture.
+#[instrument(skip(store, session, cancellation_token))]
+pub async fn start_recording(
+  store: &ModManStore,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+  request: GestureRecordingRequest,
+) -> Result<GestureRecordingSuccess, GestureRecordingError> {
+  let GestureRecordingRequest {
+    gesture_id,
+    name,
+    description,
+    module_ids,
+    component_ids,
+  } = request;
+  let mut recordings = store.gesture_recordings.lock().await;
+
+  if recordings.contains_key(&gesture_id) {
+    return Err(GestureRecordingError::AlreadyRecording { gesture_id });
+  }
+
+  let recording_token = cancellation_token.child_token();
+  for module_id in module_ids.iter() {
+    tokio::task::spawn(record_module_telemetry(
+      store.clone(),
+      session.clone(),
+      recording_token.clone(),
+      gesture_id.clone(),
+      module_id.clone(),
+    ));
+  }
+
+  info!(
+    "Recording gesture: {gesture_id}, from {} module(s)...",
+    module_ids.len()
+  );
+  recordings.insert(
+    gesture_id.clone(),
+    GestureRecording {
+      name,
+      description,
+      module_ids,
+      component_ids,
+      started_at: Instant::now(),
+      samples: HashMap::new(),
+      cancellation_token: recording_token,
+    },
+  );
+
+  Ok(GestureRecordingSuccess::Started { gesture_id })
+}
+
+/// Sample component telemetry from a module into a recording until it's stopped.
+#[instrument(skip(store, session, cancellation_token))]
+async fn record_module_telemetry(
+  store: ModManStore,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+  gesture_id: String,
+  module_id: String,
+) {
+  let key_expr = format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/recv");
+
+  let subscriber = match session.declare_subscriber(&key_expr).await {
+    Ok(subscriber) => subscriber,
+    Err(err) => {
+      error!("Failed to subscribe to: {key_expr}, module: {module_id}, will not be recorded! Due to:\n{err}");
+      return;
+    }
+  };
+
+  debug!("Recording {key_expr}...");
+  loop {
+    let sample = tokio::select! {
+      _ = cancellation_token.cancelled() => { break; }
+      sample = subscriber.recv_async() => sample,
+    };
+
+    let telemetry = match sample {
+      Ok(sample) => {
+        match sample.payload().try_to_string() {
+          Ok(payload) => match serde_json_lenient::from_str::<BusMessage>(&payload) {
+            Ok(BusMessage::Content(content)) => {
+              match rmp_serde::from_slice::<ComponentTelemetry>(&content.data) {
+                Ok(telemetry) => telemetry,
+                Err(err) => {
+                  debug!("Message from module: {module_id}, is not component telemetry, ignoring. ({err})");
+                  continue;
+                }
+              }
+            }
+            Ok(_) => continue,
+            Err(err) => {
+              warn!("Failed to parse message from module: {module_id}, due to:\n{err}");
+              continue;
+            }
+          },
+          Err(err) => {
+            warn!("Message from module: {module_id}, is not a string, due to:\n{err}");
+            continue;
+          }
+        }
+      }
+      Err(err) => {
+        error!("{err}");
+        continue;
+      }
+    };
+
+    let mut recordings = store.gesture_recordings.lock().await;
+    match recordings.get_mut(&gesture_id) {
+      Some(recording) => {
+        if let Some(component_ids) = &recording.component_ids {
+          if !component_ids.contains(&telemetry.component_id) {
+            continue;
+          }
+        }
+
+        let x = recording.started_at.elapsed().as_secs_f64() * 1000.0;
+        let component_samples = recording.samples.entry(telemetry.component_id).or_default();
+
+        for (parameter, value) in telemetry.values {
+          component_samples
+            .entry(parameter)
+            .or_default()
+            .push(GestureKeyframe { x, y: value });
+        }
+      }
+      None => break,
+    }
+  }
+
+  debug!("Stopped recording {key_expr}.");
+}
+
+/// Parameters used to play a gesture parameter back on a component, so they can be inverted.
+fn playback_parameters<'a>(
+  component_entry: &'a (CloverComponentMeta, CloverComponent),
+  parameter: &str,
+) -> Option<&'a GestureParameters> {
+  let gesture_config = component_entry.1.gesture_config();
+
+  match (
+    &component_entry.1,
+    gesture_config.and_then(|config| config.gesture_parameters.as_ref()),
+  ) {
+    (_, Some(parameters)) => Some(parameters),
+    (CloverComponent::MovementComponent(movement), None) => {
+      movement.gesture_parameters.as_ref().and_then(|parameters| {
+        parameters
+          .axes()
+          .into_iter()
+          .find(|(axis, _)| *axis == parameter)
+          .map(|(_, parameters)| parameters)
+      })
+    }
+    (_, None) => component_entry.0.base_gesture_parameters.get(parameter),
+  }
+}
+
+/// Drop samples in the middle of a plateau, since they don't change the curve.
+fn simplify_keyframes(keyframes: Vec<GestureKeyframe>) -> Vec<GestureKeyframe> {
+  let mut simplified: Vec<GestureKeyframe> = vec![];
+
+  for (index, keyframe) in keyframes.iter().enumerate() {
+    let same_as_prev = simplified.last().map(|prev| prev.y == keyframe.y) == Some(true);
+    let same_as_next = keyframes.get(index + 1).map(|next| next.y == keyframe.y) == Some(true);
+
+    if !(same_as_prev && same_as_next) {
+      simplified.push(keyframe.clone());
+    }
+  }
+
+  simplified
+}
+
+/// Normalise a recording's samples into intensity space, keyed by body and area like any other gesture.
+pub fn normalise_recording(
+  recording: &GestureRecording,
+  components: &HashMap<String, Arc<(CloverComponentMeta, CloverComponent)>>,
+  registry: &AreaRegistry,
+) -> GestureBodyConfigs {
+  let mut configs = GestureBodyConfigs::default();
+
+  for (component_id, parameters) in recording.samples.iter() {
+    let component_entry = match components.get(component_id) {
+      Some(component_entry) => component_entry,
+      None => {
+        warn!("Recorded component: {component_id}, is not in the store, skipping!");
+        continue;
+      }
+    };
+    let (body_id, area) = match registry.split_location(&component_entry.0.location) {
+      Some(split) => split,
+      None => {
+        warn!(
+          "Recorded component: {component_id}, is not located in an area of a known body ({}), skipping!",
+          component_entry.0.location
+        );
+        continue;
+      }
+    };
+
+    for (parameter, samples) in parameters.iter() {
+      let playback = match playback_parameters(component_entry, parameter) {
+        Some(playback) => playback,
+        None => {
+          debug!("Recorded component: {component_id}, has no gesture parameters for: {parameter}, skipping.");
+          continue;
+        }
+      };
+
+      let keyframes: Vec<GestureKeyframe> = samples
+        .iter()
+        .filter_map(|sample| {
+          playback
+            .inverse_intensity(sample.y)
+            .map(|y| GestureKeyframe { x: sample.x, y })
+        })
+        .collect();
+      let keyframes = simplify_keyframes(keyframes);
+
+      let track = match keyframes.as_slice() {
+        [] => continue,
+        [only] => GestureTrack::Constant(only.y),
+        _ => GestureTrack::Animated {
+          animation: GestureAnimation {
+            curve: GestureCurve::Linear,
+            repeat: GestureRepeat::Once,
+            keyframes,
+          },
+        },
+      };
+
+      configs
+        .0
+        .entry(body_id.clone())
+        .or_default()
+        .entry(area.clone())
+        .or_default()
+        .insert(parameter.clone(), track);
+    }
+  }
+
+  configs
+}
+
+/// Stop a recording, and save it unless `discard` is set.
+#[instrument(skip(store))]
+pub async fn stop_recording(
+  store: &ModManStore,
+  gesture_id: String,
+  discard: bool,
+) -> Result<GestureRecordingSuccess, GestureRecordingError> {
+  let recording = match store.gesture_recordings.lock().await.remove(&gesture_id) {
+    Some(recording) => recording,
+    None => return Err(GestureRecordingError::NotRecording { gesture_id }),
+  };
+  recording.cancellation_token.cancel();
+
+  if discard {
+    info!("Discarded recording of gesture: {gesture_id}.");
+    return Ok(GestureRecordingSuccess::Discarded { gesture_id });
+  }
+
+  let configs = {
+    let components = store.components.lock().await;
+    let registry = store.areas.lock().await;
+    normalise_recording(&recording, &components, &registry)
+  };
+
+  if configs.0.is_empty() {
+    return Err(GestureRecordingError::NothingRecorded { gesture_id });
+  }
+
+  let gesture = Gesture {
+    name: recording.name.clone(),
+    description: recording.description.clone(),
+    configs,
+  };
+
+  match save_recorded_gesture(store, &gesture_id, gesture).await {
+    Ok((pack_id, path)) => {
+      info!("Saved recorded gesture: {gesture_id}, into: {path}!");
+      Ok(GestureRecordingSuccess::Saved {
+        gesture_id: format!("{gesture_id}@{pack_id}"),
+        path,
+      })
+    }
+    Err(err) => {
+      error!("Failed to save recorded gesture: {gesture_id}, due to:\n{err}");
+      Err(GestureRecordingError::FailedToSave {
+        gesture_id,
+        reason: err.to_string(),
+      })
+    }
+  }
+}
+
+/// Write a recorded gesture into the recordings repo (creating and registering it if needed), and load it into the gesture library. Returns the pack's RFQDN and the path to the pack file.
+#[instrument(skip(store, gesture))]
+async fn save_recorded_gesture(
+  store: &ModManStore,
+  gesture_id: &String,
+  gesture: Gesture,
+) -> Result<(String, String), anyhow::Error> {
+  let (data_dir, repo_id) = {
+    let config = store.config.lock().await;
+    (
+      config.data_dir.clone(),
+      config.modman.recordings_repo.clone(),
+    )
+  };
+
+  // Same layout as Warehouse, see `update_repo_dir_structure`.
+  let repo_path = OsPath::new()
+    .join(data_dir.join("/repos/").to_string())
+    .join(repo_id.split(".").collect::<Vec<&str>>().join("/"))
+    .join("/@repo/");
+  let pack_path = repo_path.join(RECORDINGS_PACK_PATH);
+  let manifest_path = repo_path.join("/manifest.clover.json");
+
+  fs::create_dir_all(pack_path.parent().unwrap_or(repo_path.clone()).to_string()).await?;
+
+  // Warehouse only loads repos that it can open with git, a repo without remotes is left as-is.
+  if !repo_path.join("/.git/").exists() {
+    git2::Repository::init(repo_path.to_string())?;
+    info!("Created recordings repo: {repo_id}!");
+  }
+
+  if !manifest_path.exists() {
+    let mut gesture_packs = HashMap::new();
+    gesture_packs.insert(
+      repo_id.clone(),
+      RequiredSingleManifestEntry::ImportString(format!("@import('./{RECORDINGS_PACK_PATH}')")),
+    );
+
+    let manifest = ManifestSpec {
+      name: Some(RECORDINGS_NAME.to_string()),
+      version: "0.1.0".to_string(),
+      base: Some(repo_id.clone()),
+      directory: OptionalSingleManifestSpecEntry::Some(RawDirectorySpec {
+        gesture_packs: OptionalListManifestSpecEntry::Some(gesture_packs),
+        ..Default::default()
+      }),
+    };
+
+    fs::write(
+      manifest_path.to_string(),
+      serde_json::to_string_pretty(&manifest)?,
+    )
+    .await?;
+  }
+
+  let mut pack_spec = if pack_path.exists() {
+    serde_json_lenient::from_str::<RawGesturePackSpec>(&read_file(pack_path.clone()).await?)?
+  } else {
+    RawGesturePackSpec {
+      name: Some(RECORDINGS_NAME.to_string()),
+      description: Some("Gestures recorded from live module telemetry.".to_string()),
+      gestures: OptionalListManifestSpecEntry::None,
+    }
+  };
+
+  let gesture_spec = RequiredSingleManifestEntry::Some(RawGestureSpec::RawKeyframedGestureSpec(
+    RawKeyframedGestureSpec {
+      name: gesture.name.clone(),
+      description: gesture.description.clone(),
+      configs: OptionalSingleManifestSpecEntry::Some(gesture.configs.clone()),
+    },
+  ));
+  match &mut pack_spec.gestures {
+    OptionalListManifestSpecEntry::Some(gestures) => {
+      gestures.insert(gesture_id.clone(), gesture_spec);
+    }
+    OptionalListManifestSpecEntry::ImportString(import) => {
+      return Err(anyhow!(
+        "Recordings pack imports its gestures from: {import}, refusing to overwrite it."
+      ));
+    }
+    OptionalListManifestSpecEntry::None => {
+      pack_spec.gestures =
+        OptionalListManifestSpecEntry::Some(HashMap::from([(gesture_id.clone(), gesture_spec)]));
+    }
+  }
+
+  fs::write(
+    pack_path.to_string(),
+    serde_json::to_string_pretty(&pack_spec)?,
+  )
+  .await?;
+
+  {
+    let mut config = store.config.lock().await;
+    if !config.repos.contains_key(&repo_id) {
+      config.repos.insert(
+        repo_id.clone(),
+        RepoSpec {
+          name: Some(RECORDINGS_NAME.to_string()),
+          src: repo_path.to_string(),
+          branch: "main".to_string(),
+        },
+      );
+      info!("Registered recordings repo: {repo_id}, with Warehouse.");
+    }
+  }
+
+  store
+    .gesture_library
+    .lock()
+    .await
+    .packs
+    .entry(repo_id.clone())
+    .or_insert_with(|| GesturePack {
+      name: Some(RECORDINGS_NAME.to_string()),
+      description: None,
+      gestures: HashMap::new(),
+    })
+    .gestures
+    .insert(gesture_id.clone(), gesture);
+
+  Ok((repo_id, pack_path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::server::modman::{
+    components::movement::models::{
+      ConnectionType,
+      DegreesOfFreedom,
+      DegreesOfFreedomGestureParams,
+      MovementComponent,
+    },
+    gestures::areas::humanoid_body_id,
+    models::gestures::GestureConfig,
+  };
+
+  fn test_store(data_dir: &std::path::Path) -> ModManStore {
+    let store = ModManStore::new(None, None);
+    store.config.try_lock().unwrap().data_dir =
+      OsPath::from(data_dir.to_string_lossy().to_string());
+    store
+  }
+
+  fn test_gesture() -> Gesture {
+    let mut configs = GestureBodyConfigs::default();
+    configs
+      .0
+      .entry("@core.humanoid".to_string())
+      .or_default()
+      .entry("torso.head.face.mouth".to_string())
+      .or_default()
+      .insert("curve".to_string(), GestureTrack::Constant(0.5));
+
+    Gesture {
+      name: Some("Smirk".to_string()),
+      description: None,
+      configs,
+    }
+  }
+
+  #[test]
+  fn recordings_normalise_into_keyframes() {
+    let movement = CloverComponent::MovementComponent(MovementComponent {
+      initial_position: DegreesOfFreedom::OneDegree(0.0),
+      gesture_config: GestureConfig::default(),
+      // -1.0, 0.0, and 1.0 map onto -90, 0, and 90 degrees.
+      gesture_parameters: Some(DegreesOfFreedomGestureParams::OneDegree(
+        GestureParameters {
+          min: -90.0,
+          max: 0.0,
+          multiplier: 1.0,
+          offset: 0.0,
+        },
+      )),
+      axis_limits: HashMap::new(),
+      connection: ConnectionType::ModManProxy,
+    });
+    let meta = |location: &str| CloverComponentMeta {
+      name: "Tail".to_string(),
+      critical: true,
+      location: location.to_string(),
+      base_gesture_parameters: HashMap::new(),
+      internal: false,
+    };
+    let components = HashMap::from([
+      (
+        "tail".to_string(),
+        Arc::new((meta("@core.humanoid.pelvis.rear.left"), movement.clone())),
+      ),
+      (
+        "lost".to_string(),
+        Arc::new((meta("@clover.unknown.body.area"), movement)),
+      ),
+    ]);
+
+    let keyframe = |x: f64, y: f64| GestureKeyframe { x, y };
+    let recording = GestureRecording {
+      name: None,
+      description: None,
+      module_ids: vec![],
+      component_ids: None,
+      started_at: Instant::now(),
+      samples: HashMap::from([
+        (
+          "tail".to_string(),
+          HashMap::from([
+            (
+              "x".to_string(),
+              vec![
+                keyframe(0.0, -90.0),
+                keyframe(100.0, -90.0),
+                keyframe(200.0, -90.0),
+                keyframe(300.0, 0.0),
+                keyframe(400.0, 90.0),
+              ],
+            ),
+            // Not an axis of the component.
+            ("y".to_string(), vec![keyframe(0.0, 1.0)]),
+          ]),
+        ),
+        (
+          "lost".to_string(),
+          HashMap::from([("x".to_string(), vec![keyframe(0.0, 45.0)])]),
+        ),
+      ]),
+      cancellation_token: CancellationToken::new(),
+    };
+
+    let configs = normalise_recording(&recording, &components, &AreaRegistry::default());
+
+    let body_configs = &configs.0[&humanoid_body_id()];
+    assert_eq!(configs.0.len(), 1);
+    assert_eq!(body_configs.len(), 1);
+    assert_eq!(
+      body_configs["pelvis.rear.left"],
+      HashMap::from([(
+        "x".to_string(),
+        GestureTrack::Animated {
+          animation: GestureAnimation {
+            curve: GestureCurve::Linear,
+            repeat: GestureRepeat::Once,
+            // The plateau's middle sample is dropped.
+            keyframes: vec![
+              keyframe(0.0, -1.0),
+              keyframe(200.0, -1.0),
+              keyframe(300.0, 0.0),
+              keyframe(400.0, 1.0),
+            ],
+          },
+        }
+      )])
+    );
+  }
+
+  #[tokio::test]
+  async fn saving_registers_recordings_repo() {
+    let data_dir =
+      std::env::temp_dir().join(format!("clover-recording-test-{}", std::process::id()));
+    let store = test_store(&data_dir);
+    let repo_id = store.config.lock().await.modman.recordings_repo.clone();
+
+    let (pack_id, path) = save_recorded_gesture(&store, &"smirk".to_string(), test_gesture())
+      .await
+      .unwrap();
+    assert_eq!(pack_id, repo_id);
+
+    let repo = store
+      .config
+      .lock()
+      .await
+      .repos
+      .get(&repo_id)
+      .cloned()
+      .unwrap();
+    let repo_path = OsPath::from(repo.src.clone());
+    assert!(repo_path.join("/.git/").exists());
+    assert!(repo_path.join("/manifest.clover.json").exists());
+
+    let pack_spec = serde_json_lenient::from_str::<RawGesturePackSpec>(
+      &read_file(OsPath::from(path)).await.unwrap(),
+    )
+    .unwrap();
+    assert!(matches!(
+      pack_spec.gestures,
+      OptionalListManifestSpecEntry::Some(gestures) if gestures.contains_key("smirk")
+    ));
+    assert!(store.gesture_library.lock().await.packs[&repo_id]
+      .gestures
+      .contains_key("smirk"));
+
+    // A second recording reuses the registered repo and pack.
+    save_recorded_gesture(&store, &"grin".to_string(), test_gesture())
+      .await
+      .unwrap();
+    assert_eq!(store.config.lock().await.repos.len(), 1);
+    assert_eq!(
+      store.gesture_library.lock().await.packs[&repo_id]
+        .gestures
+        .len(),
+      2
+    );
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+  }
+}
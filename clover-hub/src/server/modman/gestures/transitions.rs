@@ -0,0 +1,205 @@
+//! # Gesture Transitions
+//!
+//! Gestures that are begun with an `auto_switch` delay get a `next_state`, which is promoted to their current state once the delay elapses (the replaced state is kept as `prev_state` so it can be reverted to). Paused gestures stop the clock until they're un-paused.
+//!
+//! As soon as a switch is scheduled, it's announced as a [`GesturePreload`] so the Renderer and modules can load anything they need before the switch happens.
+//!
+
+use std::{
+  collections::HashMap,
+  sync::Arc,
+};
+
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{
+  debug,
+  error,
+  info,
+  instrument,
+};
+
+use super::{
+  command_generator::gesture_command_generator,
+  GESTURE_TICK_INTERVAL,
+};
+use crate::server::modman::{
+  ipc::gestures::handle_gesture_cmd,
+  models::{
+    gestures::{
+      GestureCommand,
+      GesturePreload,
+      GestureState,
+      GestureStates,
+    },
+    store::ModManStore,
+  },
+  MODULE_EVT_ID,
+};
+
+/// Announce an upcoming switch to the Renderer, and to every component that the switch will affect.
+#[instrument(skip(store, session, gesture_state))]
+async fn preload_transition(
+  store: &ModManStore,
+  session: &zenoh::Session,
+  gesture_id: &String,
+  gesture_state: &GestureStates,
+  next_state: &GestureState,
+  next_state_at: Instant,
+) {
+  let preload = GesturePreload {
+    gesture_id: gesture_id.clone(),
+    state: next_state.clone(),
+    switch_in: next_state_at
+      .saturating_duration_since(Instant::now())
+      .as_secs_f64(),
+  };
+
+  match serde_json::to_string(&preload) {
+    Ok(preload_str) => {
+      if let Err(err) = session
+        .put(format!("{MODULE_EVT_ID}/gestures/preload"), preload_str)
+        .await
+      {
+        error!("Failed to publish preload for gesture: {gesture_id}, due to:\n{err}");
+      }
+    }
+    Err(err) => {
+      error!("Failed to serialize preload for gesture: {gesture_id}, this is a bug and should be reported! Due to:\n{err}");
+    }
+  }
+
+  // Calculate what components will get once the switch happens.
+  let upcoming_state = GestureStates {
+    prev_state: Some(gesture_state.current_state.clone()),
+    current_state: next_state.clone(),
+    next_state: None,
+    next_state_at: None,
+    began_at: None,
+    paused_at: None,
+    paused: false,
+    areas: gesture_state.areas.clone(),
+  };
+
+  for (component_id, command) in gesture_command_generator(store, gesture_id, &upcoming_state).await
+  {
+    match serde_json::to_string(&command) {
+      Ok(command_str) => {
+        if let Err(err) = session
+          .put(
+            format!("{MODULE_EVT_ID}/components/by-id/{component_id}/gesture/preload"),
+            command_str,
+          )
+          .await
+        {
+          error!("Failed to publish gesture preload for component: {component_id}, due to:\n{err}");
+        }
+      }
+      Err(err) => {
+        error!("Failed to serialize gesture preload for component: {component_id}, this is a bug and should be reported! Due to:\n{err}");
+      }
+    }
+  }
+}
+
+/// Thread to announce and promote scheduled gesture state switches.
+#[instrument(skip(store, session, cancellation_token))]
+pub async fn gesture_transition_scheduler(
+  store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+) {
+  // Switches that have already been announced, so we only preload once per switch.
+  let mut announced: HashMap<String, Instant> = HashMap::new();
+  let mut interval = tokio::time::interval(GESTURE_TICK_INTERVAL);
+
+  while !cancellation_token.is_cancelled() {
+    tokio::select! {
+      _ = cancellation_token.cancelled() => { break; }
+      _ = interval.tick() => {}
+    }
+
+    let scheduled: Vec<(String, GestureStates, GestureState, Instant)> = {
+      let gesture_states = store.gesture_states.lock().await;
+
+      gesture_states
+        .iter()
+        .filter(|(_, gesture_state)| !gesture_state.paused)
+        .filter_map(|(gesture_id, gesture_state)| {
+          match (&gesture_state.next_state, gesture_state.next_state_at) {
+            (Some((_, next_state)), Some(next_state_at)) => Some((
+              gesture_id.clone(),
+              gesture_state.clone(),
+              next_state.clone(),
+              next_state_at,
+            )),
+            _ => None,
+          }
+        })
+        .collect()
+    };
+
+    announced.retain(|gesture_id, next_state_at| {
+      scheduled
+        .iter()
+        .any(|(id, _, _, at)| id == gesture_id && at == next_state_at)
+    });
+
+    let now = Instant::now();
+    for (gesture_id, gesture_state, next_state, next_state_at) in scheduled {
+      if announced.get(&gesture_id) != Some(&next_state_at) {
+        debug!("Gesture: {gesture_id}, will switch to: {next_state:?}, announcing preload...");
+        preload_transition(
+          &store,
+          &session,
+          &gesture_id,
+          &gesture_state,
+          &next_state,
+          next_state_at,
+        )
+        .await;
+        announced.insert(gesture_id.clone(), next_state_at);
+      }
+
+      if next_state_at > now {
+        continue;
+      }
+
+      // Make sure the switch wasn't re-scheduled or cancelled since we took the snapshot, then un-schedule it so it can only fire once.
+      let still_due = match store.gesture_states.lock().await.get_mut(&gesture_id) {
+        Some(current) if current.next_state_at == Some(next_state_at) && !current.paused => {
+          current.next_state = None;
+          current.next_state_at = None;
+          true
+        }
+        _ => false,
+      };
+
+      if !still_due {
+        continue;
+      }
+
+      info!("Switching gesture: {gesture_id}, to: {next_state:?}");
+      announced.remove(&gesture_id);
+
+      match handle_gesture_cmd(
+        &store,
+        gesture_id.clone(),
+        GestureCommand {
+          state: next_state,
+          auto_switch: None,
+          next_state: None,
+          is_from_system: true,
+          areas: gesture_state.areas.clone(),
+        },
+      )
+      .await
+      {
+        Ok(res) => debug!("Gesture: {gesture_id}, switched: {res}"),
+        Err(err) => error!("Failed to switch gesture: {gesture_id}, due to: {err}"),
+      }
+    }
+  }
+
+  info!("Stopped scheduling gesture transitions!");
+}
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use log::{
+  debug,
+  error,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+
+use super::{
+  id_from_key_expr,
+  reply_result,
+};
+use crate::server::modman::{
+  gestures::areas::location_matches,
+  models::{
+    components::{
+      ComponentInfo,
+      COMPONENT_TYPE_NAMES,
+    },
+    modules::RegistryError,
+    store::ModManStore,
+  },
+  MODULE_EVT_ID,
+};
+
+/// Every component in the store, with the module that lists it.
+async fn component_infos(store: &ModManStore) -> Vec<ComponentInfo> {
+  let modules = store.modules.lock().await;
+  let components = store.components.lock().await;
+
+  components
+    .iter()
+    .map(|(component_id, component_entry)| ComponentInfo {
+      component_id: component_id.clone(),
+      module_id: modules
+        .iter()
+        .find(|(_, module)| module.components.iter().any(|(id, _)| id == component_id))
+        .map(|(module_id, _)| module_id.clone()),
+      component_type: component_entry.1.type_name().to_string(),
+      meta: component_entry.0.clone(),
+      component: component_entry.1.clone(),
+    })
+    .collect()
+}
+
+/// Get a single component, at `{MODULE_EVT_ID}/components/by-id/{component_id}/config`.
+#[instrument(skip(store, cancellation_token, session))]
+pub async fn component_config_queryable(
+  store: ModManStore,
+  cancellation_token: CancellationToken,
+  session: Arc<zenoh::Session>,
+) {
+  let prefix = format!("{MODULE_EVT_ID}/components/by-id/");
+  let key_expr = format!("{prefix}*/config");
+
+  let queryable = session.declare_queryable(&key_expr).await.unwrap();
+
+  debug!("Listening on {key_expr}!");
+  while !cancellation_token.is_cancelled() {
+    match queryable.recv_async().await {
+      Ok(query) => {
+        let reply_key_expr = query.key_expr().to_string();
+        let component_id = id_from_key_expr(&reply_key_expr, &prefix).unwrap_or_default();
+
+        let result = component_infos(&store)
+          .await
+          .into_iter()
+          .find(|component| component.component_id == component_id)
+          .ok_or(RegistryError::UnknownComponent { component_id });
+
+        reply_result(query, &reply_key_expr, result).await;
+      }
+      Err(err) => {
+        error!("{err}")
+      }
+    }
+  }
+}
+
+/// List components of a type, at `{MODULE_EVT_ID}/components/by-type/{type}/all`, see [`COMPONENT_TYPE_NAMES`].
+#[instrument(skip(store, cancellation_token, session))]
+pub async fn component_type_queryable(
+  store: ModManStore,
+  cancellation_token: CancellationToken,
+  session: Arc<zenoh::Session>,
+) {
+  let prefix = format!("{MODULE_EVT_ID}/components/by-type/");
+  let key_expr = format!("{prefix}*/all");
+
+  let queryable = session.declare_queryable(&key_expr).await.unwrap();
+
+  debug!("Listening on {key_expr}!");
+  while !cancellation_token.is_cancelled() {
+    match queryable.recv_async().await {
+      Ok(query) => {
+        let reply_key_expr = query.key_expr().to_string();
+        let component_type = id_from_key_expr(&reply_key_expr, &prefix).unwrap_or_default();
+
+        let result = if COMPONENT_TYPE_NAMES.contains(&component_type.as_str()) {
+          Ok(
+            component_infos(&store)
+              .await
+              .into_iter()
+              .filter(|component| component.component_type == component_type)
+              .collect::<Vec<ComponentInfo>>(),
+          )
+        } else {
+          Err(RegistryError::UnknownComponentType { component_type })
+        };
+
+        reply_result(query, &reply_key_expr, result).await;
+      }
+      Err(err) => {
+        error!("{err}")
+      }
+    }
+  }
+}
+
+/// List components under an [area](crate::server::modman::gestures::areas) query (e.g. `*.head.face.eyes`) sent as the payload, at `{MODULE_EVT_ID}/components/by-area`.
+#[instrument(skip(store, cancellation_token, session))]
+pub async fn component_area_queryable(
+  store: ModManStore,
+  cancellation_token: CancellationToken,
+  session: Arc<zenoh::Session>,
+) {
+  let key_expr = format!("{MODULE_EVT_ID}/components/by-area");
+
+  let queryable = session.declare_queryable(&key_expr).await.unwrap();
+
+  debug!("Listening on {key_expr}!");
+  while !cancellation_token.is_cancelled() {
+    match queryable.recv_async().await {
+      Ok(query) => {
+        let area = match query.payload() {
+          Some(payload) => match payload.try_to_string() {
+            Ok(payload_str) => Ok(payload_str.to_string()),
+            Err(err) => Err(format!("Payload is not a string: {err}")),
+          },
+          None => Err("No payload was sent.".to_string()),
+        };
+
+        let result = match area {
+          Ok(area) => {
+            let bodies = store.config.lock().await.modman.bodies.clone();
+            let patterns = store.areas.lock().await.resolve_patterns(&bodies, &[area]);
+
+            Ok(
+              component_infos(&store)
+                .await
+                .into_iter()
+                .filter(|component| {
+                  patterns
+                    .iter()
+                    .any(|pattern| location_matches(pattern, &component.meta.location))
+                })
+                .collect::<Vec<ComponentInfo>>(),
+            )
+          }
+          Err(reason) => {
+            error!("Failed to parse component area query, due to:\n{reason}");
+            Err(RegistryError::InvalidPayload { reason })
+          }
+        };
+
+        reply_result(query, &key_expr, result).await;
+      }
+      Err(err) => {
+        error!("{err}")
+      }
+    }
+  }
+}
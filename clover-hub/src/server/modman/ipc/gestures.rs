@@ -1,4 +1,7 @@
-use std::sync::Arc;
+use std::{
+  sync::Arc,
+  time::Duration,
+};
 
 use log::{
   debug,
@@ -6,15 +9,28 @@ use log::{
   info,
   warn,
 };
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use tracing::instrument;
 
+use super::reply_result;
 use crate::server::modman::{
+  gestures::recording::{
+    start_recording,
+    stop_recording,
+  },
   models::{
     gestures::{
       GestureCommand,
+      GestureError,
+      GestureRecordingCommand,
+      GestureRecordingError,
+      GestureStackEntry,
+      GestureStacks,
       GestureState,
       GestureStates,
+      GestureUpdate,
+      GestureUpdateSuccess,
     },
     store::ModManStore,
   },
@@ -35,24 +51,161 @@ pub async fn gesture_queryable(
   session: Arc<zenoh::Session>,
 ) {
   let key_expr = format!("{MODULE_EVT_ID}/gestures/update");
+  let store = Arc::new(store);
 
   let queryable = session.declare_queryable(&key_expr).await.unwrap();
 
   debug!("Listening on {key_expr}!");
   while !cancellation_token.is_cancelled() {
     match queryable.recv_async().await {
-      Ok(query) => {}
-      Err(e) => {}
+      Ok(query) => {
+        let update = match query.payload() {
+          Some(payload) => match payload.try_to_string() {
+            Ok(payload_str) => serde_json_lenient::from_str::<GestureUpdate>(&payload_str)
+              .map_err(|err| format!("Payload is not a gesture update: {err}")),
+            Err(err) => Err(format!("Payload is not a string: {err}")),
+          },
+          None => Err("No payload was sent.".to_string()),
+        };
+
+        match update {
+          Ok(update) => {
+            let result = handle_gesture_cmd(&store, update.gesture_id, update.command).await;
+            reply_result(query, &key_expr, result).await;
+          }
+          Err(reason) => {
+            error!("Failed to parse gesture update query, due to:\n{reason}");
+            reply_result(
+              query,
+              &key_expr,
+              Err::<GestureUpdateSuccess, _>(GestureError::InvalidPayload { reason }),
+            )
+            .await;
+          }
+        }
+      }
+      Err(err) => {
+        error!("{err}")
+      }
+    }
+  }
+}
+
+/// Start, stop, and discard [gesture recordings](crate::server::modman::gestures::recording).
+#[instrument(skip(store, cancellation_token, session))]
+pub async fn gesture_recording_queryable(
+  store: ModManStore,
+  cancellation_token: CancellationToken,
+  session: Arc<zenoh::Session>,
+) {
+  let key_expr = format!("{MODULE_EVT_ID}/gestures/record");
+
+  let queryable = session.declare_queryable(&key_expr).await.unwrap();
+
+  debug!("Listening on {key_expr}!");
+  while !cancellation_token.is_cancelled() {
+    match queryable.recv_async().await {
+      Ok(query) => {
+        let command = match query.payload() {
+          Some(payload) => match payload.try_to_string() {
+            Ok(payload_str) => {
+              serde_json_lenient::from_str::<GestureRecordingCommand>(&payload_str)
+                .map_err(|err| format!("Payload is not a gesture recording command: {err}"))
+            }
+            Err(err) => Err(format!("Payload is not a string: {err}")),
+          },
+          None => Err("No payload was sent.".to_string()),
+        };
+
+        let result = match command {
+          Ok(GestureRecordingCommand::Start(request)) => {
+            start_recording(&store, session.clone(), cancellation_token.clone(), request).await
+          }
+          Ok(GestureRecordingCommand::Stop { gesture_id }) => {
+            stop_recording(&store, gesture_id, false).await
+          }
+          Ok(GestureRecordingCommand::Discard { gesture_id }) => {
+            stop_recording(&store, gesture_id, true).await
+          }
+          Err(reason) => {
+            error!("Failed to parse gesture recording query, due to:\n{reason}");
+            Err(GestureRecordingError::InvalidPayload { reason })
+          }
+        };
+
+        reply_result(query, &key_expr, result).await;
+      }
+      Err(err) => {
+        error!("{err}")
+      }
+    }
+  }
+}
+
+/// Reply with both gesture priority stacks and the states of every gesture in them.
+#[instrument(skip(store, cancellation_token, session))]
+pub async fn gesture_state_queryable(
+  store: ModManStore,
+  cancellation_token: CancellationToken,
+  session: Arc<zenoh::Session>,
+) {
+  let key_expr = format!("{MODULE_EVT_ID}/gestures/state");
+
+  let queryable = session.declare_queryable(&key_expr).await.unwrap();
+
+  debug!("Listening on {key_expr}!");
+  while !cancellation_token.is_cancelled() {
+    match queryable.recv_async().await {
+      Ok(query) => {
+        debug!("Replying with gesture stacks...");
+
+        // Snapshot the stacks before locking the states, `handle_gesture_cmd` locks them in the opposite order.
+        let foreground_ids = store.foreground_gesture_priority.lock().await.clone();
+        let background_ids = store.background_gesture_priority.lock().await.clone();
+
+        let res = {
+          let gesture_states = store.gesture_states.lock().await;
+          let entries = |ids: Vec<String>| -> Vec<GestureStackEntry> {
+            ids
+              .into_iter()
+              .filter_map(|gesture_id| {
+                gesture_states
+                  .get(&gesture_id)
+                  .map(|states| GestureStackEntry {
+                    gesture_id: gesture_id.clone(),
+                    states: states.clone(),
+                  })
+              })
+              .collect()
+          };
+
+          GestureStacks {
+            foreground: entries(foreground_ids),
+            background: entries(background_ids),
+          }
+        };
+
+        match query
+          .reply(&key_expr, &serde_json_lenient::to_string(&res).unwrap())
+          .await
+        {
+          Ok(_) => debug!("Successfully replied with gesture stacks."),
+          Err(err) => error!("Failed to send reply with gesture stacks, due to:\n{err}"),
+        }
+      }
+      Err(err) => {
+        error!("{err}")
+      }
     }
   }
 }
 
 #[instrument(skip(store))]
 pub async fn handle_gesture_cmd(
-  store: &mut Arc<ModManStore>,
+  store: &Arc<ModManStore>,
   gesture_id: String,
   command: GestureCommand,
-) {
+) -> Result<GestureUpdateSuccess, GestureError> {
   info!(
     "Handling gesture \"{}\" and state command: {:#?}",
     gesture_id.clone(),
@@ -64,10 +217,15 @@ pub async fn handle_gesture_cmd(
 
   let stored_states = match gesture_state_map.get(&gesture_id.clone()) {
     Some(stored_states_from_map) => {
-      if stored_states_from_map.current_state == command.state {
+      let is_no_op = match command.state {
+        GestureState::Pause => stored_states_from_map.paused,
+        GestureState::UnPause => !stored_states_from_map.paused,
+        _ => stored_states_from_map.current_state == command.state,
+      };
+
+      if is_no_op {
         warn!("Sent gesture state command which matches current state, this is a no-op!");
-        // TODO: Send reply!
-        return;
+        return Ok(GestureUpdateSuccess::NoOp { gesture_id });
       } else {
         stored_states_from_map
       }
@@ -78,16 +236,26 @@ pub async fn handle_gesture_cmd(
           intensity: _,
           speed: _,
           background: _,
-        } => &GestureStates {
-          prev_state: None,
-          current_state: GestureState::End,
-          next_state: None,
-          paused: false,
-        },
+        } => {
+          if !store.gesture_library.lock().await.is_known(&gesture_id) {
+            error!("Gesture: {gesture_id}, is not defined by any loaded gesture pack!");
+            return Err(GestureError::UnknownGesture { gesture_id });
+          }
+
+          &GestureStates {
+            prev_state: None,
+            current_state: GestureState::End,
+            next_state: None,
+            next_state_at: None,
+            began_at: None,
+            paused_at: None,
+            paused: false,
+            areas: None,
+          }
+        }
         _ => {
           error!("Command: {:#?}, is not applicable since this gesture was not cached in the first place!", command.state);
-          // TODO: Send reply!
-          return;
+          return Err(GestureError::UnknownGesture { gesture_id });
         }
       }
     }
@@ -103,8 +271,19 @@ pub async fn handle_gesture_cmd(
       let mut priority_vec;
 
       if should_be_bg(store.clone(), background).await {
+        // A gesture can switch between back/foreground (e.g. reverting to a background `prev_state`), it's only ever in one list.
+        store
+          .foreground_gesture_priority
+          .lock()
+          .await
+          .retain(|item| item != &gesture_id);
         priority_vec = store.background_gesture_priority.lock().await;
       } else {
+        store
+          .background_gesture_priority
+          .lock()
+          .await
+          .retain(|item| item != &gesture_id);
         priority_vec = store.foreground_gesture_priority.lock().await;
       }
 
@@ -130,19 +309,57 @@ pub async fn handle_gesture_cmd(
 
       priority_vec.push(gesture_id.clone());
 
+      // Keep the state we're replacing so we can revert to it.
+      let prev_state = match stored_states.current_state {
+        GestureState::Begin { .. } => Some(stored_states.current_state.clone()),
+        _ => None,
+      };
+
+      let next_state = match command.auto_switch {
+        Some(delay) => Some((
+          delay.max(0.0),
+          match command.next_state.clone() {
+            Some(next_state) => next_state,
+            None => prev_state.clone().unwrap_or(GestureState::End),
+          },
+        )),
+        None => None,
+      };
+
       modded_gesture_state = Some(GestureStates {
-        prev_state: stored_states.prev_state.clone(),
+        prev_state,
         current_state: command.state,
-        next_state: stored_states.next_state.clone(),
+        next_state_at: next_state
+          .as_ref()
+          .map(|(delay, _)| Instant::now() + Duration::from_secs_f64(*delay)),
+        next_state,
+        began_at: Some(Instant::now()),
+        paused_at: None,
         paused: false,
+        areas: command.areas.clone(),
       });
     }
     GestureState::Pause => {
+      // Stop the clock on any scheduled switch, keeping the time remaining.
+      let next_state = match (&stored_states.next_state, stored_states.next_state_at) {
+        (Some((_, next_state)), Some(next_state_at)) => Some((
+          next_state_at
+            .saturating_duration_since(Instant::now())
+            .as_secs_f64(),
+          next_state.clone(),
+        )),
+        _ => stored_states.next_state.clone(),
+      };
+
       modded_gesture_state = Some(GestureStates {
         prev_state: stored_states.prev_state.clone(),
         current_state: stored_states.current_state.clone(),
-        next_state: stored_states.next_state.clone(),
+        next_state,
+        next_state_at: None,
+        began_at: stored_states.began_at,
+        paused_at: Some(Instant::now()),
         paused: true,
+        areas: stored_states.areas.clone(),
       });
     }
     GestureState::End => {
@@ -180,13 +397,24 @@ pub async fn handle_gesture_cmd(
         prev_state: stored_states.prev_state.clone(),
         current_state: stored_states.current_state.clone(),
         next_state: stored_states.next_state.clone(),
+        next_state_at: stored_states
+          .next_state
+          .as_ref()
+          .map(|(delay, _)| Instant::now() + Duration::from_secs_f64(*delay)),
+        // Skip over the time spent paused so curves resume where they left off.
+        began_at: match (stored_states.began_at, stored_states.paused_at) {
+          (Some(began_at), Some(paused_at)) => Some(began_at + paused_at.elapsed()),
+          (began_at, _) => began_at,
+        },
+        paused_at: None,
         paused: false,
+        areas: stored_states.areas.clone(),
       });
     }
   }
 
   // Since we already checked that the state command is different, either apply the changes or remove the gesture from the state map
-  match modded_gesture_state {
+  match modded_gesture_state.clone() {
     Some(state_to_insert) => {
       gesture_state_map.insert(gesture_id.clone(), state_to_insert);
     }
@@ -197,5 +425,70 @@ pub async fn handle_gesture_cmd(
 
   debug!("Gesture ID: {}, state set!", gesture_id.clone());
 
-  // TODO: Send reply!
+  Ok(GestureUpdateSuccess::Applied {
+    gesture_id,
+    states: modded_gesture_state,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+  use crate::server::modman::{
+    gestures::packs::{
+      Gesture,
+      GesturePack,
+    },
+    models::gestures::GestureBodyConfigs,
+  };
+
+  fn begin(intensity: f64, background: bool) -> GestureCommand {
+    GestureCommand {
+      state: GestureState::Begin {
+        intensity,
+        speed: 1.0,
+        background: Some(background),
+      },
+      auto_switch: None,
+      next_state: None,
+      is_from_system: false,
+      areas: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn begin_moves_gesture_between_priority_lists() {
+    let store = Arc::new(ModManStore::new(None, None));
+    let pack_id = store.gesture_library.lock().await.default_pack.clone();
+    store.gesture_library.lock().await.packs.insert(
+      pack_id,
+      GesturePack {
+        name: None,
+        description: None,
+        gestures: HashMap::from([(
+          "blink".to_string(),
+          Gesture {
+            name: None,
+            description: None,
+            configs: GestureBodyConfigs::default(),
+          },
+        )]),
+      },
+    );
+
+    handle_gesture_cmd(&store, "blink".to_string(), begin(1.0, false))
+      .await
+      .unwrap();
+    handle_gesture_cmd(&store, "blink".to_string(), begin(0.5, true))
+      .await
+      .unwrap();
+
+    assert!(store.foreground_gesture_priority.lock().await.is_empty());
+    assert_eq!(
+      *store.background_gesture_priority.lock().await,
+      vec!["blink".to_string()]
+    );
+  }
 }
@@ -1,19 +1,66 @@
+pub mod components;
 pub mod displays;
 pub mod gestures;
+pub mod modules;
 
+use serde::Serialize;
 use tokio_util::sync::CancellationToken;
-use tracing::instrument;
+use tracing::{
+  error,
+  instrument,
+};
 
 use crate::server::modman::{
   ipc::{
+    components::{
+      component_area_queryable,
+      component_config_queryable,
+      component_type_queryable,
+    },
     displays::display_queryable,
-    gestures::gesture_queryable,
+    gestures::{
+      gesture_queryable,
+      gesture_recording_queryable,
+      gesture_state_queryable,
+    },
+    modules::{
+      module_adoption_approval_queryable,
+      module_adoption_queryable,
+      module_config_queryable,
+      module_lifecycle_queryable,
+      module_list_queryable,
+    },
   },
   models::store::ModManStore,
 };
 
 use std::sync::Arc;
 
+/// Serialize and send the result of a query.
+pub(crate) async fn reply_result<T: Serialize, E: Serialize>(
+  query: zenoh::query::Query,
+  key_expr: &str,
+  result: Result<T, E>,
+) {
+  match serde_json_lenient::to_string(&result) {
+    Ok(res) => match query.reply(key_expr, res).await {
+      Ok(_) => {}
+      Err(err) => error!("Failed to reply to query on: {key_expr}, due to:\n{err}"),
+    },
+    Err(err) => {
+      error!("Failed to serialize query result for: {key_expr}; this is a bug and should be reported! This happened due to:\n{err}");
+    }
+  }
+}
+
+/// The ID in a key expression like `{prefix}{id}/...`, for queryables declared with a wildcard ID.
+pub(crate) fn id_from_key_expr(key_expr: &str, prefix: &str) -> Option<String> {
+  key_expr
+    .strip_prefix(prefix)
+    .and_then(|rest| rest.split('/').next())
+    .map(|id| id.to_string())
+}
+
 #[instrument(skip(ipc_token, ipc_session))]
 pub async fn handle_ipc(
   store: ModManStore,
@@ -34,5 +81,129 @@ pub async fn handle_ipc(
     gesture_queryable(gesture_store, gesture_token, gesture_session).await;
   });
 
-  futures::future::join_all(vec![displays_handle, gestures_handle]).await;
+  let gesture_state_store = store.clone();
+  let gesture_state_session = ipc_session.clone();
+  let gesture_state_token = ipc_token.clone();
+  let gesture_state_handle = tokio::task::spawn(async move {
+    gesture_state_queryable(
+      gesture_state_store,
+      gesture_state_token,
+      gesture_state_session,
+    )
+    .await;
+  });
+
+  let gesture_recording_store = store.clone();
+  let gesture_recording_session = ipc_session.clone();
+  let gesture_recording_token = ipc_token.clone();
+  let gesture_recording_handle = tokio::task::spawn(async move {
+    gesture_recording_queryable(
+      gesture_recording_store,
+      gesture_recording_token,
+      gesture_recording_session,
+    )
+    .await;
+  });
+
+  let adoption_store = store.clone();
+  let adoption_session = ipc_session.clone();
+  let adoption_token = ipc_token.clone();
+  let adoption_handle = tokio::task::spawn(async move {
+    module_adoption_queryable(adoption_store, adoption_token, adoption_session).await;
+  });
+
+  let adoption_approval_store = store.clone();
+  let adoption_approval_session = ipc_session.clone();
+  let adoption_approval_token = ipc_token.clone();
+  let adoption_approval_handle = tokio::task::spawn(async move {
+    module_adoption_approval_queryable(
+      adoption_approval_store,
+      adoption_approval_token,
+      adoption_approval_session,
+    )
+    .await;
+  });
+
+  let module_list_store = store.clone();
+  let module_list_session = ipc_session.clone();
+  let module_list_token = ipc_token.clone();
+  let module_list_handle = tokio::task::spawn(async move {
+    module_list_queryable(module_list_store, module_list_token, module_list_session).await;
+  });
+
+  let module_config_store = store.clone();
+  let module_config_session = ipc_session.clone();
+  let module_config_token = ipc_token.clone();
+  let module_config_handle = tokio::task::spawn(async move {
+    module_config_queryable(
+      module_config_store,
+      module_config_token,
+      module_config_session,
+    )
+    .await;
+  });
+
+  let module_lifecycle_store = store.clone();
+  let module_lifecycle_session = ipc_session.clone();
+  let module_lifecycle_token = ipc_token.clone();
+  let module_lifecycle_handle = tokio::task::spawn(async move {
+    module_lifecycle_queryable(
+      module_lifecycle_store,
+      module_lifecycle_token,
+      module_lifecycle_session,
+    )
+    .await;
+  });
+
+  let component_config_store = store.clone();
+  let component_config_session = ipc_session.clone();
+  let component_config_token = ipc_token.clone();
+  let component_config_handle = tokio::task::spawn(async move {
+    component_config_queryable(
+      component_config_store,
+      component_config_token,
+      component_config_session,
+    )
+    .await;
+  });
+
+  let component_type_store = store.clone();
+  let component_type_session = ipc_session.clone();
+  let component_type_token = ipc_token.clone();
+  let component_type_handle = tokio::task::spawn(async move {
+    component_type_queryable(
+      component_type_store,
+      component_type_token,
+      component_type_session,
+    )
+    .await;
+  });
+
+  let component_area_store = store.clone();
+  let component_area_session = ipc_session.clone();
+  let component_area_token = ipc_token.clone();
+  let component_area_handle = tokio::task::spawn(async move {
+    component_area_queryable(
+      component_area_store,
+      component_area_token,
+      component_area_session,
+    )
+    .await;
+  });
+
+  futures::future::join_all(vec![
+    displays_handle,
+    gestures_handle,
+    gesture_state_handle,
+    gesture_recording_handle,
+    adoption_handle,
+    adoption_approval_handle,
+    module_list_handle,
+    module_config_handle,
+    module_lifecycle_handle,
+    component_config_handle,
+    component_type_handle,
+    component_area_handle,
+  ])
+  .await;
 }
@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use log::{
+  debug,
+  error,
+  info,
+  warn,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+
+use super::{
+  id_from_key_expr,
+  reply_result,
+};
+use crate::server::modman::{
+  models::{
+    modules::{
+      AdoptionError,
+      AdoptionRequest,
+      ModuleInfo,
+      ModuleInitStatus,
+      PendingAdoption,
+      RegistryError,
+    },
+    store::ModManStore,
+  },
+  modules::{
+    adoption::{
+      adopt_module,
+      approve_adoption,
+    },
+    deinit_module,
+    init_module,
+  },
+  MODULE_EVT_ID,
+};
+
+/// Adopt modules that bus proxies have discovered, see [`crate::server::modman::modules::adoption`].
+#[instrument(skip(store, cancellation_token, session))]
+pub async fn module_adoption_queryable(
+  store: ModManStore,
+  cancellation_token: CancellationToken,
+  session: Arc<zenoh::Session>,
+) {
+  let key_expr = format!("{MODULE_EVT_ID}/modules/adopt");
+
+  let queryable = session.declare_queryable(&key_expr).await.unwrap();
+
+  debug!("Listening on {key_expr}!");
+  while !cancellation_token.is_cancelled() {
+    match queryable.recv_async().await {
+      Ok(query) => {
+        let request = match query.payload() {
+          Some(payload) => match payload.try_to_string() {
+            Ok(payload_str) => serde_json_lenient::from_str::<AdoptionRequest>(&payload_str)
+              .map_err(|err| format!("Payload is not an adoption request: {err}")),
+            Err(err) => Err(format!("Payload is not a string: {err}")),
+          },
+          None => Err("No payload was sent.".to_string()),
+        };
+
+        let result = match request {
+          Ok(request) => {
+            let result = adopt_module(&store, session.clone(), request).await;
+
+            if let Err(err) = &result {
+              warn!("Refused to adopt module, due to:\n{err}");
+            }
+
+            result
+          }
+          Err(reason) => {
+            error!("Failed to parse adoption query, due to:\n{reason}");
+            Err(AdoptionError::InvalidPayload { reason })
+          }
+        };
+
+        reply_result(query, &key_expr, result).await;
+      }
+      Err(err) => {
+        error!("{err}")
+      }
+    }
+  }
+}
+
+/// List new modules waiting to be adopted at `{MODULE_EVT_ID}/modules/adopt/pending`, and approve them at `.../modules/adopt/approve`, with their pending ID as the payload.
+#[instrument(skip(store, cancellation_token, session))]
+pub async fn module_adoption_approval_queryable(
+  store: ModManStore,
+  cancellation_token: CancellationToken,
+  session: Arc<zenoh::Session>,
+) {
+  let pending_key_expr = format!("{MODULE_EVT_ID}/modules/adopt/pending");
+  let approve_key_expr = format!("{MODULE_EVT_ID}/modules/adopt/approve");
+
+  let pending_queryable = session.declare_queryable(&pending_key_expr).await.unwrap();
+  let approve_queryable = session.declare_queryable(&approve_key_expr).await.unwrap();
+
+  debug!("Listening on {pending_key_expr} and {approve_key_expr}!");
+  loop {
+    tokio::select! {
+      _ = cancellation_token.cancelled() => break,
+      query = pending_queryable.recv_async() => match query {
+        Ok(query) => {
+          let pending: Vec<PendingAdoption> = store
+            .pending_adoptions
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect();
+
+          reply_result::<_, AdoptionError>(query, &pending_key_expr, Ok(pending)).await;
+        }
+        Err(_) => break,
+      },
+      query = approve_queryable.recv_async() => match query {
+        Ok(query) => {
+          let result = match query.payload().map(|payload| payload.try_to_string()) {
+            Some(Ok(pending_id)) => {
+              let result = approve_adoption(&store, pending_id.trim()).await;
+              if result.is_ok() {
+                info!("Approved adoption of module: {}.", pending_id.trim());
+              }
+
+              result
+            }
+            Some(Err(err)) => Err(AdoptionError::InvalidPayload {
+              reason: format!("Payload is not a string: {err}"),
+            }),
+            None => Err(AdoptionError::InvalidPayload {
+              reason: "No payload was sent.".to_string(),
+            }),
+          };
+
+          reply_result(query, &approve_key_expr, result).await;
+        }
+        Err(_) => break,
+      },
+    }
+  }
+}
+
+/// List every module with its init status, at `{MODULE_EVT_ID}/modules/all`.
+#[instrument(skip(store, cancellation_token, session))]
+pub async fn module_list_queryable(
+  store: ModManStore,
+  cancellation_token: CancellationToken,
+  session: Arc<zenoh::Session>,
+) {
+  let key_expr = format!("{MODULE_EVT_ID}/modules/all");
+
+  let queryable = session.declare_queryable(&key_expr).await.unwrap();
+
+  debug!("Listening on {key_expr}!");
+  while !cancellation_token.is_cancelled() {
+    match queryable.recv_async().await {
+      Ok(query) => {
+        let modules: Vec<ModuleInfo> = store
+          .modules
+          .lock()
+          .await
+          .iter()
+          .map(|(module_id, module)| ModuleInfo::new(module_id, module))
+          .collect();
+
+        reply_result::<_, RegistryError>(query, &key_expr, Ok(modules)).await;
+      }
+      Err(err) => {
+        error!("{err}")
+      }
+    }
+  }
+}
+
+/// Get a single module, at `{MODULE_EVT_ID}/modules/by-id/{module_id}/config`.
+#[instrument(skip(store, cancellation_token, session))]
+pub async fn module_config_queryable(
+  store: ModManStore,
+  cancellation_token: CancellationToken,
+  session: Arc<zenoh::Session>,
+) {
+  let prefix = format!("{MODULE_EVT_ID}/modules/by-id/");
+  let key_expr = format!("{prefix}*/config");
+
+  let queryable = session.declare_queryable(&key_expr).await.unwrap();
+
+  debug!("Listening on {key_expr}!");
+  while !cancellation_token.is_cancelled() {
+    match queryable.recv_async().await {
+      Ok(query) => {
+        let reply_key_expr = query.key_expr().to_string();
+        let module_id = id_from_key_expr(&reply_key_expr, &prefix).unwrap_or_default();
+
+        let result = match store.modules.lock().await.get(&module_id) {
+          Some(module) => Ok(ModuleInfo::new(&module_id, module)),
+          None => Err(RegistryError::UnknownModule { module_id }),
+        };
+
+        reply_result(query, &reply_key_expr, result).await;
+      }
+      Err(err) => {
+        error!("{err}")
+      }
+    }
+  }
+}
+
+/// Initialize or de-initialize a module on demand, at `{MODULE_EVT_ID}/modules/by-id/{module_id}/init` and `.../deinit`.
+#[instrument(skip(store, cancellation_token, session))]
+pub async fn module_lifecycle_queryable(
+  store: ModManStore,
+  cancellation_token: CancellationToken,
+  session: Arc<zenoh::Session>,
+) {
+  let prefix = format!("{MODULE_EVT_ID}/modules/by-id/");
+  let init_key_expr = format!("{prefix}*/init");
+  let deinit_key_expr = format!("{prefix}*/deinit");
+
+  let init_queryable = session.declare_queryable(&init_key_expr).await.unwrap();
+  let deinit_queryable = session.declare_queryable(&deinit_key_expr).await.unwrap();
+
+  debug!("Listening on {init_key_expr} and {deinit_key_expr}!");
+  loop {
+    let (query, init) = tokio::select! {
+      _ = cancellation_token.cancelled() => break,
+      query = init_queryable.recv_async() => (query, true),
+      query = deinit_queryable.recv_async() => (query, false),
+    };
+
+    match query {
+      Ok(query) => {
+        let reply_key_expr = query.key_expr().to_string();
+        let module_id = id_from_key_expr(&reply_key_expr, &prefix).unwrap_or_default();
+
+        let module = store.modules.lock().await.get(&module_id).cloned();
+        let result = match module {
+          Some(module) => {
+            let components = if init {
+              info!("Initializing module: {module_id}, on request...");
+              init_module(&store, module_id.clone(), module, session.clone())
+                .await
+                .1
+            } else {
+              info!("De-initializing module: {module_id}, on request...");
+              deinit_module(&store, module_id.clone(), module).await.1
+            };
+
+            let initialized = match store.modules.lock().await.get(&module_id) {
+              Some(module) => module.initialized,
+              None => false,
+            };
+
+            Ok(ModuleInitStatus {
+              module_id,
+              initialized,
+              components,
+            })
+          }
+          None => Err(RegistryError::UnknownModule { module_id }),
+        };
+
+        reply_result(query, &reply_key_expr, result).await;
+      }
+      Err(err) => {
+        error!("{err}")
+      }
+    }
+  }
+}
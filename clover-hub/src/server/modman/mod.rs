@@ -14,7 +14,20 @@ pub mod ipc;
 pub mod models;
 pub mod modules;
 
-use busses::start_busses;
+use busses::{
+  auth::save_module_auth_state,
+  start_busses,
+};
+use gestures::{
+  areas::load_bodies,
+  gesture_command_generator_manager,
+  packs::load_gesture_packs,
+  persistence::{
+    restore_background_gestures,
+    save_background_gestures,
+  },
+  transitions::gesture_transition_scheduler,
+};
 use models::store::ModManStore;
 use modules::{
   deinit_module,
@@ -79,6 +92,10 @@ pub async fn modman_main(
             .await
             .unwrap();
 
+          load_bodies(&store).await;
+          load_gesture_packs(&store).await;
+          restore_background_gestures(&Arc::new(store.clone())).await;
+
           let ipc_token = cancellation_tokens.0.clone();
           let ipc_session = session.clone();
           let ipc_store = store.clone();
@@ -91,6 +108,24 @@ pub async fn modman_main(
             start_busses(bus_store, bus_session, bus_token).await.await;
           });
 
+          let gestures_store = Arc::new(store.clone());
+          let gestures_token = cancellation_tokens.0.clone();
+          let gestures_session = session.clone();
+          let gestures_handle = tokio::task::spawn(gesture_command_generator_manager(
+            gestures_store,
+            gestures_session,
+            gestures_token,
+          ));
+
+          let transitions_store = Arc::new(store.clone());
+          let transitions_token = cancellation_tokens.0.clone();
+          let transitions_session = session.clone();
+          let transitions_handle = tokio::task::spawn(gesture_transition_scheduler(
+            transitions_store,
+            transitions_session,
+            transitions_token,
+          ));
+
           let init_session = session.clone();
           let init_store = Arc::new(store.clone());
           let init_results = cancellation_tokens
@@ -195,8 +230,13 @@ pub async fn modman_main(
             _ = mod_clean_token.cancelled() => {
               bus_handle.abort();
               ipc_handle.abort();
+              gestures_handle.abort();
+              transitions_handle.abort();
               drop(status_publisher);
 
+              save_background_gestures(&store).await;
+              save_module_auth_state(&store).await;
+
               info!("Cleaning up modules...");
 
               // TODO: Add override cancellation token to force stop!
@@ -29,7 +29,10 @@ use crate::server::modman::{
     },
   },
   models::{
-    gestures::GestureParameters,
+    gestures::{
+      GestureConfig,
+      GestureParameters,
+    },
     store::ModManStore,
   },
 };
@@ -42,7 +45,7 @@ pub struct CloverComponentMeta {
   /// Is this component required for the module to work? Default: yes.
   /// If any critical component fails to initialize, the module will fail to initialize entirely.
   pub critical: bool,
-  /// Where this component is on/in the user. RFQDN formatted, e.g. `com.reboot-codes.CORE.head.eyes.internal` for a HUD display
+  /// Where this component is on/in the user. RFQDN formatted [area](crate::server::modman::gestures::areas), e.g. `@core.humanoid.torso.head.face.eyes.left.eye` for a HUD display
   pub location: String,
   /// Parameters used for gesture events to synthesize commands to send to this component if it supports RX from Nexus.
   /// This is also used to determine if a gesture is supported by this component.
@@ -65,6 +68,48 @@ pub enum CloverComponent {
   VirtualDisplayComponent(VirtualDisplayComponent),
 }
 
+/// Names of every component type, as used by `{MODULE_EVT_ID}/components/by-type/{type}/all`.
+pub const COMPONENT_TYPE_NAMES: [&str; 8] = [
+  "audio-input",
+  "audio-output",
+  "movement",
+  "sensor",
+  "indicator",
+  "camera",
+  "physical-display",
+  "virtual-display",
+];
+
+impl CloverComponent {
+  /// Name of this component's type, one of [`COMPONENT_TYPE_NAMES`].
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      CloverComponent::AudioInputComponent(_) => "audio-input",
+      CloverComponent::AudioOutputComponent(_) => "audio-output",
+      CloverComponent::MovementComponent(_) => "movement",
+      CloverComponent::SensorComponent(_) => "sensor",
+      CloverComponent::IndicatorComponent(_) => "indicator",
+      CloverComponent::CameraComponent(_) => "camera",
+      CloverComponent::PhysicalDisplayComponent(_) => "physical-display",
+      CloverComponent::VirtualDisplayComponent(_) => "virtual-display",
+    }
+  }
+
+  /// This component's gesture configuration, if its type supports one and it's set.
+  pub fn gesture_config(&self) -> Option<&GestureConfig> {
+    match self {
+      CloverComponent::AudioOutputComponent(component) => component.gesture_config.as_ref(),
+      CloverComponent::MovementComponent(component) => Some(&component.gesture_config),
+      CloverComponent::IndicatorComponent(component) => component.gesture_config.as_ref(),
+      CloverComponent::PhysicalDisplayComponent(component) => component.gesture_config.as_ref(),
+      CloverComponent::VirtualDisplayComponent(component) => component.gesture_config.as_ref(),
+      CloverComponent::AudioInputComponent(_)
+      | CloverComponent::SensorComponent(_)
+      | CloverComponent::CameraComponent(_) => None,
+    }
+  }
+}
+
 impl CloverComponentTrait for CloverComponent {
   /// Passes the context to the inner-component function implementation.
   async fn init(&mut self, store: Arc<ModManStore>) -> Result<(), anyhow::Error> {
@@ -94,3 +139,15 @@ impl CloverComponentTrait for CloverComponent {
     }
   }
 }
+
+/// A component as reported by the registry queryables.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComponentInfo {
+  pub component_id: String,
+  /// ID of the module the component belongs to, if any module lists it.
+  pub module_id: Option<String>,
+  /// One of [`COMPONENT_TYPE_NAMES`].
+  pub component_type: String,
+  pub meta: CloverComponentMeta,
+  pub component: CloverComponent,
+}
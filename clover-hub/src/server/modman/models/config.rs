@@ -5,29 +5,78 @@ use serde::{
   Serialize,
 };
 
-use crate::server::modman::{
-  busses::proxies::group::GroupBusConfigs,
-  models::{
-    components::{
-      CloverComponent,
-      CloverComponentMeta,
+#[cfg(feature = "bt_le")]
+use crate::server::modman::busses::proxies::individual::bt_le::BTLEConfig;
+#[cfg(feature = "uart")]
+use crate::server::modman::busses::proxies::individual::uart::UARTConfig;
+use crate::server::{
+  modman::{
+    busses::proxies::group::GroupBusConfigs,
+    gestures::areas::humanoid_body_id,
+    models::{
+      components::{
+        CloverComponent,
+        CloverComponentMeta,
+      },
+      gestures::GestureStates,
+      modules::{
+        Module,
+        MovementSecurityPolicy,
+      },
+      simulation::ModuleSimulation,
     },
-    gestures::GestureStates,
-    modules::Module,
   },
+  warehouse::repos::builtin_rfqdn,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModManConfig {
   /// All ports available for modman to use to connect to modules.
   pub uart_ports: Vec<String>,
+  #[cfg(feature = "uart")]
+  #[serde(default)]
+  pub uart: UARTConfig,
+  #[cfg(feature = "bt_le")]
+  #[serde(default)]
+  pub bt_le: BTLEConfig,
   pub group_busses: GroupBusConfigs,
-  /// Whether to restart paused gestures automatically on startup.
+  /// Whether to resume the background gestures saved on shutdown automatically on startup.
   pub restart_gestures: bool,
+  /// Background gestures saved on shutdown, in the `Begin` state.
   pub gesture_states: HashMap<String, GestureStates>,
+  /// Order of the saved background gestures, lowest priority first.
+  #[serde(default)]
+  pub background_gestures: Vec<String>,
   pub gestures_bg_by_default: bool,
+  /// RFQDNs of the bodies that this instance is made of, relative gesture areas are resolved against these and the bodies they extend.
+  #[serde(default = "default_bodies")]
+  pub bodies: Vec<String>,
+  /// RFQDN of the local repo (and gesture pack) that [recorded gestures](crate::server::modman::gestures::recording) are saved into.
+  #[serde(default = "default_recordings_repo")]
+  pub recordings_repo: String,
+  /// What to do with modules that have movement components, but a security level below L3.
+  #[serde(default)]
+  pub movement_security_policy: MovementSecurityPolicy,
+  /// Seconds before an L2 module's session key is rotated.
+  #[serde(default = "default_session_key_lifetime")]
+  pub session_key_lifetime: u64,
   pub static_modules: HashMap<String, Module>,
   pub static_components: HashMap<String, (CloverComponentMeta, CloverComponent)>,
+  /// Behaviour of [simulated modules](crate::server::modman::busses::proxies::individual::simulated), keyed by the ID in their `Simulated` connection. Takes precedence over a simulation in the module's manifest entry.
+  #[serde(default)]
+  pub simulations: HashMap<String, ModuleSimulation>,
+}
+
+fn default_bodies() -> Vec<String> {
+  vec![humanoid_body_id()]
+}
+
+fn default_recordings_repo() -> String {
+  format!("{}.recordings", builtin_rfqdn(false))
+}
+
+fn default_session_key_lifetime() -> u64 {
+  60 * 60
 }
 
 impl Default for ModManConfig {
@@ -74,6 +123,15 @@ impl Default for ModManConfig {
           connection: ModuleConnection::Simulated(
             "com.reboot-codes.clover.debug-display:0".to_string(),
           ),
+          firmware_version: None,
+          serial: None,
+          security_level: Default::default(),
+          key: None,
+          exchange_secret: None,
+          public_key: None,
+          insecure_movement: false,
+          session: 0,
+          last_counter: 0,
         },
       );
 
@@ -141,11 +199,21 @@ impl Default for ModManConfig {
     Self {
       static_components,
       static_modules,
+      simulations: Default::default(),
       uart_ports: Default::default(),
+      #[cfg(feature = "uart")]
+      uart: Default::default(),
+      #[cfg(feature = "bt_le")]
+      bt_le: Default::default(),
       group_busses: Default::default(),
       restart_gestures: Default::default(),
       gesture_states: Default::default(),
+      background_gestures: Default::default(),
       gestures_bg_by_default: Default::default(),
+      bodies: default_bodies(),
+      recordings_repo: default_recordings_repo(),
+      movement_security_policy: Default::default(),
+      session_key_lifetime: default_session_key_lifetime(),
     }
   }
 }
@@ -5,6 +5,9 @@ use serde::{
   Serialize,
 };
 use strum::VariantNames;
+use tokio::time::Instant;
+
+use crate::server::modman::components::movement::models::DegreesOfFreedom;
 
 #[derive(Serialize, Deserialize, Clone, Debug, VariantNames, PartialEq)]
 #[serde(tag = "command")]
@@ -42,19 +45,224 @@ pub enum GestureState {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct GestureCommand {
   pub state: GestureState,
+  /// Seconds after a `begin` command to automatically switch to `next_state`, see [the scheduler](crate::server::modman::gestures::transitions).
   pub auto_switch: Option<f64>,
+  /// State to switch to once `auto_switch` elapses, reverts to the previously begun state (or ends the gesture) if unset.
+  #[serde(default)]
+  pub next_state: Option<GestureState>,
   pub is_from_system: bool,
+  /// Area globs to apply this gesture to, relative to the configured bodies unless absolute. See [areas](crate::server::modman::gestures::areas).
   pub areas: Option<Vec<String>>,
 }
 
+/// Payload of a `{MODULE_EVT_ID}/gestures/update` query.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GestureUpdate {
+  pub gesture_id: String,
+  pub command: GestureCommand,
+}
+
+/// Successful reply to a `{MODULE_EVT_ID}/gestures/update` query.
+#[derive(Serialize, Deserialize, Clone, Debug, strum_macros::Display)]
+#[serde(tag = "result")]
+pub enum GestureUpdateSuccess {
+  /// The command changed the gesture's state, which is `None` if the gesture was ended.
+  #[serde(rename = "applied")]
+  #[strum(serialize = "applied")]
+  Applied {
+    gesture_id: String,
+    states: Option<GestureStates>,
+  },
+  /// The gesture was already in the requested state, nothing changed.
+  #[serde(rename = "no-op")]
+  #[strum(serialize = "no-op")]
+  NoOp { gesture_id: String },
+}
+
+/// Error reply to a `{MODULE_EVT_ID}/gestures/update` query.
+#[derive(Serialize, Deserialize, Clone, Debug, strum_macros::Display)]
+#[serde(tag = "error")]
+pub enum GestureError {
+  /// The gesture isn't defined by any loaded gesture pack, or isn't running and can only be begun.
+  #[serde(rename = "unknown-gesture")]
+  #[strum(serialize = "unknown-gesture")]
+  UnknownGesture { gesture_id: String },
+  /// The query payload could not be parsed as a [`GestureUpdate`].
+  #[serde(rename = "invalid-payload")]
+  #[strum(serialize = "invalid-payload")]
+  InvalidPayload { reason: String },
+}
+
+/// Payload of a `{MODULE_EVT_ID}/gestures/record` query, see [recording](crate::server::modman::gestures::recording).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "action")]
+pub enum GestureRecordingCommand {
+  /// Start recording telemetry from modules into a new gesture.
+  #[serde(rename = "start")]
+  Start(GestureRecordingRequest),
+  /// Stop recording, normalise the telemetry into a gesture, and save it.
+  #[serde(rename = "stop")]
+  Stop { gesture_id: String },
+  /// Stop recording without saving anything.
+  #[serde(rename = "discard")]
+  Discard { gesture_id: String },
+}
+
+/// What to record, and how to save it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GestureRecordingRequest {
+  /// RFQDN for the recorded gesture, it's saved into the recordings pack.
+  pub gesture_id: String,
+  pub name: Option<String>,
+  pub description: Option<String>,
+  /// Modules to record the telemetry of.
+  pub module_ids: Vec<String>,
+  /// Only record these components, records every component of the modules if unset.
+  #[serde(default)]
+  pub component_ids: Option<Vec<String>>,
+}
+
+/// Successful reply to a `{MODULE_EVT_ID}/gestures/record` query.
+#[derive(Serialize, Deserialize, Clone, Debug, strum_macros::Display)]
+#[serde(tag = "result")]
+pub enum GestureRecordingSuccess {
+  #[serde(rename = "started")]
+  #[strum(serialize = "started")]
+  Started { gesture_id: String },
+  /// The gesture was saved and can be begun with `gesture_id` (which includes the pack) right away.
+  #[serde(rename = "saved")]
+  #[strum(serialize = "saved")]
+  Saved { gesture_id: String, path: String },
+  #[serde(rename = "discarded")]
+  #[strum(serialize = "discarded")]
+  Discarded { gesture_id: String },
+}
+
+/// Error reply to a `{MODULE_EVT_ID}/gestures/record` query.
+#[derive(Serialize, Deserialize, Clone, Debug, strum_macros::Display)]
+#[serde(tag = "error")]
+pub enum GestureRecordingError {
+  #[serde(rename = "already-recording")]
+  #[strum(serialize = "already-recording")]
+  AlreadyRecording { gesture_id: String },
+  #[serde(rename = "not-recording")]
+  #[strum(serialize = "not-recording")]
+  NotRecording { gesture_id: String },
+  /// No telemetry could be turned into a gesture parameter, nothing was saved.
+  #[serde(rename = "nothing-recorded")]
+  #[strum(serialize = "nothing-recorded")]
+  NothingRecorded { gesture_id: String },
+  #[serde(rename = "failed-to-save")]
+  #[strum(serialize = "failed-to-save")]
+  FailedToSave { gesture_id: String, reason: String },
+  /// The query payload could not be parsed as a [`GestureRecordingCommand`].
+  #[serde(rename = "invalid-payload")]
+  #[strum(serialize = "invalid-payload")]
+  InvalidPayload { reason: String },
+}
+
+/// A gesture in one of the priority stacks.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GestureStackEntry {
+  pub gesture_id: String,
+  pub states: GestureStates,
+}
+
+/// Reply to a `{MODULE_EVT_ID}/gestures/state` query, each stack is ordered from lowest to highest priority.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GestureStacks {
+  pub foreground: Vec<GestureStackEntry>,
+  pub background: Vec<GestureStackEntry>,
+}
+
+/// A point on a gesture's smoothing curve.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GestureKeyframe {
+  /// Milliseconds since the gesture began, before `speed` is applied.
+  pub x: f64,
+  /// Intensity (-1.0 to 1.0) of the parameter at this point, before the gesture's `intensity` is applied.
+  pub y: f64,
+}
+
+/// How values are interpolated between keyframes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum GestureCurve {
+  #[default]
+  Linear,
+  /// Ease between keyframes with a CSS style cubic-bezier timing function, `[x1, y1, x2, y2]`.
+  CubicBezier([f64; 4]),
+  /// Hold each keyframe's value until the next keyframe.
+  Step,
+}
+
+/// What happens once the last keyframe is reached.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum GestureRepeat {
+  /// Hold the last keyframe's value.
+  #[default]
+  Once,
+  /// Start over from the first keyframe.
+  Loop,
+  /// Play the keyframes backwards, then forwards again, and so on.
+  PingPong,
+}
+
+/// An animated gesture parameter.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GestureAnimation {
+  #[serde(default)]
+  pub curve: GestureCurve,
+  #[serde(default)]
+  pub repeat: GestureRepeat,
+  /// Ordered by `x`, manifests are sorted when they're compiled.
+  pub keyframes: Vec<GestureKeyframe>,
+}
+
+/// The value of a single parameter for a gesture, either a constant intensity or an animation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum GestureTrack {
+  Constant(f64),
+  Animated {
+    #[serde(rename = "@animation")]
+    animation: GestureAnimation,
+  },
+}
+
+/// What a gesture does, keyed by body RFQDN glob, then by [area](crate::server::modman::gestures::areas) glob relative to the body, then by parameter name.
+///
+/// ```json
+/// {
+///   "*": {
+///     "torso.head.face.eyes.*.eyebrow": { "height": 0.75 }
+///   },
+///   "@base.humanoid.tailed": {
+///     "pelvis.rear.tail.*": {
+///       "angle": {
+///         "@animation": {
+///           "curve": "linear",
+///           "keyframes": [{ "x": 0.0, "y": -0.5 }, { "x": 500.0, "y": 0.5 }]
+///         }
+///       }
+///     }
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(transparent)]
+pub struct GestureBodyConfigs(pub HashMap<String, HashMap<String, HashMap<String, GestureTrack>>>);
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct GestureConfig {
-  /// The primary gesture pack to use for this component
+  /// The primary gesture pack to use for this component, checked before the default pack for gesture IDs that don't specify one.
   pub primary_gesture_pack: Option<String>,
-  /// The default gesture for the component to use when idle.
+  /// The default gesture for the component to use when idle (no other gesture drives it).
   pub default_gesture: Option<String>,
+  /// Parameters to use for every gesture instead of the component's base parameters.
   pub gesture_parameters: Option<GestureParameters>,
-  /// Override gestures (use different pack, adjust gesture parameters, etc)
+  /// Override gestures (use different pack, adjust gesture parameters, etc), keyed by the full gesture ID or just the gesture's RFQDN.
   pub gesture_overrides: HashMap<String, GestureOverride>,
 }
 
@@ -62,6 +270,7 @@ pub struct GestureConfig {
 pub struct GestureOverride {
   /// Gesture id in `gesture_RFQDN@gesture_pack_RFQDN` format, where `@` and everything after can be ommitted to use the default gesture pack.
   pub gesture_preset_id: String,
+  /// Parameters to use for this gesture instead of the component's.
   pub gesture_parameters: Option<GestureParameters>,
 }
 
@@ -75,6 +284,46 @@ pub struct GestureStates {
   pub current_state: GestureState,
   /// The next state to switch to (pre-loaded into Renderer and Modules if it loads resources), with a delay if we want to switch automatically.
   pub next_state: Option<(f64, GestureState)>,
+  /// When `next_state` is due, unset while paused (the delay in `next_state` is then the time remaining).
+  #[serde(skip)]
+  pub next_state_at: Option<Instant>,
+  /// When the current state began, used to sample the gesture's [curves](crate::server::modman::gestures::curves).
+  #[serde(skip)]
+  pub began_at: Option<Instant>,
+  /// When the gesture was paused, so the time spent paused can be skipped over when it's un-paused.
+  #[serde(skip)]
+  pub paused_at: Option<Instant>,
+  /// Areas requested by the command that began this gesture, all areas are used if unset.
+  #[serde(default)]
+  pub areas: Option<Vec<String>>,
+}
+
+/// Published on `{MODULE_EVT_ID}/gestures/preload` when a gesture is scheduled to switch states, so resources can be loaded ahead of time.
+///
+/// The commands that components will receive are published on `{MODULE_EVT_ID}/components/by-id/{component_id}/gesture/preload` at the same time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GesturePreload {
+  pub gesture_id: String,
+  /// The state that the gesture will switch to.
+  pub state: GestureState,
+  /// Seconds until the switch.
+  pub switch_in: f64,
+}
+
+/// Values synthesized from the top-most gesture for a single component.
+///
+/// Published on `{MODULE_EVT_ID}/components/by-id/{component_id}/gesture` whenever they change.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ComponentGestureCommand {
+  /// The gesture that produced these values.
+  pub gesture_id: String,
+  /// Values per gesture parameter, calculated via [`GestureParameters::calculate_intensity`].
+  pub values: HashMap<String, f64>,
+  /// Multiplier over the time (x) axis, passed through for components that handle their own timing.
+  pub speed: f64,
+  /// Clamped per-axis target for movement components, also published on `{MODULE_EVT_ID}/components/by-id/{component_id}/position`.
+  #[serde(default)]
+  pub position: Option<DegreesOfFreedom>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -100,4 +349,61 @@ impl GestureParameters {
     return ((((intensity + 1.0) * (self.max - self.min)) + self.min) * self.multiplier)
       + self.offset;
   }
+
+  /// Inverse of [`calculate_intensity`](Self::calculate_intensity), turns a component value back into an intensity (clamped to -1.0 to 1.0).
+  ///
+  /// Returns `None` if these parameters map every intensity onto the same value.
+  pub fn inverse_intensity(&self, value: f64) -> Option<f64> {
+    if self.multiplier == 0.0 || self.max == self.min {
+      return None;
+    }
+
+    Some(
+      ((((value - self.offset) / self.multiplier) - self.min) / (self.max - self.min) - 1.0)
+        .clamp(-1.0, 1.0),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn inverse_intensity_round_trips() {
+    let parameters = GestureParameters {
+      min: -10.0,
+      max: 5.0,
+      multiplier: 2.0,
+      offset: 3.0,
+    };
+
+    for intensity in [-1.0, -0.5, 0.0, 0.25, 1.0] {
+      let value = parameters.calculate_intensity(intensity);
+      let round_tripped = parameters.inverse_intensity(value).unwrap();
+      assert!(
+        (round_tripped - intensity).abs() < 1e-9,
+        "{intensity} came back as {round_tripped}"
+      );
+    }
+
+    // Values out of range are clamped.
+    assert_eq!(
+      parameters.inverse_intensity(parameters.calculate_intensity(2.0)),
+      Some(1.0)
+    );
+  }
+
+  #[test]
+  fn inverse_intensity_of_flat_parameters() {
+    let flat = |min: f64, max: f64, multiplier: f64| GestureParameters {
+      min,
+      max,
+      multiplier,
+      offset: 0.0,
+    };
+
+    assert_eq!(flat(1.0, 1.0, 1.0).inverse_intensity(1.0), None);
+    assert_eq!(flat(0.0, 1.0, 0.0).inverse_intensity(1.0), None);
+  }
 }
@@ -12,12 +12,13 @@ pub mod components;
 pub mod config;
 pub mod gestures;
 pub mod modules;
+pub mod simulation;
 pub mod store;
 
 // TODO: Define defaults via `Default` trait impl.
 
 /// Enum used to track the status of ports that clover knows about and can use.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PortStatus {
   /// Available but unused.
   ///
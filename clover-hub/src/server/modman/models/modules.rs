@@ -3,7 +3,55 @@ use serde::{
   Serialize,
 };
 
-use crate::server::modman::connections::ModuleConnection;
+use crate::server::modman::{
+  busses::models::{
+    AdoptionHello,
+    KeyProvision,
+  },
+  connections::ModuleConnection,
+};
+
+/// How a module authenticates its [content messages](crate::server::modman::busses::models::ContentMessage), see [Security Levels](crate::server::modman::modules#security-levels).
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Default,
+  Serialize,
+  Deserialize,
+  strum_macros::Display,
+)]
+pub enum SecurityLevel {
+  /// Static symmetric key.
+  #[default]
+  #[serde(rename = "l1")]
+  #[strum(serialize = "l1")]
+  L1,
+  /// Session keys, derived from an X25519 exchange and rotated periodically.
+  #[serde(rename = "l2")]
+  #[strum(serialize = "l2")]
+  L2,
+  /// A key per message, derived from the root key and an exchange with a new X25519 key for every message.
+  #[serde(rename = "l3")]
+  #[strum(serialize = "l3")]
+  L3,
+}
+
+/// What to do with modules that have movement components, but use a security level below [L3](SecurityLevel::L3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MovementSecurityPolicy {
+  /// Refuse to adopt them.
+  #[serde(rename = "refuse")]
+  Refuse,
+  /// Adopt them, but set [`Module::insecure_movement`] so users can be warned.
+  #[default]
+  #[serde(rename = "flag")]
+  Flag,
+}
 
 /// Modules are comprised of [Components](CloverComponent) and their [Metadata](CloverComponentMeta).
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +70,32 @@ pub struct Module {
   pub registered_by: String,
   /// How is this module connected to modman?
   pub connection: ModuleConnection,
+  /// Firmware version announced when the module was adopted.
+  #[serde(default)]
+  pub firmware_version: Option<String>,
+  /// Unit-unique serial announced when the module was adopted, used to give it the same ID when it's adopted again.
+  #[serde(default)]
+  pub serial: Option<String>,
+  #[serde(default)]
+  pub security_level: SecurityLevel,
+  /// Base64 encoded key used to sign and verify [content messages](crate::server::modman::busses::models::ContentMessage) for this module. The HMAC-SHA256 key itself for L1, or the root key that session and message keys are derived from for L2 and L3.
+  #[serde(default)]
+  pub key: Option<String>,
+  /// Base64 encoded X25519 secret that ModMan uses for the message key exchanges of an L3 module.
+  #[serde(default)]
+  pub exchange_secret: Option<String>,
+  /// Base64 encoded X25519 public key that an L3 module announced during adoption.
+  #[serde(default)]
+  pub public_key: Option<String>,
+  /// The module has movement components, but a security level below L3. UIs should warn the user about it.
+  #[serde(default)]
+  pub insecure_movement: bool,
+  /// Current L2 session, see [`rotate_session_if_due`](crate::server::modman::busses::auth::rotate_session_if_due).
+  #[serde(default)]
+  pub session: u32,
+  /// Highest counter of a [content message](crate::server::modman::busses::models::ContentMessage) accepted from the module, messages with a counter that isn't higher are rejected as replays.
+  #[serde(default)]
+  pub last_counter: u64,
 }
 
 impl Module {
@@ -32,3 +106,183 @@ impl Module {
     }
   }
 }
+
+/// Payload of a `{MODULE_EVT_ID}/modules/adopt` query, sent by bus proxies when a module replies to an [`AdoptionProbe`](crate::server::modman::busses::models::AdoptionProbe).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdoptionRequest {
+  /// How the module can be reached.
+  pub connection: ModuleConnection,
+  pub hello: AdoptionHello,
+}
+
+/// A new module waiting to be approved, listed at `{MODULE_EVT_ID}/modules/adopt/pending`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingAdoption {
+  /// `{module_type}:{serial}`, the payload of a `{MODULE_EVT_ID}/modules/adopt/approve` query.
+  pub pending_id: String,
+  /// The last request the module was refused with.
+  pub request: AdoptionRequest,
+  /// Approved modules are adopted the next time they're requested.
+  pub approved: bool,
+}
+
+/// Successful reply to a `{MODULE_EVT_ID}/modules/adopt` query.
+#[derive(Serialize, Deserialize, Clone, Debug, strum_macros::Display)]
+#[serde(tag = "result")]
+pub enum AdoptionSuccess {
+  /// The module was registered and is being initialized.
+  #[serde(rename = "adopted")]
+  #[strum(serialize = "adopted")]
+  Adopted {
+    module_id: String,
+    component_ids: Vec<String>,
+    /// To be sent to the module by the bus proxy.
+    provision: KeyProvision,
+  },
+  /// The module was already adopted and initialized, only its firmware version and keys were updated.
+  #[serde(rename = "already-adopted")]
+  #[strum(serialize = "already-adopted")]
+  AlreadyAdopted {
+    module_id: String,
+    /// To be sent to the module by the bus proxy.
+    provision: KeyProvision,
+  },
+}
+
+/// Error reply to a `{MODULE_EVT_ID}/modules/adopt` query.
+#[derive(Serialize, Deserialize, Clone, Debug, strum_macros::Display)]
+#[serde(tag = "error")]
+pub enum AdoptionError {
+  #[serde(rename = "unsupported-protocol")]
+  #[strum(serialize = "unsupported-protocol")]
+  UnsupportedProtocol { protocol_version: u8 },
+  /// No Warehouse manifest defines this module type.
+  #[serde(rename = "unknown-module-type")]
+  #[strum(serialize = "unknown-module-type")]
+  UnknownModuleType { module_type: String },
+  /// The module's manifest entry defines a component that the module didn't announce.
+  #[serde(rename = "missing-component")]
+  #[strum(serialize = "missing-component")]
+  MissingComponent { component: String },
+  /// The module announced a component that its manifest entry doesn't define.
+  #[serde(rename = "unknown-component")]
+  #[strum(serialize = "unknown-component")]
+  UnknownComponent { component: String },
+  #[serde(rename = "component-mismatch")]
+  #[strum(serialize = "component-mismatch")]
+  ComponentMismatch {
+    component: String,
+    expected: String,
+    announced: String,
+  },
+  /// Components of this type can't be adopted from a bus (yet).
+  #[serde(rename = "unsupported-component-type")]
+  #[strum(serialize = "unsupported-component-type")]
+  UnsupportedComponentType {
+    component: String,
+    component_type: String,
+  },
+  /// L2 and L3 modules must announce an X25519 public key.
+  #[serde(rename = "missing-public-key")]
+  #[strum(serialize = "missing-public-key")]
+  MissingPublicKey,
+  #[serde(rename = "invalid-public-key")]
+  #[strum(serialize = "invalid-public-key")]
+  InvalidPublicKey,
+  /// The module has movement components, but a security level below L3, and the [policy](MovementSecurityPolicy) is to refuse it.
+  #[serde(rename = "insecure-movement")]
+  #[strum(serialize = "insecure-movement")]
+  InsecureMovement { security_level: SecurityLevel },
+  /// The module is already adopted with a higher security level than it announced.
+  #[serde(rename = "downgrade")]
+  #[strum(serialize = "downgrade")]
+  Downgrade {
+    security_level: SecurityLevel,
+    announced: SecurityLevel,
+  },
+  /// The module is already adopted, the bus proxy must probe it again with this challenge, and send the hello it replies with.
+  #[serde(rename = "challenge-required")]
+  #[strum(serialize = "challenge-required")]
+  ChallengeRequired { challenge: Vec<u8> },
+  /// The hello's proof wasn't made with the module's current key.
+  #[serde(rename = "invalid-proof")]
+  #[strum(serialize = "invalid-proof")]
+  InvalidProof,
+  /// The module hasn't been adopted before, and has to be approved at `{MODULE_EVT_ID}/modules/adopt/approve` first. The bus proxy should request its adoption again later.
+  #[serde(rename = "approval-required")]
+  #[strum(serialize = "approval-required")]
+  ApprovalRequired { pending_id: String },
+  /// No module is waiting to be approved with this ID.
+  #[serde(rename = "unknown-pending-adoption")]
+  #[strum(serialize = "unknown-pending-adoption")]
+  UnknownPendingAdoption { pending_id: String },
+  /// The query payload could not be parsed as an [`AdoptionRequest`].
+  #[serde(rename = "invalid-payload")]
+  #[strum(serialize = "invalid-payload")]
+  InvalidPayload { reason: String },
+}
+
+/// A module as reported by the registry queryables, without its key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleInfo {
+  pub module_id: String,
+  pub module_type: String,
+  /// Custom name if it's set, otherwise the manifest-defined name.
+  pub name: String,
+  pub initialized: bool,
+  /// Component IDs and if they're critical.
+  pub components: Vec<(String, bool)>,
+  pub registered_by: String,
+  pub connection: ModuleConnection,
+  pub firmware_version: Option<String>,
+  pub serial: Option<String>,
+  pub security_level: SecurityLevel,
+  pub insecure_movement: bool,
+}
+
+impl ModuleInfo {
+  pub fn new(module_id: &str, module: &Module) -> Self {
+    ModuleInfo {
+      module_id: module_id.to_string(),
+      module_type: module.module_type.clone(),
+      name: module.get_name(),
+      initialized: module.initialized,
+      components: module.components.clone(),
+      registered_by: module.registered_by.clone(),
+      connection: module.connection.clone(),
+      firmware_version: module.firmware_version.clone(),
+      serial: module.serial.clone(),
+      security_level: module.security_level,
+      insecure_movement: module.insecure_movement,
+    }
+  }
+}
+
+/// Successful reply to a `{MODULE_EVT_ID}/modules/by-id/{module_id}/init` or `.../deinit` query.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleInitStatus {
+  pub module_id: String,
+  /// Whether the module is initialized now.
+  pub initialized: bool,
+  /// Components that were (de)initialized.
+  pub components: usize,
+}
+
+/// Error reply to the module and component registry queryables.
+#[derive(Serialize, Deserialize, Clone, Debug, strum_macros::Display)]
+#[serde(tag = "error")]
+pub enum RegistryError {
+  #[serde(rename = "unknown-module")]
+  #[strum(serialize = "unknown-module")]
+  UnknownModule { module_id: String },
+  #[serde(rename = "unknown-component")]
+  #[strum(serialize = "unknown-component")]
+  UnknownComponent { component_id: String },
+  /// Not one of the [component type names](crate::server::modman::models::components::CloverComponent::type_name).
+  #[serde(rename = "unknown-component-type")]
+  #[strum(serialize = "unknown-component-type")]
+  UnknownComponentType { component_type: String },
+  #[serde(rename = "invalid-payload")]
+  #[strum(serialize = "invalid-payload")]
+  InvalidPayload { reason: String },
+}
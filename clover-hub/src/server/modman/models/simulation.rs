@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use serde::{
+  Deserialize,
+  Serialize,
+};
+
+use crate::server::modman::models::gestures::GestureTrack;
+
+/// How a [simulated module](crate::server::modman::busses::proxies::individual::simulated) behaves.
+///
+/// ```json
+/// {
+///   "report_interval": 50,
+///   "components": {
+///     "imu": {
+///       "pitch": { "@random": [-0.1, 0.1] },
+///       "yaw": {
+///         "@animation": {
+///           "repeat": "ping-pong",
+///           "keyframes": [{ "x": 0.0, "y": -1.0 }, { "x": 2000.0, "y": 1.0 }]
+///         }
+///       },
+///       "roll": 0.0
+///     }
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ModuleSimulation {
+  /// Milliseconds between telemetry reports.
+  #[serde(default = "default_report_interval")]
+  pub report_interval: u64,
+  /// Report the values of commands sent to the module back as its telemetry, until the next command for that parameter.
+  #[serde(default = "default_echo_commands")]
+  pub echo_commands: bool,
+  /// Values reported by each component, keyed by component ID (or for adopted modules, the component's key in the module manifest), then by parameter.
+  #[serde(default)]
+  pub components: HashMap<String, HashMap<String, SimulatedValue>>,
+}
+
+fn default_report_interval() -> u64 {
+  100
+}
+
+fn default_echo_commands() -> bool {
+  true
+}
+
+/// A simulated parameter, either scripted like a gesture track, or random.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum SimulatedValue {
+  /// A new uniformly random value between `[min, max]` every report.
+  Random {
+    #[serde(rename = "@random")]
+    range: [f64; 2],
+  },
+  /// Sampled at the time since the module was bound, like a gesture parameter.
+  Scripted(GestureTrack),
+}
+
+impl SimulatedValue {
+  /// Value of this parameter at `elapsed` milliseconds since the module was bound.
+  pub fn sample(&self, elapsed: f64) -> f64 {
+    match self {
+      SimulatedValue::Random { range: [min, max] } => {
+        if min >= max {
+          *min
+        } else {
+          rand::Rng::gen_range(&mut rand::thread_rng(), *min..=*max)
+        }
+      }
+      SimulatedValue::Scripted(track) => track.sample(elapsed),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn values_parse_by_shape() {
+    let simulation: ModuleSimulation = serde_json::from_str(
+      r#"{
+        "components": {
+          "imu": {
+            "pitch": { "@random": [-0.1, 0.1] },
+            "yaw": { "@animation": { "keyframes": [{ "x": 0.0, "y": -1.0 }, { "x": 2000.0, "y": 1.0 }] } },
+            "roll": 0.5
+          }
+        }
+      }"#,
+    )
+    .unwrap();
+    let imu = &simulation.components["imu"];
+
+    assert_eq!(simulation.report_interval, 100);
+    assert!(simulation.echo_commands);
+    assert_eq!(imu["pitch"], SimulatedValue::Random { range: [-0.1, 0.1] });
+    assert!(matches!(
+      imu["yaw"],
+      SimulatedValue::Scripted(GestureTrack::Animated { .. })
+    ));
+    assert_eq!(
+      imu["roll"],
+      SimulatedValue::Scripted(GestureTrack::Constant(0.5))
+    );
+  }
+
+  #[test]
+  fn random_values_stay_in_range() {
+    let value = SimulatedValue::Random { range: [-1.0, 1.0] };
+
+    for _ in 0..100 {
+      assert!((-1.0..=1.0).contains(&value.sample(0.0)));
+    }
+  }
+
+  #[test]
+  fn empty_random_range_is_its_min() {
+    assert_eq!(
+      SimulatedValue::Random { range: [2.0, 2.0] }.sample(0.0),
+      2.0
+    );
+    assert_eq!(
+      SimulatedValue::Random { range: [3.0, 1.0] }.sample(0.0),
+      3.0
+    );
+  }
+
+  #[test]
+  fn scripted_values_are_sampled_at_elapsed_time() {
+    let value: SimulatedValue = serde_json::from_str(
+      r#"{ "@animation": { "curve": "linear", "keyframes": [{ "x": 0.0, "y": 0.0 }, { "x": 1000.0, "y": 1.0 }] } }"#,
+    )
+    .unwrap();
+
+    assert_eq!(value.sample(0.0), 0.0);
+    assert!((value.sample(500.0) - 0.5).abs() < 1e-3);
+    assert_eq!(value.sample(1000.0), 1.0);
+  }
+}
@@ -2,20 +2,33 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use crate::server::modman::busses::auth::{
+  ModuleSession,
+  NonceWindow,
+};
+use crate::server::modman::gestures::areas::AreaRegistry;
+use crate::server::modman::gestures::packs::GestureLibrary;
+use crate::server::modman::gestures::recording::GestureRecording;
 use crate::server::modman::models::components::{
   CloverComponent,
   CloverComponentMeta,
 };
 use crate::server::modman::models::gestures::GestureStates;
-use crate::server::modman::models::modules::Module;
+use crate::server::modman::models::modules::{
+  Module,
+  PendingAdoption,
+};
 use crate::server::modman::models::PortStatus;
 use crate::server::warehouse::config::models::Config;
+use crate::server::warehouse::repos::models::Manifest;
 
 /// Used for [Bus](super::busses::models::Bus) statuses, etc
 #[derive(Debug, Clone)]
 pub struct PortStatuses {
   /// Used by the [UART Bus](super::busses::proxies::uart::UARTBus).
   pub uart: Arc<Mutex<HashMap<String, PortStatus>>>,
+  /// Framing errors seen on each UART port since it was bound, keyed by port path.
+  pub uart_framing_errors: Arc<Mutex<HashMap<String, u64>>>,
   /// Used by the [CAN2 Bus](super::busses::proxies::can_2::CAN2Bus).
   ///
   /// Identifier string format is `$IFACE/$RX:$TX` where (e.g. `can0/0x101:0x201`):
@@ -23,8 +36,24 @@ pub struct PortStatuses {
   /// - `$RX` is the ***HEX*** representation of the CAN ID used for recieving messages from the module on. (Linux kernel will automatically filter replies for us.)
   /// - `$TX` is the ***HEX*** representation of the CAN ID of the module that we're trying to communicate with.
   ///
-  /// Matches regex: `r"^(?<iface>(?:\\w|[0-9])+)\\/(?<rx_id>0[xX][0-9a-fA-F]{3}):(?<tx_id>0[xX][0-9a-fA-F]{3})$"`
+  /// IDs are either 3 hex digits (11-bit), or 8 hex digits (29-bit, e.g. `can0/0x18DAF101:0x18DA01F1`), see [`port_specifier_re`](crate::server::modman::busses::proxies::group::can_2::bus_manager::port_specifier_re).
   pub can_2: Arc<Mutex<HashMap<String, PortStatus>>>,
+  /// Used by the [CAN FD Bus](super::busses::proxies::can_fd::CANFDBus), same format as `can_2`.
+  pub can_fd: Arc<Mutex<HashMap<String, PortStatus>>>,
+  /// Used by the [I2C Bus](crate::server::modman::busses::proxies::group::i2c::I2CBus).
+  ///
+  /// Identifier string format is `$BUS/$ADDR` (e.g. `i2c-1/0x42`), where `$BUS` is the device name under `/dev`, and `$ADDR` is the ***HEX*** 7-bit address of the module.
+  pub i2c: Arc<Mutex<HashMap<String, PortStatus>>>,
+  /// Used by the [SPI Bus](crate::server::modman::busses::proxies::group::spi::SPIBus).
+  ///
+  /// Identifier string format is `$BUS/$DEVICE` (e.g. `0/imu`), where `$BUS` is the bus number, and `$DEVICE` is the module's device ID, mapped to a chip-select in the config.
+  pub spi: Arc<Mutex<HashMap<String, PortStatus>>>,
+  /// Used by the [BLE Bus](crate::server::modman::busses::proxies::individual::bt_le::BluetoothLEBus), keyed by the module's address (e.g. `AA:BB:CC:DD:EE:FF`).
+  pub bt_le: Arc<Mutex<HashMap<String, PortStatus>>>,
+  /// Used by the [App Bus](crate::server::modman::busses::proxies::individual::app::AppBus), keyed by module ID, as each app route is for one module.
+  pub app: Arc<Mutex<HashMap<String, PortStatus>>>,
+  /// Used by the [Simulated Bus](crate::server::modman::busses::proxies::individual::simulated::SimulatedBus), keyed by module ID.
+  pub simulated: Arc<Mutex<HashMap<String, PortStatus>>>,
 }
 
 /// In memory data-store for components, modules, and any needed configuration.
@@ -36,31 +65,72 @@ pub struct ModManStore {
   pub components: Arc<Mutex<HashMap<String, Arc<(CloverComponentMeta, CloverComponent)>>>>,
   /// Global access to the current configuration.
   pub config: Arc<Mutex<Config>>,
+  /// Read access to the manifests of all repos loaded by Warehouse.
+  pub repos: Arc<Mutex<HashMap<String, Manifest>>>,
+  /// Body areas for gesture and component targeting, see [`load_bodies`](crate::server::modman::gestures::areas::load_bodies).
+  pub areas: Arc<Mutex<AreaRegistry>>,
+  /// Gestures from all gesture packs, see [`load_gesture_packs`](crate::server::modman::gestures::packs::load_gesture_packs).
+  pub gesture_library: Arc<Mutex<GestureLibrary>>,
   pub gesture_states: Arc<Mutex<HashMap<String, GestureStates>>>,
+  /// Gestures currently being recorded from module telemetry, keyed by the RFQDN they'll be saved as.
+  pub gesture_recordings: Arc<Mutex<HashMap<String, GestureRecording>>>,
   pub foreground_gesture_priority: Arc<Mutex<Vec<String>>>,
   pub background_gesture_priority: Arc<Mutex<Vec<String>>>,
   /// Used for [Bus](super::busses::models::Bus) statuses, etc
   pub port_statuses: PortStatuses,
+  /// Recent nonces from each module's content messages, used to reject replays. See [`verify_bus_message`](crate::server::modman::busses::auth::verify_bus_message).
+  pub seen_nonces: Arc<Mutex<HashMap<String, NonceWindow>>>,
+  /// Counter of the last message that ModMan sent to each module, see [`next_counter`](crate::server::modman::busses::auth::next_counter).
+  pub sent_counters: Arc<Mutex<HashMap<String, u64>>>,
+  /// When each L2 module's session was last rotated, see [`rotate_session_if_due`](crate::server::modman::busses::auth::rotate_session_if_due).
+  pub module_sessions: Arc<Mutex<HashMap<String, ModuleSession>>>,
+  /// Outstanding challenge for each adopted module that's being adopted again, see [adoption](crate::server::modman::modules::adoption).
+  pub adoption_challenges: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+  /// New modules waiting to be approved before they're adopted, keyed by [`PendingAdoption::pending_id`].
+  pub pending_adoptions: Arc<Mutex<HashMap<String, PendingAdoption>>>,
 }
 
 impl ModManStore {
-  pub fn new(optional_config: Option<Arc<Mutex<Config>>>) -> Self {
+  pub fn new(
+    optional_config: Option<Arc<Mutex<Config>>>,
+    optional_repos: Option<Arc<Mutex<HashMap<String, Manifest>>>>,
+  ) -> Self {
     let config = match optional_config {
       Some(cfg) => cfg,
       Option::None => Arc::new(Mutex::new(Config::default())),
     };
+    let repos = match optional_repos {
+      Some(repos) => repos,
+      Option::None => Arc::new(Mutex::new(HashMap::new())),
+    };
 
     ModManStore {
       modules: Arc::new(Mutex::new(HashMap::new())),
       components: Arc::new(Mutex::new(HashMap::new())),
+      areas: Arc::new(Mutex::new(AreaRegistry::default())),
+      gesture_library: Arc::new(Mutex::new(GestureLibrary::default())),
       gesture_states: Arc::new(Mutex::new(HashMap::new())),
+      gesture_recordings: Arc::new(Mutex::new(HashMap::new())),
       foreground_gesture_priority: Arc::new(Mutex::new(Vec::new())),
       background_gesture_priority: Arc::new(Mutex::new(Vec::new())),
       port_statuses: PortStatuses {
         uart: Arc::new(Mutex::new(HashMap::new())),
+        uart_framing_errors: Arc::new(Mutex::new(HashMap::new())),
         can_2: Arc::new(Mutex::new(HashMap::new())),
+        can_fd: Arc::new(Mutex::new(HashMap::new())),
+        i2c: Arc::new(Mutex::new(HashMap::new())),
+        spi: Arc::new(Mutex::new(HashMap::new())),
+        bt_le: Arc::new(Mutex::new(HashMap::new())),
+        app: Arc::new(Mutex::new(HashMap::new())),
+        simulated: Arc::new(Mutex::new(HashMap::new())),
       },
+      seen_nonces: Arc::new(Mutex::new(HashMap::new())),
+      sent_counters: Arc::new(Mutex::new(HashMap::new())),
+      module_sessions: Arc::new(Mutex::new(HashMap::new())),
+      adoption_challenges: Arc::new(Mutex::new(HashMap::new())),
+      pending_adoptions: Arc::new(Mutex::new(HashMap::new())),
       config,
+      repos,
     }
   }
 }
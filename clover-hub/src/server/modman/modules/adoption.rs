@@ -0,0 +1,524 @@
+//! # Module Adoption
+//!
+//! Modules don't need to be defined in the configuration ahead of time. When a bus proxy finds a module it hasn't bound yet, it sends an [`AdoptionProbe`](crate::server::modman::busses::models::AdoptionProbe), and the module replies with an [`AdoptionHello`] that announces its `module_type` RFQDN, firmware version, serial, and components.
+//!
+//! Only the BLE and UART proxies look for new modules so far (when their `adopt_new_modules` option is turned on), using the [shared handshake](crate::server::modman::busses::adoption). Adoption isn't implemented for the CAN 2, CAN FD, I2C, and SPI proxies yet, modules on those busses still have to be defined in the configuration.
+//!
+//! The bus proxy then sends an [`AdoptionRequest`] to `{MODULE_EVT_ID}/modules/adopt`, and the hello is matched against the [`ModuleSpec`] with the same RFQDN from Warehouse's manifests. Every component in the manifest entry must be announced with the same type, and no others. The module and its components are then registered in the store and initialized.
+//!
+//! Modules that haven't been adopted before also have to be approved. Their first request is refused with [`AdoptionError::ApprovalRequired`], and they're listed at `{MODULE_EVT_ID}/modules/adopt/pending` until their [`pending_id`](PendingAdoption::pending_id) is sent to `{MODULE_EVT_ID}/modules/adopt/approve`. The bus proxy keeps requesting their adoption in the meantime.
+//!
+//! Module IDs are random, but a module that's adopted again with the same type and serial keeps its ID, and component IDs are `{module_id}.{component_id}`.
+//!
+//! Keys are provisioned for the module's announced [`SecurityLevel`] every time it's adopted, and the bus proxy sends the [`KeyProvision`](crate::server::modman::busses::models::KeyProvision) from the reply to the module. Modules with movement components that use a level below L3 are refused or flagged, depending on the configured [`MovementSecurityPolicy`].
+//!
+//! Otherwise, anyone that knows a module's type and serial could take it over by adopting it again, so a module that already has keys:
+//!
+//! - is refused if it announces a lower security level than it was adopted with,
+//! - and must prove that it holds its current key. The first request is refused with [`AdoptionError::ChallengeRequired`], the bus proxy then probes the module again with the challenge, and sends the hello it replies with, which carries an [`adoption_proof`](crate::server::modman::busses::auth::adoption_proof).
+//!
+
+use std::{
+  collections::HashMap,
+  sync::Arc,
+};
+
+use tracing::{
+  debug,
+  info,
+  instrument,
+  warn,
+};
+
+use super::init_module;
+use crate::server::{
+  modman::{
+    busses::{
+      auth::{
+        check_adoption_proof,
+        provision_keys,
+        reset_module_keys,
+        store_module_keys,
+      },
+      models::{
+        AdoptionComponent,
+        AdoptionHello,
+        ADOPTION_PROTOCOL_VERSION,
+      },
+    },
+    components::{
+      movement::models::{
+        ConnectionType as MovementConnectionType,
+        DegreesOfFreedom,
+        MovementComponent,
+      },
+      sensors::models::{
+        ConnectionType as SensorConnectionType,
+        IndicatorComponent,
+        SensorComponent,
+      },
+    },
+    models::{
+      components::{
+        CloverComponent,
+        CloverComponentMeta,
+      },
+      gestures::GestureConfig,
+      modules::{
+        AdoptionError,
+        AdoptionRequest,
+        AdoptionSuccess,
+        Module,
+        MovementSecurityPolicy,
+        PendingAdoption,
+        SecurityLevel,
+      },
+      store::ModManStore,
+    },
+    MODULE_EVT_ID,
+  },
+  warehouse::repos::{
+    builtin_rfqdn,
+    models::{
+      ModuleSpec,
+      Optional,
+      OptionalBoolean,
+      OptionalStrTHashMap,
+      OptionalString,
+    },
+  },
+};
+
+/// Find the manifest entry for a module type in any repo loaded by Warehouse.
+pub async fn find_module_spec(store: &ModManStore, module_type: &str) -> Option<ModuleSpec> {
+  store
+    .repos
+    .lock()
+    .await
+    .values()
+    .find_map(|manifest| match &manifest.directory {
+      Optional::Some(directory) => match &directory.modules {
+        OptionalStrTHashMap::Some(modules) => modules.get(module_type).cloned(),
+        OptionalStrTHashMap::None => None,
+      },
+      _ => None,
+    })
+}
+
+/// Degrees of freedom for a movement data type, e.g. `vec3d` or a single `float`.
+fn degrees_of_freedom(data_type: &str) -> Option<usize> {
+  match data_type {
+    "float" | "f32" | "f64" => Some(1),
+    _ => data_type
+      .strip_prefix("vec")
+      .and_then(|rest| rest.strip_suffix('d'))
+      .and_then(|degrees| degrees.parse().ok()),
+  }
+}
+
+/// Build a component from its announcement, only components that are proxied entirely through ModMan can be adopted.
+fn component_from_announcement(
+  component_id: &str,
+  announced: &AdoptionComponent,
+) -> Result<CloverComponent, AdoptionError> {
+  let unsupported = || AdoptionError::UnsupportedComponentType {
+    component: component_id.to_string(),
+    component_type: announced.component_type.clone(),
+  };
+
+  match announced.component_type.as_str() {
+    "movement" => {
+      let initial_position = announced
+        .input
+        .as_deref()
+        .and_then(degrees_of_freedom)
+        .and_then(DegreesOfFreedom::zeroed)
+        .ok_or_else(unsupported)?;
+
+      Ok(CloverComponent::MovementComponent(MovementComponent {
+        initial_position,
+        gesture_config: GestureConfig::default(),
+        gesture_parameters: None,
+        axis_limits: HashMap::new(),
+        connection: MovementConnectionType::ModManProxy,
+      }))
+    }
+    "indicator" => Ok(CloverComponent::IndicatorComponent(IndicatorComponent {
+      gesture_config: None,
+      connection: SensorConnectionType::ModManProxy,
+    })),
+    "sensor" => Ok(CloverComponent::SensorComponent(SensorComponent {
+      connection: SensorConnectionType::ModManProxy,
+    })),
+    _ => Err(unsupported()),
+  }
+}
+
+/// Check a hello against the module's manifest entry, and build its components keyed by their ID in the manifest.
+fn match_module_spec(
+  spec: &ModuleSpec,
+  hello: &AdoptionHello,
+) -> Result<Vec<(String, CloverComponentMeta, CloverComponent)>, AdoptionError> {
+  let optional_string = |val: &OptionalString| match val {
+    OptionalString::Some(val) => Some(val.clone()),
+    OptionalString::None => None,
+  };
+  let optional_bool = |val: &OptionalBoolean, default: bool| match val {
+    OptionalBoolean::Some(val) => *val,
+    OptionalBoolean::None => default,
+  };
+
+  let component_specs = match &spec.components {
+    OptionalStrTHashMap::Some(component_specs) => component_specs.clone(),
+    OptionalStrTHashMap::None => HashMap::new(),
+  };
+
+  if let Some(component) = hello
+    .components
+    .keys()
+    .find(|component_id| !component_specs.contains_key(*component_id))
+  {
+    return Err(AdoptionError::UnknownComponent {
+      component: component.clone(),
+    });
+  }
+
+  let module_location = optional_string(&spec.location);
+  let internal = optional_bool(&spec.internal, false);
+  let mut components = vec![];
+
+  for (component_id, component_spec) in component_specs.iter() {
+    let announced = match hello.components.get(component_id) {
+      Some(announced) => announced,
+      None => {
+        return Err(AdoptionError::MissingComponent {
+          component: component_id.clone(),
+        })
+      }
+    };
+
+    if announced.component_type != component_spec.component_type.0 {
+      return Err(AdoptionError::ComponentMismatch {
+        component: component_id.clone(),
+        expected: component_spec.component_type.0.clone(),
+        announced: announced.component_type.clone(),
+      });
+    }
+
+    let expected_input = optional_string(&component_spec.input);
+    if expected_input.is_some() && announced.input != expected_input {
+      return Err(AdoptionError::ComponentMismatch {
+        component: component_id.clone(),
+        expected: format!("input: {}", expected_input.unwrap_or_default()),
+        announced: format!("input: {}", announced.input.clone().unwrap_or_default()),
+      });
+    }
+
+    let location = match optional_string(&component_spec.location).or(module_location.clone()) {
+      Some(location) => location,
+      None => {
+        warn!("Module type: {}, does not define a location for component: {component_id}, gestures will not apply to it!", hello.module_type);
+        String::new()
+      }
+    };
+
+    components.push((
+      component_id.clone(),
+      CloverComponentMeta {
+        name: optional_string(&component_spec.name).unwrap_or(component_id.clone()),
+        critical: optional_bool(&component_spec.critical, true),
+        location,
+        base_gesture_parameters: HashMap::new(),
+        internal,
+      },
+      component_from_announcement(component_id, announced)?,
+    ));
+  }
+
+  Ok(components)
+}
+
+/// ID a new module is approved by, see [`PendingAdoption`].
+pub fn pending_adoption_id(hello: &AdoptionHello) -> String {
+  format!("{}:{}", hello.module_type, hello.serial)
+}
+
+/// Check a module against the configured [`MovementSecurityPolicy`], returns whether it has to be flagged as [`Module::insecure_movement`]. Used for adopted modules, and modules from the configuration when they're [initialized](init_module).
+pub async fn check_movement_security(
+  store: &ModManStore,
+  module_type: &str,
+  has_movement: bool,
+  security_level: SecurityLevel,
+) -> Result<bool, AdoptionError> {
+  if !has_movement || security_level >= SecurityLevel::L3 {
+    return Ok(false);
+  }
+
+  match store.config.lock().await.modman.movement_security_policy {
+    MovementSecurityPolicy::Refuse => Err(AdoptionError::InsecureMovement { security_level }),
+    MovementSecurityPolicy::Flag => {
+      warn!(
+        "Module type: {module_type}, has movement components, but only uses security level: {security_level}, it will be flagged!"
+      );
+      Ok(true)
+    }
+  }
+}
+
+/// Approve a new module, so it's adopted the next time its bus proxy requests it.
+pub async fn approve_adoption(
+  store: &ModManStore,
+  pending_id: &str,
+) -> Result<PendingAdoption, AdoptionError> {
+  match store.pending_adoptions.lock().await.get_mut(pending_id) {
+    Some(pending) => {
+      pending.approved = true;
+      Ok(pending.clone())
+    }
+    None => Err(AdoptionError::UnknownPendingAdoption {
+      pending_id: pending_id.to_string(),
+    }),
+  }
+}
+
+/// Adopt a module that announced itself with an [`AdoptionHello`], registering it and its components in the store, then initializing it in the background.
+#[instrument(skip(store, session))]
+pub async fn adopt_module(
+  store: &ModManStore,
+  session: Arc<zenoh::Session>,
+  request: AdoptionRequest,
+) -> Result<AdoptionSuccess, AdoptionError> {
+  let AdoptionRequest { connection, hello } = request;
+
+  if hello.protocol_version != ADOPTION_PROTOCOL_VERSION {
+    return Err(AdoptionError::UnsupportedProtocol {
+      protocol_version: hello.protocol_version,
+    });
+  }
+
+  let spec = match find_module_spec(store, &hello.module_type).await {
+    Some(spec) => spec,
+    None => {
+      return Err(AdoptionError::UnknownModuleType {
+        module_type: hello.module_type.clone(),
+      })
+    }
+  };
+  let components = match_module_spec(&spec, &hello)?;
+
+  let has_movement = components
+    .iter()
+    .any(|(_, _, component)| matches!(component, CloverComponent::MovementComponent(_)));
+  let insecure_movement = check_movement_security(
+    store,
+    &hello.module_type,
+    has_movement,
+    hello.security_level,
+  )
+  .await?;
+
+  let existing = store
+    .modules
+    .lock()
+    .await
+    .iter()
+    .find(|(_, module)| {
+      module.module_type == hello.module_type && module.serial.as_ref() == Some(&hello.serial)
+    })
+    .map(|(module_id, module)| (module_id.clone(), module.clone()));
+
+  if existing.is_none() {
+    let pending_id = pending_adoption_id(&hello);
+    let mut pending_adoptions = store.pending_adoptions.lock().await;
+
+    if pending_adoptions
+      .get(&pending_id)
+      .is_some_and(|pending| pending.approved)
+    {
+      pending_adoptions.remove(&pending_id);
+    } else {
+      info!("New module: {pending_id}, is waiting to be approved at: {MODULE_EVT_ID}/modules/adopt/approve");
+      pending_adoptions.insert(
+        pending_id.clone(),
+        PendingAdoption {
+          pending_id: pending_id.clone(),
+          request: AdoptionRequest { connection, hello },
+          approved: false,
+        },
+      );
+
+      return Err(AdoptionError::ApprovalRequired { pending_id });
+    }
+  }
+
+  if let Some((module_id, module)) = &existing {
+    if module.key.is_some() {
+      if hello.security_level < module.security_level {
+        return Err(AdoptionError::Downgrade {
+          security_level: module.security_level,
+          announced: hello.security_level,
+        });
+      }
+
+      check_adoption_proof(store, module_id, &hello).await?;
+    }
+  }
+
+  let (keys, provision) = provision_keys(hello.security_level, hello.public_key.as_ref())?;
+
+  let module_id = match existing {
+    Some((module_id, module)) if module.initialized => {
+      if let Some(module) = store.modules.lock().await.get_mut(&module_id) {
+        module.firmware_version = Some(hello.firmware_version.clone());
+        module.insecure_movement = insecure_movement;
+        store_module_keys(module, &keys);
+      }
+      reset_module_keys(store, &module_id).await;
+
+      debug!("Module: {module_id}, was already adopted, updated its firmware version and keys.");
+      return Ok(AdoptionSuccess::AlreadyAdopted {
+        module_id,
+        provision,
+      });
+    }
+    Some((module_id, _)) => module_id,
+    None => uuid::Uuid::new_v4().to_string(),
+  };
+
+  let mut component_ids = vec![];
+  {
+    let mut store_components = store.components.lock().await;
+
+    for (component_id, meta, component) in components {
+      let component_id = format!("{module_id}.{component_id}");
+
+      component_ids.push((component_id.clone(), meta.critical));
+      store_components.insert(component_id, Arc::new((meta, component)));
+    }
+  }
+
+  let mut module = Module {
+    module_type: hello.module_type.clone(),
+    module_name: match &spec.name {
+      OptionalString::Some(name) => name.clone(),
+      OptionalString::None => hello.module_type.clone(),
+    },
+    custom_name: None,
+    initialized: false,
+    components: component_ids.clone(),
+    registered_by: format!("{}.hub", builtin_rfqdn(false)),
+    connection,
+    firmware_version: Some(hello.firmware_version.clone()),
+    serial: Some(hello.serial.clone()),
+    security_level: hello.security_level,
+    key: None,
+    exchange_secret: None,
+    public_key: None,
+    insecure_movement,
+    session: 0,
+    last_counter: 0,
+  };
+  store_module_keys(&mut module, &keys);
+  reset_module_keys(store, &module_id).await;
+  store
+    .modules
+    .lock()
+    .await
+    .insert(module_id.clone(), module.clone());
+
+  info!(
+    "Adopted module: {} ({module_id}), type: {}, firmware: {}, security level: {}!",
+    module.get_name(),
+    hello.module_type,
+    hello.firmware_version,
+    hello.security_level
+  );
+
+  let init_store = store.clone();
+  let init_id = module_id.clone();
+  tokio::task::spawn(async move {
+    init_module(&init_store, init_id, module, session).await;
+  });
+
+  Ok(AdoptionSuccess::Adopted {
+    module_id,
+    component_ids: component_ids
+      .into_iter()
+      .map(|(component_id, _)| component_id)
+      .collect(),
+    provision,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::server::modman::connections::ModuleConnection;
+
+  fn hello() -> AdoptionHello {
+    AdoptionHello {
+      protocol_version: ADOPTION_PROTOCOL_VERSION,
+      module_type: "com.example.module".to_string(),
+      firmware_version: "1.0.0".to_string(),
+      serial: "0001".to_string(),
+      components: HashMap::new(),
+      security_level: SecurityLevel::L1,
+      public_key: None,
+      proof: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn movement_security_policy() {
+    let store = ModManStore::new(None, None);
+    let check = |has_movement, security_level| {
+      check_movement_security(&store, "com.example.module", has_movement, security_level)
+    };
+
+    store.config.lock().await.modman.movement_security_policy = MovementSecurityPolicy::Refuse;
+    assert!(matches!(
+      check(true, SecurityLevel::L2).await,
+      Err(AdoptionError::InsecureMovement { .. })
+    ));
+    assert!(matches!(check(true, SecurityLevel::L3).await, Ok(false)));
+    assert!(matches!(check(false, SecurityLevel::L1).await, Ok(false)));
+
+    store.config.lock().await.modman.movement_security_policy = MovementSecurityPolicy::Flag;
+    assert!(matches!(check(true, SecurityLevel::L1).await, Ok(true)));
+    assert!(matches!(check(true, SecurityLevel::L3).await, Ok(false)));
+  }
+
+  #[tokio::test]
+  async fn approving_unknown_module_fails() {
+    let store = ModManStore::new(None, None);
+
+    assert!(matches!(
+      approve_adoption(&store, "com.example.module:0001").await,
+      Err(AdoptionError::UnknownPendingAdoption { .. })
+    ));
+  }
+
+  #[tokio::test]
+  async fn approving_pending_module() {
+    let store = ModManStore::new(None, None);
+    let hello = hello();
+    let pending_id = pending_adoption_id(&hello);
+    assert_eq!(pending_id, "com.example.module:0001");
+
+    store.pending_adoptions.lock().await.insert(
+      pending_id.clone(),
+      PendingAdoption {
+        pending_id: pending_id.clone(),
+        request: AdoptionRequest {
+          connection: ModuleConnection::Simulated("module".to_string()),
+          hello,
+        },
+        approved: false,
+      },
+    );
+
+    let pending = approve_adoption(&store, &pending_id).await.unwrap();
+    assert!(pending.approved);
+    assert!(store.pending_adoptions.lock().await[&pending_id].approved);
+  }
+}
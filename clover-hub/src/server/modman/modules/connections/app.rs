@@ -0,0 +1,55 @@
+use tracing::{
+  debug,
+  instrument,
+};
+
+use crate::server::modman::{
+  busses::proxies::individual::app::check_app_claim,
+  models::{
+    modules::Module,
+    store::ModManStore,
+    PortStatus,
+  },
+};
+
+/// Ask the App bus to route a module through its app, if the app may claim it.
+#[instrument(skip(store, module))]
+pub async fn setup_app_connection(
+  store: &ModManStore,
+  module: &Module,
+  id: &String,
+  app_id: &String,
+) -> Result<(), anyhow::Error> {
+  check_app_claim(store, module, app_id)
+    .await
+    .map_err(|err| anyhow::anyhow!("App claim refused: {err}"))?;
+
+  debug!("Requesting app route through: {app_id}, for module: {id}...");
+
+  store
+    .port_statuses
+    .app
+    .lock()
+    .await
+    .insert(id.clone(), PortStatus::Requested(id.clone()));
+
+  Ok(())
+}
+
+/// Stop routing a module through its app.
+#[instrument(skip(store))]
+pub async fn release_app_connection(store: &ModManStore, id: &String) {
+  let mut port_statuses = store.port_statuses.app.lock().await;
+
+  match port_statuses.get(id) {
+    Some(PortStatus::Requested(module_id))
+    | Some(PortStatus::Bound(module_id))
+    | Some(PortStatus::Unavailable(module_id))
+      if module_id == id =>
+    {
+      debug!("Releasing app route for module: {id}...");
+      port_statuses.insert(id.clone(), PortStatus::Unrequested(id.clone()));
+    }
+    _ => {}
+  }
+}
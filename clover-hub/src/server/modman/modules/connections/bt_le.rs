@@ -0,0 +1,57 @@
+use bluer::Address;
+use tracing::{
+  debug,
+  instrument,
+};
+
+use crate::server::modman::models::{
+  store::ModManStore,
+  PortStatus,
+};
+
+/// Port identifier for a BLE connection, see [`PortStatuses::bt_le`](crate::server::modman::models::store::PortStatuses::bt_le).
+pub fn bt_le_port(address: &String) -> Result<String, anyhow::Error> {
+  Ok(address.parse::<Address>()?.to_string())
+}
+
+#[instrument(skip(store))]
+pub async fn setup_bt_le_connection(
+  store: &ModManStore,
+  id: &String,
+  address: &String,
+) -> Result<(), anyhow::Error> {
+  let requested_port = bt_le_port(address)?;
+
+  debug!("Requesting BLE port: {requested_port}, for module: {id}...");
+
+  store
+    .port_statuses
+    .bt_le
+    .lock()
+    .await
+    .insert(requested_port, PortStatus::Requested(id.clone()));
+
+  Ok(())
+}
+
+/// Give up a module's BLE port, the bus disconnects from it.
+#[instrument(skip(store))]
+pub async fn release_bt_le_connection(store: &ModManStore, id: &String, address: &String) {
+  let requested_port = match bt_le_port(address) {
+    Ok(requested_port) => requested_port,
+    Err(_) => return,
+  };
+  let mut port_statuses = store.port_statuses.bt_le.lock().await;
+
+  match port_statuses.get(&requested_port) {
+    Some(PortStatus::Requested(module_id))
+    | Some(PortStatus::Bound(module_id))
+    | Some(PortStatus::Unavailable(module_id))
+      if module_id == id =>
+    {
+      debug!("Releasing BLE port: {requested_port}, from module: {id}...");
+      port_statuses.insert(requested_port, PortStatus::Unrequested(id.clone()));
+    }
+    _ => {}
+  }
+}
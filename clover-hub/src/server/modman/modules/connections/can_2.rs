@@ -14,6 +14,15 @@ use crate::server::modman::{
   },
 };
 
+/// Port identifier for a CAN 2 connection, see [`PortStatuses::can_2`](crate::server::modman::models::store::PortStatuses::can_2).
+pub fn can_2_port(connection: &CAN2Connection) -> String {
+  // can0/0x201:0x101
+  format!(
+    "{}/{}:{}",
+    connection.bus_id, connection.reply_id, connection.device_id
+  )
+}
+
 #[instrument(skip(store, session))]
 pub async fn setup_can_2_connection(
   store: &ModManStore,
@@ -22,11 +31,7 @@ pub async fn setup_can_2_connection(
   connection: CAN2Connection,
   session: Arc<zenoh::Session>,
 ) -> Result<(), anyhow::Error> {
-  // can0/0x201:0x101
-  let requested_port = format!(
-    "{}/{}:{}",
-    connection.bus_id, connection.reply_id, connection.device_id
-  );
+  let requested_port = can_2_port(&connection);
 
   debug!("Requesting CAN 2 port: {requested_port}, for module: {id}...");
 
@@ -36,3 +41,26 @@ pub async fn setup_can_2_connection(
 
   Ok(())
 }
+
+/// Give up a module's CAN 2 port, the bus manager stops its listeners.
+#[instrument(skip(store))]
+pub async fn release_can_2_connection(
+  store: &ModManStore,
+  id: &String,
+  connection: &CAN2Connection,
+) {
+  let requested_port = can_2_port(connection);
+  let mut port_statuses = store.port_statuses.can_2.lock().await;
+
+  match port_statuses.get(&requested_port) {
+    Some(PortStatus::Requested(module_id))
+    | Some(PortStatus::Bound(module_id))
+    | Some(PortStatus::Unavailable(module_id))
+      if module_id == id =>
+    {
+      debug!("Releasing CAN 2 port: {requested_port}, from module: {id}...");
+      port_statuses.insert(requested_port, PortStatus::Unrequested(id.clone()));
+    }
+    _ => {}
+  }
+}
@@ -0,0 +1,63 @@
+use tracing::{
+  debug,
+  instrument,
+};
+
+use crate::server::modman::{
+  connections::CANFDConnection,
+  models::{
+    store::ModManStore,
+    PortStatus,
+  },
+};
+
+/// Port identifier for a CAN FD connection, see [`PortStatuses::can_fd`](crate::server::modman::models::store::PortStatuses::can_fd).
+pub fn can_fd_port(connection: &CANFDConnection) -> String {
+  format!(
+    "{}/{}:{}",
+    connection.bus_id, connection.reply_id, connection.device_id
+  )
+}
+
+#[instrument(skip(store))]
+pub async fn setup_can_fd_connection(
+  store: &ModManStore,
+  id: &String,
+  connection: CANFDConnection,
+) -> Result<(), anyhow::Error> {
+  let requested_port = can_fd_port(&connection);
+
+  debug!("Requesting CAN FD port: {requested_port}, for module: {id}...");
+
+  store
+    .port_statuses
+    .can_fd
+    .lock()
+    .await
+    .insert(requested_port, PortStatus::Requested(id.clone()));
+
+  Ok(())
+}
+
+/// Give up a module's CAN FD port, the bus manager stops its listeners.
+#[instrument(skip(store))]
+pub async fn release_can_fd_connection(
+  store: &ModManStore,
+  id: &String,
+  connection: &CANFDConnection,
+) {
+  let requested_port = can_fd_port(connection);
+  let mut port_statuses = store.port_statuses.can_fd.lock().await;
+
+  match port_statuses.get(&requested_port) {
+    Some(PortStatus::Requested(module_id))
+    | Some(PortStatus::Bound(module_id))
+    | Some(PortStatus::Unavailable(module_id))
+      if module_id == id =>
+    {
+      debug!("Releasing CAN FD port: {requested_port}, from module: {id}...");
+      port_statuses.insert(requested_port, PortStatus::Unrequested(id.clone()));
+    }
+    _ => {}
+  }
+}
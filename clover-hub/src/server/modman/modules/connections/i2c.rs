@@ -0,0 +1,56 @@
+use tracing::{
+  debug,
+  instrument,
+};
+
+use crate::server::modman::{
+  connections::I2CConnection,
+  models::{
+    store::ModManStore,
+    PortStatus,
+  },
+};
+
+/// Port identifier for an I2C connection, see [`PortStatuses::i2c`](crate::server::modman::models::store::PortStatuses::i2c).
+pub fn i2c_port(connection: &I2CConnection) -> String {
+  format!("{}/{}", connection.bus_id, connection.device_id)
+}
+
+#[instrument(skip(store))]
+pub async fn setup_i2c_connection(
+  store: &ModManStore,
+  id: &String,
+  connection: I2CConnection,
+) -> Result<(), anyhow::Error> {
+  let requested_port = i2c_port(&connection);
+
+  debug!("Requesting I2C port: {requested_port}, for module: {id}...");
+
+  store
+    .port_statuses
+    .i2c
+    .lock()
+    .await
+    .insert(requested_port, PortStatus::Requested(id.clone()));
+
+  Ok(())
+}
+
+/// Give up a module's I2C port, the bus stops polling it.
+#[instrument(skip(store))]
+pub async fn release_i2c_connection(store: &ModManStore, id: &String, connection: &I2CConnection) {
+  let requested_port = i2c_port(connection);
+  let mut port_statuses = store.port_statuses.i2c.lock().await;
+
+  match port_statuses.get(&requested_port) {
+    Some(PortStatus::Requested(module_id))
+    | Some(PortStatus::Bound(module_id))
+    | Some(PortStatus::Unavailable(module_id))
+      if module_id == id =>
+    {
+      debug!("Releasing I2C port: {requested_port}, from module: {id}...");
+      port_statuses.insert(requested_port, PortStatus::Unrequested(id.clone()));
+    }
+    _ => {}
+  }
+}
@@ -1,2 +1,14 @@
+pub mod app;
+#[cfg(feature = "bt_le")]
+pub mod bt_le;
 #[cfg(feature = "can_2")]
 pub mod can_2;
+#[cfg(feature = "can_fd")]
+pub mod can_fd;
+#[cfg(feature = "i2c")]
+pub mod i2c;
+pub mod simulated;
+#[cfg(feature = "spi")]
+pub mod spi;
+#[cfg(feature = "uart")]
+pub mod uart;
@@ -0,0 +1,61 @@
+use tracing::{
+  debug,
+  instrument,
+};
+
+use crate::server::modman::{
+  busses::proxies::individual::simulated::find_simulation,
+  models::{
+    modules::Module,
+    store::ModManStore,
+    PortStatus,
+  },
+};
+
+/// Ask the Simulated bus to simulate a module, if it has a simulation.
+#[instrument(skip(store, module))]
+pub async fn setup_simulated_connection(
+  store: &ModManStore,
+  module: &Module,
+  id: &String,
+  simulation_id: &String,
+) -> Result<(), anyhow::Error> {
+  if find_simulation(store, module, simulation_id)
+    .await
+    .is_none()
+  {
+    debug!(
+      "Module: {id}, has no simulation for: {simulation_id}, its components are driven directly."
+    );
+    return Ok(());
+  }
+
+  debug!("Requesting simulation: {simulation_id}, for module: {id}...");
+
+  store
+    .port_statuses
+    .simulated
+    .lock()
+    .await
+    .insert(id.clone(), PortStatus::Requested(id.clone()));
+
+  Ok(())
+}
+
+/// Stop simulating a module.
+#[instrument(skip(store))]
+pub async fn release_simulated_connection(store: &ModManStore, id: &String) {
+  let mut port_statuses = store.port_statuses.simulated.lock().await;
+
+  match port_statuses.get(id) {
+    Some(PortStatus::Requested(module_id))
+    | Some(PortStatus::Bound(module_id))
+    | Some(PortStatus::Unavailable(module_id))
+      if module_id == id =>
+    {
+      debug!("Releasing simulation for module: {id}...");
+      port_statuses.insert(id.clone(), PortStatus::Unrequested(id.clone()));
+    }
+    _ => {}
+  }
+}
@@ -0,0 +1,56 @@
+use tracing::{
+  debug,
+  instrument,
+};
+
+use crate::server::modman::{
+  connections::SPIConnection,
+  models::{
+    store::ModManStore,
+    PortStatus,
+  },
+};
+
+/// Port identifier for an SPI connection, see [`PortStatuses::spi`](crate::server::modman::models::store::PortStatuses::spi).
+pub fn spi_port(connection: &SPIConnection) -> String {
+  format!("{}/{}", connection.bus_id, connection.device_id)
+}
+
+#[instrument(skip(store))]
+pub async fn setup_spi_connection(
+  store: &ModManStore,
+  id: &String,
+  connection: SPIConnection,
+) -> Result<(), anyhow::Error> {
+  let requested_port = spi_port(&connection);
+
+  debug!("Requesting SPI port: {requested_port}, for module: {id}...");
+
+  store
+    .port_statuses
+    .spi
+    .lock()
+    .await
+    .insert(requested_port, PortStatus::Requested(id.clone()));
+
+  Ok(())
+}
+
+/// Give up a module's SPI port, the bus stops polling it.
+#[instrument(skip(store))]
+pub async fn release_spi_connection(store: &ModManStore, id: &String, connection: &SPIConnection) {
+  let requested_port = spi_port(connection);
+  let mut port_statuses = store.port_statuses.spi.lock().await;
+
+  match port_statuses.get(&requested_port) {
+    Some(PortStatus::Requested(module_id))
+    | Some(PortStatus::Bound(module_id))
+    | Some(PortStatus::Unavailable(module_id))
+      if module_id == id =>
+    {
+      debug!("Releasing SPI port: {requested_port}, from module: {id}...");
+      port_statuses.insert(requested_port, PortStatus::Unrequested(id.clone()));
+    }
+    _ => {}
+  }
+}
@@ -0,0 +1,58 @@
+use tracing::{
+  debug,
+  instrument,
+};
+
+use crate::server::modman::{
+  connections::UARTConnection,
+  models::{
+    store::ModManStore,
+    PortStatus,
+  },
+};
+
+#[instrument(skip(store))]
+pub async fn setup_uart_connection(
+  store: &ModManStore,
+  id: &String,
+  connection: UARTConnection,
+) -> Result<(), anyhow::Error> {
+  debug!(
+    "Requesting UART port: {}, for module: {id}...",
+    connection.port
+  );
+
+  store
+    .port_statuses
+    .uart
+    .lock()
+    .await
+    .insert(connection.port, PortStatus::Requested(id.clone()));
+
+  Ok(())
+}
+
+/// Give up a module's UART port, the bus closes it and marks it as available again.
+#[instrument(skip(store))]
+pub async fn release_uart_connection(
+  store: &ModManStore,
+  id: &String,
+  connection: &UARTConnection,
+) {
+  let mut port_statuses = store.port_statuses.uart.lock().await;
+
+  match port_statuses.get(&connection.port) {
+    Some(PortStatus::Requested(module_id))
+    | Some(PortStatus::Bound(module_id))
+    | Some(PortStatus::Unavailable(module_id))
+      if module_id == id =>
+    {
+      debug!(
+        "Releasing UART port: {}, from module: {id}...",
+        connection.port
+      );
+      port_statuses.insert(connection.port.clone(), PortStatus::Unrequested(id.clone()));
+    }
+    _ => {}
+  }
+}
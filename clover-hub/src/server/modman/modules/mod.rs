@@ -13,42 +13,45 @@
 //!
 //! ## Security
 //!
-//! Supported modules are required to use an asynchronous signature for all communications with Zenoh over a ModMan Proxy. An asymmetric encryption key is registered during the registration flow to ensure that all messages are legitimate.
+//! Supported modules are required to authenticate all communications with Zenoh over a ModMan Proxy. Keys are provisioned during [adoption](adoption), and every [content message](super::busses::models::ContentMessage) is signed with an HMAC, see [`auth`](super::busses::auth).
 //!
 //! This is due to Clover's security first design. No security is not an option.
 //!
 //! ### Security Levels
 //!
-//! Level 1 and 2 are designed for simple modules that do not have movement components. Regardless, Level 3 or 4 (using post-quantum encryption algorithms) is suggested for production modules and especially for modules with movement components.
+//! Level 1 and 2 are designed for simple modules that do not have movement components. Level 3 is suggested for production modules, and required for modules with movement components.
 //!
-//! If a module does not use Post-Quantum Level 3 or 4 security and have a movement component, users will be warned of this fact using a non-dismissible UI component if the configuration application is CORE/Spanner compliant!
+//! If a module has a movement component but does not use Level 3, it is either refused (during adoption, or when a module from the configuration is initialized), or flagged (see [`MovementSecurityPolicy`](super::models::modules::MovementSecurityPolicy)). Users will be warned of flagged modules using a non-dismissible UI component if the configuration application is CORE/Spanner compliant!
 //!
 //! #### Level 1
 //!
-//! The async key provided to Clover is private to a single instance and should be deleted after registration, or be hidden during normal usage if that is not feasible. (such as when using an embossed QR code on a module without a built-in display.)
+//! A static symmetric key, generated by Clover and provided to the module during adoption. The key is sent in the clear over the bus, so this level only protects against other devices on the bus after adoption.
 //!
 //! #### Level 2
 //!
-//! Clover will generate an async key and provide it to the module during the registration flow to ensure that messages from Clover are legitimate.
+//! The module announces an X25519 public key during adoption, and Clover replies with its own (ephemeral) public key. Both sides derive a root key from the exchange, and session keys from the root key. Clover rotates the session periodically by sending the module a signed rekey message.
 //!
 //! #### Level 3
 //!
-//! Similar to Level 2, however, all messages are encrypted using symmetric encryption, with the key attached to the message, encrypted using the private key of the transmitting party, a.k.a. Hybrid Cryptography.
+//! Similar to Level 2, however, every message has its own key. The sender generates a new X25519 key for each message, and sends its public key with the message. The message key is derived from the root key, and the exchange between that key and the receiver's X25519 key from adoption.
 //!
 //! #### Level 4
 //!
-//! Similar to Level 3, however, the asymmetric keys are changed constantly to ensure perfect forward secrecy.
+//! Similar to Level 3, however, the asymmetric keys are changed constantly to ensure perfect forward secrecy. Not implemented yet, since Level 3 still relies on each side's X25519 key from adoption.
 //!
 
+pub mod adoption;
 pub mod connections;
 
 use super::{
   components::models::CloverComponentTrait,
   models::{
+    components::CloverComponent,
     modules::Module,
     store::ModManStore,
   },
 };
+use adoption::check_movement_security;
 use anyhow::anyhow;
 use std::sync::Arc;
 use tracing::{
@@ -128,6 +131,18 @@ pub async fn init_component(
   }
 }
 
+/// Does any of the module's components, that are in the store, move?
+async fn has_movement_components(store: &ModManStore, module: &Module) -> bool {
+  let components = store.components.lock().await;
+
+  module.components.iter().any(|(component_id, _)| {
+    components
+      .get(component_id)
+      .map(|component| matches!(component.1, CloverComponent::MovementComponent(_)))
+      .unwrap_or(false)
+  })
+}
+
 #[instrument(skip(store, session))]
 pub async fn init_module(
   store: &ModManStore,
@@ -137,6 +152,7 @@ pub async fn init_module(
 ) -> (bool, usize) {
   let mut initialized_module = module.initialized;
   let mut initialized_module_components = 0;
+  let mut insecure_movement = module.insecure_movement;
 
   info!(
     "Initializing module: {}:\n  type: {}\n  name: {}",
@@ -146,6 +162,24 @@ pub async fn init_module(
   );
 
   if !initialized_module {
+    // Modules from the configuration weren't adopted, so they haven't been checked yet.
+    match check_movement_security(
+      store,
+      &module.module_type,
+      has_movement_components(store, &module).await,
+      module.security_level,
+    )
+    .await
+    {
+      Ok(flagged) => insecure_movement = flagged,
+      Err(err) => {
+        error!(
+          "Module: {id}, was refused by the movement security policy ({err}), it won't be initialized!"
+        );
+        return (false, 0);
+      }
+    }
+
     if module.components.len() == 0 {
       warn!(
         "Module: {}, does not have any components, skipping.",
@@ -156,22 +190,39 @@ pub async fn init_module(
       let mut critical_failiure = None;
 
       match module.connection.clone() {
-        crate::server::modman::connections::ModuleConnection::Simulated(_) => {
-          // TODO: Check for simulation bindings, and load them
-          // Otherwise fail.
+        crate::server::modman::connections::ModuleConnection::Simulated(simulation_id) => {
+          use crate::server::modman::modules::connections::simulated::setup_simulated_connection;
+
+          match setup_simulated_connection(store, &module, &id, &simulation_id).await {
+            Ok(_) => {}
+            Err(err) => {
+              critical_failiure = Some(anyhow!(format!(
+                "Module: {id}, failed to bind simulation: {simulation_id}, due to:\n{err}",
+              )));
+            }
+          }
         }
         crate::server::modman::connections::ModuleConnection::App(app_connection) => {
-          // TODO: App Module binding.
-          critical_failiure = Some(anyhow!(format!(
-            "Module: {id}, failed to bind to app: {app_connection}, due to: Unimplemented.",
-          )));
+          use crate::server::modman::modules::connections::app::setup_app_connection;
+
+          match setup_app_connection(store, &module, &id, &app_connection).await {
+            Ok(_) => {}
+            Err(err) => {
+              critical_failiure = Some(anyhow!(format!(
+                "Module: {id}, failed to bind to app: {app_connection}, due to:\n{err}",
+              )));
+            }
+          }
         }
         #[cfg(feature = "can_2")]
         crate::server::modman::connections::ModuleConnection::CAN2(can2_connection) => {
-          use crate::server::modman::modules::connections::can_2::setup_can_2_connection;
+          use crate::server::modman::modules::connections::can_2::{
+            can_2_port,
+            setup_can_2_connection,
+          };
 
           match setup_can_2_connection(
-            &store,
+            store,
             &module,
             &id,
             can2_connection.clone(),
@@ -183,10 +234,85 @@ pub async fn init_module(
             Err(err) => {
               critical_failiure = Some(anyhow!(format!(
                 "Module: {id}, failed to bind CAN 2 bus proxy: {}, due to:\n{err}",
-                format!(
-                  "{}/{}:{}",
-                  can2_connection.bus_id, can2_connection.reply_id, can2_connection.device_id
-                )
+                can_2_port(&can2_connection)
+              )));
+            }
+          }
+        }
+        #[cfg(feature = "can_fd")]
+        crate::server::modman::connections::ModuleConnection::CANFD(can_fd_connection) => {
+          use crate::server::modman::modules::connections::can_fd::{
+            can_fd_port,
+            setup_can_fd_connection,
+          };
+
+          match setup_can_fd_connection(store, &id, can_fd_connection.clone()).await {
+            Ok(_) => {}
+            Err(err) => {
+              critical_failiure = Some(anyhow!(format!(
+                "Module: {id}, failed to bind CAN FD bus proxy: {}, due to:\n{err}",
+                can_fd_port(&can_fd_connection)
+              )));
+            }
+          }
+        }
+        #[cfg(feature = "bt_le")]
+        crate::server::modman::connections::ModuleConnection::BTLE(address) => {
+          use crate::server::modman::modules::connections::bt_le::setup_bt_le_connection;
+
+          match setup_bt_le_connection(store, &id, &address).await {
+            Ok(_) => {}
+            Err(err) => {
+              critical_failiure = Some(anyhow!(format!(
+                "Module: {id}, failed to bind BLE bus proxy: {address}, due to:\n{err}",
+              )));
+            }
+          }
+        }
+        #[cfg(feature = "spi")]
+        crate::server::modman::connections::ModuleConnection::SPI(spi_connection) => {
+          use crate::server::modman::modules::connections::spi::{
+            setup_spi_connection,
+            spi_port,
+          };
+
+          match setup_spi_connection(store, &id, spi_connection.clone()).await {
+            Ok(_) => {}
+            Err(err) => {
+              critical_failiure = Some(anyhow!(format!(
+                "Module: {id}, failed to bind SPI bus proxy: {}, due to:\n{err}",
+                spi_port(&spi_connection)
+              )));
+            }
+          }
+        }
+        #[cfg(feature = "i2c")]
+        crate::server::modman::connections::ModuleConnection::I2C(i2c_connection) => {
+          use crate::server::modman::modules::connections::i2c::{
+            i2c_port,
+            setup_i2c_connection,
+          };
+
+          match setup_i2c_connection(store, &id, i2c_connection.clone()).await {
+            Ok(_) => {}
+            Err(err) => {
+              critical_failiure = Some(anyhow!(format!(
+                "Module: {id}, failed to bind I2C bus proxy: {}, due to:\n{err}",
+                i2c_port(&i2c_connection)
+              )));
+            }
+          }
+        }
+        #[cfg(feature = "uart")]
+        crate::server::modman::connections::ModuleConnection::UART(uart_connection) => {
+          use crate::server::modman::modules::connections::uart::setup_uart_connection;
+
+          match setup_uart_connection(store, &id, uart_connection.clone()).await {
+            Ok(_) => {}
+            Err(err) => {
+              critical_failiure = Some(anyhow!(format!(
+                "Module: {id}, failed to bind UART bus proxy: {}, due to:\n{err}",
+                uart_connection.port
               )));
             }
           }
@@ -274,6 +400,15 @@ pub async fn init_module(
         components: module.components.clone(),
         registered_by: module.registered_by.clone(),
         connection: module.connection.clone(),
+        firmware_version: module.firmware_version.clone(),
+        serial: module.serial.clone(),
+        security_level: module.security_level,
+        key: module.key.clone(),
+        exchange_secret: module.exchange_secret.clone(),
+        public_key: module.public_key.clone(),
+        insecure_movement,
+        session: module.session,
+        last_counter: module.last_counter,
       },
     );
     debug!("Module: {id}, In-memory store updated!");
@@ -439,6 +574,64 @@ pub async fn deinit_module(store: &ModManStore, id: String, module: Module) -> (
   }
 
   if !initialized_module {
+    if let crate::server::modman::connections::ModuleConnection::App(_) = &module.connection {
+      use crate::server::modman::modules::connections::app::release_app_connection;
+
+      release_app_connection(store, &id).await;
+    }
+    if let crate::server::modman::connections::ModuleConnection::Simulated(_) = &module.connection {
+      use crate::server::modman::modules::connections::simulated::release_simulated_connection;
+
+      release_simulated_connection(store, &id).await;
+    }
+    #[cfg(feature = "uart")]
+    if let crate::server::modman::connections::ModuleConnection::UART(uart_connection) =
+      &module.connection
+    {
+      use crate::server::modman::modules::connections::uart::release_uart_connection;
+
+      release_uart_connection(store, &id, uart_connection).await;
+    }
+    #[cfg(feature = "can_2")]
+    if let crate::server::modman::connections::ModuleConnection::CAN2(can2_connection) =
+      &module.connection
+    {
+      use crate::server::modman::modules::connections::can_2::release_can_2_connection;
+
+      release_can_2_connection(store, &id, can2_connection).await;
+    }
+    #[cfg(feature = "can_fd")]
+    if let crate::server::modman::connections::ModuleConnection::CANFD(can_fd_connection) =
+      &module.connection
+    {
+      use crate::server::modman::modules::connections::can_fd::release_can_fd_connection;
+
+      release_can_fd_connection(store, &id, can_fd_connection).await;
+    }
+    #[cfg(feature = "bt_le")]
+    if let crate::server::modman::connections::ModuleConnection::BTLE(address) = &module.connection
+    {
+      use crate::server::modman::modules::connections::bt_le::release_bt_le_connection;
+
+      release_bt_le_connection(store, &id, address).await;
+    }
+    #[cfg(feature = "spi")]
+    if let crate::server::modman::connections::ModuleConnection::SPI(spi_connection) =
+      &module.connection
+    {
+      use crate::server::modman::modules::connections::spi::release_spi_connection;
+
+      release_spi_connection(store, &id, spi_connection).await;
+    }
+    #[cfg(feature = "i2c")]
+    if let crate::server::modman::connections::ModuleConnection::I2C(i2c_connection) =
+      &module.connection
+    {
+      use crate::server::modman::modules::connections::i2c::release_i2c_connection;
+
+      release_i2c_connection(store, &id, i2c_connection).await;
+    }
+
     // Update the store with new state of the module.
     if !initialized_module {
       store.modules.lock().await.insert(
@@ -451,6 +644,15 @@ pub async fn deinit_module(store: &ModManStore, id: String, module: Module) -> (
           components: module.components.clone(),
           registered_by: module.registered_by.clone(),
           connection: module.connection.clone(),
+          firmware_version: module.firmware_version.clone(),
+          serial: module.serial.clone(),
+          security_level: module.security_level,
+          key: module.key.clone(),
+          exchange_secret: module.exchange_secret.clone(),
+          public_key: module.public_key.clone(),
+          insecure_movement: module.insecure_movement,
+          session: module.session,
+          last_counter: module.last_counter,
         },
       );
 
@@ -464,3 +666,89 @@ pub async fn deinit_module(store: &ModManStore, id: String, module: Module) -> (
 
   (!initialized_module, deinitialized_module_components)
 }
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+  use crate::{
+    server::modman::{
+      components::movement::models::{
+        ConnectionType,
+        DegreesOfFreedom,
+        MovementComponent,
+      },
+      connections::ModuleConnection,
+      models::{
+        components::CloverComponentMeta,
+        gestures::GestureConfig,
+        modules::{
+          MovementSecurityPolicy,
+          SecurityLevel,
+        },
+      },
+    },
+    utils::configure_zenoh,
+  };
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn configured_insecure_movement_is_refused() {
+    let store = ModManStore::new(None, None);
+    store.config.lock().await.modman.movement_security_policy = MovementSecurityPolicy::Refuse;
+    store.components.lock().await.insert(
+      "module.arm".to_string(),
+      Arc::new((
+        CloverComponentMeta {
+          name: "Arm".to_string(),
+          critical: true,
+          location: "@base.humanoid.arm.left".to_string(),
+          base_gesture_parameters: HashMap::new(),
+          internal: false,
+        },
+        CloverComponent::MovementComponent(MovementComponent {
+          initial_position: DegreesOfFreedom::OneDegree(0.0),
+          gesture_config: GestureConfig::default(),
+          gesture_parameters: None,
+          axis_limits: HashMap::new(),
+          connection: ConnectionType::ModManProxy,
+        }),
+      )),
+    );
+
+    let module = Module {
+      module_type: "com.example.arm".to_string(),
+      module_name: "Arm".to_string(),
+      custom_name: None,
+      initialized: false,
+      components: vec![("module.arm".to_string(), true)],
+      registered_by: "com.reboot-codes.clover.hub".to_string(),
+      connection: ModuleConnection::Simulated("module".to_string()),
+      firmware_version: None,
+      serial: None,
+      security_level: SecurityLevel::L1,
+      key: None,
+      exchange_secret: None,
+      public_key: None,
+      insecure_movement: false,
+      session: 0,
+      last_counter: 0,
+    };
+
+    let zenoh_config = configure_zenoh(vec![
+      ("mode", "\"peer\""),
+      ("listen/endpoints", "[]"),
+      ("scouting/multicast/enabled", "false"),
+    ])
+    .unwrap();
+    let session = Arc::new(zenoh::open(zenoh_config).await.unwrap());
+
+    let (initialized, components_initialized) =
+      init_module(&store, "module".to_string(), module, session).await;
+
+    assert!(!initialized);
+    assert_eq!(components_initialized, 0);
+    assert!(!store.modules.lock().await.contains_key("module"));
+    assert!(store.port_statuses.simulated.lock().await.is_empty());
+  }
+}
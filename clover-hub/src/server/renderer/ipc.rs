@@ -7,8 +7,18 @@ use tracing::{
   instrument,
 };
 
+use crate::server::modman::{
+  models::gestures::GesturePreload,
+  MODULE_EVT_ID as MODMAN_EVT_ID,
+};
+
 #[instrument(skip(ipc_token, ipc_session))]
 pub async fn handle_ipc(ipc_token: CancellationToken, ipc_session: Arc<zenoh::Session>) {
+  let preload_token = ipc_token.clone();
+  let preload_session = ipc_session.clone();
+  let preload_handle =
+    tokio::task::spawn(gesture_preload_subscriber(preload_token, preload_session));
+
   let subscriber = ipc_session
     .declare_subscriber("com/reboot-codes/clover/server/inference_engine/**")
     .await
@@ -39,4 +49,39 @@ pub async fn handle_ipc(ipc_token: CancellationToken, ipc_session: Arc<zenoh::Se
       }
     }
   }
+
+  preload_handle.abort();
+}
+
+/// Listen for upcoming gesture switches from ModMan so display resources can be loaded before they're needed.
+#[instrument(skip(ipc_token, ipc_session))]
+pub async fn gesture_preload_subscriber(
+  ipc_token: CancellationToken,
+  ipc_session: Arc<zenoh::Session>,
+) {
+  let subscriber = ipc_session
+    .declare_subscriber(format!("{MODMAN_EVT_ID}/gestures/preload"))
+    .await
+    .unwrap();
+
+  while !ipc_token.is_cancelled() {
+    match subscriber.recv_async().await {
+      Ok(sample) => match sample.payload().try_to_string() {
+        Ok(payload) => match serde_json_lenient::from_str::<GesturePreload>(&payload) {
+          Ok(preload) => {
+            // TODO: Load display resources used by the gesture once gesture packs can define them.
+            debug!(
+              "Gesture: {}, will switch to: {:?} in {}s, preloading...",
+              preload.gesture_id, preload.state, preload.switch_in
+            );
+          }
+          Err(err) => error!("Failed to parse gesture preload, due to:\n{err}"),
+        },
+        Err(err) => error!("Gesture preload payload is not a string, due to:\n{err}"),
+      },
+      Err(msg) => {
+        error!("{}", msg);
+      }
+    }
+  }
 }
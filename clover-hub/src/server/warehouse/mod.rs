@@ -300,7 +300,7 @@ pub async fn warehouse_main(
               // TODO: Lock db and clean up when done.
               debug!("Writing Config File...");
               let config = store.config.lock().await;
-              match fs::File::open(config.data_dir.join("/config.json")).await {
+              match fs::File::create(config.data_dir.join("/config.json")).await {
                 Ok(mut config_file) => {
                   debug!("Config file opened!");
 
@@ -4,6 +4,12 @@ use super::{
   resolve_entry_value,
   resolve_list_entry,
 };
+#[cfg(feature = "core")]
+use crate::server::modman::models::gestures::{
+  GestureBodyConfigs,
+  GestureTrack,
+};
+use crate::server::modman::models::simulation::ModuleSimulation;
 use crate::server::warehouse::repos::builtin_rfqdn;
 use log::debug;
 use os_path::OsPath;
@@ -906,6 +912,128 @@ impl ManifestCompilationFrom<OptionalSingleManifestSpecEntry<bool>> for Optional
   }
 }
 
+impl ManifestCompilationFrom<OptionalSingleManifestSpecEntry<Vec<String>>> for OptionalStringList {
+  async fn compile(
+    spec: OptionalSingleManifestSpecEntry<Vec<String>>,
+    resolution_ctx: ResolutionCtx,
+    repo_dir_path: OsPath,
+  ) -> Result<Self, SimpleError>
+  where
+    Self: Sized,
+  {
+    let mut err = None;
+
+    let ret = match spec {
+      OptionalSingleManifestSpecEntry::Some(vals) => OptionalStringList::Some(
+        vals
+          .into_iter()
+          .map(|val| replace_simple_directives(val, resolution_ctx.clone()))
+          .collect(),
+      ),
+      OptionalSingleManifestSpecEntry::ImportString(raw_spec) => {
+        match resolve_entry_value(raw_spec, resolution_ctx.clone(), repo_dir_path.clone()).await {
+          Ok(resolution) => match resolution {
+            Resolution::ImportedMultiple(_) => {
+              err = Some(SimpleError::new(
+                "Glob imports are not supported at this level",
+              ));
+              OptionalStringList::None
+            }
+            Resolution::ImportedSingle((here, val_str)) => {
+              let here_ctx = ResolutionCtx {
+                base: resolution_ctx.clone().base,
+                builtin: resolution_ctx.clone().builtin,
+                here,
+              };
+
+              match serde_json_lenient::from_str::<Vec<String>>(&val_str) {
+                Ok(vals) => OptionalStringList::Some(
+                  vals
+                    .into_iter()
+                    .map(|val| replace_simple_directives(val, here_ctx.clone()))
+                    .collect(),
+                ),
+                Err(e) => {
+                  err = Some(SimpleError::new(format!(
+                    "OptionalStringList, ctx: {:#?}\nerr: {}",
+                    here_ctx, e
+                  )));
+                  OptionalStringList::None
+                }
+              }
+            }
+            Resolution::NoImport(_) => {
+              err = Some(SimpleError::new(
+                "A string is not a valid value for this field unless it's an import.",
+              ));
+              OptionalStringList::None
+            }
+          },
+          Err(e) => {
+            err = Some(e);
+            OptionalStringList::None
+          }
+        }
+      }
+      OptionalSingleManifestSpecEntry::None => OptionalStringList::None,
+    };
+
+    match err {
+      Some(e) => Err(e),
+      None => Ok(ret),
+    }
+  }
+}
+
+#[cfg(feature = "core")]
+impl ManifestCompilationFrom<GestureBodyConfigs> for GestureBodyConfigs {
+  async fn compile(
+    spec: GestureBodyConfigs,
+    resolution_ctx: ResolutionCtx,
+    _repo_dir_path: OsPath,
+  ) -> Result<Self, SimpleError>
+  where
+    Self: Sized,
+  {
+    // Only the body RFQDN globs can use directives, areas are always relative to the body.
+    Ok(GestureBodyConfigs(
+      spec
+        .0
+        .into_iter()
+        .map(|(body_glob, mut areas)| {
+          // Curves are sampled by bisecting the keyframes, which have to be in order.
+          for track in areas.values_mut().flat_map(|tracks| tracks.values_mut()) {
+            if let GestureTrack::Animated { animation } = track {
+              animation
+                .keyframes
+                .sort_by(|keyframe_a, keyframe_b| keyframe_a.x.total_cmp(&keyframe_b.x));
+            }
+          }
+
+          (
+            replace_simple_directives(body_glob, resolution_ctx.clone()),
+            areas,
+          )
+        })
+        .collect(),
+    ))
+  }
+}
+
+impl ManifestCompilationFrom<ModuleSimulation> for ModuleSimulation {
+  async fn compile(
+    spec: ModuleSimulation,
+    _resolution_ctx: ResolutionCtx,
+    _repo_dir_path: OsPath,
+  ) -> Result<Self, SimpleError>
+  where
+    Self: Sized,
+  {
+    // Simulations are plain data, component keys are relative to the module.
+    Ok(spec)
+  }
+}
+
 // ---------- Begin Actual Value Compilation Implementations ----------
 
 impl Manifest {
@@ -1011,3 +1139,43 @@ impl Manifest {
     }
   }
 }
+
+#[cfg(all(test, feature = "core"))]
+mod tests {
+  use super::*;
+  use crate::server::modman::models::gestures::GestureKeyframe;
+
+  #[tokio::test]
+  async fn gesture_keyframes_are_sorted() {
+    let spec: GestureBodyConfigs = serde_json::from_str(
+      r#"{ "*": { "pelvis.rear.tail.*": { "angle": { "@animation": { "keyframes": [
+        { "x": 500.0, "y": 0.5 }, { "x": 0.0, "y": -0.5 }, { "x": 250.0, "y": 0.0 }
+      ] } } } } }"#,
+    )
+    .unwrap();
+
+    let configs = GestureBodyConfigs::compile(
+      spec,
+      ResolutionCtx {
+        base: None,
+        builtin: builtin_rfqdn(false),
+        here: OsPath::new(),
+      },
+      OsPath::new(),
+    )
+    .await
+    .unwrap();
+
+    match &configs.0["*"]["pelvis.rear.tail.*"]["angle"] {
+      GestureTrack::Animated { animation } => assert_eq!(
+        animation.keyframes,
+        vec![
+          GestureKeyframe { x: 0.0, y: -0.5 },
+          GestureKeyframe { x: 250.0, y: 0.0 },
+          GestureKeyframe { x: 500.0, y: 0.5 },
+        ]
+      ),
+      track => panic!("Expected an animation, got: {track:?}"),
+    }
+  }
+}
@@ -280,7 +280,10 @@ where
               Ok(obj_spec) => {
                 match K::compile(obj_spec, resolution_ctx.clone(), repo_dir_path.clone()).await {
                   Ok(obj) => {
-                    entries.insert(key.clone(), obj);
+                    entries.insert(
+                      replace_simple_directives(key.clone(), resolution_ctx.clone()),
+                      obj,
+                    );
                   }
                   Err(e) => {
                     entry_err = Some(e);
@@ -300,7 +303,11 @@ where
       RequiredSingleManifestEntry::Some(obj_spec) => {
         match K::compile(obj_spec, resolution_ctx.clone(), repo_dir_path.clone()).await {
           Ok(obj) => {
-            entries.insert(key.clone(), obj);
+            // Inline entries resolve their key's directives the same way imported ones do.
+            entries.insert(
+              replace_simple_directives(key.clone(), resolution_ctx.clone()),
+              obj,
+            );
           }
           Err(e) => {
             entry_err = Some(e);
@@ -842,3 +849,45 @@ pub async fn download_repo_updates(
     }
   }
 }
+
+/// Compile the manifest of the repo this crate lives in, which bundles the core repo.
+#[cfg(test)]
+pub(crate) async fn load_core_manifest() -> Manifest {
+  let mut repo_dir_path = OsPath::from(concat!(env!("CARGO_MANIFEST_DIR"), "/.."));
+  repo_dir_path.resolve();
+  let manifest_path = repo_dir_path.join("/manifest.clover.json");
+
+  let contents = fs::read_to_string(manifest_path.to_path())
+    .await
+    .expect("Failed to read the core manifest.");
+  let spec = serde_json_lenient::from_str::<ManifestSpec>(&contents)
+    .expect("Failed to parse the core manifest.");
+
+  Manifest::compile(spec, manifest_path, repo_dir_path)
+    .await
+    .expect("Failed to compile the core manifest.")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use models::{
+    DirectorySpec,
+    OptionalStrTHashMap,
+  };
+
+  #[tokio::test]
+  async fn inline_entry_keys_resolve_directives() {
+    let manifest = load_core_manifest().await;
+    let bodies = match manifest.directory {
+      models::Optional::Some(DirectorySpec {
+        bodies: OptionalStrTHashMap::Some(bodies),
+        ..
+      }) => bodies,
+      _ => panic!("Core repo doesn't define any bodies."),
+    };
+
+    assert!(bodies.contains_key("com.reboot-codes.clover.CORE.humanoid.tailed"));
+    assert!(!bodies.contains_key("@base.humanoid.tailed"));
+  }
+}
@@ -39,6 +39,9 @@
 
 use crate::server::appd::models::BuildConfig;
 #[cfg(feature = "core")]
+use crate::server::modman::models::gestures::GestureBodyConfigs;
+use crate::server::modman::models::simulation::ModuleSimulation;
+#[cfg(feature = "core")]
 use clover_hub_macros::ManifestCompile;
 use os_path::OsPath;
 use serde::{
@@ -156,6 +159,14 @@ pub enum OptionalStringListManifestSpecEntry {
   None,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(untagged)]
+pub enum OptionalStringList {
+  Some(Vec<String>),
+  #[default]
+  None,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(untagged)]
 pub enum OptionalString {
@@ -195,11 +206,55 @@ pub struct RawDirectorySpec {
   #[cfg(feature = "core")]
   #[serde(rename = "gesture-packs", default)]
   pub gesture_packs: OptionalListManifestSpecEntry<RawGesturePackSpec>,
+  #[serde(default)]
+  pub bodies: OptionalListManifestSpecEntry<RawBodySpec>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawBodySpec {
+  pub name: Option<String>,
+  /// RFQDNs of the bodies this body supplements, e.g. `@core.humanoid`.
+  #[serde(default)]
+  pub extends: OptionalSingleManifestSpecEntry<Vec<String>>,
+  /// Area paths relative to the body, `{a,b}` expands to both alternatives.
+  #[serde(default)]
+  pub areas: OptionalSingleManifestSpecEntry<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RawModuleSpec {
   pub name: Option<String>,
+  /// Default [area](crate::server::modman::gestures::areas) for the module's components.
+  #[serde(default)]
+  pub location: Option<String>,
+  #[serde(default)]
+  pub internal: OptionalSingleManifestSpecEntry<bool>,
+  /// Components that the module must announce when it's adopted, keyed by component ID.
+  #[serde(default)]
+  pub components: OptionalListManifestSpecEntry<RawModuleComponentSpec>,
+  /// How the module behaves when it's simulated, see [`ModuleSimulation`].
+  #[serde(default)]
+  pub simulation: OptionalSingleManifestSpecEntry<ModuleSimulation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawModuleComponentSpec {
+  pub name: Option<String>,
+  /// Component type, e.g. `movement`, `indicator`, or `sensor`.
+  #[serde(rename = "type")]
+  pub component_type: String,
+  /// Data type the component receives.
+  #[serde(default)]
+  pub input: Option<String>,
+  /// Data type the component reports.
+  #[serde(default)]
+  pub output: Option<String>,
+  /// Overrides the module's location.
+  #[serde(default)]
+  pub location: Option<String>,
+  /// Defaults to true.
+  #[serde(default)]
+  pub critical: OptionalSingleManifestSpecEntry<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -210,6 +265,9 @@ pub struct RawApplicationSpec {
   pub intents: OptionalStringListManifestSpecEntry,
   #[serde(default)]
   pub containers: OptionalListManifestSpecEntry<RawContainerSpec>,
+  /// Module types (by RFQDN) the application may drive over the App bus.
+  #[serde(default)]
+  pub modules: OptionalSingleManifestSpecEntry<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -241,8 +299,9 @@ pub struct RawRepoCreds {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RawGesturePackSpec {
   pub name: Option<String>,
-  //#[serde(default)]
-  //pub gestures: OptionalListManifestSpecEntry<RawGestureSpec>,
+  pub description: Option<String>,
+  #[serde(default)]
+  pub gestures: OptionalListManifestSpecEntry<RawGestureSpec>,
 }
 
 #[cfg(feature = "core")]
@@ -250,6 +309,17 @@ pub struct RawGesturePackSpec {
 #[serde(untagged)]
 pub enum RawGestureSpec {
   RawStaticGestureSpec(RawStaticGestureSpec),
+  RawKeyframedGestureSpec(RawKeyframedGestureSpec),
+}
+
+#[cfg(feature = "core")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawKeyframedGestureSpec {
+  pub name: Option<String>,
+  pub description: Option<String>,
+  /// See [`GestureBodyConfigs`], required so that a malformed gesture fails to parse instead of silently becoming an empty one.
+  pub configs: OptionalSingleManifestSpecEntry<GestureBodyConfigs>,
 }
 
 #[cfg(feature = "core")]
@@ -281,12 +351,48 @@ pub struct DirectorySpec {
   #[cfg(feature = "core")]
   #[serde(default)]
   pub gesture_packs: OptionalStrTHashMap<GesturePackSpec>,
+  #[serde(default)]
+  pub bodies: OptionalStrTHashMap<BodySpec>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ManifestCompile)]
+pub struct BodySpec {
+  #[serde(default)]
+  pub name: OptionalString,
+  #[serde(default)]
+  pub extends: OptionalStringList,
+  #[serde(default)]
+  pub areas: OptionalStringList,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ManifestCompile)]
 pub struct ModuleSpec {
   #[serde(default)]
   pub name: OptionalString,
+  #[serde(default)]
+  pub location: OptionalString,
+  #[serde(default)]
+  pub internal: OptionalBoolean,
+  #[serde(default)]
+  pub components: OptionalStrTHashMap<ModuleComponentSpec>,
+  #[serde(default)]
+  pub simulation: Optional<ModuleSimulation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ManifestCompile)]
+pub struct ModuleComponentSpec {
+  #[serde(default)]
+  pub name: OptionalString,
+  #[serde(rename = "type")]
+  pub component_type: RequiredString,
+  #[serde(default)]
+  pub input: OptionalString,
+  #[serde(default)]
+  pub output: OptionalString,
+  #[serde(default)]
+  pub location: OptionalString,
+  #[serde(default)]
+  pub critical: OptionalBoolean,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ManifestCompile)]
@@ -297,6 +403,8 @@ pub struct ApplicationSpec {
   pub intents: OptionalStrStrHashMap,
   #[serde(default)]
   pub containers: OptionalStrTHashMap<ContainerSpec>,
+  #[serde(default)]
+  pub modules: OptionalStringList,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ManifestCompile)]
@@ -312,8 +420,10 @@ pub struct ContainerSpec {
 pub struct GesturePackSpec {
   #[serde(default)]
   pub name: OptionalString,
-  //#[serde(default)]
-  //pub gestures: OptionalStrTHashMap<GestureSpec>,
+  #[serde(default)]
+  pub description: OptionalString,
+  #[serde(default)]
+  pub gestures: OptionalStrTHashMap<GestureSpec>,
 }
 
 #[cfg(feature = "core")]
@@ -321,6 +431,18 @@ pub struct GesturePackSpec {
 #[serde(untagged)]
 pub enum GestureSpec {
   StaticGestureSpec(StaticGestureSpec),
+  KeyframedGestureSpec(KeyframedGestureSpec),
+}
+
+#[cfg(feature = "core")]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, ManifestCompile)]
+pub struct KeyframedGestureSpec {
+  #[serde(default)]
+  pub name: OptionalString,
+  #[serde(default)]
+  pub description: OptionalString,
+  #[serde(default)]
+  pub configs: Optional<GestureBodyConfigs>,
 }
 
 #[cfg(feature = "core")]
@@ -341,3 +463,37 @@ pub trait ManifestCompilationFrom<T> {
     Self: Sized,
     T: for<'a> Deserialize<'a>;
 }
+
+#[cfg(all(test, feature = "core"))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn keyframed_gesture_parses() {
+    let spec: RawGestureSpec = serde_json::from_str(
+      r#"{ "name": "Smile", "configs": { "*": { "torso.head.face.mouth": { "curve": 1.0 } } } }"#,
+    )
+    .unwrap();
+
+    assert!(matches!(
+      spec,
+      RawGestureSpec::RawKeyframedGestureSpec(RawKeyframedGestureSpec {
+        configs: OptionalSingleManifestSpecEntry::Some(_),
+        ..
+      })
+    ));
+  }
+
+  #[test]
+  fn gesture_without_configs_fails() {
+    assert!(serde_json::from_str::<RawGestureSpec>(r#"{ "name": "Smile" }"#).is_err());
+  }
+
+  #[test]
+  fn gesture_with_unknown_fields_fails() {
+    assert!(serde_json::from_str::<RawGestureSpec>(
+      r#"{ "name": "Smile", "configs": {}, "keyframes": [] }"#
+    )
+    .is_err());
+  }
+}
@@ -0,0 +1,393 @@
+#![cfg(feature = "bus_harness")]
+//! Drives the bus proxies against fake modules, see [`clover_hub::server::modman::busses::harness`].
+
+use std::{
+  sync::Arc,
+  time::Duration,
+};
+
+use clover_hub::{
+  server::modman::{
+    busses::{
+      harness::{
+        can_2_connection,
+        fake_module::{
+          ExpectedMessage,
+          FakeLink,
+          FakeModule,
+          ScriptStep,
+        },
+        pty::PtyLink,
+        register_module,
+        register_module_spec,
+        start_adoption,
+        start_can_bus_manager,
+        start_uart_bus,
+        uart_connection,
+        vcan::{
+          VcanInterface,
+          VcanLink,
+        },
+        wait_for_port,
+      },
+      models::{
+        AdoptionComponent,
+        BusMessage,
+      },
+    },
+    models::{
+      modules::SecurityLevel,
+      store::ModManStore,
+      PortStatus,
+    },
+    modules::adoption::{
+      approve_adoption,
+      pending_adoption_id,
+    },
+    MODULE_EVT_ID,
+  },
+  utils::configure_zenoh,
+};
+use tokio_util::sync::CancellationToken;
+use zenoh::{
+  handlers::FifoChannelHandler,
+  pubsub::Subscriber,
+  sample::Sample,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn open_session() -> Arc<zenoh::Session> {
+  let config = configure_zenoh(vec![
+    ("mode", "\"peer\""),
+    ("listen/endpoints", "[]"),
+    ("scouting/multicast/enabled", "false"),
+    (
+      "timestamping/enabled",
+      r#"{ router: true, peer: true, client: true }"#,
+    ),
+  ])
+  .expect("Failed to configure Zenoh.");
+
+  Arc::new(
+    zenoh::open(config)
+      .await
+      .expect("Failed to open Zenoh session."),
+  )
+}
+
+async fn subscribe(
+  session: &zenoh::Session,
+  module_id: &str,
+  topic: &str,
+) -> Subscriber<FifoChannelHandler<Sample>> {
+  session
+    .declare_subscriber(format!("{MODULE_EVT_ID}/modules/by-id/{module_id}/{topic}"))
+    .await
+    .expect("Failed to subscribe.")
+}
+
+/// The payload of the next sample, `None` if nothing arrived in time.
+async fn next_payload(
+  subscriber: &Subscriber<FifoChannelHandler<Sample>>,
+  timeout: Duration,
+) -> Option<String> {
+  match tokio::time::timeout(timeout, subscriber.recv_async()).await {
+    Ok(Ok(sample)) => Some(sample.payload().try_to_string().unwrap().to_string()),
+    _ => None,
+  }
+}
+
+/// A fake module on a pty, registered and bound by a running UART bus.
+struct UartHarness {
+  store: Arc<ModManStore>,
+  session: Arc<zenoh::Session>,
+  cancellation_token: CancellationToken,
+  module_id: String,
+  path: String,
+  fake_module: FakeModule,
+  link: FakeLink,
+}
+
+impl UartHarness {
+  async fn start(module_id: &str, security_level: SecurityLevel) -> Self {
+    let store = Arc::new(ModManStore::new(None, None));
+    let session = open_session().await;
+    let cancellation_token = CancellationToken::new();
+
+    let link = PtyLink::open().expect("Failed to open pty.");
+    let path = link.path().clone();
+    let mut fake_module = FakeModule::new("harness-module", module_id, security_level);
+
+    register_module(
+      &store,
+      session.clone(),
+      module_id,
+      uart_connection(&path),
+      &mut fake_module,
+    )
+    .await
+    .expect("Failed to register module.");
+
+    start_uart_bus(store.clone(), session.clone(), cancellation_token.clone())
+      .await
+      .expect("Failed to start the UART bus.");
+
+    wait_for_port(
+      &store,
+      &path,
+      PortStatus::Bound(module_id.to_string()),
+      TIMEOUT,
+    )
+    .await
+    .expect("Port wasn't bound in time.");
+
+    UartHarness {
+      store,
+      session,
+      cancellation_token,
+      module_id: module_id.to_string(),
+      path,
+      fake_module,
+      link: FakeLink::Pty(link),
+    }
+  }
+
+  async fn run(&mut self, script: &[ScriptStep]) {
+    self
+      .fake_module
+      .run(&mut self.link, script)
+      .await
+      .expect("Script failed.");
+  }
+}
+
+impl Drop for UartHarness {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn uart_module_is_adopted() {
+  let store = Arc::new(ModManStore::new(None, None));
+  let session = open_session().await;
+  let cancellation_token = CancellationToken::new();
+
+  let link = PtyLink::open().expect("Failed to open pty.");
+  let path = link.path().clone();
+  let mut fake_module = FakeModule::new("harness-module", "uart-adopted", SecurityLevel::L2)
+    .with_component(
+      "temperature",
+      AdoptionComponent {
+        component_type: "sensor".to_string(),
+        input: None,
+        output: Some("float".to_string()),
+      },
+    );
+
+  register_module_spec(&store, &fake_module)
+    .await
+    .expect("Failed to register the module's spec.");
+  {
+    let mut config = store.config.lock().await;
+    config.modman.uart_ports.push(path.clone());
+    config.modman.uart.adopt_new_modules = true;
+  }
+
+  start_adoption(store.clone(), session.clone(), cancellation_token.clone());
+  start_uart_bus(store.clone(), session.clone(), cancellation_token.clone())
+    .await
+    .expect("Failed to start the UART bus.");
+
+  // Approve the module once it's waiting, like a user would.
+  let pending_id = pending_adoption_id(&fake_module.hello());
+  let approval_store = store.clone();
+  let approval_id = pending_id.clone();
+  let approval = tokio::task::spawn(async move {
+    loop {
+      if approval_store
+        .pending_adoptions
+        .lock()
+        .await
+        .contains_key(&approval_id)
+      {
+        return approve_adoption(&approval_store, &approval_id).await;
+      }
+      tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+  });
+
+  let mut fake_link = FakeLink::Pty(link);
+  fake_module
+    .run(
+      &mut fake_link,
+      &[
+        ScriptStep::Expect {
+          message: ExpectedMessage::Probe,
+          timeout: TIMEOUT,
+        },
+        ScriptStep::Hello,
+        // Probed again once it's been approved.
+        ScriptStep::Expect {
+          message: ExpectedMessage::Probe,
+          timeout: TIMEOUT,
+        },
+        ScriptStep::Hello,
+        ScriptStep::Expect {
+          message: ExpectedMessage::Provision,
+          timeout: TIMEOUT,
+        },
+      ],
+    )
+    .await
+    .expect("Script failed.");
+  approval
+    .await
+    .unwrap()
+    .expect("Failed to approve the module.");
+
+  let module_id = store
+    .modules
+    .lock()
+    .await
+    .iter()
+    .find(|(_, module)| module.serial.as_deref() == Some(fake_module.serial.as_str()))
+    .map(|(module_id, _)| module_id.clone())
+    .expect("Module wasn't added to the store.");
+  wait_for_port(&store, &path, PortStatus::Bound(module_id.clone()), TIMEOUT)
+    .await
+    .expect("Port wasn't bound in time.");
+
+  let recv = subscribe(&session, &module_id, "recv").await;
+  fake_module
+    .run(
+      &mut fake_link,
+      &[ScriptStep::SendContent(b"adopted".to_vec())],
+    )
+    .await
+    .expect("Script failed.");
+
+  let payload = next_payload(&recv, TIMEOUT)
+    .await
+    .expect("Content wasn't published.");
+  match serde_json::from_str::<BusMessage>(&payload).unwrap() {
+    BusMessage::Content(content) => assert_eq!(content.data, b"adopted".to_vec()),
+    message => panic!("Expected content, got: {message:?}"),
+  }
+
+  cancellation_token.cancel();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn content_is_published_on_recv() {
+  let mut harness = UartHarness::start("uart-content", SecurityLevel::L2).await;
+  let recv = subscribe(&harness.session, &harness.module_id, "recv").await;
+  let errors = subscribe(&harness.session, &harness.module_id, "error").await;
+
+  harness
+    .run(&[ScriptStep::SendContent(b"hello, hub".to_vec())])
+    .await;
+
+  let payload = next_payload(&recv, TIMEOUT)
+    .await
+    .expect("Nothing was published on recv.");
+  match serde_json::from_str::<BusMessage>(&payload).unwrap() {
+    BusMessage::Content(content) => assert_eq!(content.data, b"hello, hub".to_vec()),
+    message => panic!("Expected content, got: {message:?}"),
+  }
+  assert_eq!(
+    next_payload(&errors, Duration::from_millis(500)).await,
+    None
+  );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forged_and_replayed_content_is_reported() {
+  let mut harness = UartHarness::start("uart-forged", SecurityLevel::L2).await;
+  let recv = subscribe(&harness.session, &harness.module_id, "recv").await;
+  let errors = subscribe(&harness.session, &harness.module_id, "error").await;
+
+  harness
+    .run(&[ScriptStep::SendForgedContent(b"forged".to_vec())])
+    .await;
+  assert!(next_payload(&errors, TIMEOUT).await.is_some());
+  assert_eq!(next_payload(&recv, Duration::from_millis(500)).await, None);
+
+  harness
+    .run(&[ScriptStep::SendContent(b"authentic".to_vec())])
+    .await;
+  assert!(next_payload(&recv, TIMEOUT).await.is_some());
+
+  harness.run(&[ScriptStep::Replay]).await;
+  assert!(next_payload(&errors, TIMEOUT).await.is_some());
+  assert_eq!(next_payload(&recv, Duration::from_millis(500)).await, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn corrupt_frames_are_counted() {
+  let mut harness = UartHarness::start("uart-corrupt", SecurityLevel::L2).await;
+  let errors = subscribe(&harness.session, &harness.module_id, "error").await;
+
+  // A COBS block that claims more bytes than the frame has.
+  harness
+    .run(&[ScriptStep::SendRaw(vec![0x05, 0x01, 0x02, 0x00])])
+    .await;
+  assert!(next_payload(&errors, TIMEOUT).await.is_some());
+
+  let framing_errors = harness.store.port_statuses.uart_framing_errors.lock().await;
+  assert_eq!(framing_errors.get(&harness.path), Some(&1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs CAP_NET_ADMIN and the vcan kernel module"]
+async fn vcan_content_is_published_on_recv() {
+  let store = Arc::new(ModManStore::new(None, None));
+  let session = open_session().await;
+  let cancellation_token = CancellationToken::new();
+  let module_id = "vcan-content".to_string();
+  let (hub_rx_id, hub_tx_id) = (0x101, 0x201);
+
+  let interface = VcanInterface::create("vcanharness0").expect("Failed to create vcan interface.");
+  let mut fake_module = FakeModule::new("harness-module", &module_id, SecurityLevel::L2);
+
+  register_module(
+    &store,
+    session.clone(),
+    &module_id,
+    can_2_connection(interface.name(), hub_rx_id, hub_tx_id),
+    &mut fake_module,
+  )
+  .await
+  .expect("Failed to register module.");
+
+  start_can_bus_manager(
+    store.clone(),
+    session.clone(),
+    cancellation_token.clone(),
+    interface.name(),
+  );
+  wait_for_port(
+    &store,
+    &interface.port(hub_rx_id, hub_tx_id),
+    PortStatus::Bound(module_id.clone()),
+    TIMEOUT,
+  )
+  .await
+  .expect("Port wasn't bound in time.");
+
+  let recv = subscribe(&session, &module_id, "recv").await;
+  let mut link = FakeLink::Vcan(
+    VcanLink::open(&interface, hub_rx_id, hub_tx_id).expect("Failed to open vcan link."),
+  );
+  fake_module
+    .run(
+      &mut link,
+      &[ScriptStep::SendContent(b"hello, hub".to_vec())],
+    )
+    .await
+    .expect("Script failed.");
+
+  assert!(next_payload(&recv, TIMEOUT).await.is_some());
+  cancellation_token.cancel();
+}